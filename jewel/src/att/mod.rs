@@ -0,0 +1,14 @@
+//! Attribute protocol
+//!
+//! The server exposes a table of attributes, each with a handle, a type and a value,
+//! that the client discovers, reads and writes on the ATT fixed channel.
+//!
+//! Ref: [Core 3.F](https://www.bluetooth.com/wp-content/uploads/Files/Specification/HTML/Core-54/out/en/host/attribute-protocol--att-.html)
+
+mod pdu;
+mod server;
+mod uuid;
+
+pub use pdu::*;
+pub use server::*;
+pub use uuid::*;
@@ -0,0 +1,589 @@
+use defmt::Format;
+
+use crate::ll::ParseError;
+
+use super::Uuid;
+
+/// Error code of an ATT_ERROR_RSP
+///
+/// Ref: Core 3.F.3.4.1.1
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Format)]
+pub enum AttError {
+    InvalidHandle,
+    ReadNotPermitted,
+    WriteNotPermitted,
+    InvalidPdu,
+    InsufficientAuthentication,
+    RequestNotSupported,
+    InvalidOffset,
+    InsufficientAuthorization,
+    PrepareQueueFull,
+    AttributeNotFound,
+    AttributeNotLong,
+    InsufficientEncryptionKeySize,
+    InvalidAttributeValueLength,
+    UnlikelyError,
+    InsufficientEncryption,
+    UnsupportedGroupType,
+    InsufficientResources,
+    DatabaseOutOfSync,
+    ValueNotAllowed,
+    /// Defined by the higher layer, from 0x80 to 0x9F
+    Application(u8),
+    /// Common profile and service error codes, from 0xE0 to 0xFF
+    Common(u8),
+}
+
+impl AttError {
+    pub fn code(&self) -> u8 {
+        match self {
+            Self::InvalidHandle => 0x01,
+            Self::ReadNotPermitted => 0x02,
+            Self::WriteNotPermitted => 0x03,
+            Self::InvalidPdu => 0x04,
+            Self::InsufficientAuthentication => 0x05,
+            Self::RequestNotSupported => 0x06,
+            Self::InvalidOffset => 0x07,
+            Self::InsufficientAuthorization => 0x08,
+            Self::PrepareQueueFull => 0x09,
+            Self::AttributeNotFound => 0x0A,
+            Self::AttributeNotLong => 0x0B,
+            Self::InsufficientEncryptionKeySize => 0x0C,
+            Self::InvalidAttributeValueLength => 0x0D,
+            Self::UnlikelyError => 0x0E,
+            Self::InsufficientEncryption => 0x0F,
+            Self::UnsupportedGroupType => 0x10,
+            Self::InsufficientResources => 0x11,
+            Self::DatabaseOutOfSync => 0x12,
+            Self::ValueNotAllowed => 0x13,
+            Self::Application(code) | Self::Common(code) => *code,
+        }
+    }
+}
+
+impl TryFrom<u8> for AttError {
+    type Error = ParseError;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        Ok(match value {
+            0x01 => Self::InvalidHandle,
+            0x02 => Self::ReadNotPermitted,
+            0x03 => Self::WriteNotPermitted,
+            0x04 => Self::InvalidPdu,
+            0x05 => Self::InsufficientAuthentication,
+            0x06 => Self::RequestNotSupported,
+            0x07 => Self::InvalidOffset,
+            0x08 => Self::InsufficientAuthorization,
+            0x09 => Self::PrepareQueueFull,
+            0x0A => Self::AttributeNotFound,
+            0x0B => Self::AttributeNotLong,
+            0x0C => Self::InsufficientEncryptionKeySize,
+            0x0D => Self::InvalidAttributeValueLength,
+            0x0E => Self::UnlikelyError,
+            0x0F => Self::InsufficientEncryption,
+            0x10 => Self::UnsupportedGroupType,
+            0x11 => Self::InsufficientResources,
+            0x12 => Self::DatabaseOutOfSync,
+            0x13 => Self::ValueNotAllowed,
+            0x80..=0x9F => Self::Application(value),
+            0xE0..=0xFF => Self::Common(value),
+            _ => return Err(ParseError::InvalidValue),
+        })
+    }
+}
+
+/// Attribute protocol PDU
+///
+/// The variable length fields borrow the received buffer, nothing is copied while parsing.
+///
+///   ┌──────────┬────────────────────────────────┐
+///   │ Opcode   │ Parameters                     │
+///   │ (1 byte) │ (0 to ATT_MTU - 1 bytes)       │
+///   └──────────┴────────────────────────────────┘
+///
+/// Ref: Core 3.F.3.3 and Core 3.F.3.4
+#[derive(Debug, Clone, PartialEq, Eq, Format)]
+pub enum AttPdu<'a> {
+    ErrorRsp {
+        request: u8,
+        handle: u16,
+        error: AttError,
+    },
+    ExchangeMtuReq {
+        mtu: u16,
+    },
+    ExchangeMtuRsp {
+        mtu: u16,
+    },
+    FindInformationReq {
+        start: u16,
+        end: u16,
+    },
+    FindInformationRsp {
+        /// 0x01 for handles with 16-bit UUIDs, 0x02 for 128-bit UUIDs
+        format: u8,
+        data: &'a [u8],
+    },
+    FindByTypeValueReq {
+        start: u16,
+        end: u16,
+        attribute_type: u16,
+        value: &'a [u8],
+    },
+    FindByTypeValueRsp {
+        /// List of found handle and group end handle
+        handles: &'a [u8],
+    },
+    ReadByTypeReq {
+        start: u16,
+        end: u16,
+        attribute_type: Uuid,
+    },
+    ReadByTypeRsp {
+        /// Length of each handle-value pair
+        length: u8,
+        data: &'a [u8],
+    },
+    ReadReq {
+        handle: u16,
+    },
+    ReadRsp {
+        value: &'a [u8],
+    },
+    ReadBlobReq {
+        handle: u16,
+        offset: u16,
+    },
+    ReadBlobRsp {
+        value: &'a [u8],
+    },
+    ReadMultipleReq {
+        handles: &'a [u8],
+    },
+    ReadMultipleRsp {
+        values: &'a [u8],
+    },
+    ReadByGroupTypeReq {
+        start: u16,
+        end: u16,
+        group_type: Uuid,
+    },
+    ReadByGroupTypeRsp {
+        /// Length of each handle, end group handle and value
+        length: u8,
+        data: &'a [u8],
+    },
+    WriteReq {
+        handle: u16,
+        value: &'a [u8],
+    },
+    WriteRsp,
+    WriteCmd {
+        handle: u16,
+        value: &'a [u8],
+    },
+    PrepareWriteReq {
+        handle: u16,
+        offset: u16,
+        value: &'a [u8],
+    },
+    PrepareWriteRsp {
+        handle: u16,
+        offset: u16,
+        value: &'a [u8],
+    },
+    ExecuteWriteReq {
+        /// 0x00 cancel the prepared writes, 0x01 write them all
+        flags: u8,
+    },
+    ExecuteWriteRsp,
+    HandleValueNtf {
+        handle: u16,
+        value: &'a [u8],
+    },
+    HandleValueInd {
+        handle: u16,
+        value: &'a [u8],
+    },
+    HandleValueCfm,
+}
+
+impl<'a> AttPdu<'a> {
+    /// Set on the opcode of the commands, that have no response
+    pub const COMMAND_FLAG: u8 = 0x40;
+
+    pub fn opcode(&self) -> u8 {
+        match self {
+            Self::ErrorRsp { .. } => 0x01,
+            Self::ExchangeMtuReq { .. } => 0x02,
+            Self::ExchangeMtuRsp { .. } => 0x03,
+            Self::FindInformationReq { .. } => 0x04,
+            Self::FindInformationRsp { .. } => 0x05,
+            Self::FindByTypeValueReq { .. } => 0x06,
+            Self::FindByTypeValueRsp { .. } => 0x07,
+            Self::ReadByTypeReq { .. } => 0x08,
+            Self::ReadByTypeRsp { .. } => 0x09,
+            Self::ReadReq { .. } => 0x0A,
+            Self::ReadRsp { .. } => 0x0B,
+            Self::ReadBlobReq { .. } => 0x0C,
+            Self::ReadBlobRsp { .. } => 0x0D,
+            Self::ReadMultipleReq { .. } => 0x0E,
+            Self::ReadMultipleRsp { .. } => 0x0F,
+            Self::ReadByGroupTypeReq { .. } => 0x10,
+            Self::ReadByGroupTypeRsp { .. } => 0x11,
+            Self::WriteReq { .. } => 0x12,
+            Self::WriteRsp => 0x13,
+            Self::WriteCmd { .. } => 0x52,
+            Self::PrepareWriteReq { .. } => 0x16,
+            Self::PrepareWriteRsp { .. } => 0x17,
+            Self::ExecuteWriteReq { .. } => 0x18,
+            Self::ExecuteWriteRsp => 0x19,
+            Self::HandleValueNtf { .. } => 0x1B,
+            Self::HandleValueInd { .. } => 0x1D,
+            Self::HandleValueCfm => 0x1E,
+        }
+    }
+
+    pub fn bytes(&self, dest: &mut [u8]) -> usize {
+        dest[0] = self.opcode();
+        let mut fields = FieldWriter { dest, len: 1 };
+
+        match self {
+            Self::ErrorRsp {
+                request,
+                handle,
+                error,
+            } => {
+                fields.push_u8(*request);
+                fields.push(*handle);
+                fields.push_u8(error.code());
+            }
+            Self::ExchangeMtuReq { mtu } | Self::ExchangeMtuRsp { mtu } => fields.push(*mtu),
+            Self::FindInformationReq { start, end } => {
+                fields.push(*start);
+                fields.push(*end);
+            }
+            Self::FindInformationRsp { format, data } => {
+                fields.push_u8(*format);
+                fields.push_slice(data);
+            }
+            Self::FindByTypeValueReq {
+                start,
+                end,
+                attribute_type,
+                value,
+            } => {
+                fields.push(*start);
+                fields.push(*end);
+                fields.push(*attribute_type);
+                fields.push_slice(value);
+            }
+            Self::ReadByTypeReq {
+                start,
+                end,
+                attribute_type: uuid,
+            }
+            | Self::ReadByGroupTypeReq {
+                start,
+                end,
+                group_type: uuid,
+            } => {
+                fields.push(*start);
+                fields.push(*end);
+                fields.len += uuid.bytes(&mut fields.dest[fields.len..]);
+            }
+            Self::ReadByTypeRsp { length, data } | Self::ReadByGroupTypeRsp { length, data } => {
+                fields.push_u8(*length);
+                fields.push_slice(data);
+            }
+            Self::ReadReq { handle } => fields.push(*handle),
+            Self::ReadBlobReq { handle, offset } => {
+                fields.push(*handle);
+                fields.push(*offset);
+            }
+            Self::FindByTypeValueRsp { handles: data }
+            | Self::ReadRsp { value: data }
+            | Self::ReadBlobRsp { value: data }
+            | Self::ReadMultipleReq { handles: data }
+            | Self::ReadMultipleRsp { values: data } => fields.push_slice(data),
+            Self::WriteReq { handle, value }
+            | Self::WriteCmd { handle, value }
+            | Self::HandleValueNtf { handle, value }
+            | Self::HandleValueInd { handle, value } => {
+                fields.push(*handle);
+                fields.push_slice(value);
+            }
+            Self::PrepareWriteReq {
+                handle,
+                offset,
+                value,
+            }
+            | Self::PrepareWriteRsp {
+                handle,
+                offset,
+                value,
+            } => {
+                fields.push(*handle);
+                fields.push(*offset);
+                fields.push_slice(value);
+            }
+            Self::ExecuteWriteReq { flags } => fields.push_u8(*flags),
+            Self::WriteRsp | Self::ExecuteWriteRsp | Self::HandleValueCfm => {}
+        }
+
+        fields.len
+    }
+
+    /// Parse the PDU, unknown opcodes result in `ParseError::InvalidType`
+    pub fn parse(bytes: &'a [u8]) -> Result<Self, ParseError> {
+        let (&opcode, params) = bytes.split_first().ok_or(ParseError::InvalidLength)?;
+
+        let field = |i: usize| -> Result<u16, ParseError> {
+            params
+                .get(i..i + 2)
+                .map(|bytes| u16::from_le_bytes([bytes[0], bytes[1]]))
+                .ok_or(ParseError::InvalidLength)
+        };
+        let expect_length = |length: usize| {
+            if params.len() == length {
+                Ok(())
+            } else {
+                Err(ParseError::InvalidLength)
+            }
+        };
+        let tail = |start: usize| params.get(start..).ok_or(ParseError::InvalidLength);
+
+        let pdu = match opcode {
+            0x01 => {
+                expect_length(4)?;
+                Self::ErrorRsp {
+                    request: params[0],
+                    handle: field(1)?,
+                    error: params[3].try_into()?,
+                }
+            }
+            0x02 => {
+                expect_length(2)?;
+                Self::ExchangeMtuReq { mtu: field(0)? }
+            }
+            0x03 => {
+                expect_length(2)?;
+                Self::ExchangeMtuRsp { mtu: field(0)? }
+            }
+            0x04 => {
+                expect_length(4)?;
+                Self::FindInformationReq {
+                    start: field(0)?,
+                    end: field(2)?,
+                }
+            }
+            0x05 => Self::FindInformationRsp {
+                format: *params.first().ok_or(ParseError::InvalidLength)?,
+                data: tail(1)?,
+            },
+            0x06 => Self::FindByTypeValueReq {
+                start: field(0)?,
+                end: field(2)?,
+                attribute_type: field(4)?,
+                value: tail(6)?,
+            },
+            0x07 => Self::FindByTypeValueRsp { handles: params },
+            0x08 => Self::ReadByTypeReq {
+                start: field(0)?,
+                end: field(2)?,
+                attribute_type: Uuid::parse(tail(4)?)?,
+            },
+            0x09 => Self::ReadByTypeRsp {
+                length: *params.first().ok_or(ParseError::InvalidLength)?,
+                data: tail(1)?,
+            },
+            0x0A => {
+                expect_length(2)?;
+                Self::ReadReq { handle: field(0)? }
+            }
+            0x0B => Self::ReadRsp { value: params },
+            0x0C => {
+                expect_length(4)?;
+                Self::ReadBlobReq {
+                    handle: field(0)?,
+                    offset: field(2)?,
+                }
+            }
+            0x0D => Self::ReadBlobRsp { value: params },
+            0x0E => {
+                // At least two handles
+                if params.len() < 4 || params.len() % 2 != 0 {
+                    return Err(ParseError::InvalidLength);
+                }
+                Self::ReadMultipleReq { handles: params }
+            }
+            0x0F => Self::ReadMultipleRsp { values: params },
+            0x10 => Self::ReadByGroupTypeReq {
+                start: field(0)?,
+                end: field(2)?,
+                group_type: Uuid::parse(tail(4)?)?,
+            },
+            0x11 => Self::ReadByGroupTypeRsp {
+                length: *params.first().ok_or(ParseError::InvalidLength)?,
+                data: tail(1)?,
+            },
+            0x12 => Self::WriteReq {
+                handle: field(0)?,
+                value: tail(2)?,
+            },
+            0x13 => {
+                expect_length(0)?;
+                Self::WriteRsp
+            }
+            0x52 => Self::WriteCmd {
+                handle: field(0)?,
+                value: tail(2)?,
+            },
+            0x16 => Self::PrepareWriteReq {
+                handle: field(0)?,
+                offset: field(2)?,
+                value: tail(4)?,
+            },
+            0x17 => Self::PrepareWriteRsp {
+                handle: field(0)?,
+                offset: field(2)?,
+                value: tail(4)?,
+            },
+            0x18 => {
+                expect_length(1)?;
+                Self::ExecuteWriteReq { flags: params[0] }
+            }
+            0x19 => {
+                expect_length(0)?;
+                Self::ExecuteWriteRsp
+            }
+            0x1B => Self::HandleValueNtf {
+                handle: field(0)?,
+                value: tail(2)?,
+            },
+            0x1D => Self::HandleValueInd {
+                handle: field(0)?,
+                value: tail(2)?,
+            },
+            0x1E => {
+                expect_length(0)?;
+                Self::HandleValueCfm
+            }
+            _ => return Err(ParseError::InvalidType),
+        };
+
+        Ok(pdu)
+    }
+}
+
+/// Write consecutive little endian fields
+struct FieldWriter<'a> {
+    dest: &'a mut [u8],
+    len: usize,
+}
+
+impl FieldWriter<'_> {
+    fn push_u8(&mut self, value: u8) {
+        self.dest[self.len] = value;
+        self.len += 1;
+    }
+
+    fn push(&mut self, value: u16) {
+        self.push_slice(&value.to_le_bytes());
+    }
+
+    fn push_slice(&mut self, value: &[u8]) {
+        self.dest[self.len..self.len + value.len()].copy_from_slice(value);
+        self.len += value.len();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_requests() {
+        assert_eq!(
+            AttPdu::parse(&[0x02, 0xF7, 0x00]),
+            Ok(AttPdu::ExchangeMtuReq { mtu: 247 })
+        );
+        assert_eq!(
+            AttPdu::parse(&[0x10, 0x01, 0x00, 0xFF, 0xFF, 0x00, 0x28]),
+            Ok(AttPdu::ReadByGroupTypeReq {
+                start: 0x0001,
+                end: 0xFFFF,
+                group_type: Uuid::PRIMARY_SERVICE,
+            })
+        );
+        assert_eq!(
+            AttPdu::parse(&[0x52, 0x03, 0x00, 0xAA, 0xBB]),
+            Ok(AttPdu::WriteCmd {
+                handle: 0x0003,
+                value: &[0xAA, 0xBB],
+            })
+        );
+        assert_eq!(
+            AttPdu::parse(&[0x0E, 0x01, 0x00, 0x02, 0x00]),
+            Ok(AttPdu::ReadMultipleReq {
+                handles: &[0x01, 0x00, 0x02, 0x00],
+            })
+        );
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert_eq!(AttPdu::parse(&[]), Err(ParseError::InvalidLength));
+        assert_eq!(AttPdu::parse(&[0x0A, 0x01]), Err(ParseError::InvalidLength));
+        assert_eq!(
+            AttPdu::parse(&[0x0E, 0x01, 0x00]),
+            Err(ParseError::InvalidLength)
+        );
+        // Signed write command is not supported
+        assert_eq!(
+            AttPdu::parse(&[0xD2, 0x01, 0x00]),
+            Err(ParseError::InvalidType)
+        );
+    }
+
+    #[test]
+    fn bytes_round_trip() {
+        let pdus = [
+            AttPdu::ErrorRsp {
+                request: 0x0A,
+                handle: 0x0010,
+                error: AttError::InvalidHandle,
+            },
+            AttPdu::FindInformationRsp {
+                format: 0x01,
+                data: &[0x01, 0x00, 0x00, 0x28],
+            },
+            AttPdu::ReadByTypeReq {
+                start: 0x0001,
+                end: 0x0005,
+                attribute_type: Uuid::Uuid128([0xAB; 16]),
+            },
+            AttPdu::PrepareWriteRsp {
+                handle: 0x0003,
+                offset: 0x0012,
+                value: &[1, 2, 3],
+            },
+            AttPdu::ExecuteWriteReq { flags: 0x01 },
+            AttPdu::HandleValueCfm,
+        ];
+
+        let mut buffer = [0u8; 32];
+        for pdu in pdus {
+            let len = pdu.bytes(&mut buffer);
+            assert_eq!(AttPdu::parse(&buffer[..len]), Ok(pdu));
+        }
+
+        let len = AttPdu::ErrorRsp {
+            request: 0x0A,
+            handle: 0x0010,
+            error: AttError::Application(0x80),
+        }
+        .bytes(&mut buffer);
+        assert_eq!(buffer[..len], [0x01, 0x0A, 0x10, 0x00, 0x80]);
+    }
+}
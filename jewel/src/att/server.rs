@@ -0,0 +1,1164 @@
+use defmt::Format;
+use embassy_time::Duration;
+
+use crate::{
+    l2cap::{BasicFrame, MAX_FRAME_LENGTH},
+    ll::{ConnectionError, ParseError},
+};
+
+use super::{AttError, AttPdu, Uuid};
+
+/// ATT_MTU of a connection before the MTU exchange
+pub const DEFAULT_MTU: u16 = 23;
+
+/// Largest ATT_MTU, the PDUs fit in the L2CAP reassembly buffer
+pub const MAX_MTU: u16 = (MAX_FRAME_LENGTH - BasicFrame::HEADER_LENGTH) as u16;
+
+/// Number of ATT_PREPARE_WRITE_REQ queued until they are executed
+pub const PREPARE_QUEUE_SIZE: usize = 8;
+
+/// Bytes of the values queued by ATT_PREPARE_WRITE_REQ
+pub const PREPARE_BUFFER_SIZE: usize = 512;
+
+/// Characteristics a client can subscribe to on a connection
+pub const MAX_SUBSCRIPTIONS: usize = 8;
+
+/// ATT transaction timeout, an indication shall be confirmed before it
+///
+/// Ref: Core 3.F.3.3.3
+pub const TRANSACTION_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Largest attribute value
+///
+/// Ref: Core 3.F.3.2.9
+pub const MAX_VALUE_LENGTH: usize = 512;
+
+/// Operations allowed on an attribute
+///
+/// Ref: Core 3.F.3.2.5
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Format)]
+pub struct Permissions {
+    bits: u8,
+}
+
+impl Permissions {
+    pub const NONE: Permissions = Permissions { bits: 0 };
+    pub const READ: Permissions = Permissions { bits: 1 << 0 };
+    pub const WRITE: Permissions = Permissions { bits: 1 << 1 };
+
+    pub const fn union(self, other: Permissions) -> Permissions {
+        Permissions {
+            bits: self.bits | other.bits,
+        }
+    }
+
+    pub const fn contains(&self, other: Permissions) -> bool {
+        self.bits & other.bits == other.bits
+    }
+}
+
+/// Attribute exposed by the server, the value is accessed through the `AttributeTable`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Format)]
+pub struct Attribute {
+    pub handle: u16,
+    pub uuid: Uuid,
+    pub permissions: Permissions,
+}
+
+/// Attributes exposed by the ATT server, ordered by handle
+///
+/// The server checks the permissions before reading or writing an attribute.
+pub trait AttributeTable {
+    /// Number of attributes
+    fn count(&self) -> usize;
+
+    /// Attribute at the `index`, from 0 to `count() - 1`
+    fn attribute(&self, index: usize) -> Attribute;
+
+    /// Copy the value starting at `offset` to `dest`, truncated to its length.
+    /// Returns the number of bytes copied.
+    fn read(&mut self, index: usize, offset: usize, dest: &mut [u8]) -> Result<usize, AttError>;
+
+    /// Write the `value` starting at `offset`
+    fn write(&mut self, index: usize, offset: usize, value: &[u8]) -> Result<(), AttError>;
+}
+
+/// Table without attributes
+pub struct NoAttributes;
+
+impl AttributeTable for NoAttributes {
+    fn count(&self) -> usize {
+        0
+    }
+
+    fn attribute(&self, _index: usize) -> Attribute {
+        unreachable!()
+    }
+
+    fn read(&mut self, _index: usize, _offset: usize, _dest: &mut [u8]) -> Result<usize, AttError> {
+        Err(AttError::InvalidHandle)
+    }
+
+    fn write(&mut self, _index: usize, _offset: usize, _value: &[u8]) -> Result<(), AttError> {
+        Err(AttError::InvalidHandle)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Format)]
+pub enum NotifyError<E> {
+    Connection(ConnectionError<E>),
+
+    /// The client did not enable the notifications or indications of the characteristic
+    NotSubscribed,
+
+    /// The indication was not confirmed, no more indications are sent on the connection
+    Timeout,
+}
+
+impl<E> From<ConnectionError<E>> for NotifyError<E> {
+    fn from(error: ConnectionError<E>) -> Self {
+        NotifyError::Connection(error)
+    }
+}
+
+/// Client Characteristic Configuration written by the client
+#[derive(Debug, Clone, Copy)]
+struct Subscription {
+    cccd: u16,
+    value_handle: u16,
+    flags: u16,
+}
+
+/// Client Characteristic Configuration descriptors of a connection
+///
+/// Ref: Core 3.G.3.3.3.3
+struct Subscriptions {
+    entries: [Option<Subscription>; MAX_SUBSCRIPTIONS],
+}
+
+impl Subscriptions {
+    /// The client enabled the notifications
+    const NOTIFICATION: u16 = 0x0001;
+
+    /// The client enabled the indications
+    const INDICATION: u16 = 0x0002;
+
+    /// Characteristic properties that allow each configuration
+    const NOTIFY_PROPERTY: u8 = 0x10;
+    const INDICATE_PROPERTY: u8 = 0x20;
+
+    const fn new() -> Self {
+        Self {
+            entries: [None; MAX_SUBSCRIPTIONS],
+        }
+    }
+
+    fn flags(&self, cccd: u16) -> u16 {
+        self.entries
+            .iter()
+            .flatten()
+            .find(|entry| entry.cccd == cccd)
+            .map_or(0, |entry| entry.flags)
+    }
+
+    fn contains(&self, value_handle: u16, flag: u16) -> bool {
+        self.entries
+            .iter()
+            .flatten()
+            .any(|entry| entry.value_handle == value_handle && entry.flags & flag != 0)
+    }
+
+    fn set(&mut self, cccd: u16, value_handle: u16, flags: u16) -> Result<(), AttError> {
+        let entry = self
+            .entries
+            .iter_mut()
+            .find(|entry| entry.is_some_and(|entry| entry.cccd == cccd));
+        let entry = match entry {
+            Some(entry) => entry,
+            None if flags == 0 => return Ok(()),
+            None => self
+                .entries
+                .iter_mut()
+                .find(|entry| entry.is_none())
+                .ok_or(AttError::InsufficientResources)?,
+        };
+
+        *entry = (flags != 0).then_some(Subscription {
+            cccd,
+            value_handle,
+            flags,
+        });
+        Ok(())
+    }
+}
+
+/// Attribute table with the Client Characteristic Configuration of the connection,
+/// the descriptors are never read or written on the application table.
+struct ConfiguredTable<'a> {
+    table: &'a mut dyn AttributeTable,
+    subscriptions: &'a mut Subscriptions,
+}
+
+impl ConfiguredTable<'_> {
+    fn is_cccd(&self, index: usize) -> bool {
+        self.table.attribute(index).uuid == Uuid::CLIENT_CHARACTERISTIC_CONFIGURATION
+    }
+
+    /// Properties and value handle of the characteristic the descriptor belongs to
+    fn characteristic(&mut self, index: usize) -> Result<(u8, u16), AttError> {
+        let declaration = (0..index)
+            .rev()
+            .find(|&index| self.table.attribute(index).uuid == Uuid::CHARACTERISTIC)
+            .ok_or(AttError::UnlikelyError)?;
+
+        let mut value = [0u8; 19];
+        match self.table.read(declaration, 0, &mut value)? {
+            len if len >= 3 => Ok((value[0], u16::from_le_bytes([value[1], value[2]]))),
+            _ => Err(AttError::UnlikelyError),
+        }
+    }
+}
+
+impl AttributeTable for ConfiguredTable<'_> {
+    fn count(&self) -> usize {
+        self.table.count()
+    }
+
+    fn attribute(&self, index: usize) -> Attribute {
+        self.table.attribute(index)
+    }
+
+    fn read(&mut self, index: usize, offset: usize, dest: &mut [u8]) -> Result<usize, AttError> {
+        if !self.is_cccd(index) {
+            return self.table.read(index, offset, dest);
+        }
+
+        let flags = self.subscriptions.flags(self.attribute(index).handle);
+        let value = flags.to_le_bytes();
+        let value = value.get(offset..).ok_or(AttError::InvalidOffset)?;
+        let len = value.len().min(dest.len());
+        dest[..len].copy_from_slice(&value[..len]);
+        Ok(len)
+    }
+
+    fn write(&mut self, index: usize, offset: usize, value: &[u8]) -> Result<(), AttError> {
+        if !self.is_cccd(index) {
+            return self.table.write(index, offset, value);
+        }
+
+        let [low, high] = value else {
+            return Err(AttError::InvalidAttributeValueLength);
+        };
+        if offset != 0 {
+            return Err(AttError::InvalidOffset);
+        }
+        let flags = u16::from_le_bytes([*low, *high]);
+
+        let (properties, value_handle) = self.characteristic(index)?;
+        let allowed = [
+            (Subscriptions::NOTIFICATION, Subscriptions::NOTIFY_PROPERTY),
+            (Subscriptions::INDICATION, Subscriptions::INDICATE_PROPERTY),
+        ]
+        .into_iter()
+        .filter(|(_, property)| properties & property != 0)
+        .fold(0, |allowed, (flag, _)| allowed | flag);
+        if flags & !allowed != 0 {
+            // Client Characteristic Configuration Descriptor Improperly Configured
+            return Err(AttError::Common(0xFD));
+        }
+
+        let cccd = self.attribute(index).handle;
+        self.subscriptions.set(cccd, value_handle, flags)
+    }
+}
+
+/// Write queued by ATT_PREPARE_WRITE_REQ
+#[derive(Debug, Clone, Copy)]
+struct PreparedWrite {
+    index: usize,
+    handle: u16,
+    offset: u16,
+    start: usize,
+    len: usize,
+}
+
+/// Error response of a request, with the handle that caused it
+type RequestError = (u16, AttError);
+
+/// Attribute protocol server of a connection
+///
+/// Answer the requests of the client with the attributes of an `AttributeTable`.
+///
+/// Ref: Core 3.F.3.4
+pub struct AttServer {
+    mtu: u16,
+    prepared: PrepareQueue,
+    subscriptions: Subscriptions,
+
+    /// An indication was sent and its confirmation was not received yet
+    indication_pending: bool,
+}
+
+impl AttServer {
+    pub const fn new() -> Self {
+        Self {
+            mtu: DEFAULT_MTU,
+            prepared: PrepareQueue::new(),
+            subscriptions: Subscriptions::new(),
+            indication_pending: false,
+        }
+    }
+
+    /// ATT_MTU of the connection
+    pub fn mtu(&self) -> u16 {
+        self.mtu
+    }
+
+    /// Use the smallest ATT_MTU of the devices, the peer answered an MTU exchange
+    pub(crate) fn set_mtu(&mut self, peer_mtu: u16) {
+        self.mtu = peer_mtu.clamp(DEFAULT_MTU, MAX_MTU);
+    }
+
+    /// Write the ATT_HANDLE_VALUE_NTF of the characteristic value,
+    /// truncated to ATT_MTU - 3 bytes. The client shall have enabled the notifications.
+    ///
+    /// Ref: Core 3.G.4.10
+    pub fn notification<E>(
+        &self,
+        handle: u16,
+        value: &[u8],
+        dest: &mut [u8],
+    ) -> Result<usize, NotifyError<E>> {
+        if !self
+            .subscriptions
+            .contains(handle, Subscriptions::NOTIFICATION)
+        {
+            return Err(NotifyError::NotSubscribed);
+        }
+
+        let value = &value[..value.len().min(self.mtu as usize - 3)];
+        Ok(AttPdu::HandleValueNtf { handle, value }.bytes(dest))
+    }
+
+    /// Write the ATT_HANDLE_VALUE_IND of the characteristic value,
+    /// truncated to ATT_MTU - 3 bytes. The client shall have enabled the indications
+    /// and confirmed the previous indication.
+    ///
+    /// Ref: Core 3.G.4.11
+    pub fn indication<E>(
+        &mut self,
+        handle: u16,
+        value: &[u8],
+        dest: &mut [u8],
+    ) -> Result<usize, NotifyError<E>> {
+        if !self
+            .subscriptions
+            .contains(handle, Subscriptions::INDICATION)
+        {
+            return Err(NotifyError::NotSubscribed);
+        }
+        if self.indication_pending {
+            return Err(NotifyError::Timeout);
+        }
+
+        self.indication_pending = true;
+        let value = &value[..value.len().min(self.mtu as usize - 3)];
+        Ok(AttPdu::HandleValueInd { handle, value }.bytes(dest))
+    }
+
+    /// The last indication was not confirmed yet
+    pub fn indication_pending(&self) -> bool {
+        self.indication_pending
+    }
+
+    /// Handle a PDU received from the client, writing the response to `response`.
+    /// Returns the length of the response, commands and unexpected PDUs have no response.
+    pub fn handle(
+        &mut self,
+        table: &mut dyn AttributeTable,
+        request: &[u8],
+        response: &mut [u8],
+    ) -> Option<usize> {
+        let &opcode = request.first()?;
+        let response = &mut response[..self.mtu as usize];
+
+        let pdu = match AttPdu::parse(request) {
+            Ok(pdu) => pdu,
+            // Invalid commands are ignored
+            Err(_) if opcode & AttPdu::COMMAND_FLAG != 0 => return None,
+            Err(ParseError::InvalidType) => {
+                return Some(error_response(
+                    opcode,
+                    0x0000,
+                    AttError::RequestNotSupported,
+                    response,
+                ))
+            }
+            Err(_) => {
+                return Some(error_response(
+                    opcode,
+                    0x0000,
+                    AttError::InvalidPdu,
+                    response,
+                ))
+            }
+        };
+
+        let table = &mut ConfiguredTable {
+            table,
+            subscriptions: &mut self.subscriptions,
+        };
+        let result = match pdu {
+            AttPdu::ExchangeMtuReq { mtu } => {
+                // Ref: Core 3.F.3.4.2
+                self.set_mtu(mtu);
+                Ok(AttPdu::ExchangeMtuRsp { mtu: MAX_MTU }.bytes(response))
+            }
+            AttPdu::FindInformationReq { start, end } => {
+                find_information(table, start, end, response)
+            }
+            AttPdu::FindByTypeValueReq {
+                start,
+                end,
+                attribute_type,
+                value,
+            } => find_by_type_value(table, start, end, attribute_type, value, response),
+            AttPdu::ReadByTypeReq {
+                start,
+                end,
+                attribute_type,
+            } => read_by_type(table, start, end, attribute_type, response),
+            AttPdu::ReadReq { handle } => read(table, handle, 0, 0x0B, response),
+            AttPdu::ReadBlobReq { handle, offset } => read(table, handle, offset, 0x0D, response),
+            AttPdu::ReadMultipleReq { handles } => read_multiple(table, handles, response),
+            AttPdu::ReadByGroupTypeReq {
+                start,
+                end,
+                group_type,
+            } => read_by_group_type(table, start, end, group_type, response),
+            AttPdu::WriteReq { handle, value } => {
+                write(table, handle, value).map(|()| AttPdu::WriteRsp.bytes(response))
+            }
+            AttPdu::WriteCmd { handle, value } => {
+                let _ = write(table, handle, value);
+                return None;
+            }
+            AttPdu::PrepareWriteReq {
+                handle,
+                offset,
+                value,
+            } => self
+                .prepared
+                .prepare(table, handle, offset, value, response),
+            AttPdu::ExecuteWriteReq { flags } => self.prepared.execute(table, flags, response),
+            AttPdu::HandleValueCfm => {
+                self.indication_pending = false;
+                return None;
+            }
+            // Responses, notifications and indications are not expected by the server
+            _ => return None,
+        };
+
+        Some(
+            result
+                .unwrap_or_else(|(handle, error)| error_response(opcode, handle, error, response)),
+        )
+    }
+}
+
+impl Default for AttServer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Writes queued by ATT_PREPARE_WRITE_REQ until they are executed
+struct PrepareQueue {
+    writes: [Option<PreparedWrite>; PREPARE_QUEUE_SIZE],
+    data: [u8; PREPARE_BUFFER_SIZE],
+}
+
+impl PrepareQueue {
+    const fn new() -> Self {
+        Self {
+            writes: [None; PREPARE_QUEUE_SIZE],
+            data: [0; PREPARE_BUFFER_SIZE],
+        }
+    }
+
+    /// Ref: Core 3.F.3.4.6.1
+    fn prepare(
+        &mut self,
+        table: &mut dyn AttributeTable,
+        handle: u16,
+        offset: u16,
+        value: &[u8],
+        response: &mut [u8],
+    ) -> Result<usize, RequestError> {
+        let index = writable(table, handle)?;
+
+        let start = self
+            .writes
+            .iter()
+            .flatten()
+            .map(|write| write.start + write.len)
+            .max()
+            .unwrap_or(0);
+        let slot = self.writes.iter_mut().find(|write| write.is_none());
+        let (Some(slot), true) = (slot, start + value.len() <= PREPARE_BUFFER_SIZE) else {
+            return Err((handle, AttError::PrepareQueueFull));
+        };
+
+        self.data[start..start + value.len()].copy_from_slice(value);
+        *slot = Some(PreparedWrite {
+            index,
+            handle,
+            offset,
+            start,
+            len: value.len(),
+        });
+
+        Ok(AttPdu::PrepareWriteRsp {
+            handle,
+            offset,
+            value,
+        }
+        .bytes(response))
+    }
+
+    /// Ref: Core 3.F.3.4.6.3
+    fn execute(
+        &mut self,
+        table: &mut dyn AttributeTable,
+        flags: u8,
+        response: &mut [u8],
+    ) -> Result<usize, RequestError> {
+        let prepared = core::mem::replace(&mut self.writes, [None; PREPARE_QUEUE_SIZE]);
+
+        match flags {
+            0x00 => {}
+            0x01 => {
+                for write in prepared.iter().flatten() {
+                    let value = &self.data[write.start..write.start + write.len];
+                    table
+                        .write(write.index, write.offset as usize, value)
+                        .map_err(|error| (write.handle, error))?;
+                }
+            }
+            _ => return Err((0x0000, AttError::InvalidPdu)),
+        }
+
+        Ok(AttPdu::ExecuteWriteRsp.bytes(response))
+    }
+}
+
+fn error_response(request: u8, handle: u16, error: AttError, response: &mut [u8]) -> usize {
+    AttPdu::ErrorRsp {
+        request,
+        handle,
+        error,
+    }
+    .bytes(response)
+}
+
+/// Index of the attribute with the handle
+fn find(table: &dyn AttributeTable, handle: u16) -> Result<usize, RequestError> {
+    (0..table.count())
+        .find(|&index| table.attribute(index).handle == handle)
+        .ok_or((handle, AttError::InvalidHandle))
+}
+
+fn readable(table: &dyn AttributeTable, handle: u16) -> Result<usize, RequestError> {
+    let index = find(table, handle)?;
+    if !table
+        .attribute(index)
+        .permissions
+        .contains(Permissions::READ)
+    {
+        return Err((handle, AttError::ReadNotPermitted));
+    }
+    Ok(index)
+}
+
+fn writable(table: &dyn AttributeTable, handle: u16) -> Result<usize, RequestError> {
+    let index = find(table, handle)?;
+    if !table
+        .attribute(index)
+        .permissions
+        .contains(Permissions::WRITE)
+    {
+        return Err((handle, AttError::WriteNotPermitted));
+    }
+    Ok(index)
+}
+
+/// The handle range of a request shall start at 0x0001 and not end before its start
+fn check_range(start: u16, end: u16) -> Result<(), RequestError> {
+    if start == 0x0000 || start > end {
+        return Err((start, AttError::InvalidHandle));
+    }
+    Ok(())
+}
+
+/// Last handle of the group started by the attribute at `index`,
+/// the group ends before the next service declaration.
+fn group_end(table: &dyn AttributeTable, index: usize) -> u16 {
+    let next = (index + 1..table.count()).find(|&next| {
+        let uuid = table.attribute(next).uuid;
+        uuid == Uuid::PRIMARY_SERVICE || uuid == Uuid::SECONDARY_SERVICE
+    });
+
+    match next {
+        Some(next) => table.attribute(next - 1).handle,
+        None => 0xFFFF,
+    }
+}
+
+/// Ref: Core 3.F.3.4.3.1
+fn find_information(
+    table: &dyn AttributeTable,
+    start: u16,
+    end: u16,
+    response: &mut [u8],
+) -> Result<usize, RequestError> {
+    let mut data = [0u8; MAX_MTU as usize];
+    let capacity = response.len() - 2;
+    let mut len = 0;
+    let mut uuid_length = 0;
+
+    check_range(start, end)?;
+    for index in 0..table.count() {
+        let attribute = table.attribute(index);
+        if !(start..=end).contains(&attribute.handle) {
+            continue;
+        }
+        // All the UUIDs of the response have the same format
+        if uuid_length != 0 && attribute.uuid.length() != uuid_length {
+            break;
+        }
+        uuid_length = attribute.uuid.length();
+        if len + 2 + uuid_length > capacity {
+            break;
+        }
+
+        data[len..len + 2].copy_from_slice(&attribute.handle.to_le_bytes());
+        len += 2 + attribute.uuid.bytes(&mut data[len + 2..]);
+    }
+
+    if len == 0 {
+        return Err((start, AttError::AttributeNotFound));
+    }
+
+    let format = if uuid_length == 2 { 0x01 } else { 0x02 };
+    Ok(AttPdu::FindInformationRsp {
+        format,
+        data: &data[..len],
+    }
+    .bytes(response))
+}
+
+/// Ref: Core 3.F.3.4.3.3
+fn find_by_type_value(
+    table: &mut dyn AttributeTable,
+    start: u16,
+    end: u16,
+    attribute_type: u16,
+    value: &[u8],
+    response: &mut [u8],
+) -> Result<usize, RequestError> {
+    let mut handles = [0u8; MAX_MTU as usize];
+    let capacity = response.len() - 1;
+    let mut len = 0;
+    let mut buffer = [0u8; MAX_VALUE_LENGTH];
+
+    check_range(start, end)?;
+    for index in 0..table.count() {
+        let attribute = table.attribute(index);
+        if !(start..=end).contains(&attribute.handle)
+            || attribute.uuid != Uuid::Uuid16(attribute_type)
+        {
+            continue;
+        }
+        if !attribute.permissions.contains(Permissions::READ) {
+            continue;
+        }
+        match table.read(index, 0, &mut buffer) {
+            Ok(value_len) if buffer[..value_len] == *value => {}
+            _ => continue,
+        }
+        if len + 4 > capacity {
+            break;
+        }
+
+        let end_group = match attribute.uuid {
+            Uuid::PRIMARY_SERVICE | Uuid::SECONDARY_SERVICE => group_end(table, index),
+            _ => attribute.handle,
+        };
+        handles[len..len + 2].copy_from_slice(&attribute.handle.to_le_bytes());
+        handles[len + 2..len + 4].copy_from_slice(&end_group.to_le_bytes());
+        len += 4;
+    }
+
+    if len == 0 {
+        return Err((start, AttError::AttributeNotFound));
+    }
+
+    Ok(AttPdu::FindByTypeValueRsp {
+        handles: &handles[..len],
+    }
+    .bytes(response))
+}
+
+/// Ref: Core 3.F.3.4.4.1
+fn read_by_type(
+    table: &mut dyn AttributeTable,
+    start: u16,
+    end: u16,
+    attribute_type: Uuid,
+    response: &mut [u8],
+) -> Result<usize, RequestError> {
+    read_list(table, start, end, attribute_type, false, response)
+}
+
+/// Ref: Core 3.F.3.4.4.9
+fn read_by_group_type(
+    table: &mut dyn AttributeTable,
+    start: u16,
+    end: u16,
+    group_type: Uuid,
+    response: &mut [u8],
+) -> Result<usize, RequestError> {
+    check_range(start, end)?;
+    if group_type != Uuid::PRIMARY_SERVICE && group_type != Uuid::SECONDARY_SERVICE {
+        return Err((start, AttError::UnsupportedGroupType));
+    }
+
+    read_list(table, start, end, group_type, true, response)
+}
+
+/// List the handles and values of the attributes of a type, all with the same length.
+/// The groups also list their end group handle.
+fn read_list(
+    table: &mut dyn AttributeTable,
+    start: u16,
+    end: u16,
+    uuid: Uuid,
+    group: bool,
+    response: &mut [u8],
+) -> Result<usize, RequestError> {
+    let header_length = if group { 4 } else { 2 };
+    // Each entry length shall fit in a byte
+    let max_value = (response.len() - 2 - header_length).min(255 - header_length);
+
+    let mut data = [0u8; MAX_MTU as usize];
+    let capacity = response.len() - 2;
+    let mut len = 0;
+    let mut entry_length = 0;
+
+    check_range(start, end)?;
+    for index in 0..table.count() {
+        let attribute = table.attribute(index);
+        if !(start..=end).contains(&attribute.handle) || attribute.uuid != uuid {
+            continue;
+        }
+        if !attribute.permissions.contains(Permissions::READ) {
+            // The error is only returned for the first attribute
+            if len == 0 {
+                return Err((attribute.handle, AttError::ReadNotPermitted));
+            }
+            break;
+        }
+
+        let mut value = [0u8; MAX_VALUE_LENGTH];
+        let value_len = match table.read(index, 0, &mut value[..max_value]) {
+            Ok(value_len) => value_len,
+            Err(error) if len == 0 => return Err((attribute.handle, error)),
+            Err(_) => break,
+        };
+
+        let length = header_length + value_len;
+        if (entry_length != 0 && length != entry_length) || len + length > capacity {
+            break;
+        }
+        entry_length = length;
+
+        data[len..len + 2].copy_from_slice(&attribute.handle.to_le_bytes());
+        if group {
+            data[len + 2..len + 4].copy_from_slice(&group_end(table, index).to_le_bytes());
+        }
+        data[len + header_length..len + length].copy_from_slice(&value[..value_len]);
+        len += length;
+    }
+
+    if len == 0 {
+        return Err((start, AttError::AttributeNotFound));
+    }
+
+    let data = &data[..len];
+    let length = entry_length as u8;
+    let pdu = if group {
+        AttPdu::ReadByGroupTypeRsp { length, data }
+    } else {
+        AttPdu::ReadByTypeRsp { length, data }
+    };
+    Ok(pdu.bytes(response))
+}
+
+/// ATT_READ_REQ and ATT_READ_BLOB_REQ
+///
+/// Ref: Core 3.F.3.4.4.3 and Core 3.F.3.4.4.5
+fn read(
+    table: &mut dyn AttributeTable,
+    handle: u16,
+    offset: u16,
+    opcode: u8,
+    response: &mut [u8],
+) -> Result<usize, RequestError> {
+    let index = readable(table, handle)?;
+    let len = table
+        .read(index, offset as usize, &mut response[1..])
+        .map_err(|error| (handle, error))?;
+
+    // ATT_READ_RSP and ATT_READ_BLOB_RSP only differ in the opcode
+    response[0] = opcode;
+    Ok(1 + len)
+}
+
+/// Ref: Core 3.F.3.4.4.7
+fn read_multiple(
+    table: &mut dyn AttributeTable,
+    handles: &[u8],
+    response: &mut [u8],
+) -> Result<usize, RequestError> {
+    let mut len = 1;
+    for handle in handles.chunks_exact(2) {
+        let handle = u16::from_le_bytes([handle[0], handle[1]]);
+        let index = readable(table, handle)?;
+
+        // The values are truncated to the ATT_MTU
+        let mut value = [0u8; MAX_VALUE_LENGTH];
+        let value_len = table
+            .read(index, 0, &mut value)
+            .map_err(|error| (handle, error))?;
+        let copied = value_len.min(response.len() - len);
+        response[len..len + copied].copy_from_slice(&value[..copied]);
+        len += copied;
+    }
+
+    response[0] = 0x0F;
+    Ok(len)
+}
+
+/// Ref: Core 3.F.3.4.5.1
+fn write(table: &mut dyn AttributeTable, handle: u16, value: &[u8]) -> Result<(), RequestError> {
+    let index = writable(table, handle)?;
+    table
+        .write(index, 0, value)
+        .map_err(|error| (handle, error))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// GAP service with the device name and a battery service
+    struct TestTable {
+        battery_level: u8,
+        long_value: [u8; 40],
+    }
+
+    const ATTRIBUTES: [Attribute; 8] = [
+        Attribute {
+            handle: 0x0001,
+            uuid: Uuid::PRIMARY_SERVICE,
+            permissions: Permissions::READ,
+        },
+        Attribute {
+            handle: 0x0002,
+            uuid: Uuid::CHARACTERISTIC,
+            permissions: Permissions::READ,
+        },
+        Attribute {
+            handle: 0x0003,
+            uuid: Uuid::Uuid16(0x2A00),
+            permissions: Permissions::READ,
+        },
+        Attribute {
+            handle: 0x0004,
+            uuid: Uuid::PRIMARY_SERVICE,
+            permissions: Permissions::READ,
+        },
+        Attribute {
+            handle: 0x0005,
+            uuid: Uuid::CHARACTERISTIC,
+            permissions: Permissions::READ,
+        },
+        Attribute {
+            handle: 0x0006,
+            uuid: Uuid::Uuid16(0x2A19),
+            permissions: Permissions::READ.union(Permissions::WRITE),
+        },
+        Attribute {
+            handle: 0x0007,
+            uuid: Uuid::Uuid128([0x11; 16]),
+            permissions: Permissions::READ.union(Permissions::WRITE),
+        },
+        Attribute {
+            handle: 0x0008,
+            uuid: Uuid::Uuid16(0x2A19),
+            permissions: Permissions::WRITE,
+        },
+    ];
+
+    impl AttributeTable for TestTable {
+        fn count(&self) -> usize {
+            ATTRIBUTES.len()
+        }
+
+        fn attribute(&self, index: usize) -> Attribute {
+            ATTRIBUTES[index]
+        }
+
+        fn read(
+            &mut self,
+            index: usize,
+            offset: usize,
+            dest: &mut [u8],
+        ) -> Result<usize, AttError> {
+            let value: &[u8] = match index {
+                0 => &[0x00, 0x18],
+                1 => &[0x02, 0x03, 0x00, 0x00, 0x2A],
+                2 => b"jewel",
+                3 => &[0x0F, 0x18],
+                4 => &[0x0A, 0x06, 0x00, 0x19, 0x2A],
+                5 => core::slice::from_ref(&self.battery_level),
+                6 => &self.long_value,
+                _ => &[],
+            };
+
+            let value = value.get(offset..).ok_or(AttError::InvalidOffset)?;
+            let len = value.len().min(dest.len());
+            dest[..len].copy_from_slice(&value[..len]);
+            Ok(len)
+        }
+
+        fn write(&mut self, index: usize, offset: usize, value: &[u8]) -> Result<(), AttError> {
+            match index {
+                5 if offset == 0 && value.len() == 1 => self.battery_level = value[0],
+                5 => return Err(AttError::InvalidAttributeValueLength),
+                6 => {
+                    let dest = self
+                        .long_value
+                        .get_mut(offset..offset + value.len())
+                        .ok_or(AttError::InvalidOffset)?;
+                    dest.copy_from_slice(value);
+                }
+                _ => return Err(AttError::UnlikelyError),
+            }
+            Ok(())
+        }
+    }
+
+    fn request(server: &mut AttServer, table: &mut TestTable, request: &[u8]) -> [u8; 64] {
+        let mut response = [0u8; 64];
+        let len = server.handle(table, request, &mut response).unwrap();
+        assert!(len <= server.mtu() as usize);
+        response
+    }
+
+    fn new() -> (AttServer, TestTable) {
+        let table = TestTable {
+            battery_level: 100,
+            long_value: core::array::from_fn(|i| i as u8),
+        };
+        (AttServer::new(), table)
+    }
+
+    #[test]
+    fn exchange_mtu() {
+        let (mut server, mut table) = new();
+        let response = request(&mut server, &mut table, &[0x02, 0x40, 0x00]);
+        assert_eq!(response[..3], [0x03, 0xFC, 0x00]);
+        assert_eq!(server.mtu(), 64);
+
+        request(&mut server, &mut table, &[0x02, 0x10, 0x00]);
+        assert_eq!(server.mtu(), DEFAULT_MTU);
+    }
+
+    #[test]
+    fn discover_services() {
+        let (mut server, mut table) = new();
+
+        let response = request(
+            &mut server,
+            &mut table,
+            &[0x10, 0x01, 0x00, 0xFF, 0xFF, 0x00, 0x28],
+        );
+        assert_eq!(
+            response[..14],
+            [0x11, 6, 0x01, 0x00, 0x03, 0x00, 0x00, 0x18, 0x04, 0x00, 0xFF, 0xFF, 0x0F, 0x18]
+        );
+
+        // Only services can be grouped
+        let response = request(
+            &mut server,
+            &mut table,
+            &[0x10, 0x01, 0x00, 0xFF, 0xFF, 0x03, 0x28],
+        );
+        assert_eq!(response[..5], [0x01, 0x10, 0x01, 0x00, 0x10]);
+
+        let response = request(
+            &mut server,
+            &mut table,
+            &[0x06, 0x01, 0x00, 0xFF, 0xFF, 0x00, 0x28, 0x0F, 0x18],
+        );
+        assert_eq!(response[..5], [0x07, 0x04, 0x00, 0xFF, 0xFF]);
+
+        let response = request(
+            &mut server,
+            &mut table,
+            &[0x10, 0x05, 0x00, 0xFF, 0xFF, 0x00, 0x28],
+        );
+        assert_eq!(response[..5], [0x01, 0x10, 0x05, 0x00, 0x0A]);
+    }
+
+    #[test]
+    fn discover_characteristics() {
+        let (mut server, mut table) = new();
+
+        let response = request(
+            &mut server,
+            &mut table,
+            &[0x08, 0x01, 0x00, 0xFF, 0xFF, 0x03, 0x28],
+        );
+        assert_eq!(
+            response[..16],
+            [
+                0x09, 7, 0x02, 0x00, 0x02, 0x03, 0x00, 0x00, 0x2A, 0x05, 0x00, 0x0A, 0x06, 0x00,
+                0x19, 0x2A
+            ]
+        );
+
+        // The first attribute is not readable
+        let response = request(
+            &mut server,
+            &mut table,
+            &[0x08, 0x07, 0x00, 0xFF, 0xFF, 0x19, 0x2A],
+        );
+        assert_eq!(response[..5], [0x01, 0x08, 0x08, 0x00, 0x02]);
+
+        // The 16-bit and 128-bit UUIDs are not mixed
+        let response = request(&mut server, &mut table, &[0x04, 0x05, 0x00, 0x08, 0x00]);
+        assert_eq!(
+            response[..10],
+            [0x05, 0x01, 0x05, 0x00, 0x03, 0x28, 0x06, 0x00, 0x19, 0x2A]
+        );
+        let response = request(&mut server, &mut table, &[0x04, 0x07, 0x00, 0x08, 0x00]);
+        assert_eq!(response[..4], [0x05, 0x02, 0x07, 0x00]);
+        assert_eq!(response[4..20], [0x11; 16]);
+    }
+
+    #[test]
+    fn read() {
+        let (mut server, mut table) = new();
+
+        let response = request(&mut server, &mut table, &[0x0A, 0x03, 0x00]);
+        assert_eq!(response[..6], *b"\x0Bjewel");
+
+        // The value is truncated to the ATT_MTU
+        let response = request(&mut server, &mut table, &[0x0A, 0x07, 0x00]);
+        assert_eq!(response[1..23], table.long_value[..22]);
+        let response = request(&mut server, &mut table, &[0x0C, 0x07, 0x00, 22, 0x00]);
+        assert_eq!(response[0], 0x0D);
+        assert_eq!(response[1..19], table.long_value[22..]);
+
+        let response = request(&mut server, &mut table, &[0x0C, 0x07, 0x00, 41, 0x00]);
+        assert_eq!(response[..5], [0x01, 0x0C, 0x07, 0x00, 0x07]);
+
+        let response = request(&mut server, &mut table, &[0x0E, 0x03, 0x00, 0x06, 0x00]);
+        assert_eq!(response[..7], *b"\x0Fjewel\x64");
+
+        let response = request(&mut server, &mut table, &[0x0A, 0x08, 0x00]);
+        assert_eq!(response[..5], [0x01, 0x0A, 0x08, 0x00, 0x02]);
+        let response = request(&mut server, &mut table, &[0x0A, 0x20, 0x00]);
+        assert_eq!(response[..5], [0x01, 0x0A, 0x20, 0x00, 0x01]);
+    }
+
+    #[test]
+    fn write() {
+        let (mut server, mut table) = new();
+
+        let response = request(&mut server, &mut table, &[0x12, 0x06, 0x00, 50]);
+        assert_eq!(response[0], 0x13);
+        assert_eq!(table.battery_level, 50);
+
+        let response = request(&mut server, &mut table, &[0x12, 0x06, 0x00, 1, 2]);
+        assert_eq!(response[..5], [0x01, 0x12, 0x06, 0x00, 0x0D]);
+        let response = request(&mut server, &mut table, &[0x12, 0x03, 0x00, 1]);
+        assert_eq!(response[..5], [0x01, 0x12, 0x03, 0x00, 0x03]);
+
+        // Commands have no response, even on errors
+        let mut response = [0u8; 23];
+        assert_eq!(
+            server.handle(&mut table, &[0x52, 0x06, 0x00, 20], &mut response),
+            None
+        );
+        assert_eq!(
+            server.handle(&mut table, &[0x52, 0x03, 0x00, 20], &mut response),
+            None
+        );
+        assert_eq!(table.battery_level, 20);
+    }
+
+    #[test]
+    fn long_write() {
+        let (mut server, mut table) = new();
+
+        let response = request(
+            &mut server,
+            &mut table,
+            &[0x16, 0x07, 0x00, 0, 0, 0xA0, 0xA1],
+        );
+        assert_eq!(response[..7], [0x17, 0x07, 0x00, 0, 0, 0xA0, 0xA1]);
+        request(&mut server, &mut table, &[0x16, 0x07, 0x00, 2, 0, 0xA2]);
+        assert_eq!(table.long_value[0], 0);
+
+        let response = request(&mut server, &mut table, &[0x18, 0x01]);
+        assert_eq!(response[0], 0x19);
+        assert_eq!(table.long_value[..4], [0xA0, 0xA1, 0xA2, 3]);
+
+        // Cancel
+        request(&mut server, &mut table, &[0x16, 0x07, 0x00, 0, 0, 0xFF]);
+        request(&mut server, &mut table, &[0x18, 0x00]);
+        request(&mut server, &mut table, &[0x18, 0x01]);
+        assert_eq!(table.long_value[0], 0xA0);
+
+        // The offset is checked on execution
+        request(&mut server, &mut table, &[0x16, 0x07, 0x00, 40, 0, 0xFF]);
+        let response = request(&mut server, &mut table, &[0x18, 0x01]);
+        assert_eq!(response[..5], [0x01, 0x18, 0x07, 0x00, 0x07]);
+
+        for _ in 0..PREPARE_QUEUE_SIZE {
+            request(&mut server, &mut table, &[0x16, 0x07, 0x00, 0, 0, 0xFF]);
+        }
+        let response = request(&mut server, &mut table, &[0x16, 0x07, 0x00, 0, 0, 0xFF]);
+        assert_eq!(response[..5], [0x01, 0x16, 0x07, 0x00, 0x09]);
+    }
+
+    #[test]
+    fn invalid_requests() {
+        let (mut server, mut table) = new();
+
+        let response = request(&mut server, &mut table, &[0x20, 0x01]);
+        assert_eq!(response[..5], [0x01, 0x20, 0x00, 0x00, 0x06]);
+        let response = request(&mut server, &mut table, &[0x0A, 0x01]);
+        assert_eq!(response[..5], [0x01, 0x0A, 0x00, 0x00, 0x04]);
+        let response = request(&mut server, &mut table, &[0x04, 0x05, 0x00, 0x01, 0x00]);
+        assert_eq!(response[..5], [0x01, 0x04, 0x05, 0x00, 0x01]);
+        let response = request(&mut server, &mut table, &[0x04, 0x09, 0x00, 0xFF, 0xFF]);
+        assert_eq!(response[..5], [0x01, 0x04, 0x09, 0x00, 0x0A]);
+
+        let mut response = [0u8; 23];
+        assert_eq!(server.handle(&mut table, &[0x1E], &mut response), None);
+    }
+}
@@ -0,0 +1,102 @@
+use defmt::Format;
+
+use crate::ll::ParseError;
+
+/// Attribute type, a 16-bit UUID assigned by the Bluetooth SIG or a 128-bit UUID
+///
+/// 128-bit UUIDs built from the Bluetooth Base UUID are always parsed as 16-bit UUIDs,
+/// so two equal UUIDs compare equal whatever form they were received in.
+///
+/// Ref: Core 3.B.2.5.1
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Format)]
+pub enum Uuid {
+    Uuid16(u16),
+    /// Little endian, as sent over the air
+    Uuid128([u8; 16]),
+}
+
+impl Uuid {
+    /// 00000000-0000-1000-8000-00805F9B34FB, little endian
+    const BASE: [u8; 16] = [
+        0xFB, 0x34, 0x9B, 0x5F, 0x80, 0x00, 0x00, 0x80, 0x00, 0x10, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00,
+    ];
+
+    /// GATT Primary Service declaration
+    pub const PRIMARY_SERVICE: Uuid = Uuid::Uuid16(0x2800);
+
+    /// GATT Secondary Service declaration
+    pub const SECONDARY_SERVICE: Uuid = Uuid::Uuid16(0x2801);
+
+    /// GATT Include declaration
+    pub const INCLUDE: Uuid = Uuid::Uuid16(0x2802);
+
+    /// GATT Characteristic declaration
+    pub const CHARACTERISTIC: Uuid = Uuid::Uuid16(0x2803);
+
+    /// Client Characteristic Configuration descriptor
+    pub const CLIENT_CHARACTERISTIC_CONFIGURATION: Uuid = Uuid::Uuid16(0x2902);
+
+    /// GAP service
+    pub const GAP_SERVICE: Uuid = Uuid::Uuid16(0x1800);
+
+    /// GATT service
+    pub const GATT_SERVICE: Uuid = Uuid::Uuid16(0x1801);
+
+    /// Device Name characteristic of the GAP service
+    pub const DEVICE_NAME: Uuid = Uuid::Uuid16(0x2A00);
+
+    /// Appearance characteristic of the GAP service
+    pub const APPEARANCE: Uuid = Uuid::Uuid16(0x2A01);
+
+    /// Number of bytes of the UUID over the air
+    pub fn length(&self) -> usize {
+        match self {
+            Self::Uuid16(_) => 2,
+            Self::Uuid128(_) => 16,
+        }
+    }
+
+    pub fn bytes(&self, dest: &mut [u8]) -> usize {
+        match self {
+            Self::Uuid16(uuid) => dest[..2].copy_from_slice(&uuid.to_le_bytes()),
+            Self::Uuid128(uuid) => dest[..16].copy_from_slice(uuid),
+        }
+        self.length()
+    }
+
+    /// Parse a 2 or 16 bytes UUID
+    pub fn parse(bytes: &[u8]) -> Result<Self, ParseError> {
+        match bytes.len() {
+            2 => Ok(Self::Uuid16(u16::from_le_bytes([bytes[0], bytes[1]]))),
+            16 => {
+                let uuid: [u8; 16] = bytes.try_into().unwrap();
+                let is_base = uuid[..12] == Self::BASE[..12] && uuid[14..] == Self::BASE[14..];
+                if is_base {
+                    Ok(Self::Uuid16(u16::from_le_bytes([uuid[12], uuid[13]])))
+                } else {
+                    Ok(Self::Uuid128(uuid))
+                }
+            }
+            _ => Err(ParseError::InvalidLength),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn base_uuid() {
+        let mut bytes = Uuid::BASE;
+        bytes[12..14].copy_from_slice(&0x180Fu16.to_le_bytes());
+        assert_eq!(Uuid::parse(&bytes), Ok(Uuid::Uuid16(0x180F)));
+
+        bytes[15] = 0x01;
+        assert_eq!(Uuid::parse(&bytes), Ok(Uuid::Uuid128(bytes)));
+
+        assert_eq!(Uuid::parse(&[0x00, 0x28]), Ok(Uuid::PRIMARY_SERVICE));
+        assert_eq!(Uuid::parse(&[0x00]), Err(ParseError::InvalidLength));
+    }
+}
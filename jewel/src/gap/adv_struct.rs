@@ -295,7 +295,7 @@ mod uuid {
             use super::*;
             #[test]
             fn serialize_128_uuids() {
-                let uuid = Uuid128::from_u128(0x1234_5678_9ABC_DEF0_1234_5678_9ABC_DEF0);
+                let uuid: Uuid128 = 0x1234_5678_9ABC_DEF0_1234_5678_9ABC_DEF0;
 
                 let mut buf = [0; 16];
                 let len = uuid.bytes(&mut buf);
@@ -317,10 +317,7 @@ mod uuid {
                     0xBC, 0xDE, 0xF0,
                 ]);
 
-                assert_eq!(
-                    uuid,
-                    Uuid128::from_u128(0x1234_5678_9ABC_DEF0_1234_5678_9ABC_DEF0)
-                );
+                assert_eq!(uuid, 0x1234_5678_9ABC_DEF0_1234_5678_9ABC_DEF0);
             }
         }
     }
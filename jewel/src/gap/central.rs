@@ -0,0 +1,82 @@
+use embassy_time::Duration;
+
+use crate::{
+    att::AttributeTable,
+    l2cap::Psm,
+    ll::{ConnectionParameters, Initiating, InitiatorTarget, LinkLayer},
+    phy::Radio,
+};
+
+use super::Connection;
+
+pub struct Central<'r, 'a, R: Radio> {
+    ll: LinkLayer<'r, R, Initiating<'a>>,
+
+    /// SPSMs accepted on the connection-oriented channels
+    psms: &'static [Psm],
+
+    /// Attributes exposed by the ATT server once connected
+    attributes: Option<&'r mut dyn AttributeTable>,
+}
+
+/// Central profile. Listen on the 3 primary advertising channels and connect
+/// to the first connectable advertiser of the target.
+/// ```ignore
+/// let mut radio: RadioImpl<'_, _> = radio::ble::Radio::new(p.RADIO, Irqs).into();
+///
+/// let central = Central::new(
+///     &mut radio,
+///     InitiatorTarget::Peer(Address::new_random(0xffe1e8d0dc27)),
+///     Duration::from_millis(100),
+///     Duration::from_millis(50),
+///     ConnectionParameters::new(
+///         Duration::from_millis(30),
+///         Duration::from_millis(50),
+///         0,
+///         Duration::from_secs(2),
+///     ),
+/// );
+///
+/// let mut connection = central.connect().await.unwrap();
+/// info!("Connected to {}", connection.peer_address());
+/// ```
+impl<'r, 'a, R: Radio> Central<'r, 'a, R> {
+    /// Create a new central
+    ///
+    /// The `window` is how long the radio listens on each channel, every `interval`.
+    /// The connection uses the shortest interval of the `parameters`.
+    pub fn new(
+        radio: &'r mut R,
+        target: InitiatorTarget<'a>,
+        interval: Duration,
+        window: Duration,
+        parameters: ConnectionParameters,
+    ) -> Self {
+        let ll = LinkLayer::new(radio).initiate(target, interval, window, parameters);
+
+        Central {
+            ll,
+            psms: &[],
+            attributes: None,
+        }
+    }
+
+    /// Accept connection-oriented channels on the `psms` once connected
+    pub fn set_psms(mut self, psms: &'static [Psm]) -> Self {
+        self.psms = psms;
+        self
+    }
+
+    /// Expose the `attributes` to the client once connected
+    pub fn set_attributes(mut self, attributes: &'r mut dyn AttributeTable) -> Self {
+        self.attributes = Some(attributes);
+        self
+    }
+
+    /// Listen until the target advertises and send it the connection request
+    pub async fn connect(mut self) -> Result<Connection<'r, R>, R::Error> {
+        let request = self.ll.connect_request().await?;
+        let ll = self.ll.connect(request);
+        Ok(Connection::new(ll, self.psms, self.attributes))
+    }
+}
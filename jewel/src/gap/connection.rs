@@ -0,0 +1,822 @@
+use defmt::Format;
+use embassy_time::Instant;
+use rand::{CryptoRng, RngCore};
+
+mod client;
+
+use crate::{
+    att::{
+        AttPdu, AttServer, AttributeTable, NoAttributes, NotifyError, MAX_MTU, TRANSACTION_TIMEOUT,
+    },
+    gatt::GattClient,
+    l2cap::{BasicFrame, ChannelError, ChannelId, L2cap, L2capEvent, Psm, MAX_FRAME_LENGTH},
+    ll::{
+        self, ConnectionError, ConnectionParameters, EncryptionEvent, FeatureSet, LLData,
+        LinkLayer, ProcedureError, Role, Version,
+    },
+    phy::Radio,
+    smp::{
+        Bond, BondStore, Pairing, PairingConfig, PairingError, PairingEvent, SecurityError, SmpPdu,
+    },
+    Address,
+};
+
+/// Event of the connection for the application
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Format)]
+pub enum Event {
+    /// The central answered the connection parameters request sent on the LE signaling channel
+    ConnectionParametersResponse { accepted: bool },
+
+    /// The peripheral requested the connection parameters on the LE signaling channel,
+    /// they are applied with a connection update
+    ConnectionParametersRequest { parameters: ConnectionParameters },
+
+    /// The central opened a connection-oriented channel on a registered SPSM
+    ChannelConnected { cid: u16, spsm: u16 },
+
+    /// The connection-oriented channel was closed
+    ChannelDisconnected { cid: u16 },
+
+    /// A SDU is ready to be read with `read`
+    ChannelReceived { cid: u16 },
+
+    /// A notification or indication of the server is ready to be read with `next_notification`
+    Notification { handle: u16 },
+
+    /// The pairing progressed, completed or failed
+    Pairing(PairingEvent),
+
+    /// The link is encrypted
+    Encrypted,
+
+    /// The encryption was rejected with the error code, the link is not encrypted
+    EncryptionFailed { error_code: u8 },
+}
+
+impl From<L2capEvent> for Event {
+    fn from(event: L2capEvent) -> Self {
+        match event {
+            L2capEvent::ConnectionParametersResponse { accepted } => {
+                Event::ConnectionParametersResponse { accepted }
+            }
+            L2capEvent::ConnectionParametersRequest { parameters } => {
+                Event::ConnectionParametersRequest { parameters }
+            }
+            L2capEvent::ChannelConnected { cid, spsm } => Event::ChannelConnected { cid, spsm },
+            L2capEvent::ChannelDisconnected { cid } => Event::ChannelDisconnected { cid },
+            L2capEvent::ChannelReceived { cid } => Event::ChannelReceived { cid },
+        }
+    }
+}
+
+/// Number of events kept while a method runs the connection events
+const PENDING_EVENTS: usize = 8;
+
+/// Events that occurred while a method ran the connection events,
+/// the next calls of `connection_event` report them first
+struct PendingEvents {
+    events: [Option<Event>; PENDING_EVENTS],
+    head: usize,
+    len: usize,
+}
+
+impl PendingEvents {
+    const fn new() -> Self {
+        Self {
+            events: [None; PENDING_EVENTS],
+            head: 0,
+            len: 0,
+        }
+    }
+
+    fn is_full(&self) -> bool {
+        self.len == PENDING_EVENTS
+    }
+
+    /// Keep the event, the callers check that the queue is not full
+    fn push(&mut self, event: Event) {
+        if !self.is_full() {
+            self.events[(self.head + self.len) % PENDING_EVENTS] = Some(event);
+            self.len += 1;
+        }
+    }
+
+    /// Oldest event kept
+    fn pop(&mut self) -> Option<Event> {
+        let event = self.events[self.head].take()?;
+        self.head = (self.head + 1) % PENDING_EVENTS;
+        self.len -= 1;
+        Some(event)
+    }
+}
+
+/// A connection with a peer device
+pub struct Connection<'r, R: Radio> {
+    l2cap: L2cap<'r, R>,
+    att: AttServer,
+    attributes: Option<&'r mut dyn AttributeTable>,
+    client: GattClient,
+
+    /// Pairing of the connection, only set when pairing is enabled
+    pairing: Option<Pairing>,
+
+    /// Bond of the peer restored from a previous connection
+    bond: Option<Bond>,
+
+    pending: PendingEvents,
+}
+
+impl<'r, R: Radio> Connection<'r, R> {
+    pub(crate) fn new(
+        ll: LinkLayer<'r, R, ll::Connection>,
+        psms: &'static [Psm],
+        attributes: Option<&'r mut dyn AttributeTable>,
+    ) -> Self {
+        Connection {
+            l2cap: L2cap::new(ll, psms),
+            att: AttServer::new(),
+            attributes,
+            client: GattClient::new(),
+            pairing: None,
+            bond: None,
+            pending: PendingEvents::new(),
+        }
+    }
+
+    /// Role of this device in the connection
+    pub fn role(&self) -> Role {
+        self.l2cap.ll().role()
+    }
+
+    /// Address of the connected device
+    pub fn peer_address(&self) -> &Address {
+        self.l2cap.ll().peer_address()
+    }
+
+    /// Connection parameters in use
+    pub fn parameters(&self) -> &LLData {
+        self.l2cap.ll().parameters()
+    }
+
+    /// ATT_MTU negotiated with the peer
+    pub fn att_mtu(&self) -> u16 {
+        self.att.mtu()
+    }
+
+    /// L2CAP layer of the connection, to exchange PDUs on the fixed channels
+    pub fn l2cap(&mut self) -> &mut L2cap<'r, R> {
+        &mut self.l2cap
+    }
+
+    /// Run the next connection event
+    ///
+    /// You should call this method in a loop to keep the connection alive,
+    /// it returns an error when the connection is lost.
+    /// The PDUs received on the fixed channels are handled after the connection event.
+    ///
+    /// The events that occurred while another method ran the connection events,
+    /// such as `pair`, `read` or a GATT client request, are reported first
+    /// without running a connection event. Up to 8 events are kept, then these methods
+    /// fail with `ConnectionError::EventQueueFull` until the events are reported.
+    pub async fn connection_event(&mut self) -> Result<Option<Event>, ConnectionError<R::Error>> {
+        if let Some(event) = self.pending.pop() {
+            return Ok(Some(event));
+        }
+
+        self.next_event().await
+    }
+
+    /// Run the next connection event, keeping its event for `connection_event`
+    async fn run_connection_event(&mut self) -> Result<(), ConnectionError<R::Error>> {
+        if let Some(event) = self.wait_event().await? {
+            self.pending.push(event);
+        }
+        Ok(())
+    }
+
+    /// Run the next connection event for a method waiting on it. Fails with
+    /// `ConnectionError::EventQueueFull` when there is no room to keep its event.
+    async fn wait_event(&mut self) -> Result<Option<Event>, ConnectionError<R::Error>> {
+        if self.pending.is_full() {
+            return Err(ConnectionError::EventQueueFull);
+        }
+        self.next_event().await
+    }
+
+    /// Run the next connection event and handle the PDUs received
+    async fn next_event(&mut self) -> Result<Option<Event>, ConnectionError<R::Error>> {
+        self.l2cap.connection_event().await?;
+        if let Some(event) = self.on_encryption().await? {
+            return Ok(Some(event));
+        }
+        if let Some(event) = self.handle_received().await? {
+            return Ok(Some(event));
+        }
+
+        let timeout = self
+            .pairing
+            .as_mut()
+            .and_then(|pairing| pairing.check_timeout(Instant::now()));
+        Ok(timeout.map(Event::Pairing))
+    }
+
+    /// Handle the PDUs received until one of them results in an event
+    async fn handle_received(&mut self) -> Result<Option<Event>, ConnectionError<R::Error>> {
+        let mut buffer = [0u8; MAX_FRAME_LENGTH];
+
+        while let Some(frame) = self.l2cap.poll(&mut buffer) {
+            let event = match frame.channel() {
+                ChannelId::Att => {
+                    if let Some(event) = self.on_att(frame.payload()).await? {
+                        return Ok(Some(event));
+                    }
+                    None
+                }
+                ChannelId::Smp => {
+                    if let Some(event) = self.on_smp(frame.payload()).await? {
+                        return Ok(Some(event));
+                    }
+                    None
+                }
+                ChannelId::LeSignaling => self.l2cap.on_signaling(frame.payload()).await?,
+                ChannelId::Dynamic(cid) => self.l2cap.on_kframe(cid, frame.payload()).await?,
+            };
+
+            if let Some(event) = event {
+                return Ok(Some(event.into()));
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Answer the ATT request with the attribute table, without attributes
+    /// the server only exchanges the MTU.
+    ///
+    /// Responses, notifications and indications have odd opcodes, they are for the client.
+    async fn on_att(&mut self, request: &[u8]) -> Result<Option<Event>, ConnectionError<R::Error>> {
+        let mut response = [0u8; MAX_MTU as usize];
+        if request.first().is_some_and(|opcode| opcode & 0x01 == 0x01) {
+            let Some(handle) = self.client.on_pdu(request) else {
+                return Ok(None);
+            };
+
+            if let Ok(AttPdu::HandleValueInd { .. }) = AttPdu::parse(request) {
+                let len = AttPdu::HandleValueCfm.bytes(&mut response);
+                let frame = BasicFrame::new(ChannelId::Att, &response[..len]);
+                self.l2cap.send(&frame).await?;
+            }
+            return Ok(Some(Event::Notification { handle }));
+        }
+
+        let len = match &mut self.attributes {
+            Some(attributes) => self.att.handle(*attributes, request, &mut response),
+            None => self.att.handle(&mut NoAttributes, request, &mut response),
+        };
+
+        if let Some(len) = len {
+            let frame = BasicFrame::new(ChannelId::Att, &response[..len]);
+            self.l2cap.send(&frame).await?;
+        }
+        Ok(None)
+    }
+
+    /// Handle the event of the link layer encryption. The LTK of LE Secure Connections,
+    /// with `rand` and `ediv` set to 0, is given to the peripheral, and the keys of the
+    /// pairing are distributed once the link is encrypted. When it completes the pairing,
+    /// the pairing event is reported instead of `Event::Encrypted`.
+    async fn on_encryption(&mut self) -> Result<Option<Event>, ConnectionError<R::Error>> {
+        match self.l2cap.ll_mut().encryption_event() {
+            Some(EncryptionEvent::LongTermKeyRequest { rand: 0, ediv: 0 }) => {
+                let ltk = self.long_term_key();
+                self.l2cap.ll_mut().reply_long_term_key(ltk);
+                Ok(None)
+            }
+            Some(EncryptionEvent::LongTermKeyRequest { .. }) => {
+                self.l2cap.ll_mut().reply_long_term_key(None);
+                Ok(None)
+            }
+            Some(EncryptionEvent::Encrypted) => {
+                let event = self
+                    .pairing
+                    .as_mut()
+                    .and_then(|pairing| pairing.on_encrypted(Instant::now()));
+                self.send_pairing().await?;
+                Ok(Some(event.map_or(Event::Encrypted, Event::Pairing)))
+            }
+            Some(EncryptionEvent::Failed(error_code)) => {
+                Ok(Some(Event::EncryptionFailed { error_code }))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Handle the SMP command, without pairing enabled the pairing requests are rejected
+    async fn on_smp(&mut self, pdu: &[u8]) -> Result<Option<Event>, ConnectionError<R::Error>> {
+        // A bonded peripheral asks the central to encrypt the link with the keys of the bond
+        if self.role() == Role::Central
+            && self.bond.is_some()
+            && matches!(SmpPdu::parse(pdu), Ok(SmpPdu::SecurityRequest(_)))
+        {
+            let _ = self.encrypt();
+            return Ok(None);
+        }
+
+        let Some(pairing) = &mut self.pairing else {
+            if let Ok(SmpPdu::PairingRequest(_) | SmpPdu::SecurityRequest(_)) = SmpPdu::parse(pdu) {
+                let mut response = [0u8; SmpPdu::MAX_LENGTH];
+                let len =
+                    SmpPdu::PairingFailed(PairingError::PairingNotSupported).bytes(&mut response);
+                let frame = BasicFrame::new(ChannelId::Smp, &response[..len]);
+                self.l2cap.send(&frame).await?;
+            }
+            return Ok(None);
+        };
+
+        let event = pairing.on_pdu(pdu, Instant::now());
+        let agreed = pairing.needs_encryption() || event == Some(PairingEvent::Complete);
+        self.send_pairing().await?;
+
+        // The central encrypts the link with the new LTK
+        if agreed && !self.l2cap.ll().is_encrypted() {
+            let _ = self.encrypt();
+        }
+        Ok(event.map(Event::Pairing))
+    }
+
+    /// Send the SMP commands of the pairing
+    async fn send_pairing(&mut self) -> Result<(), ConnectionError<R::Error>> {
+        let mut pdu = [0u8; SmpPdu::MAX_LENGTH];
+        while let Some(len) = self
+            .pairing
+            .as_mut()
+            .and_then(|pairing| pairing.poll(&mut pdu))
+        {
+            let frame = BasicFrame::new(ChannelId::Smp, &pdu[..len]);
+            self.l2cap.send(&frame).await?;
+        }
+        Ok(())
+    }
+
+    /// Accept the pairing requests of the peer with the `config`, generating the keys
+    /// with the `rng` that shall be cryptographically secure.
+    ///
+    /// The progress is reported with `Event::Pairing`, it shall be enabled again
+    /// after a failure to accept a new request. On bonding, the keys are distributed
+    /// once the link is encrypted with the LTK.
+    pub fn enable_pairing(&mut self, config: PairingConfig, rng: &mut (impl RngCore + CryptoRng)) {
+        let ll = self.l2cap.ll();
+        let pairing = Pairing::new(
+            ll.role(),
+            ll.local_address(),
+            ll.peer_address().clone(),
+            config,
+            rng,
+        );
+        self.pairing = Some(pairing.set_identity_address(ll.identity_address()));
+    }
+
+    /// Start pairing with the peer using LE Secure Connections, the progress is reported
+    /// with `Event::Pairing`. A pairing in progress is not restarted.
+    ///
+    /// As peripheral, a Security Request asks the central to start the pairing.
+    pub async fn start_pairing(
+        &mut self,
+        config: PairingConfig,
+        rng: &mut (impl RngCore + CryptoRng),
+    ) -> Result<(), ConnectionError<R::Error>> {
+        if self
+            .pairing
+            .as_ref()
+            .is_none_or(|pairing| !pairing.is_pairing())
+        {
+            self.enable_pairing(config, rng);
+        }
+        if let Some(pairing) = &mut self.pairing {
+            pairing.start(Instant::now());
+        }
+        self.send_pairing().await
+    }
+
+    /// Pair with the peer using Just Works, running the connection events until
+    /// the pairing completes. The other events of the connection meanwhile are
+    /// reported by the next calls of `connection_event`.
+    ///
+    /// The association models involving the user are run with `start_pairing`.
+    pub async fn pair(
+        &mut self,
+        rng: &mut (impl RngCore + CryptoRng),
+    ) -> Result<(), SecurityError<R::Error>> {
+        self.start_pairing(PairingConfig::default(), rng).await?;
+
+        loop {
+            match self.wait_event().await? {
+                Some(Event::Pairing(PairingEvent::Complete)) => return Ok(()),
+                Some(Event::Pairing(PairingEvent::Failed(reason))) => {
+                    return Err(SecurityError::Pairing(reason))
+                }
+                Some(Event::Pairing(PairingEvent::Timeout)) => return Err(SecurityError::Timeout),
+                Some(event) => self.pending.push(event),
+                None => {}
+            }
+        }
+    }
+
+    /// Answer `PairingEvent::PasskeyRequest` with the passkey entered by the user,
+    /// `None` when the user cancelled the entry
+    pub async fn passkey_reply(
+        &mut self,
+        passkey: Option<u32>,
+    ) -> Result<Option<Event>, ConnectionError<R::Error>> {
+        let event = self
+            .pairing
+            .as_mut()
+            .and_then(|pairing| pairing.passkey_reply(passkey, Instant::now()));
+        self.send_pairing().await?;
+        Ok(event.map(Event::Pairing))
+    }
+
+    /// Answer `PairingEvent::NumericComparison` with the confirmation of the user
+    pub async fn numeric_comparison_reply(
+        &mut self,
+        confirmed: bool,
+    ) -> Result<Option<Event>, ConnectionError<R::Error>> {
+        let event = self
+            .pairing
+            .as_mut()
+            .and_then(|pairing| pairing.numeric_comparison_reply(confirmed, Instant::now()));
+        self.send_pairing().await?;
+        Ok(event.map(Event::Pairing))
+    }
+
+    /// LTK of the last pairing on the connection, or of the bond restored
+    pub fn long_term_key(&self) -> Option<[u8; 16]> {
+        self.pairing
+            .as_ref()
+            .and_then(Pairing::long_term_key)
+            .or(self.bond.as_ref().map(|bond| bond.ltk))
+    }
+
+    /// Keys of the peer to save in a `BondStore` once the pairing completed with bonding,
+    /// or the bond restored
+    pub fn bond(&self) -> Option<Bond> {
+        self.pairing
+            .as_ref()
+            .and_then(Pairing::bond)
+            .or_else(|| self.bond.clone())
+    }
+
+    /// Encrypt the link with the LTK of the last pairing or of the bond restored,
+    /// the result is reported with `Event::Encrypted` or `Event::EncryptionFailed`.
+    /// Only the central starts the encryption, the peripheral asks for it with `start_pairing`.
+    pub fn encrypt(&mut self) -> Result<(), ProcedureError> {
+        let ltk = self.long_term_key().ok_or(ProcedureError::KeyMissing)?;
+        self.l2cap.ll_mut().start_encryption(ltk, 0, 0)
+    }
+
+    /// Restore the bond of the peer from the `store`, its LTK encrypts the link again
+    /// without pairing. A peer using a resolvable private address is found by its IRK.
+    /// Returns false when the peer is not bonded.
+    pub fn restore_bond(&mut self, store: &impl BondStore) -> bool {
+        let address = self.l2cap.ll().peer_address();
+        self.bond = store.get(address).or_else(|| store.resolve(address));
+        self.bond.is_some()
+    }
+
+    /// Queue a notification of the characteristic value, truncated to `att_mtu() - 3` bytes
+    ///
+    /// The client shall have subscribed to the notifications of the characteristic.
+    pub async fn notify(&mut self, handle: u16, value: &[u8]) -> Result<(), NotifyError<R::Error>> {
+        let mut pdu = [0u8; MAX_MTU as usize];
+        let len = self.att.notification(handle, value, &mut pdu)?;
+
+        let frame = BasicFrame::new(ChannelId::Att, &pdu[..len]);
+        self.l2cap.send(&frame).await?;
+        Ok(())
+    }
+
+    /// Send an indication of the characteristic value, truncated to `att_mtu() - 3` bytes,
+    /// running the connection events until the client confirms it.
+    /// The events of the connection meanwhile are reported by the next calls of `connection_event`.
+    ///
+    /// The client shall have subscribed to the indications of the characteristic.
+    pub async fn indicate(
+        &mut self,
+        handle: u16,
+        value: &[u8],
+    ) -> Result<(), NotifyError<R::Error>> {
+        let mut pdu = [0u8; MAX_MTU as usize];
+        let len = self.att.indication(handle, value, &mut pdu)?;
+
+        let frame = BasicFrame::new(ChannelId::Att, &pdu[..len]);
+        self.l2cap.send(&frame).await?;
+
+        let deadline = Instant::now() + TRANSACTION_TIMEOUT;
+        while self.att.indication_pending() {
+            if Instant::now() > deadline {
+                return Err(NotifyError::Timeout);
+            }
+            self.run_connection_event().await?;
+        }
+        Ok(())
+    }
+
+    /// Features of the peer, known after a feature exchange
+    pub fn peer_features(&self) -> Option<FeatureSet> {
+        self.l2cap.ll().peer_features()
+    }
+
+    /// Version of the peer, known after a version exchange
+    pub fn peer_version(&self) -> Option<Version> {
+        self.l2cap.ll().peer_version()
+    }
+
+    /// Request the features of the peer, the result is available
+    /// with `peer_features` after the following connection events.
+    pub fn exchange_features(&mut self) -> Result<(), ProcedureError> {
+        self.l2cap.ll_mut().exchange_features()
+    }
+
+    /// Request the version of the peer, the result is available
+    /// with `peer_version` after the following connection events.
+    pub fn exchange_version(&mut self) -> Result<(), ProcedureError> {
+        self.l2cap.ll_mut().exchange_version()
+    }
+
+    /// Request new connection parameters to the central, for example a longer
+    /// interval to save power. The central may choose any value in the range.
+    /// Only the peripheral requests them.
+    ///
+    /// The LL Connection Parameters Request procedure is used when both devices support it,
+    /// otherwise the request is sent on the LE signaling channel and the central answers
+    /// with `Event::ConnectionParametersResponse`.
+    pub async fn request_connection_parameters(
+        &mut self,
+        parameters: ConnectionParameters,
+    ) -> Result<(), ConnectionError<R::Error>> {
+        let ll = self.l2cap.ll_mut();
+        if ll
+            .features_used()
+            .contains(FeatureSet::CONNECTION_PARAMETERS_REQUEST)
+            && ll.request_connection_parameters(parameters).is_ok()
+        {
+            return Ok(());
+        }
+
+        self.l2cap.request_connection_parameters(parameters).await
+    }
+
+    /// Terminate the connection
+    ///
+    /// The connection events should keep running until they return
+    /// `DisconnectReason::LocalHostTerminated`.
+    pub fn terminate(&mut self) {
+        self.l2cap.ll_mut().terminate()
+    }
+
+    /// Read a SDU from the connection-oriented channel, running the connection events
+    /// until it is received. The events of the connection meanwhile are reported
+    /// by the next calls of `connection_event`.
+    pub async fn read(
+        &mut self,
+        cid: u16,
+        buffer: &mut [u8],
+    ) -> Result<usize, ChannelError<R::Error>> {
+        loop {
+            if let Some(len) = self.l2cap.read(cid, buffer).await? {
+                return Ok(len);
+            }
+
+            match self.wait_event().await? {
+                // The SDU is returned by this call
+                Some(Event::ChannelReceived { cid: received }) if received == cid => {}
+                Some(event) => self.pending.push(event),
+                None => {}
+            }
+        }
+    }
+
+    /// Write a SDU to the connection-oriented channel, running the connection events
+    /// while the peer has no credits. The events of the connection meanwhile are reported
+    /// by the next calls of `connection_event`.
+    ///
+    /// On `ConnectionError::EventQueueFull` the SDU is partially sent,
+    /// the channel should be closed.
+    pub async fn write(&mut self, cid: u16, sdu: &[u8]) -> Result<(), ChannelError<R::Error>> {
+        let mut offset = 0;
+        loop {
+            match self.l2cap.write_segment(cid, sdu, offset).await? {
+                Some(next) if next >= sdu.len() => return Ok(()),
+                Some(next) => offset = next,
+                None => self.run_connection_event().await?,
+            }
+        }
+    }
+
+    /// Close the connection-oriented channel
+    pub async fn disconnect_channel(&mut self, cid: u16) -> Result<(), ConnectionError<R::Error>> {
+        self.l2cap.disconnect(cid).await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use embassy_time::Duration;
+    use rand::{rngs::SmallRng, SeedableRng};
+
+    use super::*;
+    use crate::{
+        ll::{ConnectInd, ConnectRequest},
+        phy::{Channel, ChannelMap, HeaderSize, Mode},
+        smp::PairingError,
+        testing::{block_on, now},
+    };
+
+    static PSMS: [Psm; 1] = [Psm::new(0x0080)];
+
+    /// Central sending a L2CAP PDU on each connection event,
+    /// acknowledging the packet of the peripheral
+    struct Central {
+        pdus: &'static [&'static [u8]],
+        sent: usize,
+    }
+
+    impl Radio for Central {
+        type Error = ();
+
+        fn set_mode(&mut self, _mode: Mode) {}
+        fn set_tx_power(&mut self, _power_db: i8) {}
+        fn set_header_size(&mut self, _header_size: HeaderSize) {}
+        fn set_access_address(&mut self, _access_address: u32) {}
+        fn set_channel(&mut self, _channel: Channel) {}
+        fn set_crc_poly(&mut self, _crc_poly: u32) {}
+        fn set_crc_init(&mut self, _crc_init: u32) {}
+
+        async fn transmit(&mut self, _buffer: &[u8]) -> Result<(), ()> {
+            Ok(())
+        }
+
+        async fn receive(&mut self, buffer: &mut [u8]) -> Result<(), ()> {
+            let Some(pdu) = self.pdus.get(self.sent) else {
+                return core::future::pending().await;
+            };
+
+            // Start of a L2CAP PDU, SN and NESN alternate
+            let sequence = (self.sent as u8 & 1) * 0b1100;
+            buffer[0] = 0b10 | sequence;
+            buffer[1] = pdu.len() as u8;
+            buffer[2..2 + pdu.len()].copy_from_slice(pdu);
+            self.sent += 1;
+            Ok(())
+        }
+
+        fn device_address(&self) -> Address {
+            Address::new_random(0xffe1e8d0dc27)
+        }
+    }
+
+    /// Not secure, only for the tests
+    struct TestRng(SmallRng);
+
+    impl RngCore for TestRng {
+        fn next_u32(&mut self) -> u32 {
+            self.0.next_u32()
+        }
+
+        fn next_u64(&mut self) -> u64 {
+            self.0.next_u64()
+        }
+
+        fn fill_bytes(&mut self, dest: &mut [u8]) {
+            self.0.fill_bytes(dest)
+        }
+
+        fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand::Error> {
+            self.0.try_fill_bytes(dest)
+        }
+    }
+
+    impl CryptoRng for TestRng {}
+
+    fn connect(radio: &mut Central) -> Connection<'_, Central> {
+        let adv_ind = [0x40, 0x06, 0x27, 0xdc, 0xd0, 0xe8, 0xe1, 0xff];
+        let ll_data = LLData {
+            access_address: 0x50654DE1,
+            crc_init: 0x8F_A3_55,
+            win_size: 2,
+            win_offset: 3,
+            interval: 24,
+            latency: 0,
+            timeout: 72,
+            channel_map: ChannelMap::all(),
+            hop: 9,
+            sca: 5,
+        };
+        let request = ConnectRequest {
+            pdu: ConnectInd::new(
+                Address::new_random(0xffe1e8d0dc28),
+                Address::new_random(0xffe1e8d0dc27),
+                ll_data,
+                false,
+            ),
+            timestamp: now(),
+        };
+
+        let ll = LinkLayer::new(radio)
+            .advertise(Duration::from_millis(100), &adv_ind)
+            .unwrap()
+            .connect(request);
+        Connection::new(ll, &PSMS, None)
+    }
+
+    #[test]
+    fn pairing_during_read() {
+        let mut radio = Central {
+            pdus: &[
+                // LE Credit Based Connection Request on SPSM 0x0080, source CID 0x0040
+                &[
+                    0x0E, 0x00, 0x05, 0x00, 0x14, 0x01, 0x0A, 0x00, 0x80, 0x00, 0x40, 0x00, 0x00,
+                    0x02, 0x17, 0x00, 0x0A, 0x00,
+                ],
+                // LE legacy Pairing Request
+                &[
+                    0x07, 0x00, 0x06, 0x00, 0x01, 0x03, 0x00, 0x01, 0x10, 0x00, 0x00,
+                ],
+                // SDU of 3 bytes on the channel
+                &[0x05, 0x00, 0x40, 0x00, 0x03, 0x00, 1, 2, 3],
+            ],
+            sent: 0,
+        };
+        let mut connection = connect(&mut radio);
+        connection.enable_pairing(
+            PairingConfig::default(),
+            &mut TestRng(SmallRng::seed_from_u64(1)),
+        );
+
+        let event = block_on(connection.connection_event()).unwrap();
+        assert_eq!(
+            event,
+            Some(Event::ChannelConnected {
+                cid: 0x0040,
+                spsm: 0x0080
+            })
+        );
+
+        // The pairing fails while the SDU is read
+        let mut sdu = [0u8; 16];
+        let len = block_on(connection.read(0x0040, &mut sdu)).unwrap();
+        assert_eq!(sdu[..len], [1, 2, 3]);
+
+        // The pairing event is reported after the read, not the SDU already read
+        let event = block_on(connection.connection_event()).unwrap();
+        assert_eq!(
+            event,
+            Some(Event::Pairing(PairingEvent::Failed(
+                PairingError::AuthenticationRequirements
+            )))
+        );
+        assert_eq!(connection.pending.pop(), None);
+    }
+
+    #[test]
+    fn event_queue_full() {
+        const NOTIFICATION: &[u8] = &[0x04, 0x00, 0x04, 0x00, 0x1B, 0x03, 0x00, 0xAA];
+        let mut radio = Central {
+            pdus: &[
+                // LE Credit Based Connection Request on SPSM 0x0080, source CID 0x0040
+                &[
+                    0x0E, 0x00, 0x05, 0x00, 0x14, 0x01, 0x0A, 0x00, 0x80, 0x00, 0x40, 0x00, 0x00,
+                    0x02, 0x17, 0x00, 0x0A, 0x00,
+                ],
+                // Notifications of the handle 3
+                NOTIFICATION,
+                NOTIFICATION,
+                NOTIFICATION,
+                NOTIFICATION,
+                NOTIFICATION,
+                NOTIFICATION,
+                NOTIFICATION,
+                NOTIFICATION,
+                // SDU of 3 bytes on the channel
+                &[0x05, 0x00, 0x40, 0x00, 0x03, 0x00, 1, 2, 3],
+            ],
+            sent: 0,
+        };
+        let mut connection = connect(&mut radio);
+        block_on(connection.connection_event()).unwrap();
+
+        // The read stops once the notifications fill the queue
+        let mut sdu = [0u8; 16];
+        assert_eq!(
+            block_on(connection.read(0x0040, &mut sdu)),
+            Err(ChannelError::Connection(ConnectionError::EventQueueFull))
+        );
+        for _ in 0..PENDING_EVENTS {
+            let event = block_on(connection.connection_event()).unwrap();
+            assert_eq!(event, Some(Event::Notification { handle: 3 }));
+        }
+
+        let len = block_on(connection.read(0x0040, &mut sdu)).unwrap();
+        assert_eq!(sdu[..len], [1, 2, 3]);
+    }
+}
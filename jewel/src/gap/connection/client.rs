@@ -0,0 +1,403 @@
+//! GATT client procedures
+//!
+//! The requests are sent on the ATT channel of the connection, a single one at a time.
+//! The connection events run until the response is received, the events of the
+//! connection meanwhile are reported by the next calls of `connection_event`.
+//! A request fails with `ConnectionError::EventQueueFull` when there is no room left for them.
+//!
+//! Ref: [Core 3.G.4](https://www.bluetooth.com/wp-content/uploads/Files/Specification/HTML/Core-54/out/en/host/generic-attribute-profile--gatt-.html)
+
+use embassy_time::Instant;
+
+use crate::{
+    att::{AttError, AttPdu, Uuid, MAX_MTU, TRANSACTION_TIMEOUT},
+    gatt::{
+        parse_characteristics, parse_descriptors, parse_services, set_characteristics_end,
+        Characteristic, Descriptor, GattError, Properties, Service,
+    },
+    l2cap::{BasicFrame, ChannelId},
+    ll::ConnectionError,
+    phy::Radio,
+};
+
+use super::{Connection, Event};
+
+/// Client Characteristic Configuration that enables notifications
+const NOTIFICATION: u16 = 0x0001;
+
+/// Client Characteristic Configuration that enables indications
+const INDICATION: u16 = 0x0002;
+
+impl<'r, R: Radio> Connection<'r, R> {
+    /// Send the request and run the connection events until its response is received
+    async fn request<'b>(
+        &mut self,
+        request: &AttPdu<'_>,
+        response: &'b mut [u8; MAX_MTU as usize],
+    ) -> Result<AttPdu<'b>, GattError<R::Error>> {
+        let mut pdu = [0u8; MAX_MTU as usize];
+        let len = request.bytes(&mut pdu);
+
+        self.client.start_request();
+        let frame = BasicFrame::new(ChannelId::Att, &pdu[..len]);
+        self.l2cap.send(&frame).await?;
+
+        let deadline = Instant::now() + TRANSACTION_TIMEOUT;
+        let len = loop {
+            if let Some(len) = self.client.take_response(response) {
+                break len;
+            }
+            if Instant::now() > deadline {
+                return Err(GattError::Timeout);
+            }
+            self.run_connection_event().await?;
+        };
+
+        // The response opcode follows the request opcode
+        match AttPdu::parse(&response[..len])? {
+            AttPdu::ErrorRsp {
+                request: opcode,
+                handle,
+                error,
+            } if opcode == request.opcode() => Err(GattError::Att { handle, error }),
+            pdu if pdu.opcode() == request.opcode() + 1 => Ok(pdu),
+            _ => Err(GattError::InvalidResponse),
+        }
+    }
+
+    /// Exchange the ATT_MTU with the server, returning the ATT_MTU used by both devices
+    ///
+    /// Ref: Core 3.G.4.3.1
+    pub async fn exchange_mtu(&mut self) -> Result<u16, GattError<R::Error>> {
+        let mut response = [0u8; MAX_MTU as usize];
+        let request = AttPdu::ExchangeMtuReq { mtu: MAX_MTU };
+
+        let AttPdu::ExchangeMtuRsp { mtu } = self.request(&request, &mut response).await? else {
+            return Err(GattError::InvalidResponse);
+        };
+        self.att.set_mtu(mtu);
+        Ok(self.att.mtu())
+    }
+
+    /// Discover the primary services of the server, returning the number of services
+    /// copied to `services`.
+    ///
+    /// Ref: Core 3.G.4.4.1
+    pub async fn discover_services(
+        &mut self,
+        services: &mut [Service],
+    ) -> Result<usize, GattError<R::Error>> {
+        let mut response = [0u8; MAX_MTU as usize];
+        let mut count = 0;
+        let mut start = 0x0001;
+
+        while count < services.len() {
+            let request = AttPdu::ReadByGroupTypeReq {
+                start,
+                end: 0xFFFF,
+                group_type: Uuid::PRIMARY_SERVICE,
+            };
+            let (len, last) = match self.request(&request, &mut response).await {
+                Ok(AttPdu::ReadByGroupTypeRsp { length, data }) => {
+                    parse_services(length, data, &mut services[count..])?
+                }
+                Ok(_) => return Err(GattError::InvalidResponse),
+                Err(GattError::Att {
+                    error: AttError::AttributeNotFound,
+                    ..
+                }) => break,
+                Err(error) => return Err(error),
+            };
+
+            count += len;
+            if last < start {
+                return Err(GattError::InvalidResponse);
+            }
+            if last == 0xFFFF {
+                break;
+            }
+            start = last + 1;
+        }
+
+        Ok(count)
+    }
+
+    /// Discover the characteristics of the service, returning the number of characteristics
+    /// copied to `characteristics`. The end handle of the last one is only exact when
+    /// all the characteristics of the service fit.
+    ///
+    /// Ref: Core 3.G.4.6.1
+    pub async fn discover_characteristics(
+        &mut self,
+        service: &Service,
+        characteristics: &mut [Characteristic],
+    ) -> Result<usize, GattError<R::Error>> {
+        let mut response = [0u8; MAX_MTU as usize];
+        let mut count = 0;
+        let mut start = service.start;
+
+        while count < characteristics.len() && start <= service.end {
+            let request = AttPdu::ReadByTypeReq {
+                start,
+                end: service.end,
+                attribute_type: Uuid::CHARACTERISTIC,
+            };
+            let (len, last) = match self.request(&request, &mut response).await {
+                Ok(AttPdu::ReadByTypeRsp { length, data }) => {
+                    parse_characteristics(length, data, &mut characteristics[count..])?
+                }
+                Ok(_) => return Err(GattError::InvalidResponse),
+                Err(GattError::Att {
+                    error: AttError::AttributeNotFound,
+                    ..
+                }) => break,
+                Err(error) => return Err(error),
+            };
+
+            count += len;
+            if last < start || last == 0xFFFF {
+                break;
+            }
+            start = last + 1;
+        }
+
+        set_characteristics_end(&mut characteristics[..count], service.end);
+        Ok(count)
+    }
+
+    /// Discover the descriptors of the characteristic, returning the number of descriptors
+    /// copied to `descriptors`.
+    ///
+    /// Ref: Core 3.G.4.7.1
+    pub async fn discover_descriptors(
+        &mut self,
+        characteristic: &Characteristic,
+        descriptors: &mut [Descriptor],
+    ) -> Result<usize, GattError<R::Error>> {
+        let mut count = 0;
+        let mut start = characteristic.value_handle.saturating_add(1);
+
+        while count < descriptors.len() && start <= characteristic.end {
+            let Some((len, last)) = self
+                .find_information(start, characteristic.end, &mut descriptors[count..])
+                .await?
+            else {
+                break;
+            };
+
+            count += len;
+            if last < start || last == 0xFFFF {
+                break;
+            }
+            start = last + 1;
+        }
+
+        Ok(count)
+    }
+
+    /// Copy the descriptors of a single ATT_FIND_INFORMATION_RSP,
+    /// returning the number copied and the last handle.
+    async fn find_information(
+        &mut self,
+        start: u16,
+        end: u16,
+        descriptors: &mut [Descriptor],
+    ) -> Result<Option<(usize, u16)>, GattError<R::Error>> {
+        let mut response = [0u8; MAX_MTU as usize];
+        let request = AttPdu::FindInformationReq { start, end };
+
+        match self.request(&request, &mut response).await {
+            Ok(AttPdu::FindInformationRsp { format, data }) => {
+                Ok(Some(parse_descriptors(format, data, descriptors)?))
+            }
+            Ok(_) => Err(GattError::InvalidResponse),
+            Err(GattError::Att {
+                error: AttError::AttributeNotFound,
+                ..
+            }) => Ok(None),
+            Err(error) => Err(error),
+        }
+    }
+
+    /// Read the value of the attribute, long values are read with as many requests
+    /// as needed. Returns the number of bytes copied to `buffer`.
+    ///
+    /// Ref: Core 3.G.4.8.1 and Core 3.G.4.8.3
+    pub async fn read_attribute(
+        &mut self,
+        handle: u16,
+        buffer: &mut [u8],
+    ) -> Result<usize, GattError<R::Error>> {
+        let mut response = [0u8; MAX_MTU as usize];
+        let mut len = 0;
+
+        loop {
+            let request = match len {
+                0 => AttPdu::ReadReq { handle },
+                offset => AttPdu::ReadBlobReq {
+                    handle,
+                    offset: offset as u16,
+                },
+            };
+            let value = match self.request(&request, &mut response).await {
+                Ok(AttPdu::ReadRsp { value } | AttPdu::ReadBlobRsp { value }) => value,
+                Ok(_) => return Err(GattError::InvalidResponse),
+                // The value length was a multiple of the response size
+                Err(GattError::Att {
+                    error: AttError::InvalidOffset | AttError::AttributeNotLong,
+                    ..
+                }) if len > 0 => return Ok(len),
+                Err(error) => return Err(error),
+            };
+
+            let copied = value.len().min(buffer.len() - len);
+            buffer[len..len + copied].copy_from_slice(&value[..copied]);
+            len += copied;
+
+            // A response shorter than the ATT_MTU ends the value
+            if value.len() < self.att.mtu() as usize - 1 || len == buffer.len() {
+                return Ok(len);
+            }
+        }
+    }
+
+    /// Write the value of the attribute, long values are written with
+    /// prepared writes executed at once.
+    ///
+    /// Ref: Core 3.G.4.9.3 and Core 3.G.4.9.4
+    pub async fn write_attribute(
+        &mut self,
+        handle: u16,
+        value: &[u8],
+    ) -> Result<(), GattError<R::Error>> {
+        let mut response = [0u8; MAX_MTU as usize];
+        let mtu = self.att.mtu() as usize;
+
+        if value.len() <= mtu - 3 {
+            let request = AttPdu::WriteReq { handle, value };
+            return match self.request(&request, &mut response).await? {
+                AttPdu::WriteRsp => Ok(()),
+                _ => Err(GattError::InvalidResponse),
+            };
+        }
+
+        for (i, part) in value.chunks(mtu - 5).enumerate() {
+            let offset = (i * (mtu - 5)) as u16;
+            let request = AttPdu::PrepareWriteReq {
+                handle,
+                offset,
+                value: part,
+            };
+
+            // The server echoes the part, it shall be the same
+            let result = match self.request(&request, &mut response).await {
+                Ok(AttPdu::PrepareWriteRsp {
+                    handle: echo_handle,
+                    offset: echo_offset,
+                    value: echo,
+                }) if echo_handle == handle && echo_offset == offset && echo == part => Ok(()),
+                Ok(_) => Err(GattError::InvalidResponse),
+                Err(error) => Err(error),
+            };
+            if let Err(error) = result {
+                let cancel = AttPdu::ExecuteWriteReq { flags: 0x00 };
+                if !matches!(error, GattError::Connection(_) | GattError::Timeout) {
+                    self.request(&cancel, &mut response).await?;
+                }
+                return Err(error);
+            }
+        }
+
+        let request = AttPdu::ExecuteWriteReq { flags: 0x01 };
+        match self.request(&request, &mut response).await? {
+            AttPdu::ExecuteWriteRsp => Ok(()),
+            _ => Err(GattError::InvalidResponse),
+        }
+    }
+
+    /// Enable the notifications of the characteristic, or its indications when
+    /// notifications are not supported. The values are read with `next_notification`.
+    ///
+    /// Ref: Core 3.G.4.10 and Core 3.G.4.11
+    pub async fn subscribe(
+        &mut self,
+        characteristic: &Characteristic,
+    ) -> Result<(), GattError<R::Error>> {
+        let flags = if characteristic.properties.contains(Properties::NOTIFY) {
+            NOTIFICATION
+        } else {
+            INDICATION
+        };
+        self.configure(characteristic, flags).await
+    }
+
+    /// Disable the notifications and indications of the characteristic
+    pub async fn unsubscribe(
+        &mut self,
+        characteristic: &Characteristic,
+    ) -> Result<(), GattError<R::Error>> {
+        self.configure(characteristic, 0x0000).await
+    }
+
+    /// Write the Client Characteristic Configuration descriptor of the characteristic
+    async fn configure(
+        &mut self,
+        characteristic: &Characteristic,
+        flags: u16,
+    ) -> Result<(), GattError<R::Error>> {
+        let mut descriptors = [Descriptor {
+            uuid: Uuid::Uuid16(0),
+            handle: 0,
+        }; 4];
+        let mut start = characteristic.value_handle.saturating_add(1);
+
+        while start <= characteristic.end {
+            let Some((len, last)) = self
+                .find_information(start, characteristic.end, &mut descriptors)
+                .await?
+            else {
+                break;
+            };
+
+            let cccd = descriptors[..len]
+                .iter()
+                .find(|descriptor| descriptor.uuid == Uuid::CLIENT_CHARACTERISTIC_CONFIGURATION);
+            if let Some(cccd) = cccd {
+                return self
+                    .write_attribute(cccd.handle, &flags.to_le_bytes())
+                    .await;
+            }
+
+            if last < start || last == 0xFFFF {
+                break;
+            }
+            start = last + 1;
+        }
+
+        Err(GattError::Att {
+            handle: characteristic.handle,
+            error: AttError::AttributeNotFound,
+        })
+    }
+
+    /// Run the connection events until a notification or indication is received.
+    /// Returns the handle of the characteristic value and the number of bytes copied
+    /// to `buffer`.
+    pub async fn next_notification(
+        &mut self,
+        buffer: &mut [u8],
+    ) -> Result<(u16, usize), ConnectionError<R::Error>> {
+        loop {
+            if let Some(notification) = self.client.pop_notification(buffer) {
+                return Ok(notification);
+            }
+
+            match self.wait_event().await? {
+                // The notification is returned by this call
+                Some(Event::Notification { .. }) => {}
+                Some(event) => self.pending.push(event),
+                None => {}
+            }
+        }
+    }
+}
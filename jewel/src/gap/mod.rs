@@ -1,13 +1,24 @@
 //! Generic acess profile
 
 mod adv_struct;
+mod central;
+mod connection;
+mod observer;
+mod peripheral;
 
 pub use adv_struct::*;
+pub use central::*;
+pub use connection::*;
 use embassy_time::Duration;
-use rand::rngs::SmallRng;
+pub use observer::*;
+pub use peripheral::*;
+use rand::{rngs::SmallRng, RngCore};
 
 use crate::{
-    ll::{Address, AddressAndData, AdvNonconnInd, Advertising, LinkLayer},
+    ll::{
+        AddressAndData, AdvNonconnInd, AdvScanInd, Advertising, AdvertisingError,
+        AdvertisingFilterPolicy, FilterAcceptList, LinkLayer, ScanRsp, PRIVATE_ADDRESS_TIMEOUT,
+    },
     phy::{Radio, MAX_PDU_LENGTH},
 };
 
@@ -21,7 +32,7 @@ pub struct Broadcaster<'r, 'a, R: Radio> {
 }
 
 /// Brodcast provile. Advertise legacy packages on the 3 primary advertising channels.
-/// ```no_run
+/// ```ignore
 /// #![no_std]
 /// #![no_main]
 ///
@@ -80,7 +91,9 @@ impl<'r, 'a, R: Radio> Broadcaster<'r, 'a, R> {
         let ll = LinkLayer::new(radio);
 
         // FIXME: it should be a better way to do this
-        let ll = ll.advertise(interval, buffer);
+        let ll = ll
+            .advertise(interval, buffer)
+            .expect("the advertising PDU is serialized above");
 
         Ok(Broadcaster {
             ll,
@@ -88,9 +101,65 @@ impl<'r, 'a, R: Radio> Broadcaster<'r, 'a, R> {
         })
     }
 
+    /// Create a new scannable broadcaster
+    ///
+    /// Advertise ADV_SCAN_IND and answer scanners with a SCAN_RSP carrying `scan_data`,
+    /// what gives extra 31 bytes for names and service data.
+    pub fn new_scannable(
+        radio: &'r mut R,
+        interval: Duration,
+        data: AdvData<'a>,
+        scan_data: AdvData<'a>,
+        buffer: &'a mut [u8; MAX_PDU_LENGTH],
+        scan_buffer: &'a mut [u8; MAX_PDU_LENGTH],
+    ) -> Result<Broadcaster<'r, 'a, R>, R::Error> {
+        let addr = radio.device_address();
+
+        let mut body_buffer = [0u8; MAX_PDU_LENGTH];
+        let len = data.bytes(&mut body_buffer);
+        let pdu = AdvScanInd::new(addr.clone(), &body_buffer[..len]);
+        let pdu_len = pdu.bytes(buffer);
+
+        let len = scan_data.bytes(&mut body_buffer);
+        let scan_rsp = ScanRsp::new(addr, &body_buffer[..len]);
+        let scan_rsp_len = scan_rsp.bytes(scan_buffer);
+
+        let ll = LinkLayer::new(radio);
+        let ll = ll
+            .advertise_scannable(interval, &buffer[..pdu_len], &scan_buffer[..scan_rsp_len])
+            .expect("the advertising PDUs are serialized above");
+
+        Ok(Broadcaster {
+            ll,
+            _buffer: buffer,
+        })
+    }
+
+    /// Broadcast with a resolvable private address generated from the `irk` instead of the
+    /// device address, changed every `PRIVATE_ADDRESS_TIMEOUT` so the beacon can not be tracked
+    pub fn set_privacy(mut self, irk: [u8; 16], rng: &mut impl RngCore) -> Self {
+        self.ll.set_privacy(irk, PRIVATE_ADDRESS_TIMEOUT, rng);
+        self
+    }
+
+    /// Only answer the scanners in the `list` when the `policy` filters the scan requests
+    pub fn set_filter_policy(
+        mut self,
+        policy: AdvertisingFilterPolicy,
+        list: FilterAcceptList,
+    ) -> Self {
+        self.ll.set_filter_policy(policy, list);
+        self
+    }
+
     /// Transmit the packet respecting the given interval
     /// You should call this method before the interval time
     pub async fn transmit(&mut self) -> Result<(), R::Error> {
-        self.ll.transmit().await
+        // broadcasters are not connectable, so no connection request is returned
+        // and the advertising does not time out
+        match self.ll.transmit().await {
+            Ok(_) | Err(AdvertisingError::Timeout) => Ok(()),
+            Err(AdvertisingError::Radio(error)) => Err(error),
+        }
     }
 }
@@ -0,0 +1,75 @@
+use embassy_time::Duration;
+
+use crate::{
+    ll::{AdvReport, FilterAcceptList, LinkLayer, ScannerFilterPolicy, Scanning},
+    phy::{Radio, MAX_PDU_LENGTH},
+};
+
+pub struct Observer<'r, R: Radio> {
+    ll: LinkLayer<'r, R, Scanning>,
+}
+
+/// Observer profile. Receive legacy advertising packages on the 3 primary advertising channels.
+/// ```ignore
+/// let mut radio: RadioImpl<'_, _> = radio::ble::Radio::new(p.RADIO, Irqs).into();
+/// let mut observer = Observer::new(
+///     &mut radio,
+///     Duration::from_millis(100),
+///     Duration::from_millis(50),
+/// );
+///
+/// let mut buffer = [0u8; MAX_PDU_LENGTH];
+/// loop {
+///     let report = observer.scan(&mut buffer).await.unwrap();
+///     info!("{} on {}: {}", report.timestamp, report.channel, report.pdu);
+/// }
+/// ```
+impl<'r, R: Radio> Observer<'r, R> {
+    /// Create a new observer
+    ///
+    /// The `window` is how long the radio listens on each channel, every `interval`.
+    pub fn new(radio: &'r mut R, interval: Duration, window: Duration) -> Self {
+        let ll = LinkLayer::new(radio).scan(interval, window);
+
+        Observer { ll }
+    }
+
+    /// Create a new active observer
+    ///
+    /// Scannable advertisers are asked for their scan response,
+    /// reported together with the advertising packet by `scan_active`.
+    pub fn new_active(radio: &'r mut R, interval: Duration, window: Duration) -> Self {
+        let ll = LinkLayer::new(radio).scan_active(interval, window);
+
+        Observer { ll }
+    }
+
+    /// Only report the advertising allowed by the `policy`, for example
+    /// the advertising of the devices in the `list`
+    pub fn set_filter_policy(
+        mut self,
+        policy: ScannerFilterPolicy,
+        list: FilterAcceptList,
+    ) -> Self {
+        self.ll.set_filter_policy(policy, list);
+        self
+    }
+
+    /// Wait for the next advertising packet
+    pub async fn scan<'b>(
+        &mut self,
+        buffer: &'b mut [u8; MAX_PDU_LENGTH],
+    ) -> Result<AdvReport<'b>, R::Error> {
+        self.ll.receive(buffer).await
+    }
+
+    /// Wait for the next advertising packet, an active observer reports it
+    /// with the scan response received in `scan_rsp_buffer`
+    pub async fn scan_active<'b>(
+        &mut self,
+        buffer: &'b mut [u8; MAX_PDU_LENGTH],
+        scan_rsp_buffer: &'b mut [u8; MAX_PDU_LENGTH],
+    ) -> Result<AdvReport<'b>, R::Error> {
+        self.ll.receive_active(buffer, scan_rsp_buffer).await
+    }
+}
@@ -0,0 +1,162 @@
+use embassy_time::Duration;
+use rand::{rngs::SmallRng, RngCore};
+
+use crate::{
+    att::AttributeTable,
+    l2cap::Psm,
+    ll::{
+        Address, AddressAndData, AdvDirectInd, AdvInd, Advertising, AdvertisingError,
+        AdvertisingFilterPolicy, DirectedEvent, DutyCycle, FilterAcceptList, LinkLayer, ScanRsp,
+        TwoAddress, PRIVATE_ADDRESS_TIMEOUT,
+    },
+    phy::{Radio, MAX_PDU_LENGTH},
+};
+
+use super::{AdvData, Connection};
+
+pub struct Peripheral<'r, 'a, R: Radio> {
+    ll: LinkLayer<'r, R, Advertising<'a, SmallRng>>,
+
+    /// SPSMs accepted on the connection-oriented channels
+    psms: &'static [Psm],
+
+    /// Attributes exposed by the ATT server once connected
+    attributes: Option<&'r mut dyn AttributeTable>,
+}
+
+/// Peripheral profile. Advertise connectable legacy packages (ADV_IND) on the 3 primary
+/// advertising channels until a central connects.
+/// ```ignore
+/// let mut radio: RadioImpl<'_, _> = radio::ble::Radio::new(p.RADIO, Irqs).into();
+/// let mut buffer = [0u8; MAX_PDU_LENGTH];
+/// let mut scan_buffer = [0u8; MAX_PDU_LENGTH];
+///
+/// let peripheral = Peripheral::new(
+///     &mut radio,
+///     Duration::from_millis(100),
+///     AdvData::empty().set_flags(Flags::discoverable()),
+///     AdvData::empty().set_complete_local_name("HelloRust"),
+///     &mut buffer,
+///     &mut scan_buffer,
+/// )
+/// .unwrap();
+///
+/// let connection = peripheral.advertise().await.unwrap();
+/// info!("Connected to {}", connection.peer_address());
+/// ```
+impl<'r, 'a, R: Radio> Peripheral<'r, 'a, R> {
+    /// Create a new peripheral
+    ///
+    /// The `scan_data` is sent to active scanners in the SCAN_RSP.
+    pub fn new(
+        radio: &'r mut R,
+        interval: Duration,
+        data: AdvData<'a>,
+        scan_data: AdvData<'a>,
+        buffer: &'a mut [u8; MAX_PDU_LENGTH],
+        scan_buffer: &'a mut [u8; MAX_PDU_LENGTH],
+    ) -> Result<Peripheral<'r, 'a, R>, R::Error> {
+        let addr = radio.device_address();
+
+        let mut body_buffer = [0u8; MAX_PDU_LENGTH];
+        let len = data.bytes(&mut body_buffer);
+        let mut pdu = AdvInd::new(addr.clone(), &body_buffer[..len]);
+        pdu.set_ch_sel(true);
+        let pdu_len = pdu.bytes(buffer);
+
+        let len = scan_data.bytes(&mut body_buffer);
+        let scan_rsp = ScanRsp::new(addr, &body_buffer[..len]);
+        let scan_rsp_len = scan_rsp.bytes(scan_buffer);
+
+        let ll = LinkLayer::new(radio);
+        let ll = ll
+            .advertise_scannable(interval, &buffer[..pdu_len], &scan_buffer[..scan_rsp_len])
+            .expect("the advertising PDUs are serialized above");
+
+        Ok(Peripheral {
+            ll,
+            psms: &[],
+            attributes: None,
+        })
+    }
+
+    /// Create a peripheral that advertises only to the `target` central with ADV_DIRECT_IND,
+    /// to reconnect quickly to a known device. Use `advertise_directed`, as the high
+    /// duty cycle advertising times out after 1.28 s.
+    pub fn new_directed(
+        radio: &'r mut R,
+        duty_cycle: DutyCycle,
+        target: Address,
+        buffer: &'a mut [u8; MAX_PDU_LENGTH],
+    ) -> Result<Peripheral<'r, 'a, R>, R::Error> {
+        let pdu = AdvDirectInd::new(radio.device_address(), target);
+        let pdu_len = pdu.bytes(buffer);
+
+        let ll = LinkLayer::new(radio);
+        let ll = ll
+            .advertise_directed(duty_cycle, &buffer[..pdu_len])
+            .expect("the advertising PDU is serialized above");
+
+        Ok(Peripheral {
+            ll,
+            psms: &[],
+            attributes: None,
+        })
+    }
+
+    /// Accept connection-oriented channels on the `psms` once connected
+    pub fn set_psms(mut self, psms: &'static [Psm]) -> Self {
+        self.psms = psms;
+        self
+    }
+
+    /// Expose the `attributes` to the client once connected
+    pub fn set_attributes(mut self, attributes: &'r mut dyn AttributeTable) -> Self {
+        self.attributes = Some(attributes);
+        self
+    }
+
+    /// Advertise with a resolvable private address generated from the `irk`, changed every
+    /// `PRIVATE_ADDRESS_TIMEOUT`. Distribute the same IRK on bonding with `PairingConfig::irk`
+    /// so the bonded centrals recognize this device.
+    pub fn set_privacy(mut self, irk: [u8; 16], rng: &mut impl RngCore) -> Self {
+        self.ll.set_privacy(irk, PRIVATE_ADDRESS_TIMEOUT, rng);
+        self
+    }
+
+    /// Only answer the scanners and the centrals allowed by the `policy`, for example
+    /// to accept connections only from the bonded devices in the `list`
+    pub fn set_filter_policy(
+        mut self,
+        policy: AdvertisingFilterPolicy,
+        list: FilterAcceptList,
+    ) -> Self {
+        self.ll.set_filter_policy(policy, list);
+        self
+    }
+
+    /// Advertise until a central sends a connection request
+    ///
+    /// A directed peripheral shall use `advertise_directed`, that reports the timeout
+    /// as `None`. Here it ends the advertising with `AdvertisingError::Timeout`.
+    pub async fn advertise(mut self) -> Result<Connection<'r, R>, AdvertisingError<R::Error>> {
+        loop {
+            if let Some(request) = self.ll.transmit().await? {
+                let ll = self.ll.connect(request);
+                return Ok(Connection::new(ll, self.psms, self.attributes));
+            }
+        }
+    }
+
+    /// Advertise until the target central connects, `None` when the high duty cycle
+    /// directed advertising timed out without a connection
+    pub async fn advertise_directed(mut self) -> Result<Option<Connection<'r, R>>, R::Error> {
+        match self.ll.transmit_directed().await? {
+            DirectedEvent::Connect(request) => {
+                let ll = self.ll.connect(request);
+                Ok(Some(Connection::new(ll, self.psms, self.attributes)))
+            }
+            DirectedEvent::Timeout => Ok(None),
+        }
+    }
+}
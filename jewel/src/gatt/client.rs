@@ -0,0 +1,419 @@
+use defmt::Format;
+
+use crate::{
+    att::{AttError, Uuid, MAX_MTU},
+    ll::{ConnectionError, ParseError},
+};
+
+use super::Properties;
+
+/// Notifications and indications received and not read yet
+pub const NOTIFICATION_QUEUE_SIZE: usize = 4;
+
+/// Largest value of a notification, ATT_MTU - 3 bytes
+pub const MAX_NOTIFICATION_LENGTH: usize = MAX_MTU as usize - 3;
+
+/// Primary service discovered on the server
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Format)]
+pub struct Service {
+    pub uuid: Uuid,
+
+    /// Handle of the service declaration
+    pub start: u16,
+
+    /// Last handle of the service
+    pub end: u16,
+}
+
+/// Characteristic discovered on a service
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Format)]
+pub struct Characteristic {
+    pub uuid: Uuid,
+    pub properties: Properties,
+
+    /// Handle of the characteristic declaration
+    pub handle: u16,
+    pub value_handle: u16,
+
+    /// Last handle of the characteristic, its descriptors are after the value
+    pub end: u16,
+}
+
+/// Descriptor discovered on a characteristic
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Format)]
+pub struct Descriptor {
+    pub uuid: Uuid,
+    pub handle: u16,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Format)]
+pub enum GattError<E> {
+    Connection(ConnectionError<E>),
+
+    /// The server answered with an ATT_ERROR_RSP
+    Att {
+        handle: u16,
+        error: AttError,
+    },
+
+    /// The response does not match the request
+    InvalidResponse,
+
+    /// The server did not answer within the ATT transaction timeout
+    Timeout,
+}
+
+impl<E> From<ConnectionError<E>> for GattError<E> {
+    fn from(error: ConnectionError<E>) -> Self {
+        GattError::Connection(error)
+    }
+}
+
+impl<E> From<ParseError> for GattError<E> {
+    fn from(_: ParseError) -> Self {
+        GattError::InvalidResponse
+    }
+}
+
+fn handle_at(bytes: &[u8]) -> u16 {
+    u16::from_le_bytes([bytes[0], bytes[1]])
+}
+
+/// Split the list of a response in entries of `length` bytes, each with a
+/// header of `header` bytes followed by a 16-bit or 128-bit UUID
+fn entries(
+    length: usize,
+    header: usize,
+    data: &[u8],
+) -> Result<core::slice::ChunksExact<'_, u8>, ParseError> {
+    if length != header + 2 && length != header + 16 {
+        return Err(ParseError::InvalidLength);
+    }
+    if data.is_empty() || !data.len().is_multiple_of(length) {
+        return Err(ParseError::InvalidLength);
+    }
+    Ok(data.chunks_exact(length))
+}
+
+/// Copy the services of an ATT_READ_BY_GROUP_TYPE_RSP to `dest`,
+/// returns the number of services copied and the last handle listed.
+///
+/// Ref: Core 3.G.4.4.1
+pub(crate) fn parse_services(
+    length: u8,
+    data: &[u8],
+    dest: &mut [Service],
+) -> Result<(usize, u16), ParseError> {
+    let mut count = 0;
+    let mut last = 0;
+    for (entry, dest) in entries(length as usize, 4, data)?.zip(dest) {
+        *dest = Service {
+            uuid: Uuid::parse(&entry[4..])?,
+            start: handle_at(entry),
+            end: handle_at(&entry[2..]),
+        };
+        if dest.end < dest.start {
+            return Err(ParseError::InvalidValue);
+        }
+        count += 1;
+        last = dest.end;
+    }
+    Ok((count, last))
+}
+
+/// Copy the characteristics of an ATT_READ_BY_TYPE_RSP to `dest`, their end handle
+/// is set by `set_characteristics_end` once all are known.
+/// Returns the number of characteristics copied and the last handle listed.
+///
+/// Ref: Core 3.G.4.6.1
+pub(crate) fn parse_characteristics(
+    length: u8,
+    data: &[u8],
+    dest: &mut [Characteristic],
+) -> Result<(usize, u16), ParseError> {
+    let mut count = 0;
+    let mut last = 0;
+    for (entry, dest) in entries(length as usize, 5, data)?.zip(dest) {
+        *dest = Characteristic {
+            uuid: Uuid::parse(&entry[5..])?,
+            properties: Properties::new(entry[2]),
+            handle: handle_at(entry),
+            value_handle: handle_at(&entry[3..]),
+            end: 0,
+        };
+        if dest.value_handle <= dest.handle {
+            return Err(ParseError::InvalidValue);
+        }
+        count += 1;
+        last = dest.handle;
+    }
+    Ok((count, last))
+}
+
+/// Each characteristic ends before the next one, the last one with the service
+pub(crate) fn set_characteristics_end(characteristics: &mut [Characteristic], service_end: u16) {
+    let mut end = service_end;
+    for characteristic in characteristics.iter_mut().rev() {
+        characteristic.end = end;
+        end = characteristic.handle - 1;
+    }
+}
+
+/// Copy the descriptors of an ATT_FIND_INFORMATION_RSP to `dest`,
+/// returns the number of descriptors copied and the last handle listed.
+///
+/// Ref: Core 3.G.4.7.1
+pub(crate) fn parse_descriptors(
+    format: u8,
+    data: &[u8],
+    dest: &mut [Descriptor],
+) -> Result<(usize, u16), ParseError> {
+    let length = match format {
+        0x01 => 4,
+        0x02 => 18,
+        _ => return Err(ParseError::InvalidValue),
+    };
+
+    let mut count = 0;
+    let mut last = 0;
+    for (entry, dest) in entries(length, 2, data)?.zip(dest) {
+        *dest = Descriptor {
+            uuid: Uuid::parse(&entry[2..])?,
+            handle: handle_at(entry),
+        };
+        count += 1;
+        last = dest.handle;
+    }
+    Ok((count, last))
+}
+
+/// Notification or indication received from the server
+#[derive(Clone, Copy)]
+struct Notification {
+    handle: u16,
+    len: usize,
+    value: [u8; MAX_NOTIFICATION_LENGTH],
+}
+
+/// State of the ATT client of a connection
+///
+/// A single request is sent at a time, its response is kept until it is read.
+/// The notifications are queued, they are dropped when the queue is full.
+pub(crate) struct GattClient {
+    /// A request was sent and its response was not received yet
+    pending: bool,
+
+    response: [u8; MAX_MTU as usize],
+    response_len: Option<usize>,
+
+    notifications: [Notification; NOTIFICATION_QUEUE_SIZE],
+    head: usize,
+    len: usize,
+}
+
+impl GattClient {
+    pub(crate) const fn new() -> Self {
+        const EMPTY: Notification = Notification {
+            handle: 0,
+            len: 0,
+            value: [0; MAX_NOTIFICATION_LENGTH],
+        };
+
+        Self {
+            pending: false,
+            response: [0; MAX_MTU as usize],
+            response_len: None,
+            notifications: [EMPTY; NOTIFICATION_QUEUE_SIZE],
+            head: 0,
+            len: 0,
+        }
+    }
+
+    /// A request was sent, wait for its response
+    pub(crate) fn start_request(&mut self) {
+        self.pending = true;
+        self.response_len = None;
+    }
+
+    /// Handle a response, notification or indication.
+    /// Returns the handle of the notification or indication received.
+    pub(crate) fn on_pdu(&mut self, pdu: &[u8]) -> Option<u16> {
+        match pdu {
+            // ATT_HANDLE_VALUE_NTF and ATT_HANDLE_VALUE_IND
+            [0x1B | 0x1D, low, high, value @ ..] => {
+                let handle = u16::from_le_bytes([*low, *high]);
+                if self.len < NOTIFICATION_QUEUE_SIZE {
+                    let len = value.len().min(MAX_NOTIFICATION_LENGTH);
+                    let notification =
+                        &mut self.notifications[(self.head + self.len) % NOTIFICATION_QUEUE_SIZE];
+                    notification.handle = handle;
+                    notification.len = len;
+                    notification.value[..len].copy_from_slice(&value[..len]);
+                    self.len += 1;
+                }
+                Some(handle)
+            }
+            _ if self.pending && pdu.len() <= self.response.len() => {
+                self.pending = false;
+                self.response[..pdu.len()].copy_from_slice(pdu);
+                self.response_len = Some(pdu.len());
+                None
+            }
+            // Unexpected responses are dropped
+            _ => None,
+        }
+    }
+
+    /// Copy the response of the last request, returning its length
+    pub(crate) fn take_response(&mut self, dest: &mut [u8]) -> Option<usize> {
+        let len = self.response_len.take()?;
+        dest[..len].copy_from_slice(&self.response[..len]);
+        Some(len)
+    }
+
+    /// Copy the oldest notification value, returning its handle and length
+    pub(crate) fn pop_notification(&mut self, dest: &mut [u8]) -> Option<(u16, usize)> {
+        if self.len == 0 {
+            return None;
+        }
+
+        let notification = &self.notifications[self.head];
+        let len = notification.len.min(dest.len());
+        dest[..len].copy_from_slice(&notification.value[..len]);
+
+        self.head = (self.head + 1) % NOTIFICATION_QUEUE_SIZE;
+        self.len -= 1;
+        Some((notification.handle, len))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn services() {
+        let data = [
+            0x01, 0x00, 0x05, 0x00, 0x00, 0x18, 0x06, 0x00, 0x06, 0x00, 0x01, 0x18,
+        ];
+        let mut services = [Service {
+            uuid: Uuid::Uuid16(0),
+            start: 0,
+            end: 0,
+        }; 4];
+
+        assert_eq!(parse_services(6, &data, &mut services), Ok((2, 0x0006)));
+        assert_eq!(
+            services[1],
+            Service {
+                uuid: Uuid::GATT_SERVICE,
+                start: 0x0006,
+                end: 0x0006,
+            }
+        );
+
+        // Only the services that fit are copied
+        assert_eq!(
+            parse_services(6, &data, &mut services[..1]),
+            Ok((1, 0x0005))
+        );
+
+        assert_eq!(
+            parse_services(5, &data[..10], &mut services),
+            Err(ParseError::InvalidLength)
+        );
+        assert_eq!(
+            parse_services(6, &data[..8], &mut services),
+            Err(ParseError::InvalidLength)
+        );
+    }
+
+    #[test]
+    fn characteristics() {
+        let mut data = [0u8; 28];
+        data[..7].copy_from_slice(&[0x08, 0x00, 0x12, 0x09, 0x00, 0x19, 0x2A]);
+        let mut characteristics = [Characteristic {
+            uuid: Uuid::Uuid16(0),
+            properties: Properties::new(0),
+            handle: 0,
+            value_handle: 0,
+            end: 0,
+        }; 2];
+
+        assert_eq!(
+            parse_characteristics(7, &data[..7], &mut characteristics),
+            Ok((1, 0x0008))
+        );
+        assert_eq!(characteristics[0].uuid, Uuid::Uuid16(0x2A19));
+        assert_eq!(
+            characteristics[0].properties,
+            Properties::READ.union(Properties::NOTIFY)
+        );
+        assert_eq!(characteristics[0].value_handle, 0x0009);
+
+        data[..5].copy_from_slice(&[0x0B, 0x00, 0x0A, 0x0C, 0x00]);
+        data[5..21].copy_from_slice(&[0x42; 16]);
+        assert_eq!(
+            parse_characteristics(21, &data[..21], &mut characteristics[1..]),
+            Ok((1, 0x000B))
+        );
+        assert_eq!(characteristics[1].uuid, Uuid::Uuid128([0x42; 16]));
+
+        set_characteristics_end(&mut characteristics, 0x000F);
+        assert_eq!(characteristics[0].end, 0x000A);
+        assert_eq!(characteristics[1].end, 0x000F);
+    }
+
+    #[test]
+    fn descriptors() {
+        let data = [0x0A, 0x00, 0x02, 0x29, 0x0B, 0x00, 0x01, 0x29];
+        let mut descriptors = [Descriptor {
+            uuid: Uuid::Uuid16(0),
+            handle: 0,
+        }; 4];
+
+        assert_eq!(
+            parse_descriptors(0x01, &data, &mut descriptors),
+            Ok((2, 0x000B))
+        );
+        assert_eq!(
+            descriptors[0],
+            Descriptor {
+                uuid: Uuid::CLIENT_CHARACTERISTIC_CONFIGURATION,
+                handle: 0x000A,
+            }
+        );
+        assert_eq!(
+            parse_descriptors(0x02, &data, &mut descriptors),
+            Err(ParseError::InvalidLength)
+        );
+    }
+
+    #[test]
+    fn responses_and_notifications() {
+        let mut client = GattClient::new();
+        let mut buffer = [0u8; MAX_MTU as usize];
+
+        // Responses without a request are dropped
+        assert_eq!(client.on_pdu(&[0x0B, 0x01]), None);
+        assert_eq!(client.take_response(&mut buffer), None);
+
+        client.start_request();
+        assert_eq!(client.on_pdu(&[0x1B, 0x09, 0x00, 42]), Some(0x0009));
+        assert_eq!(client.take_response(&mut buffer), None);
+        client.on_pdu(&[0x0B, 0x01]);
+        assert_eq!(client.take_response(&mut buffer), Some(2));
+        assert_eq!(buffer[..2], [0x0B, 0x01]);
+
+        for value in 0..NOTIFICATION_QUEUE_SIZE as u8 {
+            client.on_pdu(&[0x1D, 0x0C, 0x00, value]);
+        }
+        assert_eq!(client.pop_notification(&mut buffer), Some((0x0009, 1)));
+        assert_eq!(buffer[0], 42);
+        for value in 0..NOTIFICATION_QUEUE_SIZE as u8 - 1 {
+            assert_eq!(client.pop_notification(&mut buffer), Some((0x000C, 1)));
+            assert_eq!(buffer[0], value);
+        }
+        assert_eq!(client.pop_notification(&mut buffer), None);
+    }
+}
@@ -0,0 +1,12 @@
+//! Generic attribute profile
+//!
+//! Organize the attributes of the ATT server in services, each with characteristics
+//! that have a value and optional descriptors.
+//!
+//! Ref: [Core 3.G](https://www.bluetooth.com/wp-content/uploads/Files/Specification/HTML/Core-54/out/en/host/generic-attribute-profile--gatt-.html)
+
+mod client;
+mod server;
+
+pub use client::*;
+pub use server::*;
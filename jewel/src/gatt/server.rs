@@ -0,0 +1,540 @@
+use defmt::Format;
+
+use crate::att::{AttError, Attribute, AttributeTable, Permissions, Uuid};
+
+/// Operations of a characteristic value, announced in its declaration
+///
+/// Ref: Core 3.G.3.3.1.1
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Format)]
+pub struct Properties {
+    bits: u8,
+}
+
+impl Properties {
+    pub const BROADCAST: Properties = Properties { bits: 0x01 };
+    pub const READ: Properties = Properties { bits: 0x02 };
+    pub const WRITE_WITHOUT_RESPONSE: Properties = Properties { bits: 0x04 };
+    pub const WRITE: Properties = Properties { bits: 0x08 };
+    pub const NOTIFY: Properties = Properties { bits: 0x10 };
+    pub const INDICATE: Properties = Properties { bits: 0x20 };
+
+    pub const fn new(bits: u8) -> Self {
+        Self { bits }
+    }
+
+    pub const fn union(self, other: Properties) -> Properties {
+        Properties {
+            bits: self.bits | other.bits,
+        }
+    }
+
+    pub const fn contains(&self, other: Properties) -> bool {
+        self.bits & other.bits == other.bits
+    }
+
+    /// Permissions of the characteristic value
+    const fn permissions(&self) -> Permissions {
+        let mut permissions = Permissions::NONE;
+        if self.contains(Properties::READ) {
+            permissions = permissions.union(Permissions::READ);
+        }
+        if self.contains(Properties::WRITE) || self.contains(Properties::WRITE_WITHOUT_RESPONSE) {
+            permissions = permissions.union(Permissions::WRITE);
+        }
+        permissions
+    }
+}
+
+/// Value of a characteristic or descriptor
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Format)]
+pub enum Value {
+    /// Constant value, it can only be read
+    Static(&'static [u8]),
+
+    /// Value read and written by the `GattHandler`
+    Dynamic,
+}
+
+/// How the value of an attribute is obtained
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Format)]
+enum AttributeValue {
+    Value(Value),
+
+    /// Service declaration, the value is the service UUID
+    Service(Uuid),
+
+    /// Characteristic declaration, the characteristic value is the next attribute
+    Characteristic {
+        properties: Properties,
+        uuid: Uuid,
+    },
+}
+
+/// Attribute of a `GattTable`, its handle is the position in the table
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Format)]
+pub struct GattAttribute {
+    uuid: Uuid,
+    permissions: Permissions,
+    value: AttributeValue,
+}
+
+impl GattAttribute {
+    const EMPTY: GattAttribute = GattAttribute {
+        uuid: Uuid::Uuid16(0x0000),
+        permissions: Permissions::NONE,
+        value: AttributeValue::Value(Value::Static(&[])),
+    };
+
+    pub fn uuid(&self) -> Uuid {
+        self.uuid
+    }
+}
+
+/// Services, characteristics and descriptors of the GATT server, built at compile time
+///
+/// The handles are given in declaration order, so the same table always results
+/// in the same handles and the clients can keep their attribute cache.
+/// The GAP service with the device name and the GATT service are always the first ones.
+///
+/// ```
+/// use jewel::{
+///     att::Uuid,
+///     gatt::{GattTable, Properties, Value},
+/// };
+///
+/// const NAME: &str = "jewel"; // also used in `AdvData::set_complete_local_name`
+/// const BATTERY_LEVEL: Uuid = Uuid::Uuid16(0x2A19);
+///
+/// static GATT: GattTable<9> = GattTable::new(NAME)
+///     .primary_service(Uuid::Uuid16(0x180F))
+///     .characteristic(BATTERY_LEVEL, Properties::READ, Value::Dynamic);
+///
+/// const BATTERY_LEVEL_HANDLE: u16 = GATT.value_handle(BATTERY_LEVEL);
+/// assert_eq!(BATTERY_LEVEL_HANDLE, 0x0009);
+/// ```
+///
+/// Ref: Core 3.G.3
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GattTable<const N: usize> {
+    attributes: [GattAttribute; N],
+    len: usize,
+}
+
+impl<const N: usize> GattTable<N> {
+    /// Create the table with the GAP and GATT services
+    ///
+    /// The appearance is unknown and, as the table never changes,
+    /// the GATT service has no Service Changed characteristic.
+    pub const fn new(device_name: &'static str) -> Self {
+        Self {
+            attributes: [GattAttribute::EMPTY; N],
+            len: 0,
+        }
+        .primary_service(Uuid::GAP_SERVICE)
+        .characteristic(
+            Uuid::DEVICE_NAME,
+            Properties::READ,
+            Value::Static(device_name.as_bytes()),
+        )
+        .characteristic(
+            Uuid::APPEARANCE,
+            Properties::READ,
+            Value::Static(&[0x00, 0x00]),
+        )
+        .primary_service(Uuid::GATT_SERVICE)
+    }
+
+    const fn push(mut self, attribute: GattAttribute) -> Self {
+        assert!(self.len < N, "the table is full, increase its size");
+        self.attributes[self.len] = attribute;
+        self.len += 1;
+        self
+    }
+
+    /// Start a primary service, the following characteristics belong to it
+    pub const fn primary_service(self, uuid: Uuid) -> Self {
+        self.push(GattAttribute {
+            uuid: Uuid::PRIMARY_SERVICE,
+            permissions: Permissions::READ,
+            value: AttributeValue::Service(uuid),
+        })
+    }
+
+    /// Add a characteristic declaration and its value to the service
+    ///
+    /// The characteristics with notifications or indications also get a
+    /// Client Characteristic Configuration descriptor, where each client subscribes.
+    pub const fn characteristic(self, uuid: Uuid, properties: Properties, value: Value) -> Self {
+        let permissions = properties.permissions();
+        if let Value::Static(_) = value {
+            assert!(
+                !permissions.contains(Permissions::WRITE),
+                "static values can not be written"
+            );
+        }
+
+        let table = self
+            .push(GattAttribute {
+                uuid: Uuid::CHARACTERISTIC,
+                permissions: Permissions::READ,
+                value: AttributeValue::Characteristic { properties, uuid },
+            })
+            .push(GattAttribute {
+                uuid,
+                permissions,
+                value: AttributeValue::Value(value),
+            });
+
+        if properties.contains(Properties::NOTIFY) || properties.contains(Properties::INDICATE) {
+            // The ATT server keeps the configuration of each connection
+            table.descriptor(
+                Uuid::CLIENT_CHARACTERISTIC_CONFIGURATION,
+                Permissions::READ.union(Permissions::WRITE),
+                Value::Static(&[0x00, 0x00]),
+            )
+        } else {
+            table
+        }
+    }
+
+    /// Add a descriptor to the last characteristic
+    pub const fn descriptor(self, uuid: Uuid, permissions: Permissions, value: Value) -> Self {
+        self.push(GattAttribute {
+            uuid,
+            permissions,
+            value: AttributeValue::Value(value),
+        })
+    }
+
+    /// Handle of the first characteristic value with the UUID
+    pub const fn value_handle(&self, uuid: Uuid) -> u16 {
+        let mut index = 0;
+        while index < self.len {
+            if let AttributeValue::Characteristic {
+                uuid: characteristic,
+                ..
+            } = self.attributes[index].value
+            {
+                if uuid_eq(characteristic, uuid) {
+                    return handle(index + 1);
+                }
+            }
+            index += 1;
+        }
+        panic!("characteristic not found")
+    }
+
+    /// Attributes of the table, the handle of each one is its position plus one
+    pub fn attributes(&self) -> &[GattAttribute] {
+        &self.attributes[..self.len]
+    }
+}
+
+const fn uuid_eq(a: Uuid, b: Uuid) -> bool {
+    match (a, b) {
+        (Uuid::Uuid16(a), Uuid::Uuid16(b)) => a == b,
+        (Uuid::Uuid128(a), Uuid::Uuid128(b)) => {
+            let mut i = 0;
+            while i < a.len() {
+                if a[i] != b[i] {
+                    return false;
+                }
+                i += 1;
+            }
+            true
+        }
+        _ => false,
+    }
+}
+
+/// Handle of the attribute at the index of the table
+const fn handle(index: usize) -> u16 {
+    index as u16 + 1
+}
+
+/// Application callbacks of the `Value::Dynamic` attributes
+pub trait GattHandler {
+    /// Copy the value starting at `offset` to `dest`, truncated to its length.
+    /// Returns the number of bytes copied.
+    fn read(&mut self, handle: u16, offset: usize, dest: &mut [u8]) -> Result<usize, AttError>;
+
+    /// Write the `value` starting at `offset`
+    fn write(&mut self, handle: u16, offset: usize, value: &[u8]) -> Result<(), AttError>;
+}
+
+/// GATT server, expose the attributes of a `GattTable` to the ATT server
+/// and forward the dynamic values to the `GattHandler`
+pub struct GattServer<H: GattHandler> {
+    attributes: &'static [GattAttribute],
+    handler: H,
+}
+
+impl<H: GattHandler> GattServer<H> {
+    pub fn new<const N: usize>(table: &'static GattTable<N>, handler: H) -> Self {
+        Self {
+            attributes: table.attributes(),
+            handler,
+        }
+    }
+
+    pub fn handler(&mut self) -> &mut H {
+        &mut self.handler
+    }
+}
+
+/// Copy the value starting at `offset`, truncated to the destination
+fn read_value(value: &[u8], offset: usize, dest: &mut [u8]) -> Result<usize, AttError> {
+    let value = value.get(offset..).ok_or(AttError::InvalidOffset)?;
+    let len = value.len().min(dest.len());
+    dest[..len].copy_from_slice(&value[..len]);
+    Ok(len)
+}
+
+impl<H: GattHandler> AttributeTable for GattServer<H> {
+    fn count(&self) -> usize {
+        self.attributes.len()
+    }
+
+    fn attribute(&self, index: usize) -> Attribute {
+        let attribute = &self.attributes[index];
+        Attribute {
+            handle: handle(index),
+            uuid: attribute.uuid,
+            permissions: attribute.permissions,
+        }
+    }
+
+    fn read(&mut self, index: usize, offset: usize, dest: &mut [u8]) -> Result<usize, AttError> {
+        let mut buffer = [0u8; 19];
+        let value = match self.attributes[index].value {
+            AttributeValue::Value(Value::Static(value)) => value,
+            AttributeValue::Value(Value::Dynamic) => {
+                return self.handler.read(handle(index), offset, dest)
+            }
+            AttributeValue::Service(uuid) => {
+                let len = uuid.bytes(&mut buffer);
+                &buffer[..len]
+            }
+            AttributeValue::Characteristic { properties, uuid } => {
+                buffer[0] = properties.bits;
+                buffer[1..3].copy_from_slice(&handle(index + 1).to_le_bytes());
+                let len = uuid.bytes(&mut buffer[3..]);
+                &buffer[..3 + len]
+            }
+        };
+
+        read_value(value, offset, dest)
+    }
+
+    fn write(&mut self, index: usize, offset: usize, value: &[u8]) -> Result<(), AttError> {
+        match self.attributes[index].value {
+            AttributeValue::Value(Value::Dynamic) => {
+                self.handler.write(handle(index), offset, value)
+            }
+            _ => Err(AttError::WriteNotPermitted),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::att::{AttServer, NotifyError};
+
+    use super::*;
+
+    const BATTERY_SERVICE: Uuid = Uuid::Uuid16(0x180F);
+    const BATTERY_LEVEL: Uuid = Uuid::Uuid16(0x2A19);
+    const LED: Uuid = Uuid::Uuid128([0x42; 16]);
+
+    static GATT: GattTable<11> = GattTable::new("jewel")
+        .primary_service(BATTERY_SERVICE)
+        .characteristic(BATTERY_LEVEL, Properties::READ, Value::Dynamic)
+        .characteristic(
+            LED,
+            Properties::READ.union(Properties::WRITE),
+            Value::Dynamic,
+        );
+
+    const BATTERY_LEVEL_HANDLE: u16 = GATT.value_handle(BATTERY_LEVEL);
+    const LED_HANDLE: u16 = GATT.value_handle(LED);
+
+    struct Handler {
+        battery_level: u8,
+        led: bool,
+    }
+
+    impl GattHandler for Handler {
+        fn read(&mut self, handle: u16, offset: usize, dest: &mut [u8]) -> Result<usize, AttError> {
+            match handle {
+                BATTERY_LEVEL_HANDLE => read_value(&[self.battery_level], offset, dest),
+                LED_HANDLE => read_value(&[self.led as u8], offset, dest),
+                _ => Err(AttError::UnlikelyError),
+            }
+        }
+
+        fn write(&mut self, handle: u16, _offset: usize, value: &[u8]) -> Result<(), AttError> {
+            match (handle, value) {
+                (LED_HANDLE, [led]) => self.led = *led != 0,
+                (LED_HANDLE, _) => return Err(AttError::InvalidAttributeValueLength),
+                _ => return Err(AttError::UnlikelyError),
+            }
+            Ok(())
+        }
+    }
+
+    fn request(server: &mut GattServer<Handler>, request: &[u8]) -> [u8; 23] {
+        let mut response = [0u8; 23];
+        AttServer::new()
+            .handle(server, request, &mut response)
+            .unwrap();
+        response
+    }
+
+    #[test]
+    fn deterministic_handles() {
+        assert_eq!(BATTERY_LEVEL_HANDLE, 0x0009);
+        assert_eq!(LED_HANDLE, 0x000B);
+        assert_eq!(GATT.value_handle(Uuid::DEVICE_NAME), 0x0003);
+
+        let server = GattServer::new(
+            &GATT,
+            Handler {
+                battery_level: 0,
+                led: false,
+            },
+        );
+        assert_eq!(server.count(), 11);
+        assert_eq!(
+            server.attribute(5),
+            Attribute {
+                handle: 0x0006,
+                uuid: Uuid::PRIMARY_SERVICE,
+                permissions: Permissions::READ,
+            }
+        );
+    }
+
+    #[test]
+    fn discover() {
+        let mut server = GattServer::new(
+            &GATT,
+            Handler {
+                battery_level: 80,
+                led: false,
+            },
+        );
+
+        let response = request(&mut server, &[0x10, 0x01, 0x00, 0xFF, 0xFF, 0x00, 0x28]);
+        assert_eq!(
+            response[..20],
+            [
+                0x11, 6, 0x01, 0x00, 0x05, 0x00, 0x00, 0x18, 0x06, 0x00, 0x06, 0x00, 0x01, 0x18,
+                0x07, 0x00, 0xFF, 0xFF, 0x0F, 0x18
+            ]
+        );
+
+        let response = request(&mut server, &[0x08, 0x07, 0x00, 0xFF, 0xFF, 0x03, 0x28]);
+        assert_eq!(
+            response[..9],
+            [0x09, 7, 0x08, 0x00, 0x02, 0x09, 0x00, 0x19, 0x2A]
+        );
+
+        let response = request(&mut server, &[0x08, 0x0A, 0x00, 0xFF, 0xFF, 0x03, 0x28]);
+        assert_eq!(response[..7], [0x09, 21, 0x0A, 0x00, 0x0A, 0x0B, 0x00]);
+        assert_eq!(response[7..23], [0x42; 16]);
+    }
+
+    #[test]
+    fn read_and_write() {
+        let mut server = GattServer::new(
+            &GATT,
+            Handler {
+                battery_level: 80,
+                led: false,
+            },
+        );
+
+        let response = request(&mut server, &[0x0A, 0x03, 0x00]);
+        assert_eq!(response[..6], *b"\x0Bjewel");
+        let response = request(&mut server, &[0x0A, 0x09, 0x00]);
+        assert_eq!(response[..2], [0x0B, 80]);
+
+        let response = request(&mut server, &[0x12, 0x0B, 0x00, 0x01]);
+        assert_eq!(response[0], 0x13);
+        assert!(server.handler().led);
+
+        let response = request(&mut server, &[0x12, 0x09, 0x00, 0x01]);
+        assert_eq!(response[..5], [0x01, 0x12, 0x09, 0x00, 0x03]);
+        let response = request(&mut server, &[0x12, 0x0B, 0x00, 0x01, 0x02]);
+        assert_eq!(response[..5], [0x01, 0x12, 0x0B, 0x00, 0x0D]);
+    }
+
+    #[test]
+    fn subscriptions() {
+        const TEMPERATURE: Uuid = Uuid::Uuid16(0x2A6E);
+        const ALERT: Uuid = Uuid::Uuid16(0x2A06);
+        static SENSOR: GattTable<13> = GattTable::new("jewel")
+            .primary_service(Uuid::Uuid16(0x181A))
+            .characteristic(TEMPERATURE, Properties::NOTIFY, Value::Dynamic)
+            .characteristic(ALERT, Properties::INDICATE, Value::Dynamic);
+        let (temperature, alert) = (SENSOR.value_handle(TEMPERATURE), SENSOR.value_handle(ALERT));
+
+        let mut server = GattServer::new(
+            &SENSOR,
+            Handler {
+                battery_level: 80,
+                led: false,
+            },
+        );
+        let mut att = AttServer::new();
+        let mut request = |request: &[u8], att: &mut AttServer| {
+            let mut response = [0u8; 23];
+            att.handle(&mut server, request, &mut response);
+            response
+        };
+        let mut pdu = [0u8; 23];
+
+        // The descriptor follows the value
+        let response = request(&[0x04, 0x0A, 0x00, 0x0A, 0x00], &mut att);
+        assert_eq!(response[..6], [0x05, 0x01, 0x0A, 0x00, 0x02, 0x29]);
+
+        assert_eq!(
+            att.notification::<()>(temperature, &[20], &mut pdu),
+            Err(NotifyError::NotSubscribed)
+        );
+        let response = request(&[0x12, 0x0A, 0x00, 0x01, 0x00], &mut att);
+        assert_eq!(response[0], 0x13);
+        let response = request(&[0x0A, 0x0A, 0x00], &mut att);
+        assert_eq!(response[..3], [0x0B, 0x01, 0x00]);
+
+        let len = att
+            .notification::<()>(temperature, &[20], &mut pdu)
+            .unwrap();
+        assert_eq!(pdu[..len], [0x1B, 0x09, 0x00, 20]);
+        // The value is truncated to the ATT_MTU
+        let len = att
+            .notification::<()>(temperature, &[0; 30], &mut pdu)
+            .unwrap();
+        assert_eq!(len, 23);
+
+        // Indications are not supported by the characteristic
+        let response = request(&[0x12, 0x0A, 0x00, 0x02, 0x00], &mut att);
+        assert_eq!(response[..5], [0x01, 0x12, 0x0A, 0x00, 0xFD]);
+
+        request(&[0x12, 0x0D, 0x00, 0x02, 0x00], &mut att);
+        att.indication::<()>(alert, &[1], &mut pdu).unwrap();
+        assert!(att.indication_pending());
+        assert_eq!(
+            att.indication::<()>(alert, &[1], &mut pdu),
+            Err(NotifyError::Timeout)
+        );
+        request(&[0x1E], &mut att);
+        assert!(!att.indication_pending());
+
+        request(&[0x12, 0x0A, 0x00, 0x00, 0x00], &mut att);
+        assert_eq!(
+            att.notification::<()>(temperature, &[20], &mut pdu),
+            Err(NotifyError::NotSubscribed)
+        );
+    }
+}
@@ -0,0 +1,487 @@
+//! LE Credit Based Flow Control mode
+//!
+//! Connection-oriented channels are opened by the central on a SPSM registered
+//! by the application. Each SDU is segmented in K-frames of at most MPS bytes,
+//! the first one starting with the SDU length. Every K-frame costs a credit,
+//! the receiver gives credits back with L2CAP_FLOW_CONTROL_CREDIT_IND.
+//!
+//!   ┌───────────┬────────────┬──────────────────┬─────────────────────┐
+//!   │ Length    │ Channel ID │ L2CAP SDU Length │ Information payload │
+//!   │ (2 bytes) │ (2 bytes)  │ (0 or 2 bytes)   │                     │
+//!   └───────────┴────────────┴──────────────────┴─────────────────────┘
+//!
+//! Ref: Core 3.A.3.4 and Core 3.A.10.1
+
+use defmt::Format;
+
+use crate::ll::ConnectionError;
+
+use super::{BasicFrame, CreditConnectionReq, CreditConnectionResult, CreditConnectionRsp};
+
+/// Number of channels open at the same time
+pub const MAX_CHANNELS: usize = 2;
+
+/// Largest SDU received on a channel
+pub const MAX_SDU_LENGTH: usize = 512;
+
+/// Largest K-frame payload, it fits in the L2CAP reassembly buffer
+pub const MAX_MPS: u16 = (super::MAX_FRAME_LENGTH - BasicFrame::HEADER_LENGTH) as u16;
+
+/// First dynamic CID, the channel slots are allocated from it
+const FIRST_DYNAMIC_CID: u16 = 0x0040;
+
+/// SPSM accepted by the application with the parameters of its channels
+///
+/// ```
+/// use jewel::l2cap::Psm;
+///
+/// static PSMS: [Psm; 1] = [Psm::new(0x0080)];
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Format)]
+pub struct Psm {
+    /// Simplified Protocol/Service Multiplexer, 0x0080 to 0x00FF for dynamic services
+    pub spsm: u16,
+
+    /// Largest SDU received
+    pub mtu: u16,
+
+    /// Largest K-frame payload received
+    pub mps: u16,
+
+    /// Credits given to the peer when the channel opens and after each SDU read
+    pub initial_credits: u16,
+}
+
+impl Psm {
+    /// Accept channels with the largest SDU and enough credits for it
+    pub const fn new(spsm: u16) -> Self {
+        let mtu = MAX_SDU_LENGTH as u16;
+        let mps = MAX_MPS;
+        Self {
+            spsm,
+            mtu,
+            mps,
+            initial_credits: (mtu + 2).div_ceil(mps),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Format)]
+pub enum ChannelError<E> {
+    Connection(ConnectionError<E>),
+
+    /// The channel is not open
+    Closed,
+
+    /// The SDU is longer than the MTU of the peer
+    SduTooLong,
+}
+
+impl<E> From<ConnectionError<E>> for ChannelError<E> {
+    fn from(error: ConnectionError<E>) -> Self {
+        ChannelError::Connection(error)
+    }
+}
+
+/// The peer broke the rules of the channel, it shall be disconnected
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Format)]
+pub(crate) struct ChannelViolation;
+
+/// State of a connection-oriented channel
+pub(crate) struct CreditChannel {
+    spsm: u16,
+    pub(crate) local_cid: u16,
+    pub(crate) peer_cid: u16,
+
+    /// Parameters of the receiver
+    local: Psm,
+    peer_mtu: u16,
+    peer_mps: u16,
+
+    /// K-frames this device can send
+    peer_credits: u16,
+
+    /// K-frames the peer can send
+    local_credits: u16,
+
+    /// SDU being received
+    sdu: [u8; MAX_SDU_LENGTH],
+    received: usize,
+    sdu_length: Option<usize>,
+
+    /// A complete SDU waits to be read
+    ready: bool,
+}
+
+impl CreditChannel {
+    fn new(local_cid: u16, local: Psm, request: &CreditConnectionReq) -> Self {
+        Self {
+            spsm: request.spsm,
+            local_cid,
+            peer_cid: request.source_cid,
+            local,
+            peer_mtu: request.mtu,
+            peer_mps: request.mps,
+            peer_credits: request.initial_credits,
+            local_credits: local.initial_credits,
+            sdu: [0; MAX_SDU_LENGTH],
+            received: 0,
+            sdu_length: None,
+            ready: false,
+        }
+    }
+
+    pub(crate) fn spsm(&self) -> u16 {
+        self.spsm
+    }
+
+    /// Handle a K-frame, returns if a SDU is complete
+    fn on_kframe(&mut self, payload: &[u8]) -> Result<bool, ChannelViolation> {
+        if self.local_credits == 0 || payload.len() > self.local.mps as usize {
+            return Err(ChannelViolation);
+        }
+        self.local_credits -= 1;
+
+        // The peer shall wait for the SDU to be read, as no credits are given back
+        if self.ready {
+            return Err(ChannelViolation);
+        }
+
+        let data = match self.sdu_length {
+            Some(_) => payload,
+            None => {
+                let [low, high, data @ ..] = payload else {
+                    return Err(ChannelViolation);
+                };
+                let length = u16::from_le_bytes([*low, *high]) as usize;
+                if length > self.local.mtu as usize {
+                    return Err(ChannelViolation);
+                }
+                self.sdu_length = Some(length);
+                data
+            }
+        };
+
+        let end = self.received + data.len();
+        let length = self.sdu_length.unwrap_or(0);
+        if end > length {
+            return Err(ChannelViolation);
+        }
+        self.sdu[self.received..end].copy_from_slice(data);
+        self.received = end;
+
+        self.ready = end == length;
+        Ok(self.ready)
+    }
+
+    /// Copy the complete SDU to the buffer, returns its length and the credits to give back
+    fn read(&mut self, buffer: &mut [u8]) -> Option<(usize, u16)> {
+        if !self.ready {
+            return None;
+        }
+
+        let len = self.received;
+        buffer[..len].copy_from_slice(&self.sdu[..len]);
+
+        self.ready = false;
+        self.received = 0;
+        self.sdu_length = None;
+
+        let credits = self.local.initial_credits - self.local_credits;
+        self.local_credits = self.local.initial_credits;
+        Some((len, credits))
+    }
+
+    /// Add credits given by the peer, the total shall not exceed 65535
+    fn add_credits(&mut self, credits: u16) -> Result<(), ChannelViolation> {
+        self.peer_credits = self
+            .peer_credits
+            .checked_add(credits)
+            .ok_or(ChannelViolation)?;
+        Ok(())
+    }
+
+    /// Largest K-frame payload sent
+    fn segment_size(&self) -> usize {
+        self.peer_mps.min(MAX_MPS) as usize
+    }
+
+    /// Write the K-frame payload of the SDU starting at `offset` to the buffer,
+    /// returns its length and the offset of the next one.
+    /// The first K-frame starts with the SDU length.
+    fn segment(&mut self, sdu: &[u8], offset: usize, buffer: &mut [u8]) -> Option<(usize, usize)> {
+        if self.peer_credits == 0 {
+            return None;
+        }
+        self.peer_credits -= 1;
+
+        let mut len = 0;
+        if offset == 0 {
+            buffer[..2].copy_from_slice(&(sdu.len() as u16).to_le_bytes());
+            len = 2;
+        }
+
+        let end = sdu.len().min(offset + self.segment_size() - len);
+        buffer[len..len + end - offset].copy_from_slice(&sdu[offset..end]);
+        Some((len + end - offset, end))
+    }
+}
+
+/// Connection-oriented channels of the connection
+pub(crate) struct Channels {
+    psms: &'static [Psm],
+    channels: [Option<CreditChannel>; MAX_CHANNELS],
+}
+
+impl Channels {
+    pub(crate) const fn new(psms: &'static [Psm]) -> Self {
+        Self {
+            psms,
+            channels: [const { None }; MAX_CHANNELS],
+        }
+    }
+
+    pub(crate) fn get(&self, local_cid: u16) -> Option<&CreditChannel> {
+        self.channels
+            .iter()
+            .flatten()
+            .find(|c| c.local_cid == local_cid)
+    }
+
+    fn get_mut(&mut self, local_cid: u16) -> Option<&mut CreditChannel> {
+        self.channels
+            .iter_mut()
+            .flatten()
+            .find(|c| c.local_cid == local_cid)
+    }
+
+    /// Accept or refuse the channel requested by the peer
+    ///
+    /// Ref: Core 3.A.4.22
+    pub(crate) fn connect(&mut self, request: &CreditConnectionReq) -> CreditConnectionRsp {
+        let Some(psm) = self.psms.iter().find(|psm| psm.spsm == request.spsm) else {
+            return CreditConnectionRsp::refuse(CreditConnectionResult::SpsmNotSupported);
+        };
+        if !(0x0040..=0x007F).contains(&request.source_cid) {
+            return CreditConnectionRsp::refuse(CreditConnectionResult::InvalidSourceCid);
+        }
+        if self
+            .channels
+            .iter()
+            .flatten()
+            .any(|c| c.peer_cid == request.source_cid)
+        {
+            return CreditConnectionRsp::refuse(CreditConnectionResult::SourceCidAlreadyAllocated);
+        }
+        let Some(index) = self.channels.iter().position(Option::is_none) else {
+            return CreditConnectionRsp::refuse(CreditConnectionResult::NoResourcesAvailable);
+        };
+
+        let local_cid = FIRST_DYNAMIC_CID + index as u16;
+        self.channels[index] = Some(CreditChannel::new(local_cid, *psm, request));
+
+        CreditConnectionRsp {
+            destination_cid: local_cid,
+            mtu: psm.mtu,
+            mps: psm.mps,
+            initial_credits: psm.initial_credits,
+            result: CreditConnectionResult::Successful as u16,
+        }
+    }
+
+    /// Close the channel, returns the CID of the peer if it was open
+    pub(crate) fn disconnect(&mut self, local_cid: u16) -> Option<u16> {
+        let slot = self
+            .channels
+            .iter_mut()
+            .find(|c| c.as_ref().is_some_and(|c| c.local_cid == local_cid))?;
+        slot.take().map(|c| c.peer_cid)
+    }
+
+    /// Credits received from the peer on its CID
+    pub(crate) fn add_credits(
+        &mut self,
+        peer_cid: u16,
+        credits: u16,
+    ) -> Result<(), (u16, ChannelViolation)> {
+        let Some(channel) = self
+            .channels
+            .iter_mut()
+            .flatten()
+            .find(|c| c.peer_cid == peer_cid)
+        else {
+            return Ok(());
+        };
+
+        channel
+            .add_credits(credits)
+            .map_err(|violation| (channel.local_cid, violation))
+    }
+
+    /// K-frame received on the local CID, returns if a SDU is complete
+    pub(crate) fn on_kframe(
+        &mut self,
+        local_cid: u16,
+        payload: &[u8],
+    ) -> Result<bool, ChannelViolation> {
+        match self.get_mut(local_cid) {
+            Some(channel) => channel.on_kframe(payload),
+            None => Ok(false),
+        }
+    }
+
+    /// Read the SDU received, returns its length and the credits to give back
+    pub(crate) fn read(&mut self, local_cid: u16, buffer: &mut [u8]) -> Option<(usize, u16)> {
+        self.get_mut(local_cid)?.read(buffer)
+    }
+
+    /// Check the SDU can be sent on the channel
+    pub(crate) fn check_write<E>(
+        &self,
+        local_cid: u16,
+        length: usize,
+    ) -> Result<(), ChannelError<E>> {
+        let channel = self.get(local_cid).ok_or(ChannelError::Closed)?;
+        if length > channel.peer_mtu as usize {
+            return Err(ChannelError::SduTooLong);
+        }
+        Ok(())
+    }
+
+    /// Next K-frame of the SDU, `None` when there are no credits
+    pub(crate) fn segment(
+        &mut self,
+        local_cid: u16,
+        sdu: &[u8],
+        offset: usize,
+        buffer: &mut [u8],
+    ) -> Option<(u16, usize, usize)> {
+        let channel = self.get_mut(local_cid)?;
+        let (len, next) = channel.segment(sdu, offset, buffer)?;
+        Some((channel.peer_cid, len, next))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    static PSMS: [Psm; 1] = [Psm {
+        spsm: 0x0080,
+        mtu: 100,
+        mps: 40,
+        initial_credits: 3,
+    }];
+
+    fn request(source_cid: u16) -> CreditConnectionReq {
+        CreditConnectionReq {
+            spsm: 0x0080,
+            source_cid,
+            mtu: 64,
+            mps: 23,
+            initial_credits: 2,
+        }
+    }
+
+    #[test]
+    fn connect() {
+        let mut channels = Channels::new(&PSMS);
+
+        let response = channels.connect(&request(0x0041));
+        assert_eq!(
+            response,
+            CreditConnectionRsp {
+                destination_cid: 0x0040,
+                mtu: 100,
+                mps: 40,
+                initial_credits: 3,
+                result: 0,
+            }
+        );
+
+        assert_eq!(
+            channels.connect(&request(0x0041)).result,
+            CreditConnectionResult::SourceCidAlreadyAllocated as u16
+        );
+        assert_eq!(
+            channels.connect(&request(0x0010)).result,
+            CreditConnectionResult::InvalidSourceCid as u16
+        );
+        assert_eq!(channels.connect(&request(0x0042)).destination_cid, 0x0041);
+        assert_eq!(
+            channels.connect(&request(0x0043)).result,
+            CreditConnectionResult::NoResourcesAvailable as u16
+        );
+
+        let mut unknown = request(0x0044);
+        unknown.spsm = 0x0081;
+        assert_eq!(
+            channels.connect(&unknown).result,
+            CreditConnectionResult::SpsmNotSupported as u16
+        );
+
+        assert_eq!(channels.disconnect(0x0040), Some(0x0041));
+        assert_eq!(channels.disconnect(0x0040), None);
+    }
+
+    #[test]
+    fn reassemble_sdu() {
+        let mut channels = Channels::new(&PSMS);
+        channels.connect(&request(0x0041));
+
+        let mut first = [0xAA; 40];
+        first[..2].copy_from_slice(&50u16.to_le_bytes());
+        assert_eq!(channels.on_kframe(0x0040, &first), Ok(false));
+        assert_eq!(channels.on_kframe(0x0040, &[0xBB; 12]), Ok(true));
+
+        let mut buffer = [0u8; MAX_SDU_LENGTH];
+        assert_eq!(channels.read(0x0040, &mut buffer), Some((50, 2)));
+        assert_eq!(buffer[..38], [0xAA; 38]);
+        assert_eq!(buffer[38..50], [0xBB; 12]);
+        assert_eq!(channels.read(0x0040, &mut buffer), None);
+    }
+
+    #[test]
+    fn kframe_violations() {
+        let mut channels = Channels::new(&PSMS);
+        channels.connect(&request(0x0041));
+
+        // Larger than the MPS
+        assert_eq!(channels.on_kframe(0x0040, &[0; 41]), Err(ChannelViolation));
+        // SDU larger than the MTU
+        assert_eq!(channels.on_kframe(0x0040, &[101, 0]), Err(ChannelViolation));
+        // Without credits, the 3 credits were used by the frames above
+        assert_eq!(channels.on_kframe(0x0040, &[3, 0, 1]), Ok(false));
+        assert_eq!(channels.on_kframe(0x0040, &[2]), Ok(false));
+        assert_eq!(channels.on_kframe(0x0040, &[3]), Err(ChannelViolation));
+    }
+
+    #[test]
+    fn segment_sdu() {
+        let mut channels = Channels::new(&PSMS);
+        channels.connect(&request(0x0041));
+
+        let sdu: [u8; 30] = core::array::from_fn(|i| i as u8);
+        let mut buffer = [0u8; MAX_MPS as usize];
+
+        // MPS of the peer is 23, the first K-frame has the SDU length
+        let (cid, len, offset) = channels.segment(0x0040, &sdu, 0, &mut buffer).unwrap();
+        assert_eq!((cid, len, offset), (0x0041, 23, 21));
+        assert_eq!(buffer[..3], [30, 0, 0]);
+
+        let (_, len, offset) = channels.segment(0x0040, &sdu, offset, &mut buffer).unwrap();
+        assert_eq!((len, offset), (9, 30));
+        assert_eq!(buffer[..len], sdu[21..]);
+
+        // The 2 credits of the peer were used
+        assert_eq!(channels.segment(0x0040, &sdu, 0, &mut buffer), None);
+        channels.add_credits(0x0041, 1).unwrap();
+        assert!(channels.segment(0x0040, &sdu, 0, &mut buffer).is_some());
+
+        assert_eq!(channels.add_credits(0x0041, u16::MAX), Ok(()));
+        assert_eq!(
+            channels.add_credits(0x0041, 1),
+            Err((0x0040, ChannelViolation))
+        );
+    }
+}
@@ -0,0 +1,189 @@
+//! Fragmentation of L2CAP PDUs in LL Data PDUs
+//!
+//! The first fragment is sent with LLID start and the others with LLID continuation.
+//!
+//! Ref: Core 3.A.7.2
+
+use crate::ll::{DataPdu, Llid, DEFAULT_PAYLOAD_LENGTH};
+
+use super::BasicFrame;
+
+/// Largest L2CAP PDU received, header included
+pub const MAX_FRAME_LENGTH: usize = 256;
+
+/// Split a B-frame in LL Data PDUs without copying the payload to a contiguous buffer
+pub(crate) struct Fragmenter<'a> {
+    header: [u8; BasicFrame::HEADER_LENGTH],
+    payload: &'a [u8],
+
+    /// Bytes of the frame already fragmented, header included
+    offset: usize,
+}
+
+impl<'a> Fragmenter<'a> {
+    pub(crate) fn new(frame: &BasicFrame<'a>) -> Self {
+        Self {
+            header: frame.header(),
+            payload: frame.payload(),
+            offset: 0,
+        }
+    }
+
+    fn length(&self) -> usize {
+        self.header.len() + self.payload.len()
+    }
+
+    /// Next fragment, written to the buffer
+    pub(crate) fn next<'b>(
+        &mut self,
+        buffer: &'b mut [u8; DEFAULT_PAYLOAD_LENGTH],
+    ) -> Option<DataPdu<'b>> {
+        if self.offset >= self.length() {
+            return None;
+        }
+
+        let llid = if self.offset == 0 {
+            Llid::Start
+        } else {
+            Llid::Continuation
+        };
+
+        let end = self.length().min(self.offset + DEFAULT_PAYLOAD_LENGTH);
+        for (i, byte) in buffer[..end - self.offset].iter_mut().enumerate() {
+            let index = self.offset + i;
+            *byte = match index.checked_sub(self.header.len()) {
+                Some(index) => self.payload[index],
+                None => self.header[index],
+            };
+        }
+
+        let len = end - self.offset;
+        self.offset = end;
+        Some(DataPdu::new(llid, &buffer[..len]))
+    }
+}
+
+/// Rebuild a L2CAP PDU from the received LL Data PDUs
+pub(crate) struct Reassembler {
+    buffer: [u8; MAX_FRAME_LENGTH],
+    len: usize,
+
+    /// Length of the PDU being received, known after the first fragment
+    expected: Option<usize>,
+}
+
+impl Reassembler {
+    pub(crate) const fn new() -> Self {
+        Self {
+            buffer: [0; MAX_FRAME_LENGTH],
+            len: 0,
+            expected: None,
+        }
+    }
+
+    /// Add the fragment, returns the length of the PDU when it is complete.
+    /// Fragments out of order or too long for the buffer drop the PDU.
+    pub(crate) fn push(&mut self, fragment: &DataPdu) -> Option<usize> {
+        let payload = fragment.payload();
+
+        match fragment.header().llid {
+            Llid::Start => {
+                self.len = 0;
+                self.expected = None;
+            }
+            // Continuation without a start
+            _ if self.len == 0 => return None,
+            _ => {}
+        }
+
+        let end = self.len + payload.len();
+        if end > MAX_FRAME_LENGTH {
+            self.len = 0;
+            return None;
+        }
+        self.buffer[self.len..end].copy_from_slice(payload);
+        self.len = end;
+
+        if self.expected.is_none() && self.len >= BasicFrame::HEADER_LENGTH {
+            let length = u16::from_le_bytes([self.buffer[0], self.buffer[1]]) as usize;
+            self.expected = Some(BasicFrame::HEADER_LENGTH + length);
+        }
+
+        match self.expected {
+            Some(expected) if self.len >= expected => {
+                self.len = 0;
+                self.expected = None;
+                Some(expected)
+            }
+            // The PDU does not fit in the buffer
+            Some(expected) if expected > MAX_FRAME_LENGTH => {
+                self.len = 0;
+                None
+            }
+            _ => None,
+        }
+    }
+
+    /// Last complete PDU
+    pub(crate) fn frame(&self, len: usize) -> &[u8] {
+        &self.buffer[..len]
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::l2cap::ChannelId;
+
+    #[test]
+    fn fragment_and_reassemble() {
+        let payload: [u8; 60] = core::array::from_fn(|i| i as u8);
+        let frame = BasicFrame::new(ChannelId::Att, &payload);
+
+        let mut fragmenter = Fragmenter::new(&frame);
+        let mut reassembler = Reassembler::new();
+        let mut buffer = [0u8; DEFAULT_PAYLOAD_LENGTH];
+
+        let mut fragments = 0;
+        let mut complete = None;
+        while let Some(fragment) = fragmenter.next(&mut buffer) {
+            let expected_llid = if fragments == 0 {
+                Llid::Start
+            } else {
+                Llid::Continuation
+            };
+            assert_eq!(fragment.header().llid, expected_llid);
+
+            complete = reassembler.push(&fragment);
+            fragments += 1;
+        }
+
+        // 64 bytes in fragments of 27 bytes
+        assert_eq!(fragments, 3);
+        let len = complete.unwrap();
+        assert_eq!(BasicFrame::parse(reassembler.frame(len)), Ok(frame));
+    }
+
+    #[test]
+    fn continuation_without_start() {
+        let mut reassembler = Reassembler::new();
+        let fragment = DataPdu::new(Llid::Continuation, &[0x01, 0x00, 0x04, 0x00, 0xFF]);
+        assert_eq!(reassembler.push(&fragment), None);
+    }
+
+    #[test]
+    fn restart_on_new_start() {
+        let mut reassembler = Reassembler::new();
+
+        // Only the first fragment of a 10 bytes PDU
+        reassembler.push(&DataPdu::new(Llid::Start, &[0x06, 0x00, 0x04, 0x00, 0x01]));
+
+        let len = reassembler
+            .push(&DataPdu::new(Llid::Start, &[0x01, 0x00, 0x06, 0x00, 0x0A]))
+            .unwrap();
+        assert_eq!(
+            BasicFrame::parse(reassembler.frame(len)),
+            Ok(BasicFrame::new(ChannelId::Smp, &[0x0A]))
+        );
+    }
+}
@@ -0,0 +1,255 @@
+//! Logical Link Control and Adaptation Protocol
+//!
+//! Multiplex the channels of the upper layers on the LL Data PDUs of a connection.
+//! The fixed channels use the Basic L2CAP mode and the dynamic channels the
+//! LE Credit Based Flow Control mode, each L2CAP PDU is sent in one or more LL Data PDUs.
+//!
+//! Ref: [Core 3.A](https://www.bluetooth.com/wp-content/uploads/Files/Specification/HTML/Core-54/out/en/host/logical-link-control-and-adaptation-protocol-specification.html)
+
+mod coc;
+mod fragment;
+mod pdu;
+mod signaling;
+
+pub use coc::*;
+pub use fragment::*;
+pub use pdu::*;
+pub use signaling::*;
+
+use defmt::Format;
+
+use crate::{
+    ll::{
+        Connection, ConnectionError, ConnectionParameters, LinkLayer, SendError,
+        DEFAULT_PAYLOAD_LENGTH,
+    },
+    phy::Radio,
+};
+
+/// Event of the L2CAP layer for the application
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Format)]
+pub enum L2capEvent {
+    /// The central answered the connection parameter update request
+    ConnectionParametersResponse { accepted: bool },
+
+    /// The peripheral requested the connection parameters, the central accepted them
+    /// and applies them with a connection update
+    ConnectionParametersRequest { parameters: ConnectionParameters },
+
+    /// The central opened a connection-oriented channel on a registered SPSM
+    ChannelConnected { cid: u16, spsm: u16 },
+
+    /// The connection-oriented channel was closed
+    ChannelDisconnected { cid: u16 },
+
+    /// A SDU is ready to be read on the connection-oriented channel
+    ChannelReceived { cid: u16 },
+}
+
+/// L2CAP layer of a connection
+pub struct L2cap<'r, R: Radio> {
+    ll: LinkLayer<'r, R, Connection>,
+    rx: Reassembler,
+    signaling: Signaling,
+    channels: Channels,
+}
+
+impl<'r, R: Radio> L2cap<'r, R> {
+    /// Create the L2CAP layer, accepting connection-oriented channels on the `psms`
+    pub(crate) fn new(ll: LinkLayer<'r, R, Connection>, psms: &'static [Psm]) -> Self {
+        Self {
+            signaling: Signaling::new(ll.role()),
+            ll,
+            rx: Reassembler::new(),
+            channels: Channels::new(psms),
+        }
+    }
+
+    pub(crate) fn ll(&self) -> &LinkLayer<'r, R, Connection> {
+        &self.ll
+    }
+
+    pub(crate) fn ll_mut(&mut self) -> &mut LinkLayer<'r, R, Connection> {
+        &mut self.ll
+    }
+
+    /// Run the next connection event
+    pub async fn connection_event(&mut self) -> Result<(), ConnectionError<R::Error>> {
+        self.ll.connection_event().await
+    }
+
+    /// Queue the PDU to be sent, running the connection events
+    /// while the link layer queue is full.
+    pub async fn send(&mut self, frame: &BasicFrame<'_>) -> Result<(), ConnectionError<R::Error>> {
+        let mut fragmenter = Fragmenter::new(frame);
+        let mut buffer = [0u8; DEFAULT_PAYLOAD_LENGTH];
+
+        while let Some(fragment) = fragmenter.next(&mut buffer) {
+            // The fragments are never longer than the default payload length
+            while let Err(SendError::QueueFull) = self.ll.send(&fragment) {
+                self.ll.connection_event().await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Reassemble the LL Data PDUs already received, without running connection events.
+    /// Returns the first complete PDU, PDUs on unknown channels are dropped.
+    pub fn poll<'b>(&mut self, buffer: &'b mut [u8; MAX_FRAME_LENGTH]) -> Option<BasicFrame<'b>> {
+        let len = self.reassemble(buffer)?;
+        BasicFrame::parse(&buffer[..len]).ok()
+    }
+
+    /// Run the connection events until a complete PDU is received,
+    /// PDUs on unknown channels are dropped.
+    pub async fn receive<'b>(
+        &mut self,
+        buffer: &'b mut [u8; MAX_FRAME_LENGTH],
+    ) -> Result<BasicFrame<'b>, ConnectionError<R::Error>> {
+        loop {
+            if let Some(len) = self.reassemble(buffer) {
+                // The frame was already validated
+                return Ok(BasicFrame::parse(&buffer[..len]).unwrap());
+            }
+
+            self.ll.connection_event().await?;
+        }
+    }
+
+    /// Copy the first complete and valid PDU to the buffer, returning its length
+    fn reassemble(&mut self, buffer: &mut [u8; MAX_FRAME_LENGTH]) -> Option<usize> {
+        let mut fragment = [0u8; 2 + DEFAULT_PAYLOAD_LENGTH];
+
+        while let Some(pdu) = self.ll.receive(&mut fragment) {
+            let Some(len) = self.rx.push(&pdu) else {
+                continue;
+            };
+
+            if let Ok(frame) = BasicFrame::parse(self.rx.frame(len)) {
+                return Some(frame.bytes(buffer));
+            }
+        }
+
+        None
+    }
+
+    async fn send_signal(&mut self, signal: &Signal) -> Result<(), ConnectionError<R::Error>> {
+        let mut buffer = [0u8; SIGNALING_MTU];
+        let len = signal.bytes(&mut buffer);
+
+        self.send(&BasicFrame::new(ChannelId::LeSignaling, &buffer[..len]))
+            .await
+    }
+
+    /// Handle a PDU received on the LE signaling channel
+    pub async fn on_signaling(
+        &mut self,
+        payload: &[u8],
+    ) -> Result<Option<L2capEvent>, ConnectionError<R::Error>> {
+        let (mut response, mut event) = self.signaling.on_frame(payload, &mut self.channels);
+
+        // The parameters are rejected while another connection update is in progress
+        if let Some(L2capEvent::ConnectionParametersRequest { parameters }) = event {
+            if self.ll.update_connection(parameters).is_err() {
+                response = response.map(|signal| Signal {
+                    identifier: signal.identifier,
+                    command: SignalingCommand::ConnectionParameterUpdateRsp {
+                        result: PARAMETERS_REJECTED,
+                    },
+                });
+                event = None;
+            }
+        }
+
+        if let Some(response) = response {
+            self.send_signal(&response).await?;
+        }
+
+        Ok(event)
+    }
+
+    /// Request the central to update the connection parameters with the
+    /// L2CAP_CONNECTION_PARAMETER_UPDATE_REQ, the response is a `L2capEvent`.
+    pub async fn request_connection_parameters(
+        &mut self,
+        parameters: ConnectionParameters,
+    ) -> Result<(), ConnectionError<R::Error>> {
+        let request = self.signaling.connection_parameter_update(parameters);
+        self.send_signal(&request).await
+    }
+
+    /// SPSM of the open connection-oriented channel
+    pub fn channel_spsm(&self, cid: u16) -> Option<u16> {
+        self.channels.get(cid).map(|channel| channel.spsm())
+    }
+
+    /// Handle a K-frame received on a connection-oriented channel
+    pub async fn on_kframe(
+        &mut self,
+        cid: u16,
+        payload: &[u8],
+    ) -> Result<Option<L2capEvent>, ConnectionError<R::Error>> {
+        match self.channels.on_kframe(cid, payload) {
+            Ok(true) => Ok(Some(L2capEvent::ChannelReceived { cid })),
+            Ok(false) => Ok(None),
+            Err(ChannelViolation) => {
+                self.disconnect(cid).await?;
+                Ok(Some(L2capEvent::ChannelDisconnected { cid }))
+            }
+        }
+    }
+
+    /// Close the connection-oriented channel
+    pub async fn disconnect(&mut self, cid: u16) -> Result<(), ConnectionError<R::Error>> {
+        if let Some(peer_cid) = self.channels.disconnect(cid) {
+            let request = self.signaling.disconnection(cid, peer_cid);
+            self.send_signal(&request).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Copy the SDU received on the channel to the buffer, returning its length.
+    /// The credits used by the SDU are given back to the peer.
+    pub async fn read(
+        &mut self,
+        cid: u16,
+        buffer: &mut [u8],
+    ) -> Result<Option<usize>, ChannelError<R::Error>> {
+        if self.channels.get(cid).is_none() {
+            return Err(ChannelError::Closed);
+        }
+        let Some((len, credits)) = self.channels.read(cid, buffer) else {
+            return Ok(None);
+        };
+
+        if credits > 0 {
+            let indication = self.signaling.credits(cid, credits);
+            self.send_signal(&indication).await?;
+        }
+
+        Ok(Some(len))
+    }
+
+    /// Send the K-frame of the SDU starting at `offset`, returning the offset of the next one.
+    /// Returns `None` when the peer has no credits.
+    pub async fn write_segment(
+        &mut self,
+        cid: u16,
+        sdu: &[u8],
+        offset: usize,
+    ) -> Result<Option<usize>, ChannelError<R::Error>> {
+        self.channels.check_write(cid, sdu.len())?;
+
+        let mut buffer = [0u8; MAX_MPS as usize];
+        let Some((peer_cid, len, next)) = self.channels.segment(cid, sdu, offset, &mut buffer)
+        else {
+            return Ok(None);
+        };
+
+        let frame = BasicFrame::new(ChannelId::Dynamic(peer_cid), &buffer[..len]);
+        self.send(&frame).await?;
+        Ok(Some(next))
+    }
+}
@@ -0,0 +1,146 @@
+use defmt::Format;
+
+use crate::ll::ParseError;
+
+/// L2CAP channel identifier
+///
+/// The fixed channels are always open on a LE-U logical link,
+/// the dynamic channels are created with a connection request.
+///
+/// Ref: Core 3.A.2.1
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Format)]
+pub enum ChannelId {
+    /// Attribute protocol
+    Att,
+
+    /// LE L2CAP signaling channel
+    LeSignaling,
+
+    /// Security Manager protocol
+    Smp,
+
+    /// Dynamically allocated channel, from 0x0040 to 0x007F
+    Dynamic(u16),
+}
+
+impl ChannelId {
+    pub const ATT: u16 = 0x0004;
+    pub const LE_SIGNALING: u16 = 0x0005;
+    pub const SMP: u16 = 0x0006;
+
+    pub fn cid(&self) -> u16 {
+        match self {
+            Self::Att => Self::ATT,
+            Self::LeSignaling => Self::LE_SIGNALING,
+            Self::Smp => Self::SMP,
+            Self::Dynamic(cid) => *cid,
+        }
+    }
+}
+
+impl TryFrom<u16> for ChannelId {
+    type Error = ParseError;
+
+    fn try_from(value: u16) -> Result<Self, Self::Error> {
+        match value {
+            Self::ATT => Ok(Self::Att),
+            Self::LE_SIGNALING => Ok(Self::LeSignaling),
+            Self::SMP => Ok(Self::Smp),
+            0x0040..=0x007F => Ok(Self::Dynamic(value)),
+            _ => Err(ParseError::InvalidValue),
+        }
+    }
+}
+
+/// L2CAP PDU in Basic L2CAP mode, the B-frame
+///
+///   ┌───────────┬────────────┬───────────────────────┐
+///   │ Length    │ Channel ID │ Information payload   │
+///   │ (2 bytes) │ (2 bytes)  │ (0-65535 bytes)       │
+///   └───────────┴────────────┴───────────────────────┘
+///
+/// Ref: Core 3.A.3.1
+#[derive(Debug, Clone, PartialEq, Eq, Format)]
+pub struct BasicFrame<'a> {
+    channel: ChannelId,
+    payload: &'a [u8],
+}
+
+impl<'a> BasicFrame<'a> {
+    pub const HEADER_LENGTH: usize = 4;
+
+    pub fn new(channel: ChannelId, payload: &'a [u8]) -> Self {
+        assert!(payload.len() <= u16::MAX as usize);
+        Self { channel, payload }
+    }
+
+    pub fn channel(&self) -> ChannelId {
+        self.channel
+    }
+
+    pub fn payload(&self) -> &'a [u8] {
+        self.payload
+    }
+
+    pub fn header(&self) -> [u8; 4] {
+        let length = (self.payload.len() as u16).to_le_bytes();
+        let cid = self.channel.cid().to_le_bytes();
+        [length[0], length[1], cid[0], cid[1]]
+    }
+
+    pub fn bytes(&self, dest: &mut [u8]) -> usize {
+        dest[..Self::HEADER_LENGTH].copy_from_slice(&self.header());
+        let end = Self::HEADER_LENGTH + self.payload.len();
+        dest[Self::HEADER_LENGTH..end].copy_from_slice(self.payload);
+        end
+    }
+
+    pub fn parse(bytes: &'a [u8]) -> Result<Self, ParseError> {
+        if bytes.len() < Self::HEADER_LENGTH {
+            return Err(ParseError::InvalidLength);
+        }
+
+        let length = u16::from_le_bytes([bytes[0], bytes[1]]) as usize;
+        let channel = ChannelId::try_from(u16::from_le_bytes([bytes[2], bytes[3]]))?;
+        let payload = bytes
+            .get(Self::HEADER_LENGTH..Self::HEADER_LENGTH + length)
+            .ok_or(ParseError::InvalidLength)?;
+
+        Ok(Self { channel, payload })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn basic_frame_complementary() {
+        // ATT_EXCHANGE_MTU_REQ with a MTU of 247
+        let bytes = [0x03, 0x00, 0x04, 0x00, 0x02, 0xF7, 0x00];
+
+        let frame = BasicFrame::parse(&bytes).unwrap();
+        assert_eq!(frame, BasicFrame::new(ChannelId::Att, &[0x02, 0xF7, 0x00]));
+
+        let mut serialized = [0u8; 7];
+        assert_eq!(frame.bytes(&mut serialized), 7);
+        assert_eq!(serialized, bytes);
+    }
+
+    #[test]
+    fn invalid_channel() {
+        assert_eq!(
+            BasicFrame::parse(&[0x00, 0x00, 0x01, 0x00]),
+            Err(ParseError::InvalidValue)
+        );
+        assert_eq!(ChannelId::try_from(0x0040), Ok(ChannelId::Dynamic(0x0040)));
+    }
+
+    #[test]
+    fn truncated() {
+        assert_eq!(
+            BasicFrame::parse(&[0x03, 0x00, 0x04, 0x00, 0x02]),
+            Err(ParseError::InvalidLength)
+        );
+    }
+}
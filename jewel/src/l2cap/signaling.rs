@@ -0,0 +1,704 @@
+//! LE signaling channel
+//!
+//! Each C-frame on the LE signaling channel carries a single command.
+//! The identifier matches a response with its request.
+//!
+//!   ┌──────────┬────────────┬───────────┬─────────────────┐
+//!   │ Code     │ Identifier │ Length    │ Data            │
+//!   │ (1 byte) │ (1 byte)   │ (2 bytes) │ (Length bytes)  │
+//!   └──────────┴────────────┴───────────┴─────────────────┘
+//!
+//! Ref: Core 3.A.4
+
+use defmt::Format;
+
+use crate::ll::{ConnectionParameters, ParseError, Role};
+
+use super::{Channels, L2capEvent};
+
+/// Minimum MTU of the LE signaling channel, larger commands are rejected
+pub const SIGNALING_MTU: usize = 23;
+
+/// Result of a L2CAP_CONNECTION_PARAMETER_UPDATE_RSP
+///
+/// Ref: Core 3.A.4.21
+pub const PARAMETERS_ACCEPTED: u16 = 0x0000;
+pub const PARAMETERS_REJECTED: u16 = 0x0001;
+
+/// Reason of a L2CAP_COMMAND_REJECT_RSP with its data
+///
+/// Ref: Core 3.A.4.1
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Format)]
+pub enum RejectReason {
+    CommandNotUnderstood,
+    SignalingMtuExceeded { mtu: u16 },
+    InvalidCid { local_cid: u16, remote_cid: u16 },
+}
+
+impl RejectReason {
+    pub fn code(&self) -> u16 {
+        match self {
+            Self::CommandNotUnderstood => 0x0000,
+            Self::SignalingMtuExceeded { .. } => 0x0001,
+            Self::InvalidCid { .. } => 0x0002,
+        }
+    }
+}
+
+/// Result of a L2CAP_LE_CREDIT_BASED_CONNECTION_RSP
+///
+/// Ref: Core 3.A.4.23
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Format)]
+#[repr(u16)]
+pub enum CreditConnectionResult {
+    Successful = 0x0000,
+    SpsmNotSupported = 0x0002,
+    NoResourcesAvailable = 0x0004,
+    InvalidSourceCid = 0x0009,
+    SourceCidAlreadyAllocated = 0x000A,
+}
+
+/// L2CAP_LE_CREDIT_BASED_CONNECTION_REQ
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Format)]
+pub struct CreditConnectionReq {
+    /// Simplified Protocol/Service Multiplexer
+    pub spsm: u16,
+    pub source_cid: u16,
+
+    /// Largest SDU the sender can receive
+    pub mtu: u16,
+
+    /// Largest PDU payload the sender can receive
+    pub mps: u16,
+    pub initial_credits: u16,
+}
+
+/// L2CAP_LE_CREDIT_BASED_CONNECTION_RSP
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Format)]
+pub struct CreditConnectionRsp {
+    pub destination_cid: u16,
+    pub mtu: u16,
+    pub mps: u16,
+    pub initial_credits: u16,
+    pub result: u16,
+}
+
+impl CreditConnectionRsp {
+    /// Refuse the connection, the other fields shall be ignored by the receiver
+    pub fn refuse(result: CreditConnectionResult) -> Self {
+        Self {
+            destination_cid: 0,
+            mtu: 0,
+            mps: 0,
+            initial_credits: 0,
+            result: result as u16,
+        }
+    }
+}
+
+/// Command of the LE signaling channel
+///
+/// Ref: Core 3.A.4 Table 4.2
+#[derive(Debug, Clone, PartialEq, Eq, Format)]
+pub enum SignalingCommand {
+    CommandRejectRsp(RejectReason),
+    DisconnectionReq {
+        destination_cid: u16,
+        source_cid: u16,
+    },
+    DisconnectionRsp {
+        destination_cid: u16,
+        source_cid: u16,
+    },
+    ConnectionParameterUpdateReq(ConnectionParameters),
+    ConnectionParameterUpdateRsp {
+        /// 0x0000 accepted, 0x0001 rejected
+        result: u16,
+    },
+    LeCreditBasedConnectionReq(CreditConnectionReq),
+    LeCreditBasedConnectionRsp(CreditConnectionRsp),
+    FlowControlCreditInd {
+        /// Source CID of the sender
+        cid: u16,
+        credits: u16,
+    },
+}
+
+impl SignalingCommand {
+    pub fn code(&self) -> u8 {
+        match self {
+            Self::CommandRejectRsp(_) => 0x01,
+            Self::DisconnectionReq { .. } => 0x06,
+            Self::DisconnectionRsp { .. } => 0x07,
+            Self::ConnectionParameterUpdateReq(_) => 0x12,
+            Self::ConnectionParameterUpdateRsp { .. } => 0x13,
+            Self::LeCreditBasedConnectionReq(_) => 0x14,
+            Self::LeCreditBasedConnectionRsp(_) => 0x15,
+            Self::FlowControlCreditInd { .. } => 0x16,
+        }
+    }
+
+    fn data(&self, dest: &mut [u8]) -> usize {
+        let mut fields = FieldWriter { dest, len: 0 };
+        match self {
+            Self::CommandRejectRsp(reason) => {
+                fields.push(reason.code());
+                match reason {
+                    RejectReason::CommandNotUnderstood => {}
+                    RejectReason::SignalingMtuExceeded { mtu } => fields.push(*mtu),
+                    RejectReason::InvalidCid {
+                        local_cid,
+                        remote_cid,
+                    } => {
+                        fields.push(*local_cid);
+                        fields.push(*remote_cid);
+                    }
+                }
+            }
+            Self::DisconnectionReq {
+                destination_cid,
+                source_cid,
+            }
+            | Self::DisconnectionRsp {
+                destination_cid,
+                source_cid,
+            } => {
+                fields.push(*destination_cid);
+                fields.push(*source_cid);
+            }
+            Self::ConnectionParameterUpdateReq(parameters) => {
+                fields.len = parameters.bytes(fields.dest);
+            }
+            Self::ConnectionParameterUpdateRsp { result } => fields.push(*result),
+            Self::LeCreditBasedConnectionReq(request) => {
+                fields.push(request.spsm);
+                fields.push(request.source_cid);
+                fields.push(request.mtu);
+                fields.push(request.mps);
+                fields.push(request.initial_credits);
+            }
+            Self::LeCreditBasedConnectionRsp(response) => {
+                fields.push(response.destination_cid);
+                fields.push(response.mtu);
+                fields.push(response.mps);
+                fields.push(response.initial_credits);
+                fields.push(response.result);
+            }
+            Self::FlowControlCreditInd { cid, credits } => {
+                fields.push(*cid);
+                fields.push(*credits);
+            }
+        }
+        fields.len
+    }
+
+    fn parse(code: u8, data: &[u8]) -> Result<Self, ParseError> {
+        let field = |i: usize| -> Result<u16, ParseError> {
+            data.get(2 * i..2 * i + 2)
+                .map(|bytes| u16::from_le_bytes([bytes[0], bytes[1]]))
+                .ok_or(ParseError::InvalidLength)
+        };
+        let expect_length = |length: usize| {
+            if data.len() == length {
+                Ok(())
+            } else {
+                Err(ParseError::InvalidLength)
+            }
+        };
+
+        match code {
+            0x01 => {
+                let reason = match field(0)? {
+                    0x0000 => RejectReason::CommandNotUnderstood,
+                    0x0001 => RejectReason::SignalingMtuExceeded { mtu: field(1)? },
+                    0x0002 => RejectReason::InvalidCid {
+                        local_cid: field(1)?,
+                        remote_cid: field(2)?,
+                    },
+                    _ => return Err(ParseError::InvalidValue),
+                };
+                Ok(Self::CommandRejectRsp(reason))
+            }
+            0x06 => {
+                expect_length(4)?;
+                Ok(Self::DisconnectionReq {
+                    destination_cid: field(0)?,
+                    source_cid: field(1)?,
+                })
+            }
+            0x07 => {
+                expect_length(4)?;
+                Ok(Self::DisconnectionRsp {
+                    destination_cid: field(0)?,
+                    source_cid: field(1)?,
+                })
+            }
+            // The parameters are validated by the central, invalid ones are rejected
+            // with the result of the response
+            0x12 => {
+                expect_length(ConnectionParameters::LENGTH)?;
+                Ok(Self::ConnectionParameterUpdateReq(ConnectionParameters {
+                    interval_min: field(0)?,
+                    interval_max: field(1)?,
+                    latency: field(2)?,
+                    timeout: field(3)?,
+                }))
+            }
+            0x13 => {
+                expect_length(2)?;
+                Ok(Self::ConnectionParameterUpdateRsp { result: field(0)? })
+            }
+            0x14 => {
+                expect_length(10)?;
+                Ok(Self::LeCreditBasedConnectionReq(CreditConnectionReq {
+                    spsm: field(0)?,
+                    source_cid: field(1)?,
+                    mtu: field(2)?,
+                    mps: field(3)?,
+                    initial_credits: field(4)?,
+                }))
+            }
+            0x15 => {
+                expect_length(10)?;
+                Ok(Self::LeCreditBasedConnectionRsp(CreditConnectionRsp {
+                    destination_cid: field(0)?,
+                    mtu: field(1)?,
+                    mps: field(2)?,
+                    initial_credits: field(3)?,
+                    result: field(4)?,
+                }))
+            }
+            0x16 => {
+                expect_length(4)?;
+                Ok(Self::FlowControlCreditInd {
+                    cid: field(0)?,
+                    credits: field(1)?,
+                })
+            }
+            _ => Err(ParseError::InvalidType),
+        }
+    }
+}
+
+/// Write consecutive little endian fields
+struct FieldWriter<'a> {
+    dest: &'a mut [u8],
+    len: usize,
+}
+
+impl FieldWriter<'_> {
+    fn push(&mut self, value: u16) {
+        self.dest[self.len..self.len + 2].copy_from_slice(&value.to_le_bytes());
+        self.len += 2;
+    }
+}
+
+/// Signaling command with its identifier
+#[derive(Debug, Clone, PartialEq, Eq, Format)]
+pub struct Signal {
+    pub identifier: u8,
+    pub command: SignalingCommand,
+}
+
+impl Signal {
+    pub const HEADER_LENGTH: usize = 4;
+
+    pub fn bytes(&self, dest: &mut [u8]) -> usize {
+        let len = self.command.data(&mut dest[Self::HEADER_LENGTH..]);
+        dest[0] = self.command.code();
+        dest[1] = self.identifier;
+        dest[2..4].copy_from_slice(&(len as u16).to_le_bytes());
+        Self::HEADER_LENGTH + len
+    }
+
+    pub fn parse(bytes: &[u8]) -> Result<Self, ParseError> {
+        if bytes.len() < Self::HEADER_LENGTH {
+            return Err(ParseError::InvalidLength);
+        }
+
+        let length = u16::from_le_bytes([bytes[2], bytes[3]]) as usize;
+        let data = bytes
+            .get(Self::HEADER_LENGTH..Self::HEADER_LENGTH + length)
+            .ok_or(ParseError::InvalidLength)?;
+
+        Ok(Self {
+            identifier: bytes[1],
+            command: SignalingCommand::parse(bytes[0], data)?,
+        })
+    }
+}
+
+/// State of the LE signaling channel, the peripheral requests the connection
+/// parameters and the central answers the requests
+pub(crate) struct Signaling {
+    role: Role,
+
+    /// Identifier of the last request sent
+    identifier: u8,
+
+    /// Identifier of the connection parameter update request waiting for a response
+    pending_update: Option<u8>,
+}
+
+impl Signaling {
+    pub(crate) const fn new(role: Role) -> Self {
+        Self {
+            role,
+            identifier: 0,
+            pending_update: None,
+        }
+    }
+
+    /// Next identifier for a request, zero is not a valid identifier
+    fn next_identifier(&mut self) -> u8 {
+        self.identifier = self.identifier.checked_add(1).unwrap_or(1);
+        self.identifier
+    }
+
+    /// Close the channel, the peer answers with a disconnection response
+    ///
+    /// Ref: Core 3.A.4.6
+    pub(crate) fn disconnection(&mut self, local_cid: u16, peer_cid: u16) -> Signal {
+        Signal {
+            identifier: self.next_identifier(),
+            command: SignalingCommand::DisconnectionReq {
+                destination_cid: peer_cid,
+                source_cid: local_cid,
+            },
+        }
+    }
+
+    /// Give credits back to the peer
+    ///
+    /// Ref: Core 3.A.4.24
+    pub(crate) fn credits(&mut self, local_cid: u16, credits: u16) -> Signal {
+        Signal {
+            identifier: self.next_identifier(),
+            command: SignalingCommand::FlowControlCreditInd {
+                cid: local_cid,
+                credits,
+            },
+        }
+    }
+
+    /// Request the central to update the connection parameters
+    ///
+    /// Ref: Core 3.A.4.20
+    pub(crate) fn connection_parameter_update(
+        &mut self,
+        parameters: ConnectionParameters,
+    ) -> Signal {
+        let identifier = self.next_identifier();
+        self.pending_update = Some(identifier);
+
+        Signal {
+            identifier,
+            command: SignalingCommand::ConnectionParameterUpdateReq(parameters),
+        }
+    }
+
+    /// Handle a C-frame received, returns the response to send and the event to surface
+    pub(crate) fn on_frame(
+        &mut self,
+        payload: &[u8],
+        channels: &mut Channels,
+    ) -> (Option<Signal>, Option<L2capEvent>) {
+        let identifier = payload.get(1).copied().unwrap_or(0);
+        let respond = |command: SignalingCommand| Signal {
+            identifier,
+            command,
+        };
+        let reject = |reason: RejectReason| respond(SignalingCommand::CommandRejectRsp(reason));
+
+        // Commands with an identifier zero are silently discarded
+        if identifier == 0 {
+            return (None, None);
+        }
+
+        if payload.len() > SIGNALING_MTU {
+            let mtu = SIGNALING_MTU as u16;
+            return (
+                Some(reject(RejectReason::SignalingMtuExceeded { mtu })),
+                None,
+            );
+        }
+
+        let Ok(signal) = Signal::parse(payload) else {
+            return (Some(reject(RejectReason::CommandNotUnderstood)), None);
+        };
+
+        match signal.command {
+            SignalingCommand::ConnectionParameterUpdateRsp { result }
+                if self.pending_update == Some(identifier) =>
+            {
+                self.pending_update = None;
+                let event = L2capEvent::ConnectionParametersResponse {
+                    accepted: result == PARAMETERS_ACCEPTED,
+                };
+                (None, Some(event))
+            }
+            // A central that does not support the request rejects the command
+            SignalingCommand::CommandRejectRsp(_) if self.pending_update == Some(identifier) => {
+                self.pending_update = None;
+                let event = L2capEvent::ConnectionParametersResponse { accepted: false };
+                (None, Some(event))
+            }
+            SignalingCommand::LeCreditBasedConnectionReq(request) => {
+                let response = channels.connect(&request);
+                let event = (response.result == CreditConnectionResult::Successful as u16)
+                    .then_some(L2capEvent::ChannelConnected {
+                        cid: response.destination_cid,
+                        spsm: request.spsm,
+                    });
+                let signal = respond(SignalingCommand::LeCreditBasedConnectionRsp(response));
+                (Some(signal), event)
+            }
+            SignalingCommand::DisconnectionReq {
+                destination_cid,
+                source_cid,
+            } => match channels.get(destination_cid) {
+                Some(channel) if channel.peer_cid == source_cid => {
+                    channels.disconnect(destination_cid);
+                    let signal = respond(SignalingCommand::DisconnectionRsp {
+                        destination_cid,
+                        source_cid,
+                    });
+                    let event = L2capEvent::ChannelDisconnected {
+                        cid: destination_cid,
+                    };
+                    (Some(signal), Some(event))
+                }
+                _ => {
+                    let reason = RejectReason::InvalidCid {
+                        local_cid: destination_cid,
+                        remote_cid: source_cid,
+                    };
+                    (Some(reject(reason)), None)
+                }
+            },
+            SignalingCommand::FlowControlCreditInd { cid, credits } => {
+                match channels.add_credits(cid, credits) {
+                    Ok(()) => (None, None),
+                    // The credits overflowed, the channel is closed
+                    Err((local_cid, _)) => {
+                        channels.disconnect(local_cid);
+                        let signal = self.disconnection(local_cid, cid);
+                        let event = L2capEvent::ChannelDisconnected { cid: local_cid };
+                        (Some(signal), Some(event))
+                    }
+                }
+            }
+            // The central accepts valid parameters and applies them with a connection update
+            SignalingCommand::ConnectionParameterUpdateReq(parameters)
+                if self.role == Role::Central =>
+            {
+                if !parameters.is_valid() {
+                    let result = PARAMETERS_REJECTED;
+                    let signal = respond(SignalingCommand::ConnectionParameterUpdateRsp { result });
+                    return (Some(signal), None);
+                }
+
+                let result = PARAMETERS_ACCEPTED;
+                let signal = respond(SignalingCommand::ConnectionParameterUpdateRsp { result });
+                let event = L2capEvent::ConnectionParametersRequest { parameters };
+                (Some(signal), Some(event))
+            }
+            // The request is only sent by the peripheral
+            SignalingCommand::ConnectionParameterUpdateReq(_) => {
+                (Some(reject(RejectReason::CommandNotUnderstood)), None)
+            }
+            // Responses that do not match a request are ignored
+            _ => (None, None),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::l2cap::Psm;
+
+    static PSMS: [Psm; 1] = [Psm::new(0x0080)];
+
+    #[test]
+    fn connection_parameter_update() {
+        let mut signaling = Signaling::new(Role::Peripheral);
+        let mut channels = Channels::new(&[]);
+        let parameters = ConnectionParameters {
+            interval_min: 80,
+            interval_max: 100,
+            latency: 0,
+            timeout: 400,
+        };
+
+        let request = signaling.connection_parameter_update(parameters);
+        let mut bytes = [0u8; SIGNALING_MTU];
+        let len = request.bytes(&mut bytes);
+        assert_eq!(
+            bytes[..len],
+            [0x12, 0x01, 0x08, 0x00, 80, 0, 100, 0, 0, 0, 0x90, 0x01]
+        );
+        assert_eq!(Signal::parse(&bytes[..len]), Ok(request));
+
+        // Response of the central with the same identifier
+        let response = [0x13, 0x01, 0x02, 0x00, 0x00, 0x00];
+        let (signal, event) = signaling.on_frame(&response, &mut channels);
+        assert_eq!(signal, None);
+        assert_eq!(
+            event,
+            Some(L2capEvent::ConnectionParametersResponse { accepted: true })
+        );
+
+        // Only once
+        let (_, event) = signaling.on_frame(&response, &mut channels);
+        assert_eq!(event, None);
+
+        // A central that does not understand the request rejects it
+        let request = signaling.connection_parameter_update(parameters);
+        let reject = [0x01, request.identifier, 0x02, 0x00, 0x00, 0x00];
+        let (signal, event) = signaling.on_frame(&reject, &mut channels);
+        assert_eq!(signal, None);
+        assert_eq!(
+            event,
+            Some(L2capEvent::ConnectionParametersResponse { accepted: false })
+        );
+        let (_, event) = signaling.on_frame(&reject, &mut channels);
+        assert_eq!(event, None);
+    }
+
+    #[test]
+    fn answer_connection_parameter_update() {
+        let mut signaling = Signaling::new(Role::Central);
+        let mut channels = Channels::new(&[]);
+
+        // Interval 80 to 100, latency 0, timeout 4 s
+        let request = [0x12, 0x02, 0x08, 0x00, 80, 0, 100, 0, 0, 0, 0x90, 0x01];
+        let (response, event) = signaling.on_frame(&request, &mut channels);
+        let mut bytes = [0u8; SIGNALING_MTU];
+        let len = response.unwrap().bytes(&mut bytes);
+        assert_eq!(bytes[..len], [0x13, 0x02, 0x02, 0x00, 0x00, 0x00]);
+        assert_eq!(
+            event,
+            Some(L2capEvent::ConnectionParametersRequest {
+                parameters: ConnectionParameters {
+                    interval_min: 80,
+                    interval_max: 100,
+                    latency: 0,
+                    timeout: 400,
+                }
+            })
+        );
+
+        // The minimum interval is larger than the maximum
+        let request = [0x12, 0x03, 0x08, 0x00, 100, 0, 80, 0, 0, 0, 0x90, 0x01];
+        let (response, event) = signaling.on_frame(&request, &mut channels);
+        let len = response.unwrap().bytes(&mut bytes);
+        assert_eq!(bytes[..len], [0x13, 0x03, 0x02, 0x00, 0x01, 0x00]);
+        assert_eq!(event, None);
+
+        // The peripheral does not accept the request
+        let mut signaling = Signaling::new(Role::Peripheral);
+        let (response, event) = signaling.on_frame(&request, &mut channels);
+        assert_eq!(
+            response.unwrap().command,
+            SignalingCommand::CommandRejectRsp(RejectReason::CommandNotUnderstood)
+        );
+        assert_eq!(event, None);
+    }
+
+    #[test]
+    fn reject_unknown_command() {
+        let mut signaling = Signaling::new(Role::Peripheral);
+        let mut channels = Channels::new(&[]);
+
+        // L2CAP_ECHO_REQ is not supported on LE
+        let (response, _) = signaling.on_frame(&[0x08, 0x07, 0x00, 0x00], &mut channels);
+        let mut bytes = [0u8; SIGNALING_MTU];
+        let len = response.unwrap().bytes(&mut bytes);
+        assert_eq!(bytes[..len], [0x01, 0x07, 0x02, 0x00, 0x00, 0x00]);
+    }
+
+    #[test]
+    fn reject_mtu_exceeded() {
+        let mut signaling = Signaling::new(Role::Peripheral);
+        let mut channels = Channels::new(&[]);
+
+        let mut frame = [0u8; SIGNALING_MTU + 1];
+        frame[..4].copy_from_slice(&[0x14, 0x02, 20, 0x00]);
+        let (response, _) = signaling.on_frame(&frame, &mut channels);
+        assert_eq!(
+            response.unwrap().command,
+            SignalingCommand::CommandRejectRsp(RejectReason::SignalingMtuExceeded { mtu: 23 })
+        );
+    }
+
+    #[test]
+    fn refuse_credit_based_connection() {
+        let mut signaling = Signaling::new(Role::Peripheral);
+        let mut channels = Channels::new(&[]);
+
+        let request = [
+            0x14, 0x03, 0x0A, 0x00, 0x80, 0x00, 0x40, 0x00, 0x00, 0x02, 0xF7, 0x00, 0x0A, 0x00,
+        ];
+        let (response, event) = signaling.on_frame(&request, &mut channels);
+        let mut bytes = [0u8; SIGNALING_MTU];
+        let len = response.unwrap().bytes(&mut bytes);
+        assert_eq!(
+            bytes[..len],
+            [0x15, 0x03, 0x0A, 0x00, 0, 0, 0, 0, 0, 0, 0, 0, 0x02, 0x00]
+        );
+        assert_eq!(event, None);
+    }
+
+    #[test]
+    fn credit_based_channel() {
+        let mut signaling = Signaling::new(Role::Peripheral);
+        let mut channels = Channels::new(&PSMS);
+
+        // SPSM 0x0080, source CID 0x0040, MTU 512, MPS 247, 10 credits
+        let request = [
+            0x14, 0x03, 0x0A, 0x00, 0x80, 0x00, 0x40, 0x00, 0x00, 0x02, 0xF7, 0x00, 0x0A, 0x00,
+        ];
+        let (response, event) = signaling.on_frame(&request, &mut channels);
+        let mut bytes = [0u8; SIGNALING_MTU];
+        let len = response.unwrap().bytes(&mut bytes);
+        assert_eq!(
+            bytes[..len],
+            [0x15, 0x03, 0x0A, 0x00, 0x40, 0x00, 0x00, 0x02, 0xFC, 0x00, 0x03, 0x00, 0x00, 0x00]
+        );
+        assert_eq!(
+            event,
+            Some(L2capEvent::ChannelConnected {
+                cid: 0x0040,
+                spsm: 0x0080
+            })
+        );
+
+        // Disconnection with a wrong source CID
+        let (response, _) = signaling.on_frame(
+            &[0x06, 0x04, 0x04, 0x00, 0x40, 0x00, 0x41, 0x00],
+            &mut channels,
+        );
+        assert_eq!(
+            response.unwrap().command,
+            SignalingCommand::CommandRejectRsp(RejectReason::InvalidCid {
+                local_cid: 0x0040,
+                remote_cid: 0x0041
+            })
+        );
+
+        let (response, event) = signaling.on_frame(
+            &[0x06, 0x05, 0x04, 0x00, 0x40, 0x00, 0x40, 0x00],
+            &mut channels,
+        );
+        assert_eq!(
+            response.unwrap().command,
+            SignalingCommand::DisconnectionRsp {
+                destination_cid: 0x0040,
+                source_cid: 0x0040
+            }
+        );
+        assert_eq!(event, Some(L2capEvent::ChannelDisconnected { cid: 0x0040 }));
+        assert!(channels.get(0x0040).is_none());
+    }
+}
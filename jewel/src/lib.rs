@@ -1,9 +1,25 @@
 #![cfg_attr(not(test), no_std)]
 #![allow(dead_code)] // while in development
 
+pub mod att;
 pub mod gap;
+pub mod gatt;
+pub mod l2cap;
 pub(crate) mod ll;
 pub mod phy;
+pub mod smp;
+
+#[cfg(test)]
+mod testing;
 
 pub use gap::*;
-pub use ll::Address;
+pub use ll::{
+    Address, AddressAndData, AddressType, AdvDirectInd, AdvInd, AdvNonconnInd, AdvPdu, AdvReport,
+    AdvScanInd, AdvertisingError, AdvertisingFilterPolicy, AuxConnectReq, ChannelMapUpdate,
+    ConnectInd, ConnectionError, ConnectionParamReq, ConnectionParameters, ConnectionUpdate,
+    ControlPdu, CteInfo, DataChannelPdu, DataHeader, DataPdu, DirectedEvent, DisconnectReason,
+    DutyCycle, EncryptionEvent, EncryptionRequest, EncryptionResponse, FeatureSet,
+    FilterAcceptList, FilterAcceptListFull, InitiatorTarget, LLData, LlControl, Llid,
+    ProcedureError, RandomAddressKind, Role, ScanReq, ScanRsp, ScannerFilterPolicy, SendError,
+    TwoAddress, Version, FILTER_ACCEPT_LIST_SIZE, PRIVATE_ADDRESS_TIMEOUT,
+};
@@ -12,6 +12,9 @@
 ///
 /// Ref: https://www.bluetooth.com/wp-content/uploads/Files/Specification/HTML/Core-54/out/en/low-energy-controller/link-layer-specification.html#UUID-3815b05a-b69c-4e3c-5897-c8d3baa4fc30
 use defmt::Format;
+use rand::RngCore;
+
+use crate::smp::ah;
 
 #[derive(Debug, Clone, Eq, PartialEq, Format)]
 pub struct Address {
@@ -26,6 +29,19 @@ pub enum AddressType {
     Random,
 }
 
+/// Subtype of a random device address, given by its two most significant bits
+///
+/// Ref: Core 6.B.1.3.2
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Format)]
+pub enum RandomAddressKind {
+    /// Fixed for a power cycle, may be used as identity address
+    Static,
+    /// Changed periodically, can not be linked to the device
+    NonResolvable,
+    /// Changed periodically, resolved by the peers that know the device IRK
+    Resolvable,
+}
+
 impl Address {
     /// from a little endian address
     pub(crate) fn new_le(address_le: [u8; 6], address_type: AddressType) -> Self {
@@ -48,7 +64,7 @@ impl Address {
 
     /// Create a new public address from a big endian address
     /// ```
-    /// use jewel::ll::{Address, AddressType};
+    /// use jewel::{Address, AddressType};
     /// let address = Address::new_public(0xffe1e8d0dc27);
     /// assert_eq!(address.r#type, AddressType::Public);
     /// ```
@@ -58,7 +74,7 @@ impl Address {
 
     /// Create a new random address from a big endian address
     /// ```
-    /// use jewel::ll::{Address, AddressType};
+    /// use jewel::{Address, AddressType};
     /// let address = Address::new_random(0xffe1e8d0dc27);
     /// assert_eq!(address.r#type, AddressType::Random);
     /// ```
@@ -68,11 +84,169 @@ impl Address {
 
     /// Serizalie the address in little endian address for transmission
     /// ```
-    /// use jewel::ll::Address;
+    /// use jewel::Address;
     /// let address = Address::new_random(0xffe1e8d0dc27);
     /// assert_eq!(address.bytes(), [0x27, 0xdc, 0xd0, 0xe8, 0xe1, 0xff]);
     /// ```
     pub fn bytes(&self) -> [u8; 6] {
         self.address_le
     }
+
+    /// Subtype of a random address, `None` for public addresses and for the
+    /// reserved subtype
+    /// ```
+    /// use jewel::{Address, RandomAddressKind};
+    /// let address = Address::new_random(0xc1e1e8d0dc27);
+    /// assert_eq!(address.random_kind(), Some(RandomAddressKind::Static));
+    /// assert_eq!(Address::new_public(0xc1e1e8d0dc27).random_kind(), None);
+    /// ```
+    pub fn random_kind(&self) -> Option<RandomAddressKind> {
+        if self.r#type != AddressType::Random {
+            return None;
+        }
+
+        match self.address_le[5] >> 6 {
+            0b11 => Some(RandomAddressKind::Static),
+            0b00 => Some(RandomAddressKind::NonResolvable),
+            0b01 => Some(RandomAddressKind::Resolvable),
+            _ => None,
+        }
+    }
+
+    /// Check the subtype rules of a random address, the random part shall have
+    /// at least one bit 0 and one bit 1. Public addresses are always valid
+    ///
+    /// Ref: Core 6.B.1.3.2
+    /// ```
+    /// use jewel::Address;
+    /// assert!(Address::new_random(0xc1e1e8d0dc27).is_valid());
+    /// assert!(!Address::new_random(0xffffffffffff).is_valid());
+    /// assert!(!Address::new_random(0x800000000000).is_valid());
+    /// ```
+    pub fn is_valid(&self) -> bool {
+        let Some(kind) = self.random_kind() else {
+            return self.r#type == AddressType::Public;
+        };
+
+        // Resolvable addresses only constrain prand, the hash may be anything
+        let random = match kind {
+            RandomAddressKind::Resolvable => &self.address_le[3..],
+            _ => &self.address_le[..],
+        };
+        // Most significant byte first, without the two bits of the subtype
+        let mask = (1u64 << (random.len() * 8 - 2)) - 1;
+        let bits = random
+            .iter()
+            .rev()
+            .fold(0u64, |bits, b| (bits << 8) | *b as u64)
+            & mask;
+
+        bits != 0 && bits != mask
+    }
+
+    /// Create a new resolvable private address from the local identity resolving key
+    ///
+    /// Ref: Core 6.B.1.3.2.2
+    pub fn new_resolvable(irk: &[u8; 16], rng: &mut impl RngCore) -> Self {
+        loop {
+            let mut prand_le = [0u8; 3];
+            rng.fill_bytes(&mut prand_le);
+            prand_le[2] = (prand_le[2] & 0b0011_1111) | 0b0100_0000;
+
+            let address = Self::new_le(
+                [0, 0, 0, prand_le[0], prand_le[1], prand_le[2]],
+                AddressType::Random,
+            );
+            if !address.is_valid() {
+                continue;
+            }
+
+            let hash = ah(irk, [prand_le[2], prand_le[1], prand_le[0]]);
+            return Self::new_le(
+                [
+                    hash[2],
+                    hash[1],
+                    hash[0],
+                    prand_le[0],
+                    prand_le[1],
+                    prand_le[2],
+                ],
+                AddressType::Random,
+            );
+        }
+    }
+
+    /// Check if this resolvable private address was generated with the `irk`
+    ///
+    /// Ref: Core 6.B.1.3.2.3
+    /// ```
+    /// use jewel::Address;
+    /// let irk = [
+    ///     0xec, 0x02, 0x34, 0xa3, 0x57, 0xc8, 0xad, 0x05, 0x34, 0x10, 0x10, 0xa6, 0x0a, 0x39,
+    ///     0x7d, 0x9b,
+    /// ];
+    /// let address = Address::new_random(0x7081940dfbaa);
+    /// assert!(address.is_resolved_by(&irk));
+    /// assert!(!Address::new_random(0x7081940dfbab).is_resolved_by(&irk));
+    /// ```
+    pub fn is_resolved_by(&self, irk: &[u8; 16]) -> bool {
+        if self.random_kind() != Some(RandomAddressKind::Resolvable) {
+            return false;
+        }
+
+        let a = &self.address_le;
+        ah(irk, [a[5], a[4], a[3]]) == [a[2], a[1], a[0]]
+    }
+
+    /// Resolve this address against a list of identity resolving keys,
+    /// returning the index of the first key that generated it
+    pub fn resolve(&self, irks: &[[u8; 16]]) -> Option<usize> {
+        irks.iter().position(|irk| self.is_resolved_by(irk))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rand::{rngs::SmallRng, SeedableRng};
+
+    #[test]
+    fn resolvable_private_address() {
+        let irk = [0x11; 16];
+        let other = [0x22; 16];
+        let mut rng = SmallRng::seed_from_u64(7);
+
+        for _ in 0..32 {
+            let address = Address::new_resolvable(&irk, &mut rng);
+            assert_eq!(address.random_kind(), Some(RandomAddressKind::Resolvable));
+            assert!(address.is_valid());
+            assert!(address.is_resolved_by(&irk));
+            assert_eq!(address.resolve(&[other, irk]), Some(1));
+            assert_eq!(address.resolve(&[other]), None);
+        }
+
+        let first = Address::new_resolvable(&irk, &mut rng);
+        assert_ne!(first, Address::new_resolvable(&irk, &mut rng));
+    }
+
+    #[test]
+    fn random_part() {
+        // Static
+        assert!(!Address::new_random(0xC00000000000).is_valid());
+        assert!(!Address::new_random(0xFFFFFFFFFFFF).is_valid());
+        assert!(Address::new_random(0xC00000000001).is_valid());
+        assert!(Address::new_random(0xFFFFFFFFFF3F).is_valid());
+
+        // Non-resolvable
+        assert!(!Address::new_random(0x000000000000).is_valid());
+        assert!(!Address::new_random(0x3FFFFFFFFFFF).is_valid());
+        assert!(Address::new_random(0x000000000001).is_valid());
+        assert!(Address::new_random(0x3FFFFFFFFF3F).is_valid());
+
+        // Resolvable, only prand is checked
+        assert!(!Address::new_random(0x400000123456).is_valid());
+        assert!(!Address::new_random(0x7FFFFF123456).is_valid());
+        assert!(Address::new_random(0x400001000000).is_valid());
+        assert!(Address::new_random(0x7FFF3FFFFFFF).is_valid());
+    }
 }
@@ -5,6 +5,7 @@ use defmt::Format;
 
 use super::Header;
 
+pub use self::connect_ind::*;
 pub use self::direct_ind::*;
 pub use self::ind::*;
 pub use self::nonconn_ind::*;
@@ -13,10 +14,12 @@ pub use self::scan_req::*;
 pub use self::scan_rsp::*;
 
 #[derive(Debug, Clone, PartialEq, Eq, Copy, Format)]
+#[allow(clippy::enum_variant_names)]
 pub enum ParseError {
     InvalidType,
     InvalidLength,
     InvalidHeader,
+    InvalidValue,
 }
 
 #[repr(u8)]
@@ -28,6 +31,7 @@ pub enum AdvPdu<'a> {
     AdvScanInd(AdvScanInd<'a>) = AdvScanInd::PDU_TYPE,
     ScanReq(ScanReq) = ScanReq::PDU_TYPE,
     ScanRsp(ScanRsp<'a>) = ScanRsp::PDU_TYPE,
+    ConnectInd(ConnectInd) = ConnectInd::PDU_TYPE,
 }
 
 pub fn parse(bytes: &[u8]) -> Result<AdvPdu<'_>, ParseError> {
@@ -67,6 +71,12 @@ pub fn parse(bytes: &[u8]) -> Result<AdvPdu<'_>, ParseError> {
         .and_then(ScanRsp::parse)
     {
         Ok(packet.into())
+    } else if let Ok(packet) = bytes[0..36]
+        .try_into()
+        .map_err(|_| ParseError::InvalidType)
+        .and_then(ConnectInd::parse)
+    {
+        Ok(packet.into())
     } else {
         Err(ParseError::InvalidType)
     }
@@ -110,7 +120,7 @@ pub trait TwoAddress: Sized {
     }
 
     fn parse(bytes: &[u8]) -> Result<Self, ParseError> {
-        if bytes.len() < 8 {
+        if bytes.len() < 14 {
             return Err(ParseError::InvalidLength);
         }
 
@@ -124,7 +134,7 @@ pub trait TwoAddress: Sized {
             return Err(ParseError::InvalidLength);
         }
 
-        let (first, second) = pdu.split_at(6);
+        let (first, second) = (&pdu[0..6], &pdu[6..12]);
 
         let first = Address::new_le(
             first.try_into().unwrap(),
@@ -159,12 +169,20 @@ pub trait AddressAndData<'a>: Sized {
     fn data(&self) -> &[u8];
     fn from_address_and_data(address: Address, data: &'a [u8]) -> Self;
 
+    /// The ChSel bit, only used by connectable PDUs
+    fn ch_sel(&self) -> bool {
+        false
+    }
+
+    fn set_ch_sel(&mut self, _ch_sel: bool) {}
+
     fn bytes(&self, dest: &mut [u8]) -> usize {
         let payload_len = 6 + self.data().len();
         let total_len = 2 + payload_len;
         assert!(dest.len() >= total_len);
 
-        let header = Header::with_tx(self.address().r#type, Self::PDU_TYPE, payload_len as u8);
+        let mut header = Header::with_tx(self.address().r#type, Self::PDU_TYPE, payload_len as u8);
+        header.flags.ch_sel = self.ch_sel();
 
         dest[..2].copy_from_slice(&header.bytes()); // write header
         dest[2..8].copy_from_slice(&self.address().bytes()); // write address
@@ -184,7 +202,10 @@ pub trait AddressAndData<'a>: Sized {
         if header.flags.pdu_type != Self::PDU_TYPE {
             return Err(ParseError::InvalidType);
         }
-        if header.length as usize > 37 {
+        if header.length as usize > 37 || (header.length as usize) < 6 {
+            return Err(ParseError::InvalidLength);
+        }
+        if pdu.len() < header.length as usize {
             return Err(ParseError::InvalidLength);
         }
 
@@ -195,7 +216,9 @@ pub trait AddressAndData<'a>: Sized {
 
         let data = &pdu[6..(header.length as usize)];
 
-        Ok(Self::from_address_and_data(address, data))
+        let mut pdu = Self::from_address_and_data(address, data);
+        pdu.set_ch_sel(header.flags.ch_sel);
+        Ok(pdu)
     }
 }
 
@@ -210,6 +233,9 @@ mod ind {
         // It contains Advertising Data from the advertiser’s Host.
         // It can be empty.
         adv_data: &'a [u8],
+
+        // The advertiser supports the Channel Selection Algorithm #2
+        ch_sel: bool,
     }
 
     impl<'a> AdvInd<'a> {
@@ -217,6 +243,7 @@ mod ind {
             Self {
                 adv_address,
                 adv_data,
+                ch_sel: false,
             }
         }
     }
@@ -236,8 +263,17 @@ mod ind {
             Self {
                 adv_address: address,
                 adv_data: data,
+                ch_sel: false,
             }
         }
+
+        fn ch_sel(&self) -> bool {
+            self.ch_sel
+        }
+
+        fn set_ch_sel(&mut self, ch_sel: bool) {
+            self.ch_sel = ch_sel;
+        }
     }
 
     impl<'a> From<AdvInd<'a>> for AdvPdu<'a> {
@@ -256,6 +292,7 @@ mod ind {
             let actual = AdvInd {
                 adv_address: Address::new_random(0xffe1e8d0dc27),
                 adv_data: &[0x01, 0x02, 0x03],
+                ch_sel: false,
             };
 
             let expected = [
@@ -282,10 +319,22 @@ mod ind {
             let expected = AdvInd {
                 adv_address: Address::new_random(0xffe1e8d0dc27),
                 adv_data: &[0x01, 0x02, 0x03],
+                ch_sel: false,
             };
 
             assert_eq!(AdvInd::parse(&bytes).unwrap(), expected);
         }
+
+        #[test]
+        fn adv_channel_selection() {
+            let mut actual = AdvInd::new(Address::new_random(0xffe1e8d0dc27), &[]);
+            actual.set_ch_sel(true);
+
+            let mut bytes = [0u8; 39];
+            let len = actual.bytes(&mut bytes);
+            assert_eq!(bytes[0], 0x60); // ADV_IND, ChSel, Random address
+            assert_eq!(AdvInd::parse(&bytes[..len]).unwrap(), actual);
+        }
     }
 }
 
@@ -303,12 +352,25 @@ mod direct_ind {
     }
 
     impl AdvDirectInd {
+        /// Header and both addresses
+        pub const PDU_LENGTH: usize = 14;
+
         pub fn new(adv_address: Address, target_address: Address) -> Self {
             Self {
                 adv_address,
                 target_address,
             }
         }
+
+        /// Address of the advertiser
+        pub fn adv_address(&self) -> &Address {
+            &self.adv_address
+        }
+
+        /// Address of the device the advertising is addressed to
+        pub fn target_address(&self) -> &Address {
+            &self.target_address
+        }
     }
 
     impl TwoAddress for AdvDirectInd {
@@ -562,12 +624,25 @@ mod scan_req {
     }
 
     impl ScanReq {
+        /// Header and both addresses
+        pub const PDU_LENGTH: usize = 14;
+
         pub fn new(scan_address: Address, adv_address: Address) -> Self {
             Self {
                 scan_address,
                 adv_address,
             }
         }
+
+        /// Address of the scanner sending the request
+        pub fn scan_address(&self) -> &Address {
+            &self.scan_address
+        }
+
+        /// Address of the advertiser the request is addressed to
+        pub fn adv_address(&self) -> &Address {
+            &self.adv_address
+        }
     }
 
     impl TwoAddress for ScanReq {
@@ -633,6 +708,20 @@ mod scan_req {
 
             assert_eq!(ScanReq::parse(&actual).unwrap(), expected);
         }
+
+        #[test]
+        fn scan_request_from_radio_buffer() {
+            let mut buffer = [0u8; 258];
+            buffer[..14].copy_from_slice(&[
+                0xC3u8, // SCAN_REQ, Random address, Random address
+                12,     // Length of payload
+                0x27, 0xdc, 0xd0, 0xe8, 0xe1, 0xff, // Adress
+                0x28, 0xdc, 0xd0, 0xe8, 0xe1, 0xff, // Adress
+            ]);
+
+            let scan_req = ScanReq::parse(&buffer).unwrap();
+            assert_eq!(scan_req.adv_address(), &Address::new_random(0xffe1e8d0dc28));
+        }
     }
 }
 
@@ -720,3 +809,320 @@ mod scan_rsp {
         }
     }
 }
+
+mod connect_ind {
+    use super::*;
+    use crate::phy::ChannelMap;
+
+    /// Link Layer connection parameters sent by the initiator
+    ///
+    ///   ┌───────────┬───────────┬──────────┬───────────┬───────────┬───────────┬───────────┬───────────┬──────────┬──────────┐
+    ///   │ AA        │ CRCInit   │ WinSize  │ WinOffset │ Interval  │ Latency   │ Timeout   │ ChM       │ Hop      │ SCA      │
+    ///   │ (4 bytes) │ (3 bytes) │ (1 byte) │ (2 bytes) │ (2 bytes) │ (2 bytes) │ (2 bytes) │ (5 bytes) │ (5 bits) │ (3 bits) │
+    ///   └───────────┴───────────┴──────────┴───────────┴───────────┴───────────┴───────────┴───────────┴──────────┴──────────┘
+    ///
+    /// WinSize, WinOffset and Interval are in units of 1.25 ms and Timeout in units of 10 ms.
+    ///
+    /// Ref: [Core 6.B.2.3.3.1](https://www.bluetooth.com/wp-content/uploads/Files/Specification/HTML/Core-54/out/en/low-energy-controller/link-layer-specification.html)
+    #[derive(Debug, Clone, PartialEq, Eq, Format)]
+    pub struct LLData {
+        /// Access address of the connection
+        pub access_address: u32,
+
+        /// Initialization value for the CRC calculation, 24 bits
+        pub crc_init: u32,
+
+        /// transmitWindowSize, in units of 1.25 ms
+        pub win_size: u8,
+
+        /// transmitWindowOffset, in units of 1.25 ms
+        pub win_offset: u16,
+
+        /// connInterval, in units of 1.25 ms
+        pub interval: u16,
+
+        /// connPeripheralLatency, in number of connection events
+        pub latency: u16,
+
+        /// connSupervisionTimeout, in units of 10 ms
+        pub timeout: u16,
+
+        /// Used data channels
+        pub channel_map: ChannelMap,
+
+        /// hopIncrement used by the Channel Selection Algorithm #1, 5 to 16
+        pub hop: u8,
+
+        /// Worst case sleep clock accuracy of the central
+        pub sca: u8,
+    }
+
+    impl LLData {
+        pub const LENGTH: usize = 22;
+
+        pub fn bytes(&self, dest: &mut [u8]) -> usize {
+            assert!(dest.len() >= Self::LENGTH);
+
+            dest[0..4].copy_from_slice(&self.access_address.to_le_bytes());
+            dest[4..7].copy_from_slice(&self.crc_init.to_le_bytes()[..3]);
+            dest[7] = self.win_size;
+            dest[8..10].copy_from_slice(&self.win_offset.to_le_bytes());
+            dest[10..12].copy_from_slice(&self.interval.to_le_bytes());
+            dest[12..14].copy_from_slice(&self.latency.to_le_bytes());
+            dest[14..16].copy_from_slice(&self.timeout.to_le_bytes());
+            dest[16..21].copy_from_slice(&self.channel_map.bytes());
+            dest[21] = (self.hop & 0b1_1111) | (self.sca & 0b111) << 5;
+
+            Self::LENGTH
+        }
+
+        pub fn parse(bytes: &[u8]) -> Result<Self, ParseError> {
+            if bytes.len() < Self::LENGTH {
+                return Err(ParseError::InvalidLength);
+            }
+
+            let u16_at = |i: usize| u16::from_le_bytes([bytes[i], bytes[i + 1]]);
+
+            let ll_data = Self {
+                access_address: u32::from_le_bytes(bytes[0..4].try_into().unwrap()),
+                crc_init: u32::from_le_bytes([bytes[4], bytes[5], bytes[6], 0]),
+                win_size: bytes[7],
+                win_offset: u16_at(8),
+                interval: u16_at(10),
+                latency: u16_at(12),
+                timeout: u16_at(14),
+                channel_map: ChannelMap::new(bytes[16..21].try_into().unwrap()),
+                hop: bytes[21] & 0b1_1111,
+                sca: bytes[21] >> 5,
+            };
+
+            if !ll_data.is_valid() {
+                return Err(ParseError::InvalidValue);
+            }
+
+            Ok(ll_data)
+        }
+
+        /// Check the ranges of the connection parameters
+        fn is_valid(&self) -> bool {
+            let interval_ok = (6..=3200).contains(&self.interval);
+            let win_size_ok =
+                self.win_size >= 1 && self.win_size <= 8 && (self.win_size as u16) < self.interval;
+            let win_offset_ok = self.win_offset <= self.interval;
+            let latency_ok = self.latency <= 499;
+
+            // connSupervisionTimeout shall be larger than (1 + connPeripheralLatency) * connInterval * 2
+            // timeout is in 10 ms and interval in 1.25 ms, so the interval is divided by 8
+            let timeout_ok = (10..=3200).contains(&self.timeout)
+                && (self.timeout as u32) * 8
+                    > (1 + self.latency as u32) * (self.interval as u32) * 2;
+
+            let channel_map_ok = self.channel_map.len() >= 2;
+            let hop_ok = (5..=16).contains(&self.hop);
+
+            interval_ok
+                && win_size_ok
+                && win_offset_ok
+                && latency_ok
+                && timeout_ok
+                && channel_map_ok
+                && hop_ok
+        }
+    }
+
+    /// CONNECT_IND, sent by the initiator to the advertiser to create a connection
+    ///
+    ///   |LSB                                               MSB|LSB    MSB|LSB      MSB|LSB      MSB|LSB        MSB|
+    ///   ┌──────────┬──────────┬─────────┬──────────┬──────────┬──────────┬────────────┬────────────┬──────────────┐
+    ///   │ PDU Type:│ RFU:     │ ChSel:  │ TxAdd:   │ RxAdd:   │ Length   │ InitA      │ AdvA       │ LLData       │
+    ///   │ 0b0101   │ -        │ (1 bit) │ (1 bit)  │ (1 bit)  │ (8 bits) │ (6 bytes)  │ (6 bytes)  │ (22 bytes)   │
+    ///   ├──────────┴──────────┴─────────┴──────────┴──────────┴──────────┼────────────┴────────────┴──────────────┤
+    ///   │                        HEADER (2 bytes)                        │           Payload (34 bytes)           │
+    ///   └────────────────────────────────────────────────────────────────┴────────────────────────────────────────┘
+    /// The ChSel is set when the initiator supports the Channel Selection Algorithm #2.
+    ///
+    /// The AUX_CONNECT_REQ, used on the secondary advertising channels, has the same format.
+    ///
+    /// Ref: [Core 6.B.2.3.3.1](https://www.bluetooth.com/wp-content/uploads/Files/Specification/HTML/Core-54/out/en/low-energy-controller/link-layer-specification.html)
+    #[derive(Debug, Clone, PartialEq, Eq, Format)]
+    pub struct ConnectInd {
+        /// Address of the initiator
+        init_address: Address,
+
+        /// Address of the advertiser the connection is requested to
+        adv_address: Address,
+
+        /// Connection parameters
+        ll_data: LLData,
+
+        /// Initiator supports the Channel Selection Algorithm #2
+        ch_sel: bool,
+    }
+
+    /// Same format as the CONNECT_IND, sent on the secondary advertising physical channel
+    pub type AuxConnectReq = ConnectInd;
+
+    impl ConnectInd {
+        pub const PDU_TYPE: u8 = 0b0101;
+        pub const PDU_LENGTH: usize = 2 + 12 + LLData::LENGTH;
+
+        pub fn new(
+            init_address: Address,
+            adv_address: Address,
+            ll_data: LLData,
+            ch_sel: bool,
+        ) -> Self {
+            Self {
+                init_address,
+                adv_address,
+                ll_data,
+                ch_sel,
+            }
+        }
+
+        pub fn init_address(&self) -> &Address {
+            &self.init_address
+        }
+
+        pub fn adv_address(&self) -> &Address {
+            &self.adv_address
+        }
+
+        pub fn ll_data(&self) -> &LLData {
+            &self.ll_data
+        }
+
+        pub fn ch_sel(&self) -> bool {
+            self.ch_sel
+        }
+
+        pub fn bytes(&self, dest: &mut [u8]) -> usize {
+            assert!(dest.len() >= Self::PDU_LENGTH);
+
+            let mut header = Header::with_rxtx(
+                self.init_address.r#type,
+                self.adv_address.r#type,
+                Self::PDU_TYPE,
+                (Self::PDU_LENGTH - 2) as u8,
+            );
+            header.flags.ch_sel = self.ch_sel;
+
+            dest[..2].copy_from_slice(&header.bytes());
+            dest[2..8].copy_from_slice(&self.init_address.bytes());
+            dest[8..14].copy_from_slice(&self.adv_address.bytes());
+            self.ll_data.bytes(&mut dest[14..]);
+
+            Self::PDU_LENGTH
+        }
+
+        pub fn parse(bytes: &[u8]) -> Result<Self, ParseError> {
+            if bytes.len() < Self::PDU_LENGTH {
+                return Err(ParseError::InvalidLength);
+            }
+
+            let header = Header::parse(bytes[..2].try_into().unwrap());
+            if header.flags.pdu_type != Self::PDU_TYPE {
+                return Err(ParseError::InvalidType);
+            }
+            if header.length as usize != Self::PDU_LENGTH - 2 {
+                return Err(ParseError::InvalidLength);
+            }
+
+            let init_address = Address::new_le(
+                bytes[2..8].try_into().unwrap(),
+                Header::bit_to_address_type(header.flags.tx_add),
+            );
+            let adv_address = Address::new_le(
+                bytes[8..14].try_into().unwrap(),
+                Header::bit_to_address_type(header.flags.rx_add),
+            );
+            let ll_data = LLData::parse(&bytes[14..Self::PDU_LENGTH])?;
+
+            Ok(Self {
+                init_address,
+                adv_address,
+                ll_data,
+                ch_sel: header.flags.ch_sel,
+            })
+        }
+    }
+
+    impl<'a> From<ConnectInd> for AdvPdu<'a> {
+        fn from(connect_ind: ConnectInd) -> Self {
+            Self::ConnectInd(connect_ind)
+        }
+    }
+
+    #[cfg(test)]
+    mod test {
+        use super::*;
+
+        fn ll_data() -> LLData {
+            LLData {
+                access_address: 0x50654DE1,
+                crc_init: 0x8F_A3_55,
+                win_size: 2,
+                win_offset: 3,
+                interval: 24,
+                latency: 0,
+                timeout: 72,
+                channel_map: ChannelMap::all(),
+                hop: 9,
+                sca: 5,
+            }
+        }
+
+        const BYTES: [u8; 36] = [
+            0xE5u8, // CONNECT_IND, ChSel, Random address, Random address
+            34,     // Length of payload
+            0x28, 0xdc, 0xd0, 0xe8, 0xe1, 0xff, // Initiator address
+            0x27, 0xdc, 0xd0, 0xe8, 0xe1, 0xff, // Advertiser address
+            0xE1, 0x4D, 0x65, 0x50, // Access address
+            0x55, 0xA3, 0x8F, // CRC init
+            2,    // WinSize
+            3, 0, // WinOffset
+            24, 0, // Interval
+            0, 0, // Latency
+            72, 0, // Timeout
+            0xFF, 0xFF, 0xFF, 0xFF, 0x1F, // Channel map
+            0xA9, // Hop 9, SCA 5
+        ];
+
+        #[test]
+        fn connect_serialize() {
+            let actual = ConnectInd {
+                init_address: Address::new_random(0xffe1e8d0dc28),
+                adv_address: Address::new_random(0xffe1e8d0dc27),
+                ll_data: ll_data(),
+                ch_sel: true,
+            };
+
+            let mut bytes = [0u8; 39];
+            let len = actual.bytes(&mut bytes);
+            assert_eq!(bytes[..len], BYTES);
+        }
+
+        #[test]
+        fn connect_complementary() {
+            let expected = ConnectInd {
+                init_address: Address::new_random(0xffe1e8d0dc28),
+                adv_address: Address::new_random(0xffe1e8d0dc27),
+                ll_data: ll_data(),
+                ch_sel: true,
+            };
+
+            assert_eq!(ConnectInd::parse(&BYTES).unwrap(), expected);
+
+            let mut buffer = [0u8; 258];
+            buffer[..BYTES.len()].copy_from_slice(&BYTES);
+            assert_eq!(parse(&buffer).unwrap(), AdvPdu::ConnectInd(expected));
+        }
+
+        #[test]
+        fn connect_invalid_parameters() {
+            let mut bytes = BYTES;
+            bytes[24] = 2; // Interval lower than 7.5 ms
+            assert_eq!(ConnectInd::parse(&bytes), Err(ParseError::InvalidValue));
+        }
+    }
+}
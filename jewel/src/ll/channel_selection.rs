@@ -0,0 +1,179 @@
+//! Channel selection algorithms
+//!
+//! Select the data channel of each connection event from the channel map.
+//! The Channel Selection Algorithm #2 is used when both devices set the ChSel bit,
+//! otherwise the Channel Selection Algorithm #1 is used.
+//!
+//! Ref: [Core 6.B.4.5.8](https://www.bluetooth.com/wp-content/uploads/Files/Specification/HTML/Core-54/out/en/low-energy-controller/link-layer-specification.html)
+
+use defmt::Format;
+
+use crate::phy::{ChannelMap, DataChannel};
+
+/// Number of data channels
+const DATA_CHANNELS: u8 = 37;
+
+#[derive(Debug, Clone, PartialEq, Eq, Format)]
+pub enum ChannelSelection {
+    /// Channel Selection Algorithm #1
+    Algorithm1 {
+        /// hopIncrement from the CONNECT_IND, 5 to 16
+        hop_increment: u8,
+
+        /// lastUnmappedChannel
+        last_unmapped_channel: u8,
+    },
+
+    /// Channel Selection Algorithm #2
+    Algorithm2 {
+        /// channelIdentifier, derived from the access address
+        channel_identifier: u16,
+    },
+}
+
+impl ChannelSelection {
+    pub fn algorithm1(hop_increment: u8) -> Self {
+        Self::Algorithm1 {
+            hop_increment,
+            last_unmapped_channel: 0,
+        }
+    }
+
+    pub fn algorithm2(access_address: u32) -> Self {
+        Self::Algorithm2 {
+            channel_identifier: ((access_address >> 16) ^ (access_address & 0xFFFF)) as u16,
+        }
+    }
+
+    /// Select the channel of the connection event `event_counter`.
+    ///
+    /// It shall be called for every connection event in order, even the ones the
+    /// peripheral does not listen, as the algorithm #1 depends on the previous event.
+    pub fn select(&mut self, event_counter: u16, map: &ChannelMap) -> DataChannel {
+        match self {
+            Self::Algorithm1 {
+                hop_increment,
+                last_unmapped_channel,
+            } => {
+                let unmapped = (*last_unmapped_channel + *hop_increment) % DATA_CHANNELS;
+                *last_unmapped_channel = unmapped;
+
+                let channel = DataChannel::try_from(unmapped).unwrap();
+                if map.is_used(channel) {
+                    channel
+                } else {
+                    map.used(unmapped % map.len())
+                }
+            }
+            Self::Algorithm2 { channel_identifier } => {
+                let prn_e = prn_e(event_counter, *channel_identifier);
+                let unmapped = (prn_e % DATA_CHANNELS as u16) as u8;
+
+                let channel = DataChannel::try_from(unmapped).unwrap();
+                if map.is_used(channel) {
+                    channel
+                } else {
+                    let remapping_index = (map.len() as u32 * prn_e as u32) >> 16;
+                    map.used(remapping_index as u8)
+                }
+            }
+        }
+    }
+}
+
+/// Permutation, the bits of each byte are reversed
+fn perm(value: u16) -> u16 {
+    let [low, high] = value.to_le_bytes();
+    u16::from_le_bytes([low.reverse_bits(), high.reverse_bits()])
+}
+
+/// Multiply, add and modulo 2^16
+fn mam(a: u16, b: u16) -> u16 {
+    a.wrapping_mul(17).wrapping_add(b)
+}
+
+/// Pseudo-random number of the event counter, prn_e
+///
+/// Ref: Core 6.B.4.5.8.3.3
+fn prn_e(counter: u16, channel_identifier: u16) -> u16 {
+    let mut value = counter ^ channel_identifier;
+    for _ in 0..3 {
+        value = mam(perm(value), channel_identifier);
+    }
+    value ^ channel_identifier
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // Ref: Core 6.C.3 Sample data for the Channel Selection Algorithm #2
+    const ACCESS_ADDRESS: u32 = 0x8E89BED6;
+
+    #[test]
+    fn channel_identifier() {
+        assert_eq!(
+            ChannelSelection::algorithm2(ACCESS_ADDRESS),
+            ChannelSelection::Algorithm2 {
+                channel_identifier: 0x305F
+            }
+        );
+    }
+
+    #[test]
+    fn algorithm2_all_channels() {
+        let map = ChannelMap::all();
+        let mut csa = ChannelSelection::algorithm2(ACCESS_ADDRESS);
+
+        assert_eq!(prn_e(0, 0x305F), 56857);
+        assert_eq!(csa.select(0, &map), DataChannel::Ch25);
+        assert_eq!(prn_e(1, 0x305F), 1685);
+        assert_eq!(csa.select(1, &map), DataChannel::Ch20);
+        assert_eq!(prn_e(2, 0x305F), 38301);
+        assert_eq!(csa.select(2, &map), DataChannel::Ch6);
+        assert_eq!(prn_e(3, 0x305F), 27475);
+        assert_eq!(csa.select(3, &map), DataChannel::Ch21);
+    }
+
+    #[test]
+    fn algorithm2_nine_channels() {
+        // Channels 9, 10, 21, 22, 23, 33, 34, 35 and 36
+        let map = ChannelMap::new([0x00, 0x06, 0xE0, 0x00, 0x1E]);
+        let mut csa = ChannelSelection::algorithm2(ACCESS_ADDRESS);
+
+        assert_eq!(prn_e(6, 0x305F), 10975);
+        assert_eq!(csa.select(6, &map), DataChannel::Ch23);
+        assert_eq!(prn_e(7, 0x305F), 5490);
+        assert_eq!(csa.select(7, &map), DataChannel::Ch9);
+        assert_eq!(prn_e(8, 0x305F), 46970);
+        assert_eq!(csa.select(8, &map), DataChannel::Ch34);
+        assert_eq!(prn_e(9, 0x305F), 47862);
+        assert_eq!(csa.select(9, &map), DataChannel::Ch21);
+    }
+
+    #[test]
+    fn algorithm1_all_channels() {
+        let map = ChannelMap::all();
+        let mut csa = ChannelSelection::algorithm1(10);
+
+        assert_eq!(csa.select(0, &map), DataChannel::Ch10);
+        assert_eq!(csa.select(1, &map), DataChannel::Ch20);
+        assert_eq!(csa.select(2, &map), DataChannel::Ch30);
+        // (30 + 10) mod 37
+        assert_eq!(csa.select(3, &map), DataChannel::Ch3);
+    }
+
+    #[test]
+    fn algorithm1_remapping() {
+        // Channels 9, 10, 21, 22, 23, 33, 34, 35 and 36
+        let map = ChannelMap::new([0x00, 0x06, 0xE0, 0x00, 0x1E]);
+        let mut csa = ChannelSelection::algorithm1(10);
+
+        // unmapped 10 is used
+        assert_eq!(csa.select(0, &map), DataChannel::Ch10);
+        // unmapped 20 is not used, 20 mod 9 = 2
+        assert_eq!(csa.select(1, &map), DataChannel::Ch21);
+        // unmapped 30 is not used, 30 mod 9 = 3
+        assert_eq!(csa.select(2, &map), DataChannel::Ch22);
+    }
+}
@@ -0,0 +1,1017 @@
+//! Connection state
+//!
+//! The advertiser enters the connection state in the peripheral role after
+//! receiving a CONNECT_IND addressed to it, and the initiator in the central role
+//! after sending it.
+//!
+//! The central starts each connection event at the anchor point, and the
+//! peripheral listens around it. As the clocks of both devices drift, the
+//! peripheral widens its receive window proportionally to the time since
+//! the last anchor point it has received.
+//!
+//! Ref: [Core 6.B.4.5](https://www.bluetooth.com/wp-content/uploads/Files/Specification/HTML/Core-54/out/en/low-energy-controller/link-layer-specification.html)
+
+use defmt::Format;
+use embassy_time::{with_timeout, Duration, Instant, Timer};
+use rand::Rng;
+
+use crate::phy::{DataChannel, HeaderSize, Radio, MAX_PDU_LENGTH};
+
+use super::{
+    air_time, response_timeout, Address, Advertising, ChannelSelection, ConnectInd,
+    ConnectionUpdate, ControlPdu, DataChannelPdu, DataHeader, DataPdu, LLData, LinkLayer,
+    DEFAULT_PAYLOAD_LENGTH, MAX_CTR_DATA_LENGTH, T_ACA, T_IFS, T_SCA,
+};
+
+mod control;
+mod encryption;
+mod queue;
+
+pub use control::*;
+pub use encryption::*;
+pub use queue::*;
+
+/// Unit of the connection interval, window size and window offset
+const CONN_UNIT: Duration = Duration::from_micros(1_250);
+
+/// Unit of the supervision timeout
+const TIMEOUT_UNIT: Duration = Duration::from_millis(10);
+
+/// Delay between the end of the CONNECT_IND and the transmit window on the LE 1M PHY
+///
+/// Ref: Core 6.B.4.5.3
+const TRANSMIT_WINDOW_DELAY: Duration = Duration::from_micros(1_250);
+
+/// Number of connection events the central has to send the first packet
+const ESTABLISHMENT_EVENTS: u32 = 6;
+
+/// Worst case sleep clock accuracy of this device, in ppm
+const LOCAL_SCA_PPM: u64 = 500;
+
+/// Worst case sleep clock accuracy of the peer, in ppm, from the SCA field
+///
+/// Ref: Core 6.B.2.3.3.1 Table 2.22
+fn sca_ppm(sca: u8) -> u64 {
+    match sca {
+        0 => 500,
+        1 => 250,
+        2 => 150,
+        3 => 100,
+        4 => 75,
+        5 => 50,
+        6 => 30,
+        _ => 20,
+    }
+}
+
+/// Time the peripheral listens before and after the expected anchor point,
+/// to compensate the drift of both sleep clocks since the last received anchor point.
+///
+/// windowWidening = ((centralSCA + peripheralSCA) / 1000000) * timeSinceLastAnchor
+///
+/// Ref: Core 6.B.4.5.7
+fn window_widening(central_sca: u8, since_last_anchor: Duration) -> Duration {
+    let ppm = sca_ppm(central_sca) + LOCAL_SCA_PPM;
+    let drift = Duration::from_micros(since_last_anchor.as_micros() * ppm / 1_000_000);
+
+    // The instantaneous deviation of the clocks is not covered by the drift
+    drift + T_SCA + T_ACA
+}
+
+/// Reason of the connection termination
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Format)]
+pub enum DisconnectReason {
+    /// No valid packet was received for the supervision timeout
+    ConnectionTimeout,
+
+    /// No packet was received in the first 6 connection events
+    ConnectionFailedToBeEstablished,
+
+    /// The peer terminated the connection with the error code
+    RemoteTerminated(u8),
+
+    /// The connection was terminated by this device
+    LocalHostTerminated,
+
+    /// The peer did not answer a LL Control procedure in time
+    LlResponseTimeout,
+
+    /// The instant of an update was already passed when it was received
+    InstantPassed,
+
+    /// An encrypted packet failed the MIC check
+    MicFailure,
+}
+
+impl DisconnectReason {
+    /// HCI error code of the reason
+    ///
+    /// Ref: Core 1.F.1.3
+    pub fn error_code(&self) -> u8 {
+        match self {
+            DisconnectReason::ConnectionTimeout => 0x08,
+            DisconnectReason::ConnectionFailedToBeEstablished => 0x3E,
+            DisconnectReason::RemoteTerminated(code) => *code,
+            DisconnectReason::LocalHostTerminated => 0x16,
+            DisconnectReason::LlResponseTimeout => 0x22,
+            DisconnectReason::InstantPassed => 0x28,
+            DisconnectReason::MicFailure => 0x3D,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Format)]
+pub enum ConnectionError<E> {
+    /// The radio failed
+    Radio(E),
+
+    /// The connection is over
+    Disconnected(DisconnectReason),
+
+    /// The events kept while a method ran the connection events fill the queue,
+    /// `connection_event` shall report them before calling the method again
+    EventQueueFull,
+}
+
+/// Role of the device in the connection
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Format)]
+pub enum Role {
+    /// Sent the CONNECT_IND, starts every connection event
+    Central,
+
+    /// Received the CONNECT_IND, answers the central
+    Peripheral,
+}
+
+/// Source of the last packet sent, retransmitted until it is acknowledged
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum InFlight {
+    Empty,
+    Control,
+    Data,
+}
+
+/// A CONNECT_IND received while advertising or sent while initiating
+#[derive(Debug, Clone, PartialEq, Eq, Format)]
+pub struct ConnectRequest {
+    pub pdu: ConnectInd,
+
+    /// When the reception or the transmission of the CONNECT_IND was completed,
+    /// the transmit window is relative to it
+    pub timestamp: Instant,
+}
+
+pub struct Connection {
+    role: Role,
+
+    /// Address of this device in the CONNECT_IND, a resolvable private address
+    /// when the advertising used privacy
+    local_address: Address,
+
+    /// Address of the peer
+    peer_address: Address,
+
+    /// Connection parameters sent by the central
+    parameters: LLData,
+
+    /// Channel selection algorithm agreed on the connection
+    channel_selection: ChannelSelection,
+
+    /// Data channel of the current connection event
+    channel: DataChannel,
+
+    /// connEventCounter, incremented on each connection event, even the ones not listened
+    event_counter: u16,
+
+    /// Expected anchor point of the next connection event.
+    /// Before the first packet is received it is the start of the transmit window.
+    anchor: Instant,
+
+    /// Last anchor point where a packet from the central was received,
+    /// only used by the peripheral
+    last_anchor: Instant,
+
+    /// Last time a packet with a valid CRC was received
+    last_received: Instant,
+
+    /// Start of the connection, for the establishment timeout
+    created: Instant,
+
+    /// A packet was received from the peer
+    established: bool,
+
+    /// Transmit window of the next connection event, where the central sends
+    /// the first packet after the connection creation or a connection update
+    transmit_window: Duration,
+
+    /// Sequence number of the last packet sent
+    sn: bool,
+
+    /// Next expected sequence number
+    nesn: bool,
+
+    /// LL Control procedures in progress
+    control: Procedures,
+
+    /// Data PDUs waiting to be sent, the first one is removed when acknowledged
+    tx: PduQueue<TX_QUEUE_SIZE>,
+
+    /// Data PDUs received and not read yet
+    rx: PduQueue<RX_QUEUE_SIZE>,
+
+    in_flight: InFlight,
+}
+
+impl Connection {
+    /// Create the connection from the CONNECT_IND.
+    /// `ch_sel` is set when the advertiser supports the Channel Selection Algorithm #2.
+    pub fn new(request: ConnectRequest, ch_sel: bool, role: Role) -> Self {
+        let parameters = request.pdu.ll_data().clone();
+
+        let mut channel_selection = if ch_sel && request.pdu.ch_sel() {
+            ChannelSelection::algorithm2(parameters.access_address)
+        } else {
+            ChannelSelection::algorithm1(parameters.hop)
+        };
+        let channel = channel_selection.select(0, &parameters.channel_map);
+
+        // The first packet is sent by the central inside the transmit window,
+        // that starts after transmitWindowDelay and transmitWindowOffset.
+        // The central sends it right on the start of the window.
+        let window_start =
+            request.timestamp + TRANSMIT_WINDOW_DELAY + CONN_UNIT * parameters.win_offset as u32;
+
+        // The SKD and IV of the encryption shall differ on each connection
+        let seed = request.timestamp.as_ticks()
+            ^ (parameters.access_address as u64) << 32
+            ^ parameters.crc_init as u64;
+
+        let (local_address, peer_address) = match role {
+            Role::Central => (request.pdu.init_address(), request.pdu.adv_address()),
+            Role::Peripheral => (request.pdu.adv_address(), request.pdu.init_address()),
+        };
+
+        Connection {
+            role,
+            local_address: local_address.clone(),
+            peer_address: peer_address.clone(),
+            channel_selection,
+            channel,
+            event_counter: 0,
+            anchor: window_start,
+            last_anchor: request.timestamp,
+            last_received: request.timestamp,
+            created: request.timestamp,
+            established: false,
+            transmit_window: CONN_UNIT * parameters.win_size as u32,
+            sn: false,
+            nesn: false,
+            control: Procedures::new(role, seed),
+            tx: PduQueue::new(),
+            rx: PduQueue::new(),
+            in_flight: InFlight::Empty,
+            parameters,
+        }
+    }
+
+    /// connInterval
+    pub fn interval(&self) -> Duration {
+        CONN_UNIT * self.parameters.interval as u32
+    }
+
+    /// connSupervisionTimeout
+    pub fn supervision_timeout(&self) -> Duration {
+        TIMEOUT_UNIT * self.parameters.timeout as u32
+    }
+
+    /// Window where the first packet of the connection event is expected.
+    ///
+    /// After the connection creation or a connection update it is the transmit window,
+    /// otherwise it is the anchor point, both widened by the clock drift.
+    fn receive_window(&self) -> (Instant, Instant) {
+        let widening = window_widening(
+            self.parameters.sca,
+            self.anchor.saturating_duration_since(self.last_anchor),
+        );
+
+        // The receive window shall not overlap the previous connection event
+        let widening = widening.min(self.interval() / 2 - T_IFS);
+
+        let window = self.transmit_window;
+
+        let start = Instant::from_ticks(self.anchor.as_ticks().saturating_sub(widening.as_ticks()));
+        (start, self.anchor + window + widening)
+    }
+
+    /// Check the supervision timeout
+    ///
+    /// Ref: Core 6.B.4.5.2
+    fn check_timeout(&self, now: Instant) -> Result<(), DisconnectReason> {
+        if !self.established {
+            if now >= self.anchor_of(ESTABLISHMENT_EVENTS) {
+                return Err(DisconnectReason::ConnectionFailedToBeEstablished);
+            }
+        } else if now.saturating_duration_since(self.last_received) > self.supervision_timeout() {
+            return Err(DisconnectReason::ConnectionTimeout);
+        }
+        self.control.check_timeout(now)
+    }
+
+    /// Expected anchor of the connection event `events` after the first one
+    fn anchor_of(&self, events: u32) -> Instant {
+        let first =
+            self.created + TRANSMIT_WINDOW_DELAY + CONN_UNIT * self.parameters.win_offset as u32;
+        first + self.interval() * events
+    }
+
+    /// Connection events the peripheral skips without listening
+    ///
+    /// The peripheral latency is only used when there is nothing to send
+    /// and the connection is established.
+    /// The peripheral shall listen on the instant of an update.
+    fn events_to_skip(&self) -> u16 {
+        if self.role == Role::Central || !self.established || self.has_pending() {
+            return 0;
+        }
+
+        match self.control.events_to_instant(self.event_counter) {
+            Some(events) => self.parameters.latency.min(events.saturating_sub(1)),
+            None => self.parameters.latency,
+        }
+    }
+
+    /// Advance to the next connection event, skipping `skip` events
+    fn next_event(&mut self, skip: u16) {
+        for _ in 0..=skip {
+            let previous_anchor = self.anchor;
+            self.event_counter = self.event_counter.wrapping_add(1);
+            self.anchor += self.interval();
+
+            if let Some(update) = self.control.take_connection_update(self.event_counter) {
+                self.apply_connection_update(previous_anchor, update);
+            }
+            if let Some(update) = self.control.take_channel_map_update(self.event_counter) {
+                self.parameters.channel_map = update.channel_map;
+            }
+
+            self.channel = self
+                .channel_selection
+                .select(self.event_counter, &self.parameters.channel_map);
+        }
+    }
+
+    /// Apply the new connection parameters on the instant
+    ///
+    /// The transmit window starts connInterval_old + transmitWindowOffset after
+    /// the anchor point of the event before the instant.
+    ///
+    /// Ref: Core 6.B.5.1.1
+    fn apply_connection_update(&mut self, previous_anchor: Instant, update: ConnectionUpdate) {
+        self.anchor = previous_anchor + self.interval() + CONN_UNIT * update.win_offset as u32;
+        self.transmit_window = CONN_UNIT * update.win_size as u32;
+
+        self.parameters.win_size = update.win_size;
+        self.parameters.win_offset = update.win_offset;
+        self.parameters.interval = update.interval;
+        self.parameters.latency = update.latency;
+        self.parameters.timeout = update.timeout;
+    }
+
+    /// A new packet exchange fits before the end of the connection event,
+    /// that shall close T_IFS before the next anchor point
+    ///
+    /// Ref: Core 6.B.4.5.6
+    fn fits_in_event(&self, now: Instant) -> bool {
+        let exchange = (T_IFS + air_time(MAX_PDU_LENGTH)) * 2;
+        now + exchange + T_IFS < self.anchor + self.interval()
+    }
+
+    /// The first packet of the connection event was received at `received`
+    fn on_anchor(&mut self, received: Instant, pdu_length: usize) {
+        // The anchor point is the start of the packet sent by the central
+        let anchor = received - air_time(pdu_length);
+
+        self.anchor = anchor;
+        self.last_anchor = anchor;
+        self.last_received = received;
+        self.established = true;
+        self.transmit_window = Duration::from_ticks(0);
+    }
+
+    /// A packet from the peripheral was received at `received`,
+    /// the anchor points of the central do not move
+    fn on_response(&mut self, received: Instant) {
+        self.last_received = received;
+        self.established = true;
+    }
+
+    /// Handle the acknowledgement and the content of a received packet
+    ///
+    /// Ref: Core 6.B.4.5.9
+    fn on_packet(&mut self, received: &[u8]) -> Result<(), DisconnectReason> {
+        // A PDU with a reserved LLID or an invalid length is ignored
+        let Ok(pdu) = DataChannelPdu::parse(received) else {
+            return Ok(());
+        };
+        let header = pdu.header();
+        let nesn = self.nesn;
+
+        // Our last packet was acknowledged
+        if header.nesn != self.sn {
+            self.sn = !self.sn;
+            self.control.encryption.on_acknowledged();
+            match core::mem::replace(&mut self.in_flight, InFlight::Empty) {
+                InFlight::Control => self.control.on_acknowledged()?,
+                InFlight::Data => self.tx.pop(),
+                InFlight::Empty => {}
+            }
+        }
+
+        // A new packet was received
+        if header.sn == self.nesn {
+            match pdu {
+                // Not acknowledged until the response can be sent, the central retransmits it
+                DataChannelPdu::Control(_) if !self.control.is_ready() => {}
+                DataChannelPdu::Control(pdu) => {
+                    self.nesn = !self.nesn;
+                    self.control.on_control(&pdu, self.event_counter)?;
+                }
+                // Longer payloads are not allowed without the Data Length Update procedure,
+                // the PDU is not acknowledged
+                DataChannelPdu::Data(pdu) if pdu.payload().len() > DEFAULT_PAYLOAD_LENGTH => {}
+                // Not acknowledged until there is space, the central retransmits it
+                DataChannelPdu::Data(pdu) if !pdu.is_empty() && self.rx.is_full() => {}
+                DataChannelPdu::Data(pdu) => {
+                    self.nesn = !self.nesn;
+                    if !pdu.is_empty() {
+                        // The space was checked above
+                        let _ = self.rx.push(&pdu);
+                    }
+                }
+            }
+        }
+        if self.nesn != nesn {
+            self.control.encryption.on_received();
+        }
+
+        Ok(())
+    }
+
+    /// There is a PDU to send, the data PDUs wait for the encryption procedures
+    fn has_pending(&self) -> bool {
+        self.data_ready() || self.control.has_pending()
+    }
+
+    fn data_ready(&self) -> bool {
+        !self.tx.is_empty() && !self.control.encryption.blocks_data()
+    }
+
+    /// Serialize the next packet to send, an empty PDU when there is nothing to send.
+    ///
+    /// The last packet is sent again until it is acknowledged, the LL Control PDUs
+    /// go before the data PDUs. The MD bit is set when there are more PDUs to send.
+    fn next_pdu(&mut self, now: Instant, dest: &mut [u8]) -> usize {
+        if self.in_flight == InFlight::Empty {
+            self.in_flight = if self.control.next(now).is_some() {
+                InFlight::Control
+            } else if self.data_ready() {
+                InFlight::Data
+            } else {
+                InFlight::Empty
+            };
+        }
+        let data = if self.data_ready() { self.tx.len() } else { 0 };
+        let more_data = data + self.control.has_pending() as usize > 1;
+
+        let mut ctr_data = [0u8; MAX_CTR_DATA_LENGTH];
+        let control = match self.in_flight {
+            InFlight::Control => self.control.next(now),
+            _ => None,
+        };
+        let data = match self.in_flight {
+            InFlight::Data => self.tx.front(),
+            _ => None,
+        };
+
+        let mut pdu = if let Some(control) = control {
+            let len = control.ctr_data(&mut ctr_data);
+            DataChannelPdu::Control(ControlPdu::new(control.opcode() as u8, &ctr_data[..len]))
+        } else {
+            DataChannelPdu::Data(data.unwrap_or(DataPdu::empty()))
+        };
+
+        let header = pdu.header_mut();
+        header.nesn = self.nesn;
+        header.sn = self.sn;
+        header.md = more_data;
+        pdu.bytes(dest)
+    }
+}
+
+impl<'r, R: Radio, RNG: Rng> LinkLayer<'r, R, Advertising<'_, RNG>> {
+    /// Stop advertising and enter the connection state as peripheral
+    pub fn connect(self, request: ConnectRequest) -> LinkLayer<'r, R, Connection> {
+        let state = Connection::new(request, self.state.ch_sel, Role::Peripheral);
+        LinkLayer::enter_connection(self.radio, state)
+    }
+}
+
+impl<'r, R: Radio> LinkLayer<'r, R, Connection> {
+    /// Set the radio to use the data physical channel of the connection
+    pub(super) fn enter_connection(radio: &'r mut R, state: Connection) -> Self {
+        radio.set_header_size(HeaderSize::TwoBytes);
+        radio.set_access_address(state.parameters.access_address);
+        radio.set_crc_init(state.parameters.crc_init);
+        radio.set_device_address_match(None);
+
+        LinkLayer { radio, state }
+    }
+
+    /// Role of this device in the connection
+    pub fn role(&self) -> Role {
+        self.state.role
+    }
+
+    /// Address of this device used on the connection
+    pub fn local_address(&self) -> Address {
+        self.state.local_address.clone()
+    }
+
+    /// Identity address of this device, the public or static address of the radio
+    pub fn identity_address(&self) -> Address {
+        self.radio.device_address()
+    }
+
+    /// Address of the peer
+    pub fn peer_address(&self) -> &Address {
+        &self.state.peer_address
+    }
+
+    /// Connection parameters in use
+    pub fn parameters(&self) -> &LLData {
+        &self.state.parameters
+    }
+
+    /// Run the next connection event
+    ///
+    /// As peripheral, wait for the anchor point, receive the packet of the central and
+    /// answer it within T_IFS. As central, send the first packet on the anchor point.
+    /// You should call this method in a loop to keep the connection alive.
+    pub async fn connection_event(&mut self) -> Result<(), ConnectionError<R::Error>> {
+        if self.state.role == Role::Central {
+            return self.central_event().await;
+        }
+
+        let (start, end) = self.state.receive_window();
+        Timer::at(start).await;
+
+        self.radio.set_channel(self.state.channel.into());
+
+        let mut buffer = [0u8; MAX_PDU_LENGTH];
+        let timeout = end.saturating_duration_since(Instant::now()) + air_time(MAX_PDU_LENGTH);
+        let received = with_timeout(timeout, self.radio.receive(&mut buffer)).await;
+
+        // A packet received with an error is not acknowledged, the connection event
+        // is closed and the anchor point is kept
+        let mut skip = 0;
+        if let Ok(Ok(())) = received {
+            let now = Instant::now();
+
+            let pdu_length = 2 + buffer[1] as usize;
+            self.state.on_anchor(now, pdu_length);
+            let mut more_data = self.respond(&mut buffer, now).await?;
+
+            // The connection event continues while any device has more data
+            while more_data && self.state.fits_in_event(Instant::now()) {
+                let timeout = response_timeout(MAX_PDU_LENGTH);
+                let Ok(Ok(())) = with_timeout(timeout, self.radio.receive(&mut buffer)).await
+                else {
+                    break;
+                };
+                let now = Instant::now();
+
+                self.state.last_received = now;
+                more_data = self.respond(&mut buffer, now).await?;
+            }
+
+            skip = self.state.events_to_skip();
+        }
+
+        self.state
+            .check_timeout(Instant::now())
+            .map_err(ConnectionError::Disconnected)?;
+        self.state.next_event(skip);
+
+        Ok(())
+    }
+
+    /// Run the connection event as central, sending a packet on the anchor point
+    /// and another one T_IFS after each packet of the peripheral while any device has more data.
+    ///
+    /// Ref: Core 6.B.4.5.1
+    async fn central_event(&mut self) -> Result<(), ConnectionError<R::Error>> {
+        Timer::at(self.state.anchor).await;
+        self.radio.set_channel(self.state.channel.into());
+
+        let mut buffer = [0u8; MAX_PDU_LENGTH];
+        let mut received = [0u8; MAX_PDU_LENGTH];
+        loop {
+            self.state.next_pdu(Instant::now(), &mut buffer);
+            let len = self.state.seal(self.radio, &mut buffer);
+            self.radio
+                .transmit(&buffer[..len])
+                .await
+                .map_err(ConnectionError::Radio)?;
+
+            // The connection event is closed when the peripheral does not answer,
+            // or when its packet is received with an error
+            let timeout = response_timeout(MAX_PDU_LENGTH);
+            let Ok(Ok(())) = with_timeout(timeout, self.radio.receive(&mut received)).await else {
+                break;
+            };
+            let now = Instant::now();
+
+            self.state.on_response(now);
+            self.state
+                .open(self.radio, &mut received)
+                .and_then(|_| self.state.on_packet(&received))
+                .map_err(ConnectionError::Disconnected)?;
+
+            let more_data = DataHeader::parse(&received).is_ok_and(|header| header.md)
+                || self.state.has_pending();
+            if !more_data || !self.state.fits_in_event(Instant::now()) {
+                break;
+            }
+        }
+
+        self.state
+            .check_timeout(Instant::now())
+            .map_err(ConnectionError::Disconnected)?;
+        self.state.next_event(0);
+
+        Ok(())
+    }
+
+    /// Answer the received packet, acknowledging it.
+    /// Returns if any device has more data to send on the connection event.
+    async fn respond(
+        &mut self,
+        received: &mut [u8],
+        now: Instant,
+    ) -> Result<bool, ConnectionError<R::Error>> {
+        // A packet failing the MIC check is not answered
+        self.state
+            .open(self.radio, received)
+            .map_err(ConnectionError::Disconnected)?;
+
+        // The packet is acknowledged even when the connection is terminated
+        let result = self.state.on_packet(received);
+        let central_more_data = DataHeader::parse(received).is_ok_and(|header| header.md);
+
+        let mut buffer = [0u8; MAX_PDU_LENGTH];
+        self.state.next_pdu(now, &mut buffer);
+        let len = self.state.seal(self.radio, &mut buffer);
+        self.radio
+            .transmit(&buffer[..len])
+            .await
+            .map_err(ConnectionError::Radio)?;
+
+        result.map_err(ConnectionError::Disconnected)?;
+
+        let more_data = DataHeader::parse(&buffer).is_ok_and(|header| header.md);
+        Ok(central_more_data || more_data)
+    }
+
+    /// Queue a data PDU to be sent on the next connection events
+    pub fn send(&mut self, pdu: &DataPdu) -> Result<(), SendError> {
+        self.state.tx.push(pdu)
+    }
+
+    /// Read the oldest data PDU received, copying it to the buffer
+    pub fn receive<'b>(&mut self, buffer: &'b mut [u8]) -> Option<DataPdu<'b>> {
+        let len = self.state.rx.front()?.bytes(buffer);
+        self.state.rx.pop();
+
+        DataPdu::parse(&buffer[..len]).ok()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        ll::{ChannelMapUpdate, ConnectInd, LlControl, Llid},
+        phy::{Channel, ChannelMap, Mode},
+        testing::{block_on, now},
+    };
+
+    /// Peer receiving the packets with a CRC error while `crc_errors` is not zero,
+    /// then empty PDUs
+    struct NoisyRadio {
+        crc_errors: usize,
+    }
+
+    impl Radio for NoisyRadio {
+        type Error = ();
+
+        fn set_mode(&mut self, _mode: Mode) {}
+        fn set_tx_power(&mut self, _power_db: i8) {}
+        fn set_header_size(&mut self, _header_size: HeaderSize) {}
+        fn set_access_address(&mut self, _access_address: u32) {}
+        fn set_channel(&mut self, _channel: Channel) {}
+        fn set_crc_poly(&mut self, _crc_poly: u32) {}
+        fn set_crc_init(&mut self, _crc_init: u32) {}
+
+        async fn transmit(&mut self, _buffer: &[u8]) -> Result<(), ()> {
+            Ok(())
+        }
+
+        async fn receive(&mut self, buffer: &mut [u8]) -> Result<(), ()> {
+            if self.crc_errors > 0 {
+                self.crc_errors -= 1;
+                return Err(());
+            }
+
+            buffer[..2].copy_from_slice(&[Llid::Continuation as u8, 0]);
+            Ok(())
+        }
+
+        fn device_address(&self) -> Address {
+            Address::new_random(0xffe1e8d0dc27)
+        }
+    }
+
+    fn connection() -> Connection {
+        connection_at(Instant::from_ticks(0), Role::Peripheral)
+    }
+
+    fn connection_at(timestamp: Instant, role: Role) -> Connection {
+        let ll_data = LLData {
+            access_address: 0x50654DE1,
+            crc_init: 0x8F_A3_55,
+            win_size: 2,
+            win_offset: 3,
+            interval: 24,
+            latency: 2,
+            timeout: 72,
+            channel_map: ChannelMap::all(),
+            hop: 9,
+            sca: 5,
+        };
+        let pdu = ConnectInd::new(
+            Address::new_random(0xffe1e8d0dc28),
+            Address::new_random(0xffe1e8d0dc27),
+            ll_data,
+            false,
+        );
+
+        Connection::new(ConnectRequest { pdu, timestamp }, false, role)
+    }
+
+    #[test]
+    fn widening() {
+        // 50 ppm + 500 ppm over 1 s
+        assert_eq!(
+            window_widening(5, Duration::from_secs(1)),
+            Duration::from_micros(550) + T_SCA + T_ACA
+        );
+    }
+
+    #[test]
+    fn transmit_window() {
+        let connection = connection();
+
+        // 1.25 ms delay + 3 * 1.25 ms offset
+        let expected_start = Instant::from_micros(5_000);
+        assert_eq!(connection.anchor, expected_start);
+
+        let (start, end) = connection.receive_window();
+        assert!(start < expected_start);
+        // transmitWindowSize of 2.5 ms
+        assert!(end > expected_start + Duration::from_micros(2_500));
+    }
+
+    #[test]
+    fn anchor_and_latency() {
+        let mut connection = connection();
+        connection.on_anchor(Instant::from_micros(6_000), 2);
+        assert_eq!(connection.anchor, Instant::from_micros(6_000) - air_time(2));
+
+        connection.next_event(connection.events_to_skip());
+        assert_eq!(connection.event_counter, 3);
+        // Channel Selection Algorithm #1, hop 9 on each event
+        assert_eq!(connection.channel, DataChannel::Ch36);
+        assert_eq!(
+            connection.anchor,
+            Instant::from_micros(6_000) - air_time(2) + Duration::from_micros(3 * 30_000)
+        );
+    }
+
+    #[test]
+    fn supervision_timeout() {
+        let mut connection = connection();
+        assert_eq!(
+            connection.check_timeout(Instant::from_micros(5_000 + 6 * 30_000)),
+            Err(DisconnectReason::ConnectionFailedToBeEstablished)
+        );
+
+        connection.on_anchor(Instant::from_micros(6_000), 2);
+        assert_eq!(connection.check_timeout(Instant::from_millis(700)), Ok(()));
+        assert_eq!(
+            connection.check_timeout(Instant::from_millis(727)),
+            Err(DisconnectReason::ConnectionTimeout)
+        );
+    }
+
+    #[test]
+    fn answer_control_procedure() {
+        let mut connection = connection();
+        let now = Instant::from_ticks(0);
+        let mut buffer = [0u8; MAX_PDU_LENGTH];
+
+        // LL_VERSION_IND from the central, SN 0 NESN 0
+        let request = [0x03, 6, 0x0C, 0x0D, 0x59, 0x00, 0x34, 0x12];
+        connection.on_packet(&request).unwrap();
+        let len = connection.next_pdu(now, &mut buffer);
+        let response = buffer;
+        let response = ControlPdu::parse(&response[..len]).unwrap();
+        assert_eq!(response.opcode(), 0x0C);
+        assert!(response.header().nesn);
+        assert!(!response.header().sn);
+
+        // The central missed the response and retransmits the request
+        connection.on_packet(&request).unwrap();
+        let len = connection.next_pdu(now, &mut buffer);
+        assert_eq!(ControlPdu::parse(&buffer[..len]).unwrap(), response);
+
+        // Empty PDU acknowledging the response, SN 1 NESN 1
+        connection.on_packet(&[0b0000_1101, 0]).unwrap();
+        let len = connection.next_pdu(now, &mut buffer);
+        assert_eq!(buffer[..len], [0b0000_1001, 0]);
+    }
+
+    #[test]
+    fn connection_update() {
+        let mut connection = connection();
+        connection.on_anchor(Instant::from_micros(6_000), 2);
+        let anchor = connection.anchor;
+
+        let update = ConnectionUpdate {
+            win_size: 2,
+            win_offset: 4,
+            interval: 40,
+            latency: 0,
+            timeout: 100,
+            instant: 2,
+        };
+        let mut buffer = [0u8; 32];
+        let len = LlControl::ConnectionUpdateInd(update).bytes(&mut buffer);
+        connection.on_packet(&buffer[..len]).unwrap();
+
+        // The latency is 2 but the peripheral shall listen on the instant
+        assert_eq!(connection.events_to_skip(), 1);
+        connection.next_event(connection.events_to_skip());
+        assert_eq!(connection.event_counter, 2);
+        assert_eq!(connection.interval(), Duration::from_millis(50));
+        // Old interval of 30 ms after the previous event and the offset of 5 ms
+        assert_eq!(
+            connection.anchor,
+            anchor + Duration::from_millis(30 + 30 + 5)
+        );
+        assert_eq!(connection.transmit_window, Duration::from_micros(2_500));
+    }
+
+    #[test]
+    fn channel_map_update() {
+        let mut connection = connection();
+        let mut buffer = [0u8; 32];
+        let update = ChannelMapUpdate {
+            channel_map: ChannelMap::new([0x01, 0, 0, 0, 0x10]),
+            instant: 1,
+        };
+        let len = LlControl::ChannelMapInd(update).bytes(&mut buffer);
+        connection.on_packet(&buffer[..len]).unwrap();
+
+        connection.next_event(0);
+        assert_eq!(connection.parameters.channel_map, update.channel_map);
+        assert!(matches!(
+            connection.channel,
+            DataChannel::Ch0 | DataChannel::Ch36
+        ));
+    }
+
+    #[test]
+    fn retransmit_until_acknowledged() {
+        let mut connection = connection();
+        let now = Instant::from_ticks(0);
+        let mut buffer = [0u8; MAX_PDU_LENGTH];
+
+        connection
+            .tx
+            .push(&DataPdu::new(Llid::Start, &[1, 2]))
+            .unwrap();
+        connection
+            .tx
+            .push(&DataPdu::new(Llid::Start, &[3]))
+            .unwrap();
+
+        // Empty PDU from the central, SN 0 NESN 0
+        connection.on_packet(&[0b0000_0001, 0]).unwrap();
+        let len = connection.next_pdu(now, &mut buffer);
+        // SN 0, NESN 1 and more data
+        assert_eq!(buffer[..len], [0b0001_0110, 2, 1, 2]);
+
+        // The central did not receive it, NESN 0
+        connection.on_packet(&[0b0000_1001, 0]).unwrap();
+        let len = connection.next_pdu(now, &mut buffer);
+        assert_eq!(buffer[..len], [0b0001_0010, 2, 1, 2]);
+
+        // Acknowledged, NESN 1
+        connection.on_packet(&[0b0000_0101, 0]).unwrap();
+        let len = connection.next_pdu(now, &mut buffer);
+        assert_eq!(buffer[..len], [0b0000_1110, 1, 3]);
+    }
+
+    #[test]
+    fn receive_queue_full() {
+        let mut connection = connection();
+        let now = Instant::from_ticks(0);
+        let mut buffer = [0u8; MAX_PDU_LENGTH];
+
+        for _ in 0..RX_QUEUE_SIZE {
+            connection
+                .rx
+                .push(&DataPdu::new(Llid::Start, &[0]))
+                .unwrap();
+        }
+
+        // Not acknowledged, NESN stays 0
+        connection.on_packet(&[0b0000_0010, 1, 0xAA]).unwrap();
+        let len = connection.next_pdu(now, &mut buffer);
+        assert_eq!(buffer[..len], [0b0000_0001, 0]);
+
+        connection.rx.pop();
+        connection.on_packet(&[0b0000_0010, 1, 0xAA]).unwrap();
+        let len = connection.next_pdu(now, &mut buffer);
+        assert_eq!(buffer[..len], [0b0000_0101, 0]);
+        assert_eq!(connection.rx.len(), RX_QUEUE_SIZE);
+    }
+
+    #[test]
+    fn payload_too_long() {
+        let mut connection = connection();
+        let now = Instant::from_ticks(0);
+        let mut buffer = [0u8; MAX_PDU_LENGTH];
+
+        // Not acknowledged nor stored, NESN stays 0
+        let mut received = [0u8; 2 + DEFAULT_PAYLOAD_LENGTH + 1];
+        received[..2].copy_from_slice(&[0b0000_0010, DEFAULT_PAYLOAD_LENGTH as u8 + 1]);
+        connection.on_packet(&received).unwrap();
+        let len = connection.next_pdu(now, &mut buffer);
+        assert_eq!(buffer[..len], [0b0000_0001, 0]);
+        assert!(connection.rx.is_empty());
+
+        // Nor sent
+        let payload = [0u8; DEFAULT_PAYLOAD_LENGTH + 1];
+        assert_eq!(
+            connection.tx.push(&DataPdu::new(Llid::Start, &payload)),
+            Err(SendError::PayloadTooLong)
+        );
+    }
+
+    #[test]
+    fn crc_error() {
+        let mut radio = NoisyRadio { crc_errors: 1 };
+        let mut ll =
+            LinkLayer::enter_connection(&mut radio, connection_at(now(), Role::Peripheral));
+        let anchor = ll.state.anchor;
+
+        // The packet is not acknowledged, the anchor point moves by one interval
+        block_on(ll.connection_event()).unwrap();
+        assert_eq!(ll.state.event_counter, 1);
+        assert_eq!(ll.state.anchor, anchor + ll.state.interval());
+        assert!(!ll.state.established);
+
+        block_on(ll.connection_event()).unwrap();
+        assert!(ll.state.established);
+
+        // Only the supervision timeout drops the link
+        ll.radio.crc_errors = usize::MAX;
+        let error = loop {
+            if let Err(error) = block_on(ll.connection_event()) {
+                break error;
+            }
+        };
+        assert_eq!(
+            error,
+            ConnectionError::Disconnected(DisconnectReason::ConnectionTimeout)
+        );
+
+        // The central closes the connection event
+        let mut radio = NoisyRadio { crc_errors: 1 };
+        let connection = connection_at(now(), Role::Central);
+        let mut ll = LinkLayer::enter_connection(&mut radio, connection);
+        block_on(ll.connection_event()).unwrap();
+        assert_eq!(ll.state.event_counter, 1);
+    }
+}
@@ -0,0 +1,782 @@
+//! LL Control procedures
+//!
+//! Each device can have a single procedure initiated by itself in progress,
+//! the procedures initiated by the peer are answered automatically.
+//! The response is sent on a following packet, so a new LL Control PDU is only
+//! acknowledged when the previous one was delivered.
+//!
+//! Ref: [Core 6.B.5](https://www.bluetooth.com/wp-content/uploads/Files/Specification/HTML/Core-54/out/en/low-energy-controller/link-layer-specification.html)
+
+use defmt::Format;
+use embassy_time::{Duration, Instant};
+
+use crate::{
+    ll::{
+        ChannelMapUpdate, ConnectionParamReq, ConnectionParameters, ConnectionUpdate,
+        ControlOpcode, ControlPdu, FeatureSet, LinkLayer, LlControl, ParseError, Version,
+    },
+    phy::Radio,
+};
+
+use super::{Connection, DisconnectReason, Encryption, Role};
+
+/// Time the peer has to answer a procedure
+///
+/// Ref: Core 6.B.5.2
+pub(super) const PROCEDURE_RESPONSE_TIMEOUT: Duration = Duration::from_secs(40);
+
+/// Error code sent when the user terminates the connection
+const REMOTE_USER_TERMINATED_CONNECTION: u8 = 0x13;
+
+/// Error code sent when the requested connection parameters are not valid
+const INVALID_LL_PARAMETERS: u8 = 0x1E;
+
+/// Connection events between the LL_CONNECTION_UPDATE_IND and its instant,
+/// giving time to the peripheral to acknowledge it
+const UPDATE_INSTANT_OFFSET: u16 = 6;
+
+/// Link Layer features supported by this device
+pub const LOCAL_FEATURES: FeatureSet = FeatureSet::LE_ENCRYPTION
+    .union(FeatureSet::CONNECTION_PARAMETERS_REQUEST)
+    .union(FeatureSet::EXTENDED_REJECT_INDICATION)
+    .union(FeatureSet::PERIPHERAL_INITIATED_FEATURES_EXCHANGE)
+    .union(FeatureSet::CHANNEL_SELECTION_ALGORITHM_2);
+
+/// The instant shall be in the future, less than 32767 events after the current one
+///
+/// Ref: Core 6.B.5.5.1
+fn is_future(instant: u16, event_counter: u16) -> bool {
+    // An instant on the current event is also passed, it can not be applied anymore
+    (1..32767).contains(&instant.wrapping_sub(event_counter))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Format)]
+pub enum ProcedureError {
+    /// Another procedure initiated by this device is in progress
+    Busy,
+
+    /// The peer does not support the procedure
+    Unsupported,
+
+    /// There is no LTK to encrypt the link
+    KeyMissing,
+
+    /// The parameters are out of their valid ranges
+    InvalidParameters,
+}
+
+/// Procedure initiated by this device
+struct LocalProcedure {
+    request: LlControl,
+
+    /// Set when the request is sent, the peer shall answer before it
+    deadline: Option<Instant>,
+}
+
+pub(super) struct Procedures {
+    role: Role,
+
+    /// LL Control PDU being sent, retransmitted until acknowledged
+    tx: Option<LlControl>,
+
+    /// Procedure initiated by this device waiting for the response
+    local: Option<LocalProcedure>,
+
+    /// Error code of the termination requested by the host
+    terminate: Option<u8>,
+
+    peer_features: Option<FeatureSet>,
+
+    /// Features supported by both devices
+    features_used: FeatureSet,
+
+    peer_version: Option<Version>,
+
+    /// The LL_VERSION_IND is only sent once on the connection
+    version_sent: bool,
+
+    /// Connection parameters to apply at the instant
+    connection_update: Option<ConnectionUpdate>,
+
+    /// Channel map to apply at the instant
+    channel_map_update: Option<ChannelMapUpdate>,
+
+    /// Encryption procedures and the encryption of the PDUs
+    pub(super) encryption: Encryption,
+}
+
+impl Procedures {
+    /// The `seed` generates the random values of the encryption
+    pub(super) fn new(role: Role, seed: u64) -> Self {
+        Self {
+            role,
+            tx: None,
+            local: None,
+            terminate: None,
+            peer_features: None,
+            features_used: FeatureSet::empty(),
+            peer_version: None,
+            version_sent: false,
+            connection_update: None,
+            channel_map_update: None,
+            encryption: Encryption::new(role, seed),
+        }
+    }
+
+    /// There is a LL Control PDU to send
+    pub(super) fn has_pending(&self) -> bool {
+        self.tx.is_some()
+            || self.terminate.is_some()
+            || self.encryption.has_pending()
+            || self.local.as_ref().is_some_and(|p| p.deadline.is_none())
+    }
+
+    /// A new LL Control PDU can be answered
+    pub(super) fn is_ready(&self) -> bool {
+        self.tx.is_none() && !self.encryption.has_pending()
+    }
+
+    /// The last LL Control PDU sent was acknowledged by the peer
+    pub(super) fn on_acknowledged(&mut self) -> Result<(), DisconnectReason> {
+        match self.tx.take() {
+            Some(LlControl::TerminateInd(_)) => Err(DisconnectReason::LocalHostTerminated),
+            _ => Ok(()),
+        }
+    }
+
+    /// Handle a LL Control PDU received from the peer on the connection event `event_counter`
+    pub(super) fn on_control(
+        &mut self,
+        pdu: &ControlPdu,
+        event_counter: u16,
+    ) -> Result<(), DisconnectReason> {
+        let control = match LlControl::parse(pdu) {
+            Ok(control) => control,
+            Err(ParseError::InvalidType) => {
+                self.tx = Some(LlControl::UnknownRsp(pdu.opcode()));
+                return Ok(());
+            }
+            // Malformed PDUs of a supported opcode are dropped
+            Err(_) => return Ok(()),
+        };
+        if self.encryption.on_control(&control) {
+            return Ok(());
+        }
+
+        let central = self.role == Role::Central;
+        match control {
+            // Only sent by the central
+            LlControl::ConnectionUpdateInd(_) | LlControl::ChannelMapInd(_) if central => {
+                self.tx = Some(LlControl::UnknownRsp(pdu.opcode()));
+            }
+            LlControl::ConnectionUpdateInd(update) => {
+                if !is_future(update.instant, event_counter) {
+                    return Err(DisconnectReason::InstantPassed);
+                }
+                self.connection_update = Some(update);
+                self.complete(ControlOpcode::ConnectionParamReq);
+            }
+            LlControl::ChannelMapInd(update) => {
+                if !is_future(update.instant, event_counter) {
+                    return Err(DisconnectReason::InstantPassed);
+                }
+                self.channel_map_update = Some(update);
+            }
+            // The central answers with the LL_CONNECTION_UPDATE_IND,
+            // using the shortest interval of the requested range
+            LlControl::ConnectionParamReq(request) if central => {
+                self.tx = Some(self.connection_update(&request.parameters, event_counter));
+            }
+            // The central defines the parameters on the LL_CONNECTION_UPDATE_IND,
+            // any value in the requested range is accepted
+            LlControl::ConnectionParamReq(request) => {
+                self.tx = Some(LlControl::ConnectionParamRsp(request));
+            }
+            LlControl::RejectExtInd { opcode, .. } => {
+                if let Ok(opcode) = ControlOpcode::try_from(opcode) {
+                    self.complete(opcode);
+                }
+            }
+            // Rejects the procedure in progress
+            LlControl::RejectInd(_) => self.local = None,
+            LlControl::TerminateInd(code) => return Err(DisconnectReason::RemoteTerminated(code)),
+            LlControl::FeatureReq(features) => {
+                self.set_peer_features(features);
+                self.tx = Some(LlControl::FeatureRsp(Self::response_features(features)));
+            }
+            LlControl::FeatureRsp(features) => {
+                self.set_peer_features(features);
+                self.complete(ControlOpcode::FeatureReq);
+                self.complete(ControlOpcode::PeripheralFeatureReq);
+            }
+            LlControl::PeripheralFeatureReq(features) if central => {
+                self.set_peer_features(features);
+                self.tx = Some(LlControl::FeatureRsp(Self::response_features(features)));
+            }
+            LlControl::VersionInd(version) => {
+                self.peer_version = Some(version);
+                if !self.version_sent {
+                    self.version_sent = true;
+                    self.tx = Some(LlControl::VersionInd(Version::LOCAL));
+                }
+                self.complete(ControlOpcode::VersionInd);
+            }
+            LlControl::UnknownRsp(opcode) => {
+                if let Ok(opcode) = ControlOpcode::try_from(opcode) {
+                    self.complete(opcode);
+                }
+            }
+            // Only sent by the peripheral
+            LlControl::PeripheralFeatureReq(_) | LlControl::ConnectionParamRsp(_) => {
+                self.tx = Some(LlControl::UnknownRsp(pdu.opcode()));
+            }
+            LlControl::EncRsp(_) | LlControl::StartEncReq if !central => {
+                self.tx = Some(LlControl::UnknownRsp(pdu.opcode()));
+            }
+            // Only sent by the central
+            LlControl::EncReq(_) | LlControl::PauseEncReq if central => {
+                self.tx = Some(LlControl::UnknownRsp(pdu.opcode()));
+            }
+            // Not expected on the current step of the encryption procedures
+            LlControl::EncReq(_)
+            | LlControl::EncRsp(_)
+            | LlControl::StartEncReq
+            | LlControl::StartEncRsp
+            | LlControl::PauseEncReq
+            | LlControl::PauseEncRsp => {}
+        }
+
+        Ok(())
+    }
+
+    /// LL Control PDU to send on the next packet, if any
+    pub(super) fn next(&mut self, now: Instant) -> Option<&LlControl> {
+        if self.tx.is_none() {
+            if let Some(code) = self.terminate {
+                self.tx = Some(LlControl::TerminateInd(code));
+            } else if let Some(control) = self.encryption.next(now) {
+                self.tx = Some(control);
+            } else if let Some(local) = self.local.as_mut().filter(|p| p.deadline.is_none()) {
+                local.deadline = Some(now + PROCEDURE_RESPONSE_TIMEOUT);
+                self.tx = Some(local.request.clone());
+            }
+        }
+
+        self.tx.as_ref()
+    }
+
+    /// Check the procedure response timeout
+    pub(super) fn check_timeout(&self, now: Instant) -> Result<(), DisconnectReason> {
+        self.encryption.check_timeout(now)?;
+        match self.local.as_ref().and_then(|p| p.deadline) {
+            Some(deadline) if now > deadline => Err(DisconnectReason::LlResponseTimeout),
+            _ => Ok(()),
+        }
+    }
+
+    /// Connection parameters that apply on the connection event `event_counter`
+    pub(super) fn take_connection_update(
+        &mut self,
+        event_counter: u16,
+    ) -> Option<ConnectionUpdate> {
+        self.connection_update
+            .take_if(|update| update.instant == event_counter)
+    }
+
+    /// Channel map that applies on the connection event `event_counter`
+    pub(super) fn take_channel_map_update(
+        &mut self,
+        event_counter: u16,
+    ) -> Option<ChannelMapUpdate> {
+        self.channel_map_update
+            .take_if(|update| update.instant == event_counter)
+    }
+
+    /// Events until the nearest pending instant, the peripheral shall listen on it
+    pub(super) fn events_to_instant(&self, event_counter: u16) -> Option<u16> {
+        let connection = self.connection_update.map(|u| u.instant);
+        let channel_map = self.channel_map_update.map(|u| u.instant);
+
+        connection
+            .into_iter()
+            .chain(channel_map)
+            .map(|instant| instant.wrapping_sub(event_counter))
+            .min()
+    }
+
+    fn start(&mut self, request: LlControl) -> Result<(), ProcedureError> {
+        if self.local.is_some() || self.terminate.is_some() {
+            return Err(ProcedureError::Busy);
+        }
+
+        self.local = Some(LocalProcedure {
+            request,
+            deadline: None,
+        });
+        Ok(())
+    }
+
+    /// Complete the local procedure started with `opcode`
+    fn complete(&mut self, opcode: ControlOpcode) {
+        if self
+            .local
+            .as_ref()
+            .is_some_and(|p| p.request.opcode() == opcode)
+        {
+            self.local = None;
+        }
+    }
+
+    /// Accept the parameters requested by the peripheral, applying them
+    /// `UPDATE_INSTANT_OFFSET` events later. Invalid parameters are rejected.
+    ///
+    /// Ref: Core 6.B.5.1.7.2
+    fn connection_update(
+        &mut self,
+        parameters: &ConnectionParameters,
+        event_counter: u16,
+    ) -> LlControl {
+        if !parameters.is_valid() {
+            return LlControl::RejectExtInd {
+                opcode: ControlOpcode::ConnectionParamReq as u8,
+                error_code: INVALID_LL_PARAMETERS,
+            };
+        }
+
+        let update = ConnectionUpdate {
+            win_size: 1,
+            win_offset: 0,
+            interval: parameters.interval_min,
+            latency: parameters.latency,
+            timeout: parameters.timeout,
+            instant: event_counter.wrapping_add(UPDATE_INSTANT_OFFSET),
+        };
+        self.connection_update = Some(update);
+        LlControl::ConnectionUpdateInd(update)
+    }
+
+    /// Send the LL_CONNECTION_UPDATE_IND of a connection update started by the central
+    fn update(
+        &mut self,
+        parameters: &ConnectionParameters,
+        event_counter: u16,
+    ) -> Result<(), ProcedureError> {
+        if self.role == Role::Peripheral {
+            return Err(ProcedureError::Unsupported);
+        }
+        if !parameters.is_valid() {
+            return Err(ProcedureError::InvalidParameters);
+        }
+        if self.tx.is_some() || self.connection_update.is_some() || self.terminate.is_some() {
+            return Err(ProcedureError::Busy);
+        }
+
+        self.tx = Some(self.connection_update(parameters, event_counter));
+        Ok(())
+    }
+
+    fn set_peer_features(&mut self, features: FeatureSet) {
+        self.peer_features = Some(features);
+        self.features_used = LOCAL_FEATURES.intersection(features);
+    }
+
+    /// The first byte of the response has the features supported by both devices,
+    /// the others the features supported by this device.
+    ///
+    /// Ref: Core 6.B.2.4.2.10
+    fn response_features(peer: FeatureSet) -> FeatureSet {
+        let first_byte = FeatureSet::new(0xFF);
+        let others = FeatureSet::new(!0xFF);
+
+        LOCAL_FEATURES
+            .intersection(peer)
+            .intersection(first_byte)
+            .union(LOCAL_FEATURES.intersection(others))
+    }
+}
+
+impl<'r, R: Radio> LinkLayer<'r, R, Connection> {
+    /// Features of the peer, known after a feature exchange
+    pub fn peer_features(&self) -> Option<FeatureSet> {
+        self.state.control.peer_features
+    }
+
+    /// Features supported by both devices
+    pub fn features_used(&self) -> FeatureSet {
+        self.state.control.features_used
+    }
+
+    /// Version of the peer, known after a version exchange
+    pub fn peer_version(&self) -> Option<Version> {
+        self.state.control.peer_version
+    }
+
+    /// Start the feature exchange, with LL_FEATURE_REQ as central
+    /// and LL_PERIPHERAL_FEATURE_REQ as peripheral
+    ///
+    /// Ref: Core 6.B.5.1.4
+    pub fn exchange_features(&mut self) -> Result<(), ProcedureError> {
+        let control = &mut self.state.control;
+        if control.role == Role::Central {
+            return control.start(LlControl::FeatureReq(LOCAL_FEATURES));
+        }
+
+        if control
+            .peer_features
+            .is_some_and(|f| !f.contains(FeatureSet::PERIPHERAL_INITIATED_FEATURES_EXCHANGE))
+        {
+            return Err(ProcedureError::Unsupported);
+        }
+
+        control.start(LlControl::PeripheralFeatureReq(LOCAL_FEATURES))
+    }
+
+    /// Start the version exchange, it does nothing if the version was already sent
+    ///
+    /// Ref: Core 6.B.5.1.5
+    pub fn exchange_version(&mut self) -> Result<(), ProcedureError> {
+        let control = &mut self.state.control;
+        if control.version_sent {
+            return Ok(());
+        }
+
+        control.start(LlControl::VersionInd(Version::LOCAL))?;
+        control.version_sent = true;
+        Ok(())
+    }
+
+    /// Request new connection parameters with LL_CONNECTION_PARAM_REQ,
+    /// the central applies them with a connection update.
+    /// Only the peripheral requests them.
+    ///
+    /// Ref: Core 6.B.5.1.7
+    pub fn request_connection_parameters(
+        &mut self,
+        parameters: ConnectionParameters,
+    ) -> Result<(), ProcedureError> {
+        let event_counter = self.state.event_counter;
+        let control = &mut self.state.control;
+        if control.role == Role::Central
+            || control
+                .peer_features
+                .is_some_and(|f| !f.contains(FeatureSet::CONNECTION_PARAMETERS_REQUEST))
+        {
+            return Err(ProcedureError::Unsupported);
+        }
+
+        control.start(LlControl::ConnectionParamReq(ConnectionParamReq::new(
+            parameters,
+            event_counter,
+        )))
+    }
+
+    /// Apply new connection parameters with LL_CONNECTION_UPDATE_IND,
+    /// `UPDATE_INSTANT_OFFSET` events later. Only the central updates them.
+    ///
+    /// Ref: Core 6.B.5.1.1
+    pub fn update_connection(
+        &mut self,
+        parameters: ConnectionParameters,
+    ) -> Result<(), ProcedureError> {
+        let event_counter = self.state.event_counter;
+        self.state.control.update(&parameters, event_counter)
+    }
+
+    /// Terminate the connection, it is closed once the peer acknowledges it
+    /// and the next connection event returns `DisconnectReason::LocalHostTerminated`.
+    ///
+    /// Ref: Core 6.B.5.1.6
+    pub fn terminate(&mut self) {
+        self.state.control.terminate = Some(REMOTE_USER_TERMINATED_CONNECTION);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn control_pdu(control: LlControl, buffer: &mut [u8]) -> ControlPdu<'_> {
+        let len = control.bytes(buffer);
+        ControlPdu::parse(&buffer[..len]).unwrap()
+    }
+
+    #[test]
+    fn feature_exchange() {
+        let mut procedures = Procedures::new(Role::Peripheral, 0);
+        let mut buffer = [0u8; 32];
+
+        let central = FeatureSet::LE_ENCRYPTION.union(FeatureSet::CHANNEL_SELECTION_ALGORITHM_2);
+        let pdu = control_pdu(LlControl::FeatureReq(central), &mut buffer);
+        procedures.on_control(&pdu, 0).unwrap();
+
+        // The central does not support the peripheral-initiated feature exchange
+        assert_eq!(
+            procedures.next(Instant::from_ticks(0)),
+            Some(&LlControl::FeatureRsp(central))
+        );
+        assert_eq!(procedures.features_used, central);
+        assert!(!procedures.is_ready());
+
+        procedures.on_acknowledged().unwrap();
+        assert!(procedures.is_ready());
+        assert_eq!(procedures.next(Instant::from_ticks(0)), None);
+    }
+
+    #[test]
+    fn version_exchange() {
+        let mut procedures = Procedures::new(Role::Peripheral, 0);
+        let mut buffer = [0u8; 32];
+
+        let version = Version {
+            version: 0x0C,
+            company_id: 0x004C,
+            subversion: 0x0001,
+        };
+        let pdu = control_pdu(LlControl::VersionInd(version), &mut buffer);
+        procedures.on_control(&pdu, 0).unwrap();
+        assert_eq!(procedures.peer_version, Some(version));
+        assert_eq!(
+            procedures.next(Instant::from_ticks(0)),
+            Some(&LlControl::VersionInd(Version::LOCAL))
+        );
+        procedures.on_acknowledged().unwrap();
+
+        // The version is only sent once
+        procedures.on_control(&pdu, 0).unwrap();
+        assert_eq!(procedures.next(Instant::from_ticks(0)), None);
+    }
+
+    #[test]
+    fn unknown_response() {
+        let mut procedures = Procedures::new(Role::Peripheral, 0);
+
+        // LL_PING_REQ
+        let pdu = ControlPdu::parse(&[0x03, 1, 0x12]).unwrap();
+        procedures.on_control(&pdu, 0).unwrap();
+        assert_eq!(
+            procedures.next(Instant::from_ticks(0)),
+            Some(&LlControl::UnknownRsp(0x12))
+        );
+    }
+
+    #[test]
+    fn remote_terminate() {
+        let mut procedures = Procedures::new(Role::Peripheral, 0);
+        let pdu = ControlPdu::parse(&[0x03, 2, 0x02, 0x13]).unwrap();
+
+        assert_eq!(
+            procedures.on_control(&pdu, 0),
+            Err(DisconnectReason::RemoteTerminated(0x13))
+        );
+    }
+
+    #[test]
+    fn local_procedure_timeout() {
+        let mut procedures = Procedures::new(Role::Peripheral, 0);
+        let mut buffer = [0u8; 32];
+
+        procedures
+            .start(LlControl::PeripheralFeatureReq(LOCAL_FEATURES))
+            .unwrap();
+        assert_eq!(
+            procedures.start(LlControl::VersionInd(Version::LOCAL)),
+            Err(ProcedureError::Busy)
+        );
+
+        let sent = Instant::from_secs(1);
+        assert!(procedures.next(sent).is_some());
+        procedures.on_acknowledged().unwrap();
+        assert_eq!(
+            procedures.check_timeout(sent + Duration::from_secs(40)),
+            Ok(())
+        );
+        assert_eq!(
+            procedures.check_timeout(sent + Duration::from_secs(41)),
+            Err(DisconnectReason::LlResponseTimeout)
+        );
+
+        // The central does not support it
+        let pdu = control_pdu(LlControl::UnknownRsp(0x0E), &mut buffer);
+        procedures.on_control(&pdu, 0).unwrap();
+        assert!(procedures.local.is_none());
+        assert_eq!(
+            procedures.check_timeout(sent + Duration::from_secs(41)),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn instant() {
+        assert!(is_future(10, 5));
+        assert!(is_future(2, 65530));
+        assert!(!is_future(5, 5));
+        assert!(!is_future(4, 5));
+        assert!(!is_future(32767 + 5, 5));
+
+        let mut procedures = Procedures::new(Role::Peripheral, 0);
+        let mut buffer = [0u8; 32];
+        let update = ChannelMapUpdate {
+            channel_map: crate::phy::ChannelMap::new([0x03, 0, 0, 0, 0]),
+            instant: 10,
+        };
+        let pdu = control_pdu(LlControl::ChannelMapInd(update), &mut buffer);
+        procedures.on_control(&pdu, 5).unwrap();
+
+        assert_eq!(procedures.events_to_instant(5), Some(5));
+        assert_eq!(procedures.take_channel_map_update(9), None);
+        assert_eq!(procedures.take_channel_map_update(10), Some(update));
+        assert_eq!(procedures.events_to_instant(10), None);
+
+        assert_eq!(
+            procedures.on_control(&pdu, 10),
+            Err(DisconnectReason::InstantPassed)
+        );
+    }
+
+    #[test]
+    fn connection_parameters_request() {
+        let mut procedures = Procedures::new(Role::Peripheral, 0);
+        let mut buffer = [0u8; 32];
+
+        let parameters = ConnectionParameters::new(
+            Duration::from_millis(100),
+            Duration::from_millis(200),
+            0,
+            Duration::from_secs(5),
+        );
+        let request = LlControl::ConnectionParamReq(ConnectionParamReq::new(parameters, 0));
+        procedures.start(request.clone()).unwrap();
+        assert_eq!(procedures.next(Instant::from_ticks(0)), Some(&request));
+        procedures.on_acknowledged().unwrap();
+
+        // The central answers with the new parameters
+        let update = ConnectionUpdate {
+            win_size: 1,
+            win_offset: 0,
+            interval: 160,
+            latency: 0,
+            timeout: 500,
+            instant: 6,
+        };
+        let pdu = control_pdu(LlControl::ConnectionUpdateInd(update), &mut buffer);
+        procedures.on_control(&pdu, 1).unwrap();
+        assert!(procedures.local.is_none());
+        assert_eq!(procedures.take_connection_update(6), Some(update));
+    }
+
+    #[test]
+    fn central_procedures() {
+        let mut procedures = Procedures::new(Role::Central, 0);
+        let mut buffer = [0u8; 32];
+
+        // The peripheral requests new parameters, the central applies them 6 events later
+        let parameters = ConnectionParameters::new(
+            Duration::from_millis(100),
+            Duration::from_millis(200),
+            0,
+            Duration::from_secs(5),
+        );
+        let request = LlControl::ConnectionParamReq(ConnectionParamReq::new(parameters, 10));
+        procedures
+            .on_control(&control_pdu(request, &mut buffer), 10)
+            .unwrap();
+        let update = ConnectionUpdate {
+            win_size: 1,
+            win_offset: 0,
+            interval: 80,
+            latency: 0,
+            timeout: 500,
+            instant: 16,
+        };
+        assert_eq!(
+            procedures.next(Instant::from_ticks(0)),
+            Some(&LlControl::ConnectionUpdateInd(update))
+        );
+        procedures.on_acknowledged().unwrap();
+        assert_eq!(procedures.events_to_instant(10), Some(6));
+
+        // Only the central sends the LL_CONNECTION_UPDATE_IND
+        let pdu = control_pdu(LlControl::ConnectionUpdateInd(update), &mut buffer);
+        procedures.on_control(&pdu, 11).unwrap();
+        assert_eq!(
+            procedures.next(Instant::from_ticks(0)),
+            Some(&LlControl::UnknownRsp(
+                ControlOpcode::ConnectionUpdateInd as u8
+            ))
+        );
+        procedures.on_acknowledged().unwrap();
+
+        let features = FeatureSet::CHANNEL_SELECTION_ALGORITHM_2;
+        let pdu = control_pdu(LlControl::PeripheralFeatureReq(features), &mut buffer);
+        procedures.on_control(&pdu, 12).unwrap();
+        assert_eq!(
+            procedures.next(Instant::from_ticks(0)),
+            Some(&LlControl::FeatureRsp(features))
+        );
+    }
+
+    #[test]
+    fn central_connection_update() {
+        let mut procedures = Procedures::new(Role::Central, 0);
+        let parameters = ConnectionParameters {
+            interval_min: 40,
+            interval_max: 80,
+            latency: 0,
+            timeout: 400,
+        };
+
+        let invalid = ConnectionParameters {
+            interval_min: 4,
+            ..parameters
+        };
+        assert_eq!(
+            procedures.update(&invalid, 20),
+            Err(ProcedureError::InvalidParameters)
+        );
+
+        procedures.update(&parameters, 20).unwrap();
+        let update = ConnectionUpdate {
+            win_size: 1,
+            win_offset: 0,
+            interval: 40,
+            latency: 0,
+            timeout: 400,
+            instant: 26,
+        };
+        assert_eq!(
+            procedures.next(Instant::from_ticks(0)),
+            Some(&LlControl::ConnectionUpdateInd(update))
+        );
+        procedures.on_acknowledged().unwrap();
+
+        // One connection update at a time
+        assert_eq!(
+            procedures.update(&parameters, 21),
+            Err(ProcedureError::Busy)
+        );
+        assert_eq!(procedures.take_connection_update(26), Some(update));
+        procedures.update(&parameters, 27).unwrap();
+
+        // Only the central updates the connection
+        let mut procedures = Procedures::new(Role::Peripheral, 0);
+        assert_eq!(
+            procedures.update(&parameters, 20),
+            Err(ProcedureError::Unsupported)
+        );
+    }
+
+    #[test]
+    fn local_terminate() {
+        let mut procedures = Procedures::new(Role::Peripheral, 0);
+        procedures.terminate = Some(REMOTE_USER_TERMINATED_CONNECTION);
+
+        assert_eq!(
+            procedures.next(Instant::from_ticks(0)),
+            Some(&LlControl::TerminateInd(0x13))
+        );
+        assert_eq!(
+            procedures.on_acknowledged(),
+            Err(DisconnectReason::LocalHostTerminated)
+        );
+    }
+}
@@ -0,0 +1,817 @@
+//! Link Layer encryption
+//!
+//! The central starts the encryption with the LTK, both devices contribute to the
+//! session key diversifier (SKD) and to the initialization vector (IV).
+//! The non-empty data PDUs are then encrypted with AES-CCM and authenticated with a MIC,
+//! the nonce is built from the packet counter of each direction and the IV.
+//! An encrypted link is paused before being encrypted again with a new key.
+//!
+//! Ref: [Core 6.B.5.1.3](https://www.bluetooth.com/wp-content/uploads/Files/Specification/HTML/Core-54/out/en/low-energy-controller/link-layer-specification.html)
+//! and Core 6.E.2
+
+use aes::{
+    cipher::{BlockEncrypt, KeyInit},
+    Aes128,
+};
+use ccm::{
+    aead::AeadInPlace,
+    consts::{U13, U4},
+    Ccm,
+};
+use defmt::Format;
+use embassy_time::Instant;
+use rand::{rngs::SmallRng, Rng, SeedableRng};
+
+use crate::{
+    ll::{ControlOpcode, EncryptionRequest, EncryptionResponse, FeatureSet, LinkLayer, LlControl},
+    phy::Radio,
+};
+
+use super::{Connection, DisconnectReason, ProcedureError, Role, PROCEDURE_RESPONSE_TIMEOUT};
+
+/// Length of the Message Integrity Check appended to the encrypted payload
+pub const MIC_LENGTH: usize = 4;
+
+/// Error code of the reject when the peripheral has no LTK
+const PIN_OR_KEY_MISSING: u8 = 0x06;
+
+/// Error code of the failure when the peer does not support the encryption
+const UNSUPPORTED_REMOTE_FEATURE: u8 = 0x1A;
+
+/// The NESN, SN and MD bits of the header are not authenticated
+const AAD_MASK: u8 = 0b1110_0011;
+
+/// Session key of the connection, SK = e(LTK, SKD) with SKD = SKDm || SKDs,
+/// the central part in the least significant octets.
+/// The keys are big endian, as in the specification.
+///
+/// Ref: Core 6.B.5.1.3.1
+pub fn session_key(ltk: &[u8; 16], skd_central: u64, skd_peripheral: u64) -> [u8; 16] {
+    let mut block = [0u8; 16];
+    block[..8].copy_from_slice(&skd_peripheral.to_be_bytes());
+    block[8..].copy_from_slice(&skd_central.to_be_bytes());
+
+    Aes128::new(ltk.into()).encrypt_block((&mut block).into());
+    block
+}
+
+/// AES-CCM of the payload in place with the 1 octet of additional data, returning the MIC
+fn encrypt(key: &[u8; 16], nonce: &[u8; 13], aad: u8, payload: &mut [u8]) -> [u8; MIC_LENGTH] {
+    Ccm::<Aes128, U4, U13>::new(key.into())
+        .encrypt_in_place_detached(nonce.into(), &[aad], payload)
+        .unwrap()
+        .into()
+}
+
+/// Decrypt the payload in place, returns false when the MIC does not match
+fn decrypt(
+    key: &[u8; 16],
+    nonce: &[u8; 13],
+    aad: u8,
+    payload: &mut [u8],
+    mic: &[u8; MIC_LENGTH],
+) -> bool {
+    Ccm::<Aes128, U4, U13>::new(key.into())
+        .decrypt_in_place_detached(nonce.into(), &[aad], payload, mic.into())
+        .is_ok()
+}
+
+/// Event of the encryption for the host
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Format)]
+pub enum EncryptionEvent {
+    /// The central started the encryption, the peripheral shall answer
+    /// with `reply_long_term_key`. Both values are 0 for an LTK of LE Secure Connections.
+    LongTermKeyRequest { rand: u64, ediv: u16 },
+
+    /// The link is encrypted
+    Encrypted,
+
+    /// The encryption was rejected with the error code, the link is not encrypted
+    Failed(u8),
+}
+
+/// Key and packet counters of the encrypted link
+struct Session {
+    key: [u8; 16],
+
+    /// IV = IVm || IVs, the central part in the least significant octets
+    iv: [u8; 8],
+
+    /// Counter of the next new PDU sent, 39 bits
+    tx_counter: u64,
+
+    /// Counter of the next new PDU expected from the peer
+    rx_counter: u64,
+
+    /// The PDUs sent are encrypted
+    tx: bool,
+
+    /// The PDUs received are encrypted
+    rx: bool,
+}
+
+impl Session {
+    fn new(ltk: &[u8; 16], request: &EncryptionRequest, response: &EncryptionResponse) -> Self {
+        let mut iv = [0u8; 8];
+        iv[..4].copy_from_slice(&request.iv.to_le_bytes());
+        iv[4..].copy_from_slice(&response.iv.to_le_bytes());
+
+        Self {
+            key: session_key(ltk, request.skd, response.skd),
+            iv,
+            tx_counter: 0,
+            rx_counter: 0,
+            tx: false,
+            rx: false,
+        }
+    }
+
+    /// Nonce of the CCM, the packet counter with the direction bit followed by the IV
+    ///
+    /// Ref: Core 6.E.2.1
+    fn nonce(&self, counter: u64, from_central: bool) -> [u8; 13] {
+        let mut nonce = [0u8; 13];
+        nonce[..5].copy_from_slice(&counter.to_le_bytes()[..5]);
+        nonce[4] = nonce[4] & 0x7F | (from_central as u8) << 7;
+        nonce[5..].copy_from_slice(&self.iv);
+        nonce
+    }
+}
+
+/// Step of the encryption procedures
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Idle,
+
+    /// Central, the link is encrypted with another key
+    SendPauseReq,
+    /// Both roles, waiting for the LL_PAUSE_ENC_RSP of the peer
+    WaitPauseRsp,
+    /// Both roles, the central sends it unencrypted
+    SendPauseRsp,
+    /// Peripheral, the encryption is paused until a new LL_ENC_REQ
+    Paused,
+
+    /// Central, the first step of the encryption start
+    SendEncReq,
+    WaitEncRsp,
+    WaitStartEncReq,
+
+    /// Peripheral, the host is asked for the LTK once the LL_ENC_RSP is sent
+    SendEncRsp,
+    WaitLtk,
+    SendStartEncReq,
+    /// Peripheral, the host has no LTK
+    SendReject,
+
+    /// Both roles, sent encrypted
+    SendStartEncRsp,
+    WaitStartEncRsp,
+}
+
+/// Encryption start and pause procedures, and the encryption of the PDUs
+pub(super) struct Encryption {
+    role: Role,
+    state: State,
+
+    /// The peer shall complete the procedure before it
+    deadline: Option<Instant>,
+
+    /// LTK of the encryption started by the central
+    ltk: [u8; 16],
+
+    /// Values of the LL_ENC_REQ, sent by the central or received by the peripheral
+    request: EncryptionRequest,
+
+    /// Values of the LL_ENC_RSP, received by the central or sent by the peripheral
+    response: EncryptionResponse,
+
+    session: Option<Session>,
+
+    /// The last PDU sent was encrypted, its counter is used once acknowledged
+    sent_encrypted: bool,
+
+    /// The last PDU received was decrypted
+    received_encrypted: bool,
+
+    /// Reject sent when the host has no LTK
+    reject: Option<LlControl>,
+
+    event: Option<EncryptionEvent>,
+
+    /// Random SKD and IV of each procedure
+    rng: SmallRng,
+}
+
+impl Encryption {
+    pub(super) fn new(role: Role, seed: u64) -> Self {
+        let request = EncryptionRequest {
+            rand: 0,
+            ediv: 0,
+            skd: 0,
+            iv: 0,
+        };
+
+        Self {
+            role,
+            state: State::Idle,
+            deadline: None,
+            ltk: [0; 16],
+            request,
+            response: EncryptionResponse { skd: 0, iv: 0 },
+            session: None,
+            sent_encrypted: false,
+            received_encrypted: false,
+            reject: None,
+            event: None,
+            rng: SmallRng::seed_from_u64(seed),
+        }
+    }
+
+    /// The link is encrypted in both directions
+    pub(super) fn is_encrypted(&self) -> bool {
+        self.session.as_ref().is_some_and(|s| s.tx && s.rx)
+    }
+
+    /// No data PDU is sent while a procedure is in progress or the encryption is paused
+    pub(super) fn blocks_data(&self) -> bool {
+        self.state != State::Idle
+    }
+
+    /// There is a LL Control PDU to send
+    pub(super) fn has_pending(&self) -> bool {
+        matches!(
+            self.state,
+            State::SendPauseReq
+                | State::SendPauseRsp
+                | State::SendEncReq
+                | State::SendEncRsp
+                | State::SendStartEncReq
+                | State::SendReject
+                | State::SendStartEncRsp
+        )
+    }
+
+    /// Start the encryption as central, pausing it first when the link is encrypted
+    fn start(&mut self, ltk: [u8; 16], rand: u64, ediv: u16) -> Result<(), ProcedureError> {
+        if self.role != Role::Central {
+            return Err(ProcedureError::Unsupported);
+        }
+        if self.state != State::Idle {
+            return Err(ProcedureError::Busy);
+        }
+
+        self.ltk = ltk;
+        self.request = EncryptionRequest {
+            rand,
+            ediv,
+            skd: self.rng.gen(),
+            iv: self.rng.gen(),
+        };
+        self.state = match self.session {
+            Some(_) => State::SendPauseReq,
+            None => State::SendEncReq,
+        };
+        Ok(())
+    }
+
+    /// Answer the LTK request as peripheral, `None` rejects the encryption
+    fn reply(&mut self, ltk: Option<[u8; 16]>, extended_reject: bool) {
+        if self.state != State::WaitLtk {
+            return;
+        }
+
+        match ltk {
+            Some(ltk) => {
+                self.session = Some(Session::new(&ltk, &self.request, &self.response));
+                self.state = State::SendStartEncReq;
+            }
+            None => {
+                self.reject = Some(match extended_reject {
+                    true => LlControl::RejectExtInd {
+                        opcode: ControlOpcode::EncReq as u8,
+                        error_code: PIN_OR_KEY_MISSING,
+                    },
+                    false => LlControl::RejectInd(PIN_OR_KEY_MISSING),
+                });
+                self.state = State::SendReject;
+            }
+        }
+    }
+
+    /// Handle a LL Control PDU of the encryption procedures,
+    /// returns false when it is not expected on the current step
+    pub(super) fn on_control(&mut self, control: &LlControl) -> bool {
+        let central = self.role == Role::Central;
+        match (self.state, control) {
+            (State::WaitPauseRsp, LlControl::PauseEncRsp) if central => {
+                self.session = None;
+                self.state = State::SendPauseRsp;
+            }
+            (State::WaitPauseRsp, LlControl::PauseEncRsp) => {
+                self.session = None;
+                self.state = State::Paused;
+            }
+            (State::Idle, LlControl::PauseEncReq) if !central && self.is_encrypted() => {
+                self.state = State::SendPauseRsp;
+            }
+            (State::Idle | State::Paused, LlControl::EncReq(request))
+                if !central && self.session.is_none() =>
+            {
+                self.request = *request;
+                self.response = EncryptionResponse {
+                    skd: self.rng.gen(),
+                    iv: self.rng.gen(),
+                };
+                self.state = State::SendEncRsp;
+            }
+            (State::WaitEncRsp, LlControl::EncRsp(response)) => {
+                self.response = *response;
+                self.session = Some(Session::new(&self.ltk, &self.request, response));
+                self.state = State::WaitStartEncReq;
+            }
+            (State::WaitStartEncReq, LlControl::StartEncReq) => {
+                if let Some(session) = &mut self.session {
+                    session.tx = true;
+                    session.rx = true;
+                }
+                self.state = State::SendStartEncRsp;
+            }
+            (State::WaitStartEncRsp, LlControl::StartEncRsp) if central => {
+                self.complete();
+                self.event = Some(EncryptionEvent::Encrypted);
+            }
+            (State::WaitStartEncRsp, LlControl::StartEncRsp) => {
+                if let Some(session) = &mut self.session {
+                    session.tx = true;
+                }
+                self.state = State::SendStartEncRsp;
+            }
+            // The peripheral rejects the LL_ENC_REQ instead of starting the encryption
+            (State::WaitEncRsp | State::WaitStartEncReq, LlControl::RejectInd(code)) => {
+                self.fail(*code)
+            }
+            (
+                State::WaitEncRsp | State::WaitStartEncReq,
+                LlControl::RejectExtInd { opcode, error_code },
+            ) if *opcode == ControlOpcode::EncReq as u8 => self.fail(*error_code),
+            (State::WaitEncRsp, LlControl::UnknownRsp(opcode))
+                if *opcode == ControlOpcode::EncReq as u8 =>
+            {
+                self.fail(UNSUPPORTED_REMOTE_FEATURE)
+            }
+            _ => return false,
+        }
+
+        true
+    }
+
+    /// LL Control PDU to send on the next packet, if any.
+    /// The encryption of each direction changes when the PDUs are sent.
+    pub(super) fn next(&mut self, now: Instant) -> Option<LlControl> {
+        let (pdu, state) = match self.state {
+            State::SendPauseReq => (LlControl::PauseEncReq, State::WaitPauseRsp),
+            State::SendPauseRsp if self.role == Role::Central => {
+                (LlControl::PauseEncRsp, State::SendEncReq)
+            }
+            State::SendPauseRsp => {
+                // The PDUs of the central are unencrypted after this one
+                if let Some(session) = &mut self.session {
+                    session.rx = false;
+                }
+                (LlControl::PauseEncRsp, State::WaitPauseRsp)
+            }
+            State::SendEncReq => (LlControl::EncReq(self.request), State::WaitEncRsp),
+            State::SendEncRsp => {
+                self.event = Some(EncryptionEvent::LongTermKeyRequest {
+                    rand: self.request.rand,
+                    ediv: self.request.ediv,
+                });
+                (LlControl::EncRsp(self.response), State::WaitLtk)
+            }
+            State::SendStartEncReq => {
+                // The central answers with an encrypted PDU
+                if let Some(session) = &mut self.session {
+                    session.rx = true;
+                }
+                (LlControl::StartEncReq, State::WaitStartEncRsp)
+            }
+            State::SendReject => {
+                self.fail(PIN_OR_KEY_MISSING);
+                return self.reject.take();
+            }
+            State::SendStartEncRsp if self.role == Role::Central => {
+                (LlControl::StartEncRsp, State::WaitStartEncRsp)
+            }
+            State::SendStartEncRsp => {
+                self.complete();
+                self.event = Some(EncryptionEvent::Encrypted);
+                return Some(LlControl::StartEncRsp);
+            }
+            _ => return None,
+        };
+
+        self.deadline
+            .get_or_insert(now + PROCEDURE_RESPONSE_TIMEOUT);
+        self.state = state;
+        Some(pdu)
+    }
+
+    /// Check the procedure response timeout
+    pub(super) fn check_timeout(&self, now: Instant) -> Result<(), DisconnectReason> {
+        match self.deadline {
+            Some(deadline) if now > deadline => Err(DisconnectReason::LlResponseTimeout),
+            _ => Ok(()),
+        }
+    }
+
+    /// The last PDU sent was acknowledged, the next new PDU uses the next counter
+    pub(super) fn on_acknowledged(&mut self) {
+        if let Some(session) = self.session.as_mut().filter(|_| self.sent_encrypted) {
+            session.tx_counter += 1;
+        }
+        self.sent_encrypted = false;
+    }
+
+    /// The last PDU received was accepted, the next new PDU uses the next counter
+    pub(super) fn on_received(&mut self) {
+        if let Some(session) = self.session.as_mut().filter(|_| self.received_encrypted) {
+            session.rx_counter += 1;
+        }
+        self.received_encrypted = false;
+    }
+
+    /// Encrypt the serialized PDU when the transmission is encrypted,
+    /// returning its length with the MIC. Empty PDUs are never encrypted.
+    fn seal(&mut self, radio: &mut impl Radio, pdu: &mut [u8]) -> usize {
+        let len = 2 + pdu[1] as usize;
+        self.sent_encrypted = false;
+        let Some(session) = self.session.as_ref().filter(|s| s.tx && len > 2) else {
+            return len;
+        };
+
+        let nonce = session.nonce(session.tx_counter, self.role == Role::Central);
+        let aad = pdu[0] & AAD_MASK;
+        let payload = &mut pdu[2..len];
+        let mic = radio
+            .ccm_encrypt(&session.key, &nonce, aad, payload)
+            .unwrap_or_else(|| encrypt(&session.key, &nonce, aad, payload));
+
+        pdu[len..len + MIC_LENGTH].copy_from_slice(&mic);
+        pdu[1] += MIC_LENGTH as u8;
+        self.sent_encrypted = true;
+        len + MIC_LENGTH
+    }
+
+    /// Decrypt a new PDU received when the reception is encrypted, removing the MIC
+    /// from the length. A PDU that fails the MIC check terminates the connection.
+    ///
+    /// Ref: Core 6.B.5.1.3.1
+    fn open(&mut self, radio: &mut impl Radio, pdu: &mut [u8]) -> Result<(), DisconnectReason> {
+        self.received_encrypted = false;
+        let Some(session) = self.session.as_ref().filter(|s| s.rx && pdu[1] > 0) else {
+            return Ok(());
+        };
+
+        let len = 2 + pdu[1] as usize;
+        if len < 2 + MIC_LENGTH || len > pdu.len() {
+            return Err(DisconnectReason::MicFailure);
+        }
+
+        let nonce = session.nonce(session.rx_counter, self.role == Role::Peripheral);
+        let aad = pdu[0] & AAD_MASK;
+        let mic: [u8; MIC_LENGTH] = pdu[len - MIC_LENGTH..len].try_into().unwrap();
+        let payload = &mut pdu[2..len - MIC_LENGTH];
+        let valid = radio
+            .ccm_decrypt(&session.key, &nonce, aad, payload, &mic)
+            .unwrap_or_else(|| decrypt(&session.key, &nonce, aad, payload, &mic));
+        if !valid {
+            return Err(DisconnectReason::MicFailure);
+        }
+
+        pdu[1] -= MIC_LENGTH as u8;
+        self.received_encrypted = true;
+        Ok(())
+    }
+
+    fn complete(&mut self) {
+        self.state = State::Idle;
+        self.deadline = None;
+    }
+
+    fn fail(&mut self, error_code: u8) {
+        self.complete();
+        self.session = None;
+        self.event = Some(EncryptionEvent::Failed(error_code));
+    }
+}
+
+impl Connection {
+    /// Encrypt the serialized PDU to send, returning its length
+    pub(super) fn seal(&mut self, radio: &mut impl Radio, pdu: &mut [u8]) -> usize {
+        self.control.encryption.seal(radio, pdu)
+    }
+
+    /// Decrypt the PDU received, the retransmissions of the peer are not decrypted
+    /// as only their header is used
+    pub(super) fn open(
+        &mut self,
+        radio: &mut impl Radio,
+        pdu: &mut [u8],
+    ) -> Result<(), DisconnectReason> {
+        let Ok(header) = super::DataHeader::parse(pdu) else {
+            return Ok(());
+        };
+        if header.sn != self.nesn {
+            return Ok(());
+        }
+
+        self.control.encryption.open(radio, pdu)
+    }
+}
+
+impl<'r, R: Radio> LinkLayer<'r, R, Connection> {
+    /// The link is encrypted in both directions
+    pub fn is_encrypted(&self) -> bool {
+        self.state.control.encryption.is_encrypted()
+    }
+
+    /// Encrypt the link with the LTK identified by `rand` and `ediv`, both 0 for an LTK
+    /// of LE Secure Connections. An encrypted link is paused first.
+    /// Only the central starts it, the result is reported by `encryption_event`.
+    ///
+    /// Ref: Core 6.B.5.1.3.1
+    pub fn start_encryption(
+        &mut self,
+        ltk: [u8; 16],
+        rand: u64,
+        ediv: u16,
+    ) -> Result<(), ProcedureError> {
+        if self
+            .peer_features()
+            .is_some_and(|f| !f.contains(FeatureSet::LE_ENCRYPTION))
+        {
+            return Err(ProcedureError::Unsupported);
+        }
+
+        self.state.control.encryption.start(ltk, rand, ediv)
+    }
+
+    /// Answer `EncryptionEvent::LongTermKeyRequest` with the LTK,
+    /// `None` rejects the encryption when there is no key for the peer
+    pub fn reply_long_term_key(&mut self, ltk: Option<[u8; 16]>) {
+        let extended_reject = self
+            .features_used()
+            .contains(FeatureSet::EXTENDED_REJECT_INDICATION);
+        self.state.control.encryption.reply(ltk, extended_reject);
+    }
+
+    /// Take the last event of the encryption
+    pub fn encryption_event(&mut self) -> Option<EncryptionEvent> {
+        self.state.control.encryption.event.take()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        ll::{ConnectInd, ConnectRequest, DataPdu, LLData, Llid},
+        phy::{Channel, ChannelMap, HeaderSize, Mode, MAX_PDU_LENGTH},
+        Address,
+    };
+
+    /// Sample data of Core 6.C.1
+    const LTK: [u8; 16] = [
+        0x4C, 0x68, 0x38, 0x41, 0x39, 0xF5, 0x74, 0xD8, 0x36, 0xBC, 0xF3, 0x4E, 0x9D, 0xFB, 0x01,
+        0xBF,
+    ];
+
+    fn request() -> EncryptionRequest {
+        EncryptionRequest {
+            rand: 0,
+            ediv: 0,
+            skd: 0xACBDCEDFE0F10213,
+            iv: 0xBADCAB24,
+        }
+    }
+
+    fn response() -> EncryptionResponse {
+        EncryptionResponse {
+            skd: 0x0213243546576879,
+            iv: 0xDEAFBABE,
+        }
+    }
+
+    #[test]
+    fn key_derivation() {
+        let session = Session::new(&LTK, &request(), &response());
+        assert_eq!(
+            session.key,
+            [
+                0x99, 0xAD, 0x1B, 0x52, 0x26, 0xA3, 0x7E, 0x3E, 0x05, 0x8E, 0x3B, 0x8E, 0x27, 0xC2,
+                0xC6, 0x66
+            ]
+        );
+        assert_eq!(
+            session.nonce(1, true),
+            [0x01, 0x00, 0x00, 0x00, 0x80, 0x24, 0xAB, 0xDC, 0xBA, 0xBE, 0xBA, 0xAF, 0xDE]
+        );
+    }
+
+    #[test]
+    fn packet_encryption() {
+        let session = Session::new(&LTK, &request(), &response());
+
+        // LL_START_ENC_RSP of the central, the first encrypted PDU
+        let nonce = session.nonce(0, true);
+        let mut payload = [0x06];
+        let mic = encrypt(&session.key, &nonce, 0x0F & AAD_MASK, &mut payload);
+        assert_eq!(
+            [payload[0], mic[0], mic[1], mic[2], mic[3]],
+            [0x9F, 0xCD, 0xA7, 0xF4, 0x48]
+        );
+
+        assert!(decrypt(&session.key, &nonce, 0x03, &mut payload, &mic));
+        assert_eq!(payload, [0x06]);
+
+        // The counter of the other direction
+        let mut payload = [0x9F];
+        assert!(!decrypt(
+            &session.key,
+            &session.nonce(0, false),
+            0x03,
+            &mut payload,
+            &mic
+        ));
+    }
+
+    /// Radio without a CCM block, the software implementation is used
+    struct SoftwareCcm;
+
+    impl Radio for SoftwareCcm {
+        type Error = ();
+
+        fn set_mode(&mut self, _mode: Mode) {}
+        fn set_tx_power(&mut self, _power_db: i8) {}
+        fn set_header_size(&mut self, _header_size: HeaderSize) {}
+        fn set_access_address(&mut self, _access_address: u32) {}
+        fn set_channel(&mut self, _channel: Channel) {}
+        fn set_crc_poly(&mut self, _crc_poly: u32) {}
+        fn set_crc_init(&mut self, _crc_init: u32) {}
+
+        async fn transmit(&mut self, _buffer: &[u8]) -> Result<(), ()> {
+            Ok(())
+        }
+
+        async fn receive(&mut self, _buffer: &mut [u8]) -> Result<(), ()> {
+            Ok(())
+        }
+
+        fn device_address(&self) -> Address {
+            Address::new_random(0xffe1e8d0dc27)
+        }
+    }
+
+    fn connections() -> (Connection, Connection) {
+        let ll_data = LLData {
+            access_address: 0x50654DE1,
+            crc_init: 0x8F_A3_55,
+            win_size: 2,
+            win_offset: 3,
+            interval: 24,
+            latency: 0,
+            timeout: 72,
+            channel_map: ChannelMap::all(),
+            hop: 9,
+            sca: 5,
+        };
+        let request = ConnectRequest {
+            pdu: ConnectInd::new(
+                Address::new_random(0xffe1e8d0dc28),
+                Address::new_random(0xffe1e8d0dc27),
+                ll_data,
+                false,
+            ),
+            timestamp: Instant::from_ticks(0),
+        };
+
+        (
+            Connection::new(request.clone(), false, Role::Central),
+            Connection::new(request, false, Role::Peripheral),
+        )
+    }
+
+    /// A packet of the central and the answer of the peripheral,
+    /// `tamper` changes the encrypted payload of the central
+    fn exchange(
+        central: &mut Connection,
+        peripheral: &mut Connection,
+        tamper: bool,
+    ) -> Result<(), DisconnectReason> {
+        let now = Instant::from_ticks(0);
+        let mut buffer = [0u8; MAX_PDU_LENGTH];
+
+        central.next_pdu(now, &mut buffer);
+        central.seal(&mut SoftwareCcm, &mut buffer);
+        buffer[2] ^= tamper as u8;
+        peripheral.open(&mut SoftwareCcm, &mut buffer)?;
+        peripheral.on_packet(&buffer)?;
+
+        peripheral.next_pdu(now, &mut buffer);
+        peripheral.seal(&mut SoftwareCcm, &mut buffer);
+        central.open(&mut SoftwareCcm, &mut buffer)?;
+        central.on_packet(&buffer)
+    }
+
+    #[test]
+    fn encryption_start() {
+        let (mut central, mut peripheral) = connections();
+        central
+            .tx
+            .push(&DataPdu::new(Llid::Start, &[1, 2, 3]))
+            .unwrap();
+        central.control.encryption.start(LTK, 0, 0).unwrap();
+
+        // LL_ENC_REQ and LL_ENC_RSP, the data waits for the encryption
+        exchange(&mut central, &mut peripheral, false).unwrap();
+        exchange(&mut central, &mut peripheral, false).unwrap();
+        assert_eq!(
+            peripheral.control.encryption.event.take(),
+            Some(EncryptionEvent::LongTermKeyRequest { rand: 0, ediv: 0 })
+        );
+        assert!(peripheral.rx.is_empty());
+        peripheral.control.encryption.reply(Some(LTK), true);
+
+        // LL_START_ENC_REQ, then the LL_START_ENC_RSP of each device encrypted
+        for _ in 0..3 {
+            exchange(&mut central, &mut peripheral, false).unwrap();
+        }
+        assert!(central.control.encryption.is_encrypted());
+        assert!(peripheral.control.encryption.is_encrypted());
+        assert_eq!(
+            central.control.encryption.event,
+            Some(EncryptionEvent::Encrypted)
+        );
+        assert_eq!(
+            peripheral.control.encryption.event,
+            Some(EncryptionEvent::Encrypted)
+        );
+
+        exchange(&mut central, &mut peripheral, false).unwrap();
+        assert_eq!(peripheral.rx.front().unwrap().payload(), [1, 2, 3]);
+
+        // The encryption is paused before a new key is used
+        central.control.encryption.event = None;
+        central.control.encryption.start(LTK, 0, 0).unwrap();
+        for _ in 0..8 {
+            exchange(&mut central, &mut peripheral, false).unwrap();
+            if let Some(EncryptionEvent::LongTermKeyRequest { .. }) =
+                peripheral.control.encryption.event
+            {
+                assert!(!peripheral.control.encryption.is_encrypted());
+                peripheral.control.encryption.reply(Some(LTK), true);
+            }
+        }
+        assert_eq!(
+            central.control.encryption.event,
+            Some(EncryptionEvent::Encrypted)
+        );
+        assert!(peripheral.control.encryption.is_encrypted());
+
+        central.tx.push(&DataPdu::new(Llid::Start, &[4])).unwrap();
+        assert_eq!(
+            exchange(&mut central, &mut peripheral, true),
+            Err(DisconnectReason::MicFailure)
+        );
+    }
+
+    #[test]
+    fn missing_key() {
+        let (mut central, mut peripheral) = connections();
+        central
+            .control
+            .encryption
+            .start(LTK, 0x1234, 0x5678)
+            .unwrap();
+
+        exchange(&mut central, &mut peripheral, false).unwrap();
+        exchange(&mut central, &mut peripheral, false).unwrap();
+        assert_eq!(
+            peripheral.control.encryption.event.take(),
+            Some(EncryptionEvent::LongTermKeyRequest {
+                rand: 0x1234,
+                ediv: 0x5678
+            })
+        );
+        peripheral.control.encryption.reply(None, false);
+
+        exchange(&mut central, &mut peripheral, false).unwrap();
+        assert_eq!(
+            central.control.encryption.event,
+            Some(EncryptionEvent::Failed(PIN_OR_KEY_MISSING))
+        );
+        assert!(!central.control.encryption.blocks_data());
+        assert!(!peripheral.control.encryption.blocks_data());
+    }
+}
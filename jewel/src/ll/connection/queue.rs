@@ -0,0 +1,123 @@
+//! Bounded queue of data channel PDUs
+//!
+//! The PDUs are stored inline, so the connection does not need an allocator.
+//! Only the default payload length is supported, as the Data Length Update
+//! procedure is not implemented.
+
+use defmt::Format;
+
+use crate::ll::{DataPdu, DEFAULT_PAYLOAD_LENGTH};
+
+/// Largest data PDU stored, a 2 bytes header and the default payload
+const PDU_SIZE: usize = 2 + DEFAULT_PAYLOAD_LENGTH;
+
+/// Number of PDUs waiting to be sent
+pub const TX_QUEUE_SIZE: usize = 4;
+
+/// Number of PDUs received and not read yet
+pub const RX_QUEUE_SIZE: usize = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Format)]
+pub enum SendError {
+    /// The queue is full, a PDU shall be sent first
+    QueueFull,
+
+    /// The payload is longer than the default payload length
+    PayloadTooLong,
+}
+
+/// First in, first out ring buffer of data PDUs
+pub(super) struct PduQueue<const N: usize> {
+    pdus: [[u8; PDU_SIZE]; N],
+    head: usize,
+    len: usize,
+}
+
+impl<const N: usize> PduQueue<N> {
+    pub(super) const fn new() -> Self {
+        Self {
+            pdus: [[0; PDU_SIZE]; N],
+            head: 0,
+            len: 0,
+        }
+    }
+
+    pub(super) fn len(&self) -> usize {
+        self.len
+    }
+
+    pub(super) fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub(super) fn is_full(&self) -> bool {
+        self.len == N
+    }
+
+    /// Store a copy of the PDU at the end of the queue
+    pub(super) fn push(&mut self, pdu: &DataPdu) -> Result<(), SendError> {
+        if pdu.payload().len() > DEFAULT_PAYLOAD_LENGTH {
+            return Err(SendError::PayloadTooLong);
+        }
+        if self.is_full() {
+            return Err(SendError::QueueFull);
+        }
+
+        let tail = (self.head + self.len) % N;
+        pdu.bytes(&mut self.pdus[tail]);
+        self.len += 1;
+        Ok(())
+    }
+
+    /// PDU at the start of the queue
+    pub(super) fn front(&self) -> Option<DataPdu<'_>> {
+        if self.is_empty() {
+            return None;
+        }
+
+        DataPdu::parse(&self.pdus[self.head]).ok()
+    }
+
+    pub(super) fn pop(&mut self) {
+        if !self.is_empty() {
+            self.head = (self.head + 1) % N;
+            self.len -= 1;
+        }
+    }
+
+    pub(super) fn clear(&mut self) {
+        self.head = 0;
+        self.len = 0;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ll::Llid;
+
+    #[test]
+    fn fifo() {
+        let mut queue = PduQueue::<2>::new();
+        assert_eq!(queue.front(), None);
+
+        queue.push(&DataPdu::new(Llid::Start, &[1, 2])).unwrap();
+        queue.push(&DataPdu::new(Llid::Continuation, &[3])).unwrap();
+        assert!(queue.is_full());
+        assert_eq!(
+            queue.push(&DataPdu::new(Llid::Start, &[4])),
+            Err(SendError::QueueFull)
+        );
+
+        assert_eq!(queue.front(), Some(DataPdu::new(Llid::Start, &[1, 2])));
+        queue.pop();
+
+        // Wraps around the end of the buffer
+        queue.push(&DataPdu::new(Llid::Start, &[4])).unwrap();
+        assert_eq!(queue.front(), Some(DataPdu::new(Llid::Continuation, &[3])));
+        queue.pop();
+        assert_eq!(queue.front(), Some(DataPdu::new(Llid::Start, &[4])));
+        queue.pop();
+        assert!(queue.is_empty());
+    }
+}
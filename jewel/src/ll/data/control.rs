@@ -0,0 +1,838 @@
+//! LL Control PDUs
+//!
+//! Ref: [Core 6.B.2.4.2](https://www.bluetooth.com/wp-content/uploads/Files/Specification/HTML/Core-54/out/en/low-energy-controller/link-layer-specification.html)
+
+use defmt::Format;
+use embassy_time::Duration;
+
+use crate::{ll::ParseError, phy::ChannelMap};
+
+use super::ControlPdu;
+
+/// Largest CtrData of the supported LL Control PDUs
+pub const MAX_CTR_DATA_LENGTH: usize = 26;
+
+/// Opcode of the LL Control PDU
+///
+/// Ref: Core 6.B.2.4.2 Table 2.18
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Format)]
+#[repr(u8)]
+pub enum ControlOpcode {
+    ConnectionUpdateInd = 0x00,
+    ChannelMapInd = 0x01,
+    TerminateInd = 0x02,
+    EncReq = 0x03,
+    EncRsp = 0x04,
+    StartEncReq = 0x05,
+    StartEncRsp = 0x06,
+    UnknownRsp = 0x07,
+    FeatureReq = 0x08,
+    FeatureRsp = 0x09,
+    PauseEncReq = 0x0A,
+    PauseEncRsp = 0x0B,
+    VersionInd = 0x0C,
+    RejectInd = 0x0D,
+    PeripheralFeatureReq = 0x0E,
+    ConnectionParamReq = 0x0F,
+    ConnectionParamRsp = 0x10,
+    RejectExtInd = 0x11,
+    PingReq = 0x12,
+    PingRsp = 0x13,
+    LengthReq = 0x14,
+    LengthRsp = 0x15,
+    PhyReq = 0x16,
+    PhyRsp = 0x17,
+    PhyUpdateInd = 0x18,
+    MinUsedChannelsInd = 0x19,
+}
+
+impl TryFrom<u8> for ControlOpcode {
+    type Error = ParseError;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        use ControlOpcode::*;
+
+        const OPCODES: [ControlOpcode; 26] = [
+            ConnectionUpdateInd,
+            ChannelMapInd,
+            TerminateInd,
+            EncReq,
+            EncRsp,
+            StartEncReq,
+            StartEncRsp,
+            UnknownRsp,
+            FeatureReq,
+            FeatureRsp,
+            PauseEncReq,
+            PauseEncRsp,
+            VersionInd,
+            RejectInd,
+            PeripheralFeatureReq,
+            ConnectionParamReq,
+            ConnectionParamRsp,
+            RejectExtInd,
+            PingReq,
+            PingRsp,
+            LengthReq,
+            LengthRsp,
+            PhyReq,
+            PhyRsp,
+            PhyUpdateInd,
+            MinUsedChannelsInd,
+        ];
+
+        OPCODES
+            .get(value as usize)
+            .copied()
+            .ok_or(ParseError::InvalidType)
+    }
+}
+
+/// Link Layer features supported by a device, a bit field of 64 bits
+///
+/// Ref: Core 6.B.4.6
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Format)]
+pub struct FeatureSet {
+    features: u64,
+}
+
+impl FeatureSet {
+    pub const LE_ENCRYPTION: FeatureSet = FeatureSet::new(1 << 0);
+    pub const CONNECTION_PARAMETERS_REQUEST: FeatureSet = FeatureSet::new(1 << 1);
+    pub const EXTENDED_REJECT_INDICATION: FeatureSet = FeatureSet::new(1 << 2);
+    pub const PERIPHERAL_INITIATED_FEATURES_EXCHANGE: FeatureSet = FeatureSet::new(1 << 3);
+    pub const LE_PING: FeatureSet = FeatureSet::new(1 << 4);
+    pub const LE_DATA_PACKET_LENGTH_EXTENSION: FeatureSet = FeatureSet::new(1 << 5);
+    pub const LL_PRIVACY: FeatureSet = FeatureSet::new(1 << 6);
+    pub const EXTENDED_SCANNER_FILTER_POLICIES: FeatureSet = FeatureSet::new(1 << 7);
+    pub const LE_2M_PHY: FeatureSet = FeatureSet::new(1 << 8);
+    pub const CHANNEL_SELECTION_ALGORITHM_2: FeatureSet = FeatureSet::new(1 << 14);
+
+    pub const fn new(features: u64) -> Self {
+        Self { features }
+    }
+
+    pub const fn empty() -> Self {
+        Self::new(0)
+    }
+
+    pub const fn union(self, other: FeatureSet) -> Self {
+        Self::new(self.features | other.features)
+    }
+
+    pub const fn intersection(self, other: FeatureSet) -> Self {
+        Self::new(self.features & other.features)
+    }
+
+    pub fn contains(&self, other: FeatureSet) -> bool {
+        self.features & other.features == other.features
+    }
+
+    pub fn bytes(&self) -> [u8; 8] {
+        self.features.to_le_bytes()
+    }
+
+    pub fn parse(bytes: &[u8]) -> Result<Self, ParseError> {
+        let bytes = bytes.try_into().map_err(|_| ParseError::InvalidLength)?;
+        Ok(Self::new(u64::from_le_bytes(bytes)))
+    }
+}
+
+/// Version information of the Link Layer
+///
+/// Ref: Core 6.B.2.4.2.13
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Format)]
+pub struct Version {
+    /// Version of the Bluetooth Core specification, from the Assigned Numbers
+    pub version: u8,
+
+    /// Company identifier of the manufacturer, from the Assigned Numbers
+    pub company_id: u16,
+
+    /// Revision of the implementation, defined by the manufacturer
+    pub subversion: u16,
+}
+
+impl Version {
+    pub const LENGTH: usize = 5;
+
+    /// Version of this implementation
+    pub const LOCAL: Version = Version {
+        // Bluetooth Core Specification 5.4
+        version: 0x0D,
+        // Reserved for use in tests and development
+        company_id: 0xFFFF,
+        subversion: 0x0000,
+    };
+
+    pub fn bytes(&self, dest: &mut [u8]) -> usize {
+        dest[0] = self.version;
+        dest[1..3].copy_from_slice(&self.company_id.to_le_bytes());
+        dest[3..5].copy_from_slice(&self.subversion.to_le_bytes());
+        Self::LENGTH
+    }
+
+    pub fn parse(bytes: &[u8]) -> Result<Self, ParseError> {
+        if bytes.len() != Self::LENGTH {
+            return Err(ParseError::InvalidLength);
+        }
+
+        Ok(Self {
+            version: bytes[0],
+            company_id: u16::from_le_bytes([bytes[1], bytes[2]]),
+            subversion: u16::from_le_bytes([bytes[3], bytes[4]]),
+        })
+    }
+}
+
+/// Connection parameters requested by a device
+///
+/// The interval is in 1.25 ms units and the timeout in 10 ms units,
+/// the same encoding is used by the L2CAP connection parameter update.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Format)]
+pub struct ConnectionParameters {
+    pub interval_min: u16,
+    pub interval_max: u16,
+    pub latency: u16,
+    pub timeout: u16,
+}
+
+impl ConnectionParameters {
+    pub const LENGTH: usize = 8;
+
+    /// Create the parameters from durations, rounding them down to the units
+    pub fn new(
+        interval_min: Duration,
+        interval_max: Duration,
+        latency: u16,
+        timeout: Duration,
+    ) -> Self {
+        let parameters = Self {
+            interval_min: (interval_min.as_micros() / 1_250) as u16,
+            interval_max: (interval_max.as_micros() / 1_250) as u16,
+            latency,
+            timeout: (timeout.as_millis() / 10) as u16,
+        };
+        assert!(parameters.is_valid());
+
+        parameters
+    }
+
+    /// Check the ranges of the parameters
+    ///
+    /// Ref: Core 6.B.2.4.2.16
+    pub fn is_valid(&self) -> bool {
+        let interval_ok = (6..=3200).contains(&self.interval_min)
+            && (self.interval_min..=3200).contains(&self.interval_max);
+        let latency_ok = self.latency <= 499;
+
+        // connSupervisionTimeout shall be larger than (1 + connPeripheralLatency) * connInterval_Max * 2
+        let timeout_ok = (10..=3200).contains(&self.timeout)
+            && (self.timeout as u32) * 8
+                > (1 + self.latency as u32) * (self.interval_max as u32) * 2;
+
+        interval_ok && latency_ok && timeout_ok
+    }
+
+    pub fn bytes(&self, dest: &mut [u8]) -> usize {
+        dest[0..2].copy_from_slice(&self.interval_min.to_le_bytes());
+        dest[2..4].copy_from_slice(&self.interval_max.to_le_bytes());
+        dest[4..6].copy_from_slice(&self.latency.to_le_bytes());
+        dest[6..8].copy_from_slice(&self.timeout.to_le_bytes());
+        Self::LENGTH
+    }
+
+    pub fn parse(bytes: &[u8]) -> Result<Self, ParseError> {
+        if bytes.len() < Self::LENGTH {
+            return Err(ParseError::InvalidLength);
+        }
+
+        let parameters = Self {
+            interval_min: u16::from_le_bytes([bytes[0], bytes[1]]),
+            interval_max: u16::from_le_bytes([bytes[2], bytes[3]]),
+            latency: u16::from_le_bytes([bytes[4], bytes[5]]),
+            timeout: u16::from_le_bytes([bytes[6], bytes[7]]),
+        };
+        if !parameters.is_valid() {
+            return Err(ParseError::InvalidValue);
+        }
+
+        Ok(parameters)
+    }
+}
+
+/// CtrData of LL_CONNECTION_UPDATE_IND, the new parameters apply at the instant
+///
+///   ┌──────────┬───────────┬──────────┬──────────┬──────────┬──────────┐
+///   │ WinSize  │ WinOffset │ Interval │ Latency  │ Timeout  │ Instant  │
+///   │ (1 byte) │ (2 bytes) │ (2 bytes)│ (2 bytes)│ (2 bytes)│ (2 bytes)│
+///   └──────────┴───────────┴──────────┴──────────┴──────────┴──────────┘
+///
+/// Ref: Core 6.B.2.4.2.1
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Format)]
+pub struct ConnectionUpdate {
+    pub win_size: u8,
+    pub win_offset: u16,
+    pub interval: u16,
+    pub latency: u16,
+    pub timeout: u16,
+    pub instant: u16,
+}
+
+impl ConnectionUpdate {
+    pub const LENGTH: usize = 11;
+
+    pub fn bytes(&self, dest: &mut [u8]) -> usize {
+        dest[0] = self.win_size;
+        dest[1..3].copy_from_slice(&self.win_offset.to_le_bytes());
+        dest[3..5].copy_from_slice(&self.interval.to_le_bytes());
+        dest[5..7].copy_from_slice(&self.latency.to_le_bytes());
+        dest[7..9].copy_from_slice(&self.timeout.to_le_bytes());
+        dest[9..11].copy_from_slice(&self.instant.to_le_bytes());
+        Self::LENGTH
+    }
+
+    pub fn parse(bytes: &[u8]) -> Result<Self, ParseError> {
+        if bytes.len() != Self::LENGTH {
+            return Err(ParseError::InvalidLength);
+        }
+
+        let u16_at = |i: usize| u16::from_le_bytes([bytes[i], bytes[i + 1]]);
+        let update = Self {
+            win_size: bytes[0],
+            win_offset: u16_at(1),
+            interval: u16_at(3),
+            latency: u16_at(5),
+            timeout: u16_at(7),
+            instant: u16_at(9),
+        };
+        if !update.is_valid() {
+            return Err(ParseError::InvalidValue);
+        }
+
+        Ok(update)
+    }
+
+    fn is_valid(&self) -> bool {
+        let interval_ok = (6..=3200).contains(&self.interval);
+        let win_size_ok = self.win_size >= 1
+            && self.win_size <= 8
+            && (self.win_size as u16) < self.interval.saturating_sub(1);
+        let win_offset_ok = self.win_offset <= self.interval;
+        let latency_ok = self.latency <= 499;
+        let timeout_ok = (10..=3200).contains(&self.timeout)
+            && (self.timeout as u32) * 8 > (1 + self.latency as u32) * (self.interval as u32) * 2;
+
+        interval_ok && win_size_ok && win_offset_ok && latency_ok && timeout_ok
+    }
+}
+
+/// CtrData of LL_CHANNEL_MAP_IND, the new map applies at the instant
+///
+/// Ref: Core 6.B.2.4.2.2
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Format)]
+pub struct ChannelMapUpdate {
+    pub channel_map: ChannelMap,
+    pub instant: u16,
+}
+
+impl ChannelMapUpdate {
+    pub const LENGTH: usize = 7;
+
+    pub fn bytes(&self, dest: &mut [u8]) -> usize {
+        dest[0..5].copy_from_slice(&self.channel_map.bytes());
+        dest[5..7].copy_from_slice(&self.instant.to_le_bytes());
+        Self::LENGTH
+    }
+
+    pub fn parse(bytes: &[u8]) -> Result<Self, ParseError> {
+        if bytes.len() != Self::LENGTH {
+            return Err(ParseError::InvalidLength);
+        }
+
+        let channel_map = ChannelMap::new(bytes[0..5].try_into().unwrap());
+        if channel_map.len() < 2 {
+            return Err(ParseError::InvalidValue);
+        }
+
+        Ok(Self {
+            channel_map,
+            instant: u16::from_le_bytes([bytes[5], bytes[6]]),
+        })
+    }
+}
+
+/// CtrData of LL_CONNECTION_PARAM_REQ and LL_CONNECTION_PARAM_RSP
+///
+///   ┌────────────────┬─────────────────────┬─────────────────────────┬───────────────────┐
+///   │ Parameters     │ PreferredPeriodicity│ ReferenceConnEventCount │ Offset0..Offset5  │
+///   │ (8 bytes)      │ (1 byte)            │ (2 bytes)               │ (12 bytes)        │
+///   └────────────────┴─────────────────────┴─────────────────────────┴───────────────────┘
+///
+/// Ref: Core 6.B.2.4.2.16
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Format)]
+pub struct ConnectionParamReq {
+    pub parameters: ConnectionParameters,
+
+    /// The interval should be a multiple of it, 0 when it is not relevant
+    pub preferred_periodicity: u8,
+
+    /// Connection event counter the offsets are relative to
+    pub reference_event_counter: u16,
+
+    /// Preferred anchor point offsets, 0xFFFF when not used
+    pub offsets: [u16; 6],
+}
+
+impl ConnectionParamReq {
+    pub const LENGTH: usize = 23;
+
+    pub fn new(parameters: ConnectionParameters, reference_event_counter: u16) -> Self {
+        Self {
+            parameters,
+            preferred_periodicity: 0,
+            reference_event_counter,
+            offsets: [0xFFFF; 6],
+        }
+    }
+
+    pub fn bytes(&self, dest: &mut [u8]) -> usize {
+        self.parameters.bytes(dest);
+        dest[8] = self.preferred_periodicity;
+        dest[9..11].copy_from_slice(&self.reference_event_counter.to_le_bytes());
+        for (i, offset) in self.offsets.iter().enumerate() {
+            dest[11 + 2 * i..13 + 2 * i].copy_from_slice(&offset.to_le_bytes());
+        }
+        Self::LENGTH
+    }
+
+    pub fn parse(bytes: &[u8]) -> Result<Self, ParseError> {
+        if bytes.len() != Self::LENGTH {
+            return Err(ParseError::InvalidLength);
+        }
+
+        let u16_at = |i: usize| u16::from_le_bytes([bytes[i], bytes[i + 1]]);
+        Ok(Self {
+            parameters: ConnectionParameters::parse(&bytes[0..8])?,
+            preferred_periodicity: bytes[8],
+            reference_event_counter: u16_at(9),
+            offsets: core::array::from_fn(|i| u16_at(11 + 2 * i)),
+        })
+    }
+}
+
+/// CtrData of LL_ENC_REQ, sent by the central to start the encryption
+///
+///   ┌───────────┬───────────┬───────────┬───────────┐
+///   │ Rand      │ EDIV      │ SKDm      │ IVm       │
+///   │ (8 bytes) │ (2 bytes) │ (8 bytes) │ (4 bytes) │
+///   └───────────┴───────────┴───────────┴───────────┘
+///
+/// Ref: Core 6.B.2.4.2.4
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Format)]
+pub struct EncryptionRequest {
+    /// Random number identifying the LTK, 0 for an LTK of LE Secure Connections
+    pub rand: u64,
+
+    /// Encrypted diversifier identifying the LTK, 0 for an LTK of LE Secure Connections
+    pub ediv: u16,
+
+    /// Central part of the session key diversifier
+    pub skd: u64,
+
+    /// Central part of the initialization vector
+    pub iv: u32,
+}
+
+impl EncryptionRequest {
+    pub const LENGTH: usize = 22;
+
+    pub fn bytes(&self, dest: &mut [u8]) -> usize {
+        dest[0..8].copy_from_slice(&self.rand.to_le_bytes());
+        dest[8..10].copy_from_slice(&self.ediv.to_le_bytes());
+        dest[10..18].copy_from_slice(&self.skd.to_le_bytes());
+        dest[18..22].copy_from_slice(&self.iv.to_le_bytes());
+        Self::LENGTH
+    }
+
+    pub fn parse(bytes: &[u8]) -> Result<Self, ParseError> {
+        if bytes.len() != Self::LENGTH {
+            return Err(ParseError::InvalidLength);
+        }
+
+        Ok(Self {
+            rand: u64::from_le_bytes(bytes[0..8].try_into().unwrap()),
+            ediv: u16::from_le_bytes([bytes[8], bytes[9]]),
+            skd: u64::from_le_bytes(bytes[10..18].try_into().unwrap()),
+            iv: u32::from_le_bytes(bytes[18..22].try_into().unwrap()),
+        })
+    }
+}
+
+/// CtrData of LL_ENC_RSP, the peripheral parts of the session key diversifier
+/// and of the initialization vector
+///
+/// Ref: Core 6.B.2.4.2.5
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Format)]
+pub struct EncryptionResponse {
+    pub skd: u64,
+    pub iv: u32,
+}
+
+impl EncryptionResponse {
+    pub const LENGTH: usize = 12;
+
+    pub fn bytes(&self, dest: &mut [u8]) -> usize {
+        dest[0..8].copy_from_slice(&self.skd.to_le_bytes());
+        dest[8..12].copy_from_slice(&self.iv.to_le_bytes());
+        Self::LENGTH
+    }
+
+    pub fn parse(bytes: &[u8]) -> Result<Self, ParseError> {
+        if bytes.len() != Self::LENGTH {
+            return Err(ParseError::InvalidLength);
+        }
+
+        Ok(Self {
+            skd: u64::from_le_bytes(bytes[0..8].try_into().unwrap()),
+            iv: u32::from_le_bytes(bytes[8..12].try_into().unwrap()),
+        })
+    }
+}
+
+/// LL Control PDU decoded from its opcode
+#[derive(Debug, Clone, PartialEq, Eq, Format)]
+pub enum LlControl {
+    /// LL_CONNECTION_UPDATE_IND
+    ConnectionUpdateInd(ConnectionUpdate),
+
+    /// LL_CHANNEL_MAP_IND
+    ChannelMapInd(ChannelMapUpdate),
+
+    /// LL_TERMINATE_IND with the error code of the reason
+    TerminateInd(u8),
+
+    /// LL_ENC_REQ
+    EncReq(EncryptionRequest),
+
+    /// LL_ENC_RSP
+    EncRsp(EncryptionResponse),
+
+    /// LL_START_ENC_REQ
+    StartEncReq,
+
+    /// LL_START_ENC_RSP
+    StartEncRsp,
+
+    /// LL_UNKNOWN_RSP with the opcode that is not supported
+    UnknownRsp(u8),
+
+    /// LL_FEATURE_REQ with the features of the central
+    FeatureReq(FeatureSet),
+
+    /// LL_FEATURE_RSP with the features of the responder
+    FeatureRsp(FeatureSet),
+
+    /// LL_PAUSE_ENC_REQ
+    PauseEncReq,
+
+    /// LL_PAUSE_ENC_RSP
+    PauseEncRsp,
+
+    /// LL_VERSION_IND
+    VersionInd(Version),
+
+    /// LL_PERIPHERAL_FEATURE_REQ with the features of the peripheral
+    PeripheralFeatureReq(FeatureSet),
+
+    /// LL_REJECT_IND with the error code
+    RejectInd(u8),
+
+    /// LL_CONNECTION_PARAM_REQ
+    ConnectionParamReq(ConnectionParamReq),
+
+    /// LL_CONNECTION_PARAM_RSP
+    ConnectionParamRsp(ConnectionParamReq),
+
+    /// LL_REJECT_EXT_IND with the opcode of the rejected PDU and the error code
+    RejectExtInd { opcode: u8, error_code: u8 },
+}
+
+impl LlControl {
+    pub fn opcode(&self) -> ControlOpcode {
+        match self {
+            Self::ConnectionUpdateInd(_) => ControlOpcode::ConnectionUpdateInd,
+            Self::ChannelMapInd(_) => ControlOpcode::ChannelMapInd,
+            Self::TerminateInd(_) => ControlOpcode::TerminateInd,
+            Self::EncReq(_) => ControlOpcode::EncReq,
+            Self::EncRsp(_) => ControlOpcode::EncRsp,
+            Self::StartEncReq => ControlOpcode::StartEncReq,
+            Self::StartEncRsp => ControlOpcode::StartEncRsp,
+            Self::UnknownRsp(_) => ControlOpcode::UnknownRsp,
+            Self::FeatureReq(_) => ControlOpcode::FeatureReq,
+            Self::FeatureRsp(_) => ControlOpcode::FeatureRsp,
+            Self::PauseEncReq => ControlOpcode::PauseEncReq,
+            Self::PauseEncRsp => ControlOpcode::PauseEncRsp,
+            Self::VersionInd(_) => ControlOpcode::VersionInd,
+            Self::PeripheralFeatureReq(_) => ControlOpcode::PeripheralFeatureReq,
+            Self::RejectInd(_) => ControlOpcode::RejectInd,
+            Self::ConnectionParamReq(_) => ControlOpcode::ConnectionParamReq,
+            Self::ConnectionParamRsp(_) => ControlOpcode::ConnectionParamRsp,
+            Self::RejectExtInd { .. } => ControlOpcode::RejectExtInd,
+        }
+    }
+
+    /// Serialize the CtrData, the opcode is given by `opcode()`
+    pub fn ctr_data(&self, dest: &mut [u8]) -> usize {
+        match self {
+            Self::ConnectionUpdateInd(update) => update.bytes(dest),
+            Self::ChannelMapInd(update) => update.bytes(dest),
+            Self::TerminateInd(code) | Self::UnknownRsp(code) | Self::RejectInd(code) => {
+                dest[0] = *code;
+                1
+            }
+            Self::EncReq(request) => request.bytes(dest),
+            Self::EncRsp(response) => response.bytes(dest),
+            Self::StartEncReq | Self::StartEncRsp | Self::PauseEncReq | Self::PauseEncRsp => 0,
+            Self::FeatureReq(features)
+            | Self::FeatureRsp(features)
+            | Self::PeripheralFeatureReq(features) => {
+                dest[..8].copy_from_slice(&features.bytes());
+                8
+            }
+            Self::VersionInd(version) => version.bytes(dest),
+            Self::ConnectionParamReq(request) | Self::ConnectionParamRsp(request) => {
+                request.bytes(dest)
+            }
+            Self::RejectExtInd { opcode, error_code } => {
+                dest[0] = *opcode;
+                dest[1] = *error_code;
+                2
+            }
+        }
+    }
+
+    /// Serialize the complete data physical channel PDU
+    pub fn bytes(&self, dest: &mut [u8]) -> usize {
+        let mut ctr_data = [0u8; MAX_CTR_DATA_LENGTH];
+        let len = self.ctr_data(&mut ctr_data);
+        ControlPdu::new(self.opcode() as u8, &ctr_data[..len]).bytes(dest)
+    }
+
+    /// Decode the LL Control PDU.
+    /// An opcode that is unknown or not supported returns `InvalidType`.
+    pub fn parse(pdu: &ControlPdu) -> Result<Self, ParseError> {
+        let data = pdu.ctr_data();
+        let single = || match data {
+            [value] => Ok(*value),
+            _ => Err(ParseError::InvalidLength),
+        };
+        let empty = |control: Self| match data {
+            [] => Ok(control),
+            _ => Err(ParseError::InvalidLength),
+        };
+
+        match ControlOpcode::try_from(pdu.opcode())? {
+            ControlOpcode::ConnectionUpdateInd => {
+                ConnectionUpdate::parse(data).map(Self::ConnectionUpdateInd)
+            }
+            ControlOpcode::ChannelMapInd => ChannelMapUpdate::parse(data).map(Self::ChannelMapInd),
+            ControlOpcode::TerminateInd => single().map(Self::TerminateInd),
+            ControlOpcode::EncReq => EncryptionRequest::parse(data).map(Self::EncReq),
+            ControlOpcode::EncRsp => EncryptionResponse::parse(data).map(Self::EncRsp),
+            ControlOpcode::StartEncReq => empty(Self::StartEncReq),
+            ControlOpcode::StartEncRsp => empty(Self::StartEncRsp),
+            ControlOpcode::PauseEncReq => empty(Self::PauseEncReq),
+            ControlOpcode::PauseEncRsp => empty(Self::PauseEncRsp),
+            ControlOpcode::UnknownRsp => single().map(Self::UnknownRsp),
+            ControlOpcode::FeatureReq => FeatureSet::parse(data).map(Self::FeatureReq),
+            ControlOpcode::FeatureRsp => FeatureSet::parse(data).map(Self::FeatureRsp),
+            ControlOpcode::VersionInd => Version::parse(data).map(Self::VersionInd),
+            ControlOpcode::PeripheralFeatureReq => {
+                FeatureSet::parse(data).map(Self::PeripheralFeatureReq)
+            }
+            ControlOpcode::RejectInd => single().map(Self::RejectInd),
+            ControlOpcode::ConnectionParamReq => {
+                ConnectionParamReq::parse(data).map(Self::ConnectionParamReq)
+            }
+            ControlOpcode::ConnectionParamRsp => {
+                ConnectionParamReq::parse(data).map(Self::ConnectionParamRsp)
+            }
+            ControlOpcode::RejectExtInd => match data {
+                [opcode, error_code] => Ok(Self::RejectExtInd {
+                    opcode: *opcode,
+                    error_code: *error_code,
+                }),
+                _ => Err(ParseError::InvalidLength),
+            },
+            _ => Err(ParseError::InvalidType),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn roundtrip(control: LlControl, expected: &[u8]) {
+        let mut bytes = [0u8; 2 + 1 + MAX_CTR_DATA_LENGTH];
+        let len = control.bytes(&mut bytes);
+        assert_eq!(&bytes[..len], expected);
+
+        let pdu = ControlPdu::parse(&bytes[..len]).unwrap();
+        assert_eq!(LlControl::parse(&pdu), Ok(control));
+    }
+
+    #[test]
+    fn terminate_ind() {
+        roundtrip(LlControl::TerminateInd(0x13), &[0x03, 2, 0x02, 0x13]);
+    }
+
+    #[test]
+    fn feature_rsp() {
+        let features = FeatureSet::LE_ENCRYPTION.union(FeatureSet::CHANNEL_SELECTION_ALGORITHM_2);
+        roundtrip(
+            LlControl::FeatureRsp(features),
+            &[0x03, 9, 0x09, 0x01, 0x40, 0, 0, 0, 0, 0, 0],
+        );
+        assert!(features.contains(FeatureSet::LE_ENCRYPTION));
+        assert!(!features.contains(FeatureSet::LE_PING));
+    }
+
+    #[test]
+    fn version_ind() {
+        let version = Version {
+            version: 0x0D,
+            company_id: 0x0059,
+            subversion: 0x1234,
+        };
+        roundtrip(
+            LlControl::VersionInd(version),
+            &[0x03, 6, 0x0C, 0x0D, 0x59, 0x00, 0x34, 0x12],
+        );
+    }
+
+    #[test]
+    fn connection_update_ind() {
+        let update = ConnectionUpdate {
+            win_size: 2,
+            win_offset: 0x0010,
+            interval: 0x0028,
+            latency: 0,
+            timeout: 0x01F4,
+            instant: 0x1234,
+        };
+        roundtrip(
+            LlControl::ConnectionUpdateInd(update),
+            &[
+                0x03, 12, 0x00, 0x02, 0x10, 0x00, 0x28, 0x00, 0x00, 0x00, 0xF4, 0x01, 0x34, 0x12,
+            ],
+        );
+    }
+
+    #[test]
+    fn channel_map_ind() {
+        let update = ChannelMapUpdate {
+            channel_map: ChannelMap::new([0xFF, 0x00, 0x00, 0x00, 0x1F]),
+            instant: 100,
+        };
+        roundtrip(
+            LlControl::ChannelMapInd(update),
+            &[0x03, 8, 0x01, 0xFF, 0x00, 0x00, 0x00, 0x1F, 100, 0],
+        );
+
+        // At least two channels shall be used
+        let pdu = ControlPdu::parse(&[0x03, 8, 0x01, 0x01, 0, 0, 0, 0, 100, 0]).unwrap();
+        assert_eq!(LlControl::parse(&pdu), Err(ParseError::InvalidValue));
+    }
+
+    #[test]
+    fn connection_param_req() {
+        let parameters = ConnectionParameters::new(
+            Duration::from_millis(30),
+            Duration::from_millis(50),
+            4,
+            Duration::from_secs(4),
+        );
+        assert_eq!(
+            parameters,
+            ConnectionParameters {
+                interval_min: 24,
+                interval_max: 40,
+                latency: 4,
+                timeout: 400,
+            }
+        );
+
+        let mut expected = [0xFFu8; 3 + ConnectionParamReq::LENGTH];
+        expected[..14].copy_from_slice(&[
+            0x03, 24, 0x0F, 24, 0, 40, 0, 4, 0, 0x90, 0x01, 0, 0x07, 0x00,
+        ]);
+        roundtrip(
+            LlControl::ConnectionParamReq(ConnectionParamReq::new(parameters, 7)),
+            &expected,
+        );
+    }
+
+    #[test]
+    fn encryption() {
+        let request = EncryptionRequest {
+            rand: 0x0102030405060708,
+            ediv: 0x1234,
+            skd: 0xACBDCEDFE0F10213,
+            iv: 0xBADCAB24,
+        };
+        roundtrip(
+            LlControl::EncReq(request),
+            &[
+                0x03, 23, 0x03, 0x08, 0x07, 0x06, 0x05, 0x04, 0x03, 0x02, 0x01, 0x34, 0x12, 0x13,
+                0x02, 0xF1, 0xE0, 0xDF, 0xCE, 0xBD, 0xAC, 0x24, 0xAB, 0xDC, 0xBA,
+            ],
+        );
+
+        let response = EncryptionResponse {
+            skd: 0x0213243546576879,
+            iv: 0xDEAFBABE,
+        };
+        roundtrip(
+            LlControl::EncRsp(response),
+            &[
+                0x03, 13, 0x04, 0x79, 0x68, 0x57, 0x46, 0x35, 0x24, 0x13, 0x02, 0xBE, 0xBA, 0xAF,
+                0xDE,
+            ],
+        );
+
+        roundtrip(LlControl::StartEncReq, &[0x03, 1, 0x05]);
+        roundtrip(LlControl::PauseEncRsp, &[0x03, 1, 0x0B]);
+
+        let pdu = ControlPdu::parse(&[0x03, 2, 0x06, 0x00]).unwrap();
+        assert_eq!(LlControl::parse(&pdu), Err(ParseError::InvalidLength));
+    }
+
+    #[test]
+    fn reject_ext_ind() {
+        roundtrip(
+            LlControl::RejectExtInd {
+                opcode: 0x0F,
+                error_code: 0x1A,
+            },
+            &[0x03, 3, 0x11, 0x0F, 0x1A],
+        );
+    }
+
+    #[test]
+    fn unsupported_opcode() {
+        // LL_PING_REQ is known but not supported
+        let pdu = ControlPdu::parse(&[0x03, 1, 0x12]).unwrap();
+        assert_eq!(LlControl::parse(&pdu), Err(ParseError::InvalidType));
+
+        let pdu = ControlPdu::parse(&[0x03, 1, 0xFF]).unwrap();
+        assert_eq!(LlControl::parse(&pdu), Err(ParseError::InvalidType));
+    }
+
+    #[test]
+    fn invalid_length() {
+        let pdu = ControlPdu::parse(&[0x03, 3, 0x02, 0x13, 0x00]).unwrap();
+        assert_eq!(LlControl::parse(&pdu), Err(ParseError::InvalidLength));
+    }
+}
@@ -0,0 +1,220 @@
+use defmt::Format;
+
+use crate::{ll::ParseError, phy::HeaderSize};
+
+/// Link Layer Identifier, the kind of the data physical channel PDU
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Format)]
+#[repr(u8)]
+pub enum Llid {
+    /// Continuation fragment of an L2CAP message, or an empty PDU
+    Continuation = 0b01,
+
+    /// Start of an L2CAP message or a complete L2CAP message with no fragmentation
+    Start = 0b10,
+
+    /// LL Control PDU
+    Control = 0b11,
+}
+
+impl TryFrom<u8> for Llid {
+    type Error = ParseError;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0b01 => Ok(Self::Continuation),
+            0b10 => Ok(Self::Start),
+            0b11 => Ok(Self::Control),
+            _ => Err(ParseError::InvalidHeader),
+        }
+    }
+}
+
+/// Constant Tone Extension information, only present when the CP bit is set
+///
+///   |LSB               MSB|
+///   ┌──────────┬─────────┬──────────┐
+///   │ CTETime  │ RFU     │ CTEType  │
+///   │ (5 bits) │ (1 bit) │ (2 bits) │
+///   └──────────┴─────────┴──────────┘
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Format)]
+pub struct CteInfo {
+    /// Length of the Constant Tone Extension in 8 us units
+    pub time: u8,
+
+    /// 0: AoA, 1: AoD with 1 us slots, 2: AoD with 2 us slots
+    pub r#type: u8,
+}
+
+impl CteInfo {
+    pub fn byte(&self) -> u8 {
+        (self.time & 0b1_1111) | (self.r#type & 0b11) << 6
+    }
+
+    pub fn parse_byte(byte: u8) -> Self {
+        Self {
+            time: byte & 0b1_1111,
+            r#type: byte >> 6,
+        }
+    }
+}
+
+/// Header of the data physical channel PDU, 2 or 3 bytes
+///
+///   |LSB                                                            MSB|
+///   ┌──────────┬─────────┬─────────┬─────────┬─────────┬──────────┬──────────┬──────────┐
+///   │ LLID     │ NESN    │ SN      │ MD      │ CP      │ RFU      │ Length   │ CTEInfo  │
+///   │ (2 bits) │ (1 bit) │ (1 bit) │ (1 bit) │ (1 bit) │ (2 bits) │ (8 bits) │ [8 bits] │
+///   └──────────┴─────────┴─────────┴─────────┴─────────┴──────────┴──────────┴──────────┘
+///
+/// Ref: [Core 6.B.2.4](https://www.bluetooth.com/wp-content/uploads/Files/Specification/HTML/Core-54/out/en/low-energy-controller/link-layer-specification.html)
+#[derive(Debug, Clone, PartialEq, Eq, Format)]
+pub struct DataHeader {
+    pub llid: Llid,
+
+    /// Next Expected Sequence Number
+    pub nesn: bool,
+
+    /// Sequence Number
+    pub sn: bool,
+
+    /// More Data
+    pub md: bool,
+
+    /// Length of the payload
+    pub length: u8,
+
+    /// Set when the packet has a Constant Tone Extension, the CP bit
+    pub cte_info: Option<CteInfo>,
+}
+
+impl DataHeader {
+    pub fn new(llid: Llid, length: u8) -> Self {
+        Self {
+            llid,
+            nesn: false,
+            sn: false,
+            md: false,
+            length,
+            cte_info: None,
+        }
+    }
+
+    /// Size of the header, the CTEInfo is an extra byte
+    pub fn size(&self) -> HeaderSize {
+        match self.cte_info {
+            Some(_) => HeaderSize::ThreeBytes,
+            None => HeaderSize::TwoBytes,
+        }
+    }
+
+    fn len(&self) -> usize {
+        match self.size() {
+            HeaderSize::TwoBytes => 2,
+            HeaderSize::ThreeBytes => 3,
+        }
+    }
+
+    pub fn bytes(&self, dest: &mut [u8]) -> usize {
+        let len = self.len();
+        assert!(dest.len() >= len);
+
+        dest[0] = self.llid as u8
+            | (self.nesn as u8) << 2
+            | (self.sn as u8) << 3
+            | (self.md as u8) << 4
+            | (self.cte_info.is_some() as u8) << 5;
+        dest[1] = self.length;
+        if let Some(cte_info) = &self.cte_info {
+            dest[2] = cte_info.byte();
+        }
+
+        len
+    }
+
+    pub fn parse(bytes: &[u8]) -> Result<Self, ParseError> {
+        if bytes.len() < 2 {
+            return Err(ParseError::InvalidLength);
+        }
+
+        let cp = bytes[0] & 0b0010_0000 != 0;
+        let cte_info = match (cp, bytes.get(2)) {
+            (false, _) => None,
+            (true, Some(byte)) => Some(CteInfo::parse_byte(*byte)),
+            (true, None) => return Err(ParseError::InvalidLength),
+        };
+
+        Ok(Self {
+            llid: Llid::try_from(bytes[0] & 0b11)?,
+            nesn: bytes[0] & 0b0000_0100 != 0,
+            sn: bytes[0] & 0b0000_1000 != 0,
+            md: bytes[0] & 0b0001_0000 != 0,
+            length: bytes[1],
+            cte_info,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn serialize_two_bytes() {
+        let header = DataHeader {
+            llid: Llid::Start,
+            nesn: true,
+            sn: false,
+            md: true,
+            length: 27,
+            cte_info: None,
+        };
+
+        let mut bytes = [0u8; 3];
+        let len = header.bytes(&mut bytes);
+        assert_eq!(bytes[..len], [0b0001_0110, 27]);
+    }
+
+    #[test]
+    fn parse_two_bytes() {
+        let header = DataHeader::parse(&[0b0000_1001, 0]).unwrap();
+        assert_eq!(
+            header,
+            DataHeader {
+                llid: Llid::Continuation,
+                nesn: false,
+                sn: true,
+                md: false,
+                length: 0,
+                cte_info: None,
+            }
+        );
+    }
+
+    #[test]
+    fn three_bytes_complementary() {
+        let header = DataHeader {
+            llid: Llid::Control,
+            nesn: false,
+            sn: false,
+            md: false,
+            length: 1,
+            cte_info: Some(CteInfo {
+                time: 20,
+                r#type: 1,
+            }),
+        };
+
+        let mut bytes = [0u8; 3];
+        let len = header.bytes(&mut bytes);
+        assert_eq!(bytes[..len], [0b0010_0011, 1, 0b0101_0100]);
+        assert_eq!(DataHeader::parse(&bytes).unwrap(), header);
+    }
+
+    #[test]
+    fn reserved_llid() {
+        assert_eq!(
+            DataHeader::parse(&[0b0000_0000, 0]),
+            Err(ParseError::InvalidHeader)
+        );
+    }
+}
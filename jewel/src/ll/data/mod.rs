@@ -0,0 +1,23 @@
+//! Data physical channel PDU
+//!
+//!    LSB                                                                                        MSB
+//!   ┌──────────┬──────────┬─────────┬──────────┬──────────┬──────────┬──────────┬---------------┬─────────────────────┐
+//!   │ LLID     │ NESN     │ SN      │ MD       │ CP       │ RFU      │ Length   │ CTEInfo       │                     │
+//!   │ (2 bits) │ (1 bit)  │ (1 bit) │ (1 bit)  │ (1 bit)  │ (2 bits) │ (8 bits) │ (0 or 8 bits) │                     │
+//!   ├──────────┴──────────┴─────────┴──────────┴──────────┴──────────┴──────────┴---------------┼─────────────────────┤
+//!   │                                  HEADER (16 or 24 bits)                                   │ Payload (0-251 bytes)│
+//!   └───────────────────────────────────────────────────────────────────────────────────────────┴─────────────────────┘
+//!
+//! The LLID defines if the payload is an LL Data PDU, carrying L2CAP messages,
+//! or an LL Control PDU, used to control the connection.
+//! The CTEInfo is only present when the CP bit is set.
+//!
+//! Ref: [Core 6.B.2.4](https://www.bluetooth.com/wp-content/uploads/Files/Specification/HTML/Core-54/out/en/low-energy-controller/link-layer-specification.html)
+
+mod control;
+mod header;
+mod pdu;
+
+pub use control::*;
+pub use header::*;
+pub use pdu::*;
@@ -0,0 +1,267 @@
+//! Ref: [Core 6.B.2.4.1](https://www.bluetooth.com/wp-content/uploads/Files/Specification/HTML/Core-54/out/en/low-energy-controller/link-layer-specification.html)
+
+use defmt::Format;
+
+use crate::ll::ParseError;
+
+use super::{DataHeader, Llid};
+
+/// Maximum payload of a data physical channel PDU, with the LE Data Packet Length Extension
+pub const MAX_PAYLOAD_LENGTH: usize = 251;
+
+/// Payload supported by all devices, without the LE Data Packet Length Extension
+pub const DEFAULT_PAYLOAD_LENGTH: usize = 27;
+
+#[derive(Debug, Clone, PartialEq, Eq, Format)]
+pub enum DataChannelPdu<'a> {
+    Data(DataPdu<'a>),
+    Control(ControlPdu<'a>),
+}
+
+impl<'a> DataChannelPdu<'a> {
+    pub fn header(&self) -> &DataHeader {
+        match self {
+            Self::Data(pdu) => &pdu.header,
+            Self::Control(pdu) => &pdu.header,
+        }
+    }
+
+    pub fn header_mut(&mut self) -> &mut DataHeader {
+        match self {
+            Self::Data(pdu) => &mut pdu.header,
+            Self::Control(pdu) => &mut pdu.header,
+        }
+    }
+
+    pub fn bytes(&self, dest: &mut [u8]) -> usize {
+        match self {
+            Self::Data(pdu) => pdu.bytes(dest),
+            Self::Control(pdu) => pdu.bytes(dest),
+        }
+    }
+
+    pub fn parse(bytes: &'a [u8]) -> Result<Self, ParseError> {
+        let header = DataHeader::parse(bytes)?;
+        match header.llid {
+            Llid::Control => ControlPdu::parse(bytes).map(Self::Control),
+            _ => DataPdu::parse(bytes).map(Self::Data),
+        }
+    }
+}
+
+/// Split the header and the payload, checking the length
+fn split(bytes: &[u8]) -> Result<(DataHeader, &[u8]), ParseError> {
+    let header = DataHeader::parse(bytes)?;
+    let start = header.bytes(&mut [0u8; 3]);
+    let end = start + header.length as usize;
+
+    if header.length as usize > MAX_PAYLOAD_LENGTH || bytes.len() < end {
+        return Err(ParseError::InvalidLength);
+    }
+
+    Ok((header, &bytes[start..end]))
+}
+
+/// LL Data PDU, carrying a L2CAP message or a fragment of it
+///
+///   ┌─────────────────────┬─────────────────────────┐
+///   │ Header              │ Payload                 │
+///   │ LLID: 0b01 or 0b10  │ (0-251 bytes)           │
+///   └─────────────────────┴─────────────────────────┘
+/// An LL Data PDU with LLID 0b01 and no payload is an empty PDU,
+/// used to acknowledge packets when there is no data to send.
+#[derive(Debug, Clone, PartialEq, Eq, Format)]
+pub struct DataPdu<'a> {
+    header: DataHeader,
+    payload: &'a [u8],
+}
+
+impl<'a> DataPdu<'a> {
+    pub fn new(llid: Llid, payload: &'a [u8]) -> Self {
+        assert!(llid != Llid::Control);
+        assert!(payload.len() <= MAX_PAYLOAD_LENGTH);
+        // A start fragment shall not be empty
+        assert!(llid != Llid::Start || !payload.is_empty());
+
+        Self {
+            header: DataHeader::new(llid, payload.len() as u8),
+            payload,
+        }
+    }
+
+    pub fn empty() -> Self {
+        Self::new(Llid::Continuation, &[])
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.payload.is_empty()
+    }
+
+    pub fn header(&self) -> &DataHeader {
+        &self.header
+    }
+
+    pub fn header_mut(&mut self) -> &mut DataHeader {
+        &mut self.header
+    }
+
+    pub fn payload(&self) -> &'a [u8] {
+        self.payload
+    }
+
+    pub fn bytes(&self, dest: &mut [u8]) -> usize {
+        let start = self.header.bytes(dest);
+        let end = start + self.payload.len();
+        dest[start..end].copy_from_slice(self.payload);
+        end
+    }
+
+    pub fn parse(bytes: &'a [u8]) -> Result<Self, ParseError> {
+        let (header, payload) = split(bytes)?;
+        if header.llid == Llid::Control {
+            return Err(ParseError::InvalidType);
+        }
+
+        Ok(Self { header, payload })
+    }
+}
+
+/// LL Control PDU, used to control the connection
+///
+///   ┌─────────────────────┬──────────┬─────────────────────────┐
+///   │ Header              │ Opcode   │ CtrData                 │
+///   │ LLID: 0b11          │ (1 byte) │ (0-250 bytes)           │
+///   └─────────────────────┴──────────┴─────────────────────────┘
+///
+/// Ref: [Core 6.B.2.4.2](https://www.bluetooth.com/wp-content/uploads/Files/Specification/HTML/Core-54/out/en/low-energy-controller/link-layer-specification.html)
+#[derive(Debug, Clone, PartialEq, Eq, Format)]
+pub struct ControlPdu<'a> {
+    header: DataHeader,
+    opcode: u8,
+    ctr_data: &'a [u8],
+}
+
+impl<'a> ControlPdu<'a> {
+    pub fn new(opcode: u8, ctr_data: &'a [u8]) -> Self {
+        assert!(ctr_data.len() < MAX_PAYLOAD_LENGTH);
+
+        Self {
+            header: DataHeader::new(Llid::Control, 1 + ctr_data.len() as u8),
+            opcode,
+            ctr_data,
+        }
+    }
+
+    pub fn header(&self) -> &DataHeader {
+        &self.header
+    }
+
+    pub fn header_mut(&mut self) -> &mut DataHeader {
+        &mut self.header
+    }
+
+    pub fn opcode(&self) -> u8 {
+        self.opcode
+    }
+
+    pub fn ctr_data(&self) -> &'a [u8] {
+        self.ctr_data
+    }
+
+    pub fn bytes(&self, dest: &mut [u8]) -> usize {
+        let start = self.header.bytes(dest);
+        dest[start] = self.opcode;
+        let end = start + 1 + self.ctr_data.len();
+        dest[start + 1..end].copy_from_slice(self.ctr_data);
+        end
+    }
+
+    pub fn parse(bytes: &'a [u8]) -> Result<Self, ParseError> {
+        let (header, payload) = split(bytes)?;
+        if header.llid != Llid::Control {
+            return Err(ParseError::InvalidType);
+        }
+        let Some((opcode, ctr_data)) = payload.split_first() else {
+            return Err(ParseError::InvalidLength);
+        };
+
+        Ok(Self {
+            header,
+            opcode: *opcode,
+            ctr_data,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ll::CteInfo;
+
+    #[test]
+    fn empty_pdu() {
+        let mut pdu = DataPdu::empty();
+        pdu.header_mut().sn = true;
+
+        let mut bytes = [0u8; 2];
+        let len = pdu.bytes(&mut bytes);
+        assert_eq!(bytes[..len], [0b0000_1001, 0]);
+        assert!(DataPdu::parse(&bytes).unwrap().is_empty());
+    }
+
+    #[test]
+    fn data_serialize() {
+        let pdu = DataPdu::new(Llid::Start, &[0x01, 0x02, 0x03]);
+
+        let mut bytes = [0u8; 10];
+        let len = pdu.bytes(&mut bytes);
+        assert_eq!(bytes[..len], [0b0000_0010, 3, 0x01, 0x02, 0x03]);
+    }
+
+    #[test]
+    fn data_complementary() {
+        // The radio buffer is larger than the PDU
+        let bytes = [0b0000_0010, 3, 0x01, 0x02, 0x03, 0xFF, 0xFF];
+
+        let pdu = DataChannelPdu::parse(&bytes).unwrap();
+        assert_eq!(
+            pdu,
+            DataChannelPdu::Data(DataPdu::new(Llid::Start, &[0x01, 0x02, 0x03]))
+        );
+    }
+
+    #[test]
+    fn data_with_cte_info() {
+        let bytes = [0b0010_0010, 1, 0b0000_0010, 0xAB];
+
+        let pdu = DataPdu::parse(&bytes).unwrap();
+        assert_eq!(pdu.header().cte_info, Some(CteInfo { time: 2, r#type: 0 }));
+        assert_eq!(pdu.payload(), &[0xAB]);
+    }
+
+    #[test]
+    fn control_complementary() {
+        // LL_TERMINATE_IND with Remote User Terminated Connection
+        let bytes = [0b0000_0011, 2, 0x02, 0x13];
+
+        let pdu = DataChannelPdu::parse(&bytes).unwrap();
+        let expected = ControlPdu::new(0x02, &[0x13]);
+        assert_eq!(pdu, DataChannelPdu::Control(expected.clone()));
+
+        let mut serialized = [0u8; 4];
+        let len = expected.bytes(&mut serialized);
+        assert_eq!(serialized[..len], bytes);
+    }
+
+    #[test]
+    fn truncated() {
+        assert_eq!(
+            DataChannelPdu::parse(&[0b0000_0010, 3, 0x01]),
+            Err(ParseError::InvalidLength)
+        );
+        assert_eq!(
+            ControlPdu::parse(&[0b0000_0011, 0]),
+            Err(ParseError::InvalidLength)
+        );
+    }
+}
@@ -0,0 +1,284 @@
+//! Filter Accept List
+//!
+//! The advertiser and the scanner may ignore the devices that are not in the
+//! Filter Accept List, as set by their filter policy. The filters are applied in
+//! software, radios with a device address match can also apply them in hardware.
+//!
+//! Ref: [Core 6.B.4.3](https://www.bluetooth.com/wp-content/uploads/Files/Specification/HTML/Core-54/out/en/low-energy-controller/link-layer-specification.html)
+
+use defmt::Format;
+
+use super::{AddressAndData, AdvPdu, RandomAddressKind};
+use crate::ll::Address;
+
+/// Number of entries of the Filter Accept List, the size of the nRF device address match
+pub const FILTER_ACCEPT_LIST_SIZE: usize = 8;
+
+/// The Filter Accept List has no room for a new address
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Format)]
+pub struct FilterAcceptListFull;
+
+/// Addresses of the devices allowed by the filter policies
+#[derive(Debug, Clone, PartialEq, Eq, Format)]
+pub struct FilterAcceptList {
+    entries: [Option<Address>; FILTER_ACCEPT_LIST_SIZE],
+}
+
+impl FilterAcceptList {
+    pub const fn new() -> Self {
+        FilterAcceptList {
+            entries: [const { None }; FILTER_ACCEPT_LIST_SIZE],
+        }
+    }
+
+    /// Add the `address`, adding an address already in the list does nothing
+    pub fn add(&mut self, address: Address) -> Result<(), FilterAcceptListFull> {
+        if self.contains(&address) {
+            return Ok(());
+        }
+
+        let slot = self
+            .entries
+            .iter()
+            .position(Option::is_none)
+            .ok_or(FilterAcceptListFull)?;
+        self.entries[slot] = Some(address);
+        Ok(())
+    }
+
+    /// Remove the `address`, returns false when it was not in the list
+    pub fn remove(&mut self, address: &Address) -> bool {
+        let Some(slot) = self
+            .entries
+            .iter()
+            .position(|e| e.as_ref() == Some(address))
+        else {
+            return false;
+        };
+        self.entries[slot] = None;
+        true
+    }
+
+    pub fn clear(&mut self) {
+        self.entries = [const { None }; FILTER_ACCEPT_LIST_SIZE];
+    }
+
+    /// The comparison includes the address type
+    pub fn contains(&self, address: &Address) -> bool {
+        self.iter().any(|entry| entry == address)
+    }
+
+    /// Addresses in the list, in no particular order
+    pub fn iter(&self) -> impl Iterator<Item = &Address> {
+        self.entries.iter().flatten()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.iter().next().is_none()
+    }
+}
+
+impl Default for FilterAcceptList {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Advertising filter policy, which scanners and initiators the advertiser answers
+///
+/// Ref: Core 6.B.4.3.2
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Format)]
+pub enum AdvertisingFilterPolicy {
+    /// Process scan and connection requests from all devices
+    #[default]
+    ProcessAll,
+
+    /// Process scan requests only from the list, connection requests from all devices
+    ScanFromList,
+
+    /// Process scan requests from all devices, connection requests only from the list
+    ConnectFromList,
+
+    /// Process scan and connection requests only from the list
+    ScanAndConnectFromList,
+}
+
+impl AdvertisingFilterPolicy {
+    /// The SCAN_REQ of the scanner with the `address` shall be answered
+    pub fn allows_scan(&self, list: &FilterAcceptList, address: &Address) -> bool {
+        match self {
+            Self::ProcessAll | Self::ConnectFromList => true,
+            Self::ScanFromList | Self::ScanAndConnectFromList => list.contains(address),
+        }
+    }
+
+    /// The CONNECT_IND of the initiator with the `address` shall be accepted
+    pub fn allows_connect(&self, list: &FilterAcceptList, address: &Address) -> bool {
+        match self {
+            Self::ProcessAll | Self::ScanFromList => true,
+            Self::ConnectFromList | Self::ScanAndConnectFromList => list.contains(address),
+        }
+    }
+
+    /// Every request is filtered by the list, so the radio can drop the others
+    pub(super) fn filters_all(&self) -> bool {
+        *self == Self::ScanAndConnectFromList
+    }
+}
+
+/// Scanner filter policy, which advertising PDUs the scanner reports
+///
+/// Directed advertising is only reported when addressed to this device, the
+/// extended policies also report it when the target is a resolvable private address,
+/// so the host can try to resolve it.
+///
+/// Ref: Core 6.B.4.3.3
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Format)]
+pub enum ScannerFilterPolicy {
+    /// Report the advertising of all devices
+    #[default]
+    AcceptAll,
+
+    /// Report only the advertising of the devices in the list
+    AcceptFromList,
+
+    /// As `AcceptAll`, also reporting directed advertising to resolvable private addresses
+    AcceptAllResolvable,
+
+    /// As `AcceptFromList`, also reporting directed advertising to resolvable private addresses
+    AcceptFromListResolvable,
+}
+
+impl ScannerFilterPolicy {
+    fn uses_list(&self) -> bool {
+        matches!(self, Self::AcceptFromList | Self::AcceptFromListResolvable)
+    }
+
+    /// The `pdu` received by the scanner with the `local` address shall be reported.
+    /// PDUs not sent by advertisers are only reported when the list is not used.
+    pub fn accepts(&self, list: &FilterAcceptList, pdu: &AdvPdu, local: &Address) -> bool {
+        let (advertiser, target) = match pdu {
+            AdvPdu::AdvInd(pdu) => (pdu.address(), None),
+            AdvPdu::AdvNonconnInd(pdu) => (pdu.address(), None),
+            AdvPdu::AdvScanInd(pdu) => (pdu.address(), None),
+            AdvPdu::AdvDirectInd(pdu) => (pdu.adv_address(), Some(pdu.target_address())),
+            _ => return !self.uses_list(),
+        };
+
+        if self.uses_list() && !list.contains(advertiser) {
+            return false;
+        }
+
+        match target {
+            None => true,
+            Some(target) if target == local => true,
+            Some(target) => {
+                matches!(
+                    self,
+                    Self::AcceptAllResolvable | Self::AcceptFromListResolvable
+                ) && target.random_kind() == Some(RandomAddressKind::Resolvable)
+            }
+        }
+    }
+
+    /// Every reported PDU is filtered by the list, so the radio can drop the others
+    pub(super) fn filters_all(&self) -> bool {
+        self.uses_list()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ll::{AdvDirectInd, AdvInd, ScanReq};
+
+    #[test]
+    fn accept_list() {
+        let mut list = FilterAcceptList::new();
+        assert!(list.is_empty());
+
+        for address in 0..FILTER_ACCEPT_LIST_SIZE as u64 {
+            assert_eq!(list.add(Address::new_public(address)), Ok(()));
+        }
+        assert_eq!(list.add(Address::new_public(0)), Ok(()));
+        assert_eq!(
+            list.add(Address::new_public(0xff)),
+            Err(FilterAcceptListFull)
+        );
+
+        // The address type is part of the address
+        assert!(list.contains(&Address::new_public(1)));
+        assert!(!list.contains(&Address::new_random(1)));
+
+        assert!(list.remove(&Address::new_public(1)));
+        assert!(!list.remove(&Address::new_public(1)));
+        assert_eq!(list.add(Address::new_public(0xff)), Ok(()));
+        assert_eq!(list.iter().count(), FILTER_ACCEPT_LIST_SIZE);
+
+        list.clear();
+        assert!(list.is_empty());
+    }
+
+    #[test]
+    fn advertising_policies() {
+        let known = Address::new_public(0x0102030405);
+        let unknown = Address::new_random(0xc0ffee000001);
+        let mut list = FilterAcceptList::new();
+        list.add(known.clone()).unwrap();
+
+        let allowed = |policy: AdvertisingFilterPolicy, address: &Address| {
+            (
+                policy.allows_scan(&list, address),
+                policy.allows_connect(&list, address),
+            )
+        };
+
+        use AdvertisingFilterPolicy::*;
+        for policy in [
+            ProcessAll,
+            ScanFromList,
+            ConnectFromList,
+            ScanAndConnectFromList,
+        ] {
+            assert_eq!(allowed(policy, &known), (true, true));
+        }
+        assert_eq!(allowed(ProcessAll, &unknown), (true, true));
+        assert_eq!(allowed(ScanFromList, &unknown), (false, true));
+        assert_eq!(allowed(ConnectFromList, &unknown), (true, false));
+        assert_eq!(allowed(ScanAndConnectFromList, &unknown), (false, false));
+    }
+
+    #[test]
+    fn scanner_policies() {
+        let local = Address::new_random(0xc1e1e8d0dc27);
+        let known = Address::new_public(0x0102030405);
+        let unknown = Address::new_public(0x0a0b0c0d0e);
+        let resolvable = Address::new_random(0x7081940dfbaa);
+        let mut list = FilterAcceptList::new();
+        list.add(known.clone()).unwrap();
+
+        let adv = |address: &Address| AdvPdu::AdvInd(AdvInd::new(address.clone(), &[]));
+        let direct = |target: &Address| {
+            AdvPdu::AdvDirectInd(AdvDirectInd::new(known.clone(), target.clone()))
+        };
+        let scan_req = AdvPdu::ScanReq(ScanReq::new(unknown.clone(), known.clone()));
+
+        use ScannerFilterPolicy::*;
+        let accepts =
+            |policy: ScannerFilterPolicy, pdu: &AdvPdu| policy.accepts(&list, pdu, &local);
+
+        assert!(accepts(AcceptAll, &adv(&unknown)));
+        assert!(accepts(AcceptAll, &direct(&local)));
+        assert!(!accepts(AcceptAll, &direct(&resolvable)));
+        assert!(accepts(AcceptAll, &scan_req));
+
+        assert!(accepts(AcceptFromList, &adv(&known)));
+        assert!(!accepts(AcceptFromList, &adv(&unknown)));
+        assert!(!accepts(AcceptFromList, &scan_req));
+
+        assert!(accepts(AcceptAllResolvable, &direct(&resolvable)));
+        assert!(!accepts(AcceptAllResolvable, &direct(&unknown)));
+        assert!(accepts(AcceptFromListResolvable, &direct(&resolvable)));
+        assert!(!accepts(AcceptFromListResolvable, &adv(&unknown)));
+    }
+}
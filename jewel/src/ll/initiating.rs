@@ -0,0 +1,289 @@
+//! Initiating state
+//!
+//! The initiator listens on the primary advertising channels like a passive
+//! scanner. When a connectable advertising PDU of the target is received, it
+//! answers with a CONNECT_IND within T_IFS and enters the connection state in
+//! the central role.
+//!
+//! Ref: [Core 6.B.4.4.4](https://www.bluetooth.com/wp-content/uploads/Files/Specification/HTML/Core-54/out/en/low-energy-controller/link-layer-specification.html)
+
+use defmt::Format;
+use embassy_time::{with_timeout, Duration, Instant, Timer};
+use rand::{rngs::SmallRng, Rng, SeedableRng};
+
+use crate::phy::{ChannelMap, Radio, ADV_ADDRESS, MAX_PDU_LENGTH};
+
+use super::{
+    adv, Address, AddressAndData, AdvPdu, ConnectInd, ConnectRequest, Connection,
+    ConnectionParameters, Header, LLData, LinkLayer, Role, ScanType, Scanning, Standby, TwoAddress,
+};
+
+/// Sleep clock accuracy sent in the CONNECT_IND, 251 ppm to 500 ppm
+///
+/// Ref: Core 6.B.2.3.3.1 Table 2.22
+const LOCAL_SCA: u8 = 0;
+
+/// Advertisers the initiator connects to
+#[derive(Debug, Clone, PartialEq, Eq, Format)]
+pub enum InitiatorTarget<'a> {
+    /// A single device
+    Peer(Address),
+
+    /// Any device of the list, the first one found
+    List(&'a [Address]),
+}
+
+impl InitiatorTarget<'_> {
+    fn contains(&self, address: &Address) -> bool {
+        match self {
+            InitiatorTarget::Peer(peer) => peer == address,
+            InitiatorTarget::List(list) => list.contains(address),
+        }
+    }
+}
+
+/// Check the rules of the access address of a connection on the LE 1M PHY
+///
+/// Ref: Core 6.B.2.1.2
+pub fn is_valid_access_address(address: u32) -> bool {
+    // No more than six consecutive zeros or ones
+    let long_run = (0..=32 - 7).any(|shift| matches!((address >> shift) & 0x7F, 0 | 0x7F));
+
+    // Not the advertising access address, neither differ from it by only one bit
+    let advertising = (address ^ ADV_ADDRESS).count_ones() <= 1;
+
+    let octets = address.to_le_bytes();
+    let equal_octets = octets.iter().all(|&octet| octet == octets[0]);
+
+    let transitions = ((address ^ (address >> 1)) & 0x7FFF_FFFF).count_ones();
+
+    // Minimum of two transitions in the most significant six bits
+    let msb = address >> 26;
+    let msb_transitions = ((msb ^ (msb >> 1)) & 0x1F).count_ones();
+
+    !long_run && !advertising && !equal_octets && transitions <= 24 && msb_transitions >= 2
+}
+
+/// Random access address for a new connection
+pub fn random_access_address(rng: &mut impl Rng) -> u32 {
+    loop {
+        let address = rng.gen();
+        if is_valid_access_address(address) {
+            return address;
+        }
+    }
+}
+
+/// Advertiser address and Channel Selection Algorithm #2 support of a connectable
+/// advertising PDU of the target. ADV_DIRECT_IND shall be addressed to `own_address`.
+fn connectable(
+    bytes: &[u8],
+    target: &InitiatorTarget,
+    own_address: &Address,
+) -> Option<(Address, bool)> {
+    let adv_address = match adv::parse(bytes).ok()? {
+        AdvPdu::AdvInd(pdu) => pdu.address().clone(),
+        AdvPdu::AdvDirectInd(pdu) if pdu.second() == own_address => pdu.first().clone(),
+        _ => return None,
+    };
+    let ch_sel = Header::parse(bytes[..2].try_into().unwrap()).flags.ch_sel;
+
+    target
+        .contains(&adv_address)
+        .then_some((adv_address, ch_sel))
+}
+
+pub struct Initiating<'a> {
+    /// Scan windows where the initiator listens
+    scanning: Scanning,
+
+    target: InitiatorTarget<'a>,
+
+    /// Connection parameters sent in the CONNECT_IND, chosen before listening
+    /// to answer within T_IFS
+    ll_data: LLData,
+
+    /// The advertiser supports the Channel Selection Algorithm #2
+    ch_sel: bool,
+}
+
+impl<'a> Initiating<'a> {
+    pub fn new(
+        rng: &mut impl Rng,
+        target: InitiatorTarget<'a>,
+        interval: Duration,
+        window: Duration,
+        parameters: ConnectionParameters,
+    ) -> Self {
+        assert!(parameters.is_valid());
+
+        let ll_data = LLData {
+            access_address: random_access_address(rng),
+            crc_init: rng.gen_range(0..1 << 24),
+            win_size: 1,
+            win_offset: 0,
+            interval: parameters.interval_min,
+            latency: parameters.latency,
+            timeout: parameters.timeout,
+            channel_map: ChannelMap::all(),
+            hop: rng.gen_range(5..=16),
+            sca: LOCAL_SCA,
+        };
+
+        Initiating {
+            scanning: Scanning::new(ScanType::Passive, interval, window),
+            target,
+            ll_data,
+            ch_sel: false,
+        }
+    }
+}
+
+impl<'r, R: Radio> LinkLayer<'r, R, Standby> {
+    /// Listen on the primary advertising channels to connect to the `target`
+    ///
+    /// The connection uses the shortest interval of the `parameters`.
+    pub fn initiate(
+        mut self,
+        target: InitiatorTarget<'_>,
+        interval: Duration,
+        window: Duration,
+        parameters: ConnectionParameters,
+    ) -> LinkLayer<'r, R, Initiating<'_>> {
+        self.configure_advertising_physical_channel();
+
+        // Each connection shall have a new access address, the seed changes with the
+        // time the initiating starts and between devices
+        let mut address = [0u8; 8];
+        address[..6].copy_from_slice(&self.radio.device_address().bytes());
+        let seed = Instant::now().as_ticks() ^ u64::from_le_bytes(address);
+        let mut rng = SmallRng::seed_from_u64(seed);
+
+        let state = Initiating::new(&mut rng, target, interval, window, parameters);
+        self.radio.set_channel(state.scanning.channel.into());
+
+        LinkLayer {
+            radio: self.radio,
+            state,
+        }
+    }
+}
+
+impl<'r, R: Radio> LinkLayer<'r, R, Initiating<'_>> {
+    /// Listen until a connectable advertising PDU of the target is received
+    /// and answer it with a CONNECT_IND within T_IFS.
+    ///
+    /// The link layer should move to the connection state with the returned request.
+    pub async fn connect_request(&mut self) -> Result<ConnectRequest, R::Error> {
+        let mut buffer = [0u8; MAX_PDU_LENGTH];
+        let own_address = self.radio.device_address();
+
+        loop {
+            let scanning = &mut self.state.scanning;
+            let now = Instant::now();
+            if now >= scanning.window_end() {
+                scanning.next_window();
+                Timer::at(scanning.window_start).await;
+                self.radio.set_channel(scanning.channel.into());
+                continue;
+            }
+
+            let timeout = scanning.window_end() - now;
+            let Ok(received) = with_timeout(timeout, self.radio.receive(&mut buffer)).await else {
+                continue;
+            };
+            received?;
+
+            let Some((adv_address, ch_sel)) =
+                connectable(&buffer, &self.state.target, &own_address)
+            else {
+                continue;
+            };
+
+            let pdu = ConnectInd::new(own_address, adv_address, self.state.ll_data.clone(), true);
+            let mut connect_ind = [0u8; ConnectInd::PDU_LENGTH];
+            pdu.bytes(&mut connect_ind);
+            self.radio.transmit(&connect_ind).await?;
+
+            self.state.ch_sel = ch_sel;
+            return Ok(ConnectRequest {
+                pdu,
+                timestamp: Instant::now(),
+            });
+        }
+    }
+
+    /// Stop initiating and enter the connection state as central
+    pub fn connect(self, request: ConnectRequest) -> LinkLayer<'r, R, Connection> {
+        let state = Connection::new(request, self.state.ch_sel, Role::Central);
+        LinkLayer::enter_connection(self.radio, state)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use rand::rngs::SmallRng;
+
+    use super::*;
+    use crate::ll::{AdvDirectInd, AdvInd, AdvScanInd};
+
+    #[test]
+    fn access_address_rules() {
+        assert!(is_valid_access_address(0x50654DE1));
+
+        assert!(!is_valid_access_address(ADV_ADDRESS));
+        assert!(!is_valid_access_address(ADV_ADDRESS ^ 0x0001_0000));
+        assert!(!is_valid_access_address(0x5A5A5A5A));
+        // Seven consecutive ones
+        assert!(!is_valid_access_address(0x50654FE1));
+        // 31 transitions
+        assert!(!is_valid_access_address(0x55555555 ^ 0x0000_0100));
+        // A single transition in the six most significant bits
+        assert!(!is_valid_access_address(0x0E654DE1));
+
+        let mut rng = SmallRng::seed_from_u64(7);
+        for _ in 0..100 {
+            assert!(is_valid_access_address(random_access_address(&mut rng)));
+        }
+    }
+
+    #[test]
+    fn connectable_advertisers() {
+        let own = Address::new_random(0xffe1e8d0dc27);
+        let peer = Address::new_random(0xffe1e8d0dc28);
+        let target = InitiatorTarget::Peer(peer.clone());
+        let mut buffer = [0u8; MAX_PDU_LENGTH];
+
+        let mut pdu = AdvInd::new(peer.clone(), &[0x02, 0x01, 0x06]);
+        pdu.set_ch_sel(true);
+        pdu.bytes(&mut buffer);
+        assert_eq!(
+            connectable(&buffer, &target, &own),
+            Some((peer.clone(), true))
+        );
+
+        let other = Address::new_public(0x0102030405);
+        let list = [other.clone(), peer.clone()];
+        assert_eq!(
+            connectable(&buffer, &InitiatorTarget::List(&list), &own),
+            Some((peer.clone(), true))
+        );
+        assert_eq!(
+            connectable(&buffer, &InitiatorTarget::Peer(other.clone()), &own),
+            None
+        );
+
+        // Directed advertising is only accepted when addressed to us
+        AdvDirectInd::new(peer.clone(), own.clone()).bytes(&mut buffer);
+        assert_eq!(
+            connectable(&buffer, &target, &own),
+            Some((peer.clone(), false))
+        );
+        AdvDirectInd::new(peer.clone(), other).bytes(&mut buffer);
+        assert_eq!(connectable(&buffer, &target, &own), None);
+
+        // Not connectable
+        AdvScanInd::new(peer, &[]).bytes(&mut buffer);
+        assert_eq!(connectable(&buffer, &target, &own), None);
+    }
+}
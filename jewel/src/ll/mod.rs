@@ -1,14 +1,29 @@
 mod address;
 mod adv;
+mod channel_selection;
+mod connection;
+mod data;
+mod filter;
+mod initiating;
+mod scanning;
 
 pub use address::*;
 pub use adv::*;
-use embassy_time::{Duration, Instant, Timer};
+pub use channel_selection::*;
+pub use connection::*;
+pub use data::*;
+use defmt::Format;
+use embassy_time::{with_timeout, Duration, Instant, Timer};
+pub use filter::*;
+pub use initiating::*;
+pub use scanning::*;
 
-use rand::{rngs::SmallRng, Rng, SeedableRng};
+use rand::{rngs::SmallRng, Rng, RngCore, SeedableRng};
 
 use crate::phy::Mode::Ble1mbit;
-use crate::phy::{AdvertisingChannel, HeaderSize, Radio, ADV_ADDRESS, ADV_CRC_INIT, CRC_POLY};
+use crate::phy::{
+    AdvertisingChannel, HeaderSize, Radio, ADV_ADDRESS, ADV_CRC_INIT, CRC_POLY, MAX_PDU_LENGTH,
+};
 
 ///  Inter Frame Space
 ///  The time interval between two consecutive packets on the same channel index
@@ -31,7 +46,7 @@ const T_MAFS: Duration = Duration::from_micros(300);
 /// Ref: https://www.bluetooth.com/wp-content/uploads/Files/Specification/HTML/Core-54/out/en/low-energy-controller/link-layer-specification.html#UUID-ea6717b6-1fb3-c5ec-9153-04e4b5ee20fb
 const T_MSS: Duration = Duration::from_micros(150);
 
-// TODO: Implement clock accuracy based on the receiver's clock accuracy and jitter.
+// The clock accuracy of the peer is used in the window widening of the connection state.
 // Ref: https://www.bluetooth.com/wp-content/uploads/Files/Specification/HTML/Core-54/out/en/low-energy-controller/link-layer-specification.html#UUID-1cdb9b08-1996-f9bd-9dd5-9587794799b1
 
 /// Active clock accuracy
@@ -53,7 +68,36 @@ const PROPAGATION_DISTANCE: u64 = 10; // meters
 /// Ref: https://www.bluetooth.com/wp-content/uploads/Files/Specification/HTML/Core-54/out/en/low-energy-controller/link-layer-specification.html#UUID-e16c5296-3b60-01b4-3251-a8f289f1cdb2
 const RANGE_DELAY: Duration = Duration::from_nanos(2 * PROPAGATION_DISTANCE * 4);
 
-// TODO: Implement Window widening
+/// Time on air of a packet on the LE 1M PHY, including preamble, access address and CRC.
+///
+/// Ref: Core 6.B.2.1
+fn air_time(pdu_length: usize) -> Duration {
+    // 1 byte preamble + 4 bytes access address + 3 bytes CRC, at 1 us per bit
+    Duration::from_micros(((1 + 4 + pdu_length + 3) * 8) as u64)
+}
+
+/// How long to listen after a transmission for a response that shall start T_IFS later
+fn response_timeout(pdu_length: usize) -> Duration {
+    T_IFS + air_time(pdu_length) + T_ACA + RANGE_DELAY
+}
+
+/// Maximum time between the start of two ADV_DIRECT_IND on the same channel,
+/// on high duty cycle directed advertising
+///
+/// Ref: Core 6.B.4.4.2.4.3
+const HIGH_DUTY_CYCLE_INTERVAL: Duration = Duration::from_micros(3_750);
+
+/// High duty cycle directed advertising ends after 1.28 s
+///
+/// Ref: Core 6.B.4.4.2.4.3
+const HIGH_DUTY_CYCLE_TIMEOUT: Duration = Duration::from_millis(1_280);
+
+/// Time between the changes of the resolvable private address, TGAP(private_addr_int)
+///
+/// Ref: Core 3.C.Appendix A
+pub const PRIVATE_ADDRESS_TIMEOUT: Duration = Duration::from_secs(15 * 60);
+
+// Window widening is implemented in the connection state, see `connection::window_widening`
 // Ref: https://www.bluetooth.com/wp-content/uploads/Files/Specification/HTML/Core-54/out/en/low-energy-controller/link-layer-specification.html#UUID-fed93539-5fa3-b4de-4789-1b8a1b48fa13
 
 // pattern from https://hoverbear.org/blog/rust-state-machine-pattern/
@@ -69,6 +113,17 @@ impl<'r, R: Radio> LinkLayer<'r, R, Standby> {
             state: Standby {},
         }
     }
+
+    /// Set the radio to use the primary advertising physical channel
+    fn configure_advertising_physical_channel(&mut self) {
+        self.radio.set_mode(Ble1mbit);
+        self.radio.set_tx_power(0);
+        self.radio.set_header_size(HeaderSize::TwoBytes);
+        self.radio.set_access_address(ADV_ADDRESS);
+        self.radio.set_crc_init(ADV_CRC_INIT);
+        self.radio.set_crc_poly(CRC_POLY);
+        self.radio.set_device_address_match(None);
+    }
 }
 
 impl<'r, R: Radio> LinkLayer<'r, R, Standby> {
@@ -78,39 +133,249 @@ impl<'r, R: Radio> LinkLayer<'r, R, Standby> {
 
         // needs to alive for the lifetime of the advertising
         data: &'a [u8],
-    ) -> LinkLayer<'r, R, Advertising<'a, SmallRng>> {
+    ) -> Result<LinkLayer<'r, R, Advertising<'a, SmallRng>>, ParseError> {
+        self.start_advertising(interval, data, None)
+    }
+
+    /// Advertise a scannable PDU (ADV_SCAN_IND or ADV_IND) and answer SCAN_REQ with the given SCAN_RSP PDU
+    ///
+    /// When the PDU is connectable (ADV_IND) the link layer also accepts CONNECT_IND.
+    pub fn advertise_scannable<'a>(
+        self,
+        interval: Duration,
+
+        // needs to alive for the lifetime of the advertising
+        data: &'a [u8],
+        scan_rsp: &'a [u8],
+    ) -> Result<LinkLayer<'r, R, Advertising<'a, SmallRng>>, ParseError> {
+        self.start_advertising(interval, data, Some(scan_rsp))
+    }
+
+    /// Advertise the connectable directed PDU (ADV_DIRECT_IND), only the target device
+    /// may connect. The high duty cycle advertising ends after 1.28 s, see `transmit_directed`.
+    pub fn advertise_directed<'a>(
+        mut self,
+        duty_cycle: DutyCycle,
+
+        // needs to alive for the lifetime of the advertising
+        data: &'a [u8],
+    ) -> Result<LinkLayer<'r, R, Advertising<'a, SmallRng>>, ParseError> {
         let rng = SmallRng::seed_from_u64(42);
+        let state = Advertising::<'a>::new_directed(rng, duty_cycle, data)?;
 
-        self.radio.set_mode(Ble1mbit);
-        self.radio.set_tx_power(0);
-        self.radio.set_header_size(HeaderSize::TwoBytes);
-        self.radio.set_access_address(ADV_ADDRESS);
-        self.radio.set_crc_init(ADV_CRC_INIT);
-        self.radio.set_crc_poly(CRC_POLY);
+        self.configure_advertising_physical_channel();
 
-        LinkLayer {
+        Ok(LinkLayer {
             radio: self.radio,
-            state: Advertising::<'a>::new(rng, interval, data),
-        }
+            state,
+        })
+    }
+
+    fn start_advertising<'a>(
+        mut self,
+        interval: Duration,
+        data: &'a [u8],
+        scan_rsp: Option<&'a [u8]>,
+    ) -> Result<LinkLayer<'r, R, Advertising<'a, SmallRng>>, ParseError> {
+        let rng = SmallRng::seed_from_u64(42);
+        let state = Advertising::<'a>::new(rng, interval, data, scan_rsp)?;
+
+        self.configure_advertising_physical_channel();
+
+        Ok(LinkLayer {
+            radio: self.radio,
+            state,
+        })
     }
 }
 
 impl<'r, R: Radio, RNG: Rng> LinkLayer<'r, R, Advertising<'_, RNG>> {
     /// Transmit the advertising data on all advertising channels
     /// You should call this method in a loop to keep advertising with at max the interal time
-    pub async fn transmit(&mut self) -> Result<(), R::Error> {
+    ///
+    /// On connectable advertising, returns the connection request of an initiator.
+    /// The advertising stops and the link layer should move to the connection state.
+    ///
+    /// Once the high duty cycle directed advertising times out nothing is sent anymore,
+    /// it returns `AdvertisingError::Timeout` and the link layer should go back to standby.
+    ///
+    /// Ref: Core 6.B.4.4.2.4.3
+    pub async fn transmit(&mut self) -> Result<Option<ConnectRequest>, AdvertisingError<R::Error>> {
         Timer::at(self.state.event).await;
+        if self.state.is_timed_out(Instant::now()) {
+            return Err(AdvertisingError::Timeout);
+        }
         self.state.event = self.state.next_event();
+        self.state.rotate_address(Instant::now());
+
+        let mut buffer = [0u8; MAX_PDU_LENGTH];
+        let data = self.state.with_address(self.state.data, &mut buffer);
 
         for channel in AdvertisingChannel::channels() {
             self.radio.set_channel(channel.into());
-            self.radio.transmit(&self.state.data).await?;
+            self.radio
+                .transmit(data)
+                .await
+                .map_err(AdvertisingError::Radio)?;
+
+            if self.state.is_connectable() || self.state.scan_rsp.is_some() {
+                let request = self.listen().await.map_err(AdvertisingError::Radio)?;
+                if let Some(request) = request {
+                    return Ok(Some(request));
+                }
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Listen right after the advertising PDU, answering a SCAN_REQ within T_IFS
+    /// or accepting a CONNECT_IND when they are addressed to us.
+    ///
+    /// Ref: Core 6.B.4.4.2
+    async fn listen(&mut self) -> Result<Option<ConnectRequest>, R::Error> {
+        let mut buffer = [0u8; MAX_PDU_LENGTH];
+        let timeout = response_timeout(ConnectInd::PDU_LENGTH);
+
+        // no request was received, go to the next channel
+        let Ok(received) = with_timeout(timeout, self.radio.receive(&mut buffer)).await else {
+            return Ok(None);
+        };
+        received?;
+        let timestamp = Instant::now();
+
+        match adv::parse(&buffer) {
+            Ok(AdvPdu::ScanReq(scan_req))
+                if scan_req.adv_address() == &self.state.address
+                    && self
+                        .state
+                        .filter_policy
+                        .allows_scan(&self.state.accept_list, scan_req.scan_address()) =>
+            {
+                if let Some(scan_rsp) = self.state.scan_rsp {
+                    let mut buffer = [0u8; MAX_PDU_LENGTH];
+                    let scan_rsp = self.state.with_address(scan_rsp, &mut buffer);
+                    self.radio.transmit(scan_rsp).await?;
+                }
+                Ok(None)
+            }
+            Ok(AdvPdu::ConnectInd(pdu)) if self.state.accepts_connect(&pdu) => {
+                Ok(Some(ConnectRequest { pdu, timestamp }))
+            }
+            _ => Ok(None),
+        }
+    }
+}
+
+impl<'r, R: Radio, RNG: Rng> LinkLayer<'r, R, Advertising<'_, RNG>> {
+    /// Advertise until the target of the directed advertising connects,
+    /// or until the high duty cycle advertising times out
+    pub async fn transmit_directed(&mut self) -> Result<DirectedEvent, R::Error> {
+        loop {
+            match self.transmit().await {
+                Ok(Some(request)) => return Ok(DirectedEvent::Connect(request)),
+                Ok(None) => {}
+                Err(AdvertisingError::Timeout) => return Ok(DirectedEvent::Timeout),
+                Err(AdvertisingError::Radio(error)) => return Err(error),
+            }
         }
+    }
+
+    /// The high duty cycle directed advertising ended without a connection,
+    /// the link layer should go back to standby
+    pub fn is_timed_out(&self) -> bool {
+        self.state.is_timed_out(Instant::now())
+    }
+
+    /// Only answer the scanners and initiators allowed by the `policy` and the `list`
+    pub fn set_filter_policy(&mut self, policy: AdvertisingFilterPolicy, list: FilterAcceptList) {
+        let hardware = policy.filters_all().then_some(&list);
+        self.radio.set_device_address_match(hardware);
+
+        self.state.filter_policy = policy;
+        self.state.accept_list = list;
+    }
+}
+
+impl<'r, R: Radio, RNG: Rng + SeedableRng> LinkLayer<'r, R, Advertising<'_, RNG>> {
+    /// Advertise with a resolvable private address generated from the local `irk`,
+    /// changed every `timeout`. Only the peers that know the IRK can link the addresses.
+    ///
+    /// The pseudo-random generator is seeded again from `rng`, so each power cycle
+    /// generates different addresses.
+    ///
+    /// Ref: Core 6.B.1.3.2.2
+    pub fn set_privacy(&mut self, irk: [u8; 16], timeout: Duration, rng: &mut impl RngCore) {
+        if let Ok(seeded) = RNG::from_rng(rng) {
+            self.state.rng = seeded;
+        }
+        self.state.privacy = Some(Privacy {
+            irk,
+            timeout,
+            renew: Instant::MIN,
+        });
+        self.state.rotate_address(Instant::now());
+    }
 
-        Ok(())
+    /// Address currently advertised
+    pub fn address(&self) -> &Address {
+        &self.state.address
     }
 }
 
+/// Duty cycle of the connectable directed advertising
+///
+/// Ref: Core 6.B.4.4.2.4
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Format)]
+pub enum DutyCycle {
+    /// ADV_DIRECT_IND every 3.75 ms at most, for fast reconnections, during 1.28 s
+    High,
+
+    /// ADV_DIRECT_IND every advertising interval, as the undirected advertising,
+    /// without time limit
+    Low(Duration),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Format)]
+pub enum AdvertisingError<E> {
+    /// The radio failed
+    Radio(E),
+
+    /// The high duty cycle directed advertising ended without a connection
+    Timeout,
+}
+
+/// End of the connectable directed advertising
+#[derive(Debug, Clone, PartialEq, Eq, Format)]
+pub enum DirectedEvent {
+    /// The target sent a CONNECT_IND, the link layer should move to the connection state
+    Connect(ConnectRequest),
+
+    /// The high duty cycle advertising ended without a connection
+    Timeout,
+}
+
+/// Connectable directed advertising state
+struct Directed {
+    /// Initiator allowed to connect, the TargetA of the ADV_DIRECT_IND
+    target: Address,
+
+    /// End of the high duty cycle advertising
+    deadline: Option<Instant>,
+}
+
+/// Resolvable private address rotation of the advertising
+struct Privacy {
+    /// Local IRK that generates the addresses
+    irk: [u8; 16],
+
+    /// Time between the address changes
+    timeout: Duration,
+
+    /// When the next address is generated
+    renew: Instant,
+}
+
 pub struct Standby {}
 pub struct Advertising<'a, RNG: Rng> {
     /// Pseudo-random value used to generate the advDelay between each advertising event
@@ -123,25 +388,217 @@ pub struct Advertising<'a, RNG: Rng> {
 
     event: Instant,
 
+    /// Advertising PDU sent on each channel
     data: &'a [u8],
+
+    /// SCAN_RSP PDU, only set when the advertising is scannable
+    scan_rsp: Option<&'a [u8]>,
+
+    /// Advertiser address (AdvA) of the advertising PDU,
+    /// replaced on the transmitted PDUs when privacy is enabled
+    address: Address,
+
+    /// The address is a resolvable private address changed periodically
+    privacy: Option<Privacy>,
+
+    /// Which scanners and initiators are answered
+    filter_policy: AdvertisingFilterPolicy,
+
+    accept_list: FilterAcceptList,
+
+    /// Only set on connectable directed advertising
+    directed: Option<Directed>,
+
+    /// Type of the advertising PDU
+    pdu_type: u8,
+
+    /// The advertising PDU announces support to the Channel Selection Algorithm #2
+    ch_sel: bool,
 }
 
 impl<'a, RNG: Rng> Advertising<'a, RNG> {
-    pub fn new(rng: RNG, interval: Duration, data: &'a [u8]) -> Self {
+    /// Advertising of the legacy advertising PDU in `data`, answering SCAN_REQ with the
+    /// SCAN_RSP PDU in `scan_rsp`. Fails when the PDUs are truncated or of another type.
+    pub fn new(
+        rng: RNG,
+        interval: Duration,
+        data: &'a [u8],
+        scan_rsp: Option<&'a [u8]>,
+    ) -> Result<Self, ParseError> {
+        Self::check_interval(interval);
+        Self::at(rng, interval, data, scan_rsp, Instant::now())
+    }
+
+    /// Connectable directed advertising of the ADV_DIRECT_IND in `data`
+    pub fn new_directed(
+        rng: RNG,
+        duty_cycle: DutyCycle,
+        data: &'a [u8],
+    ) -> Result<Self, ParseError> {
+        Self::directed_at(rng, duty_cycle, data, Instant::now())
+    }
+
+    /// Header of a legacy PDU starting with the AdvA, checking that the `pdu` holds it
+    fn parse_header(pdu: &[u8]) -> Result<Header, ParseError> {
+        if pdu.len() < 8 {
+            return Err(ParseError::InvalidLength);
+        }
+
+        let header = Header::parse(pdu[..2].try_into().unwrap());
+        if (header.length as usize) < 6 || header.length as usize + 2 > pdu.len() {
+            return Err(ParseError::InvalidLength);
+        }
+        Ok(header)
+    }
+
+    fn check_interval(interval: Duration) {
         assert!(interval >= Duration::from_micros(20_000));
         assert!(interval <= Duration::from_micros(10_485_759_375));
+    }
+
+    fn directed_at(
+        rng: RNG,
+        duty_cycle: DutyCycle,
+        data: &'a [u8],
+        now: Instant,
+    ) -> Result<Self, ParseError> {
+        let pdu = AdvDirectInd::parse(data)?;
+
+        let (interval, deadline) = match duty_cycle {
+            DutyCycle::High => (
+                HIGH_DUTY_CYCLE_INTERVAL,
+                Some(now + HIGH_DUTY_CYCLE_TIMEOUT),
+            ),
+            DutyCycle::Low(interval) => {
+                Self::check_interval(interval);
+                (interval, None)
+            }
+        };
+
+        let mut advertising = Self::at(rng, interval, data, None, now)?;
+        advertising.directed = Some(Directed {
+            target: pdu.target_address().clone(),
+            deadline,
+        });
+        Ok(advertising)
+    }
+
+    fn at(
+        rng: RNG,
+        interval: Duration,
+        data: &'a [u8],
+        scan_rsp: Option<&'a [u8]>,
+        event: Instant,
+    ) -> Result<Self, ParseError> {
+        // All legacy advertising PDUs start with the AdvA right after the header
+        let header = Self::parse_header(data)?;
+        let advertising_types = [
+            AdvInd::PDU_TYPE,
+            AdvDirectInd::PDU_TYPE,
+            AdvNonconnInd::PDU_TYPE,
+            AdvScanInd::PDU_TYPE,
+        ];
+        if !advertising_types.contains(&header.flags.pdu_type) {
+            return Err(ParseError::InvalidType);
+        }
+        if let Some(scan_rsp) = scan_rsp {
+            if Self::parse_header(scan_rsp)?.flags.pdu_type != ScanRsp::PDU_TYPE {
+                return Err(ParseError::InvalidType);
+            }
+        }
+
+        let address = Address::new_le(
+            data[2..8].try_into().unwrap(),
+            Header::bit_to_address_type(header.flags.tx_add),
+        );
 
         // Data should be set in the radio before starting the advertising
-        Advertising {
+        Ok(Advertising {
             rng,
             interval,
-            event: Instant::now(),
+            event,
             data,
+            scan_rsp,
+            address,
+            privacy: None,
+            filter_policy: AdvertisingFilterPolicy::ProcessAll,
+            accept_list: FilterAcceptList::new(),
+            directed: None,
+            pdu_type: header.flags.pdu_type,
+            ch_sel: header.flags.ch_sel,
+        })
+    }
+
+    /// Connectable advertising accepts CONNECT_IND after the advertising PDU
+    fn is_connectable(&self) -> bool {
+        self.pdu_type == AdvInd::PDU_TYPE || self.pdu_type == AdvDirectInd::PDU_TYPE
+    }
+
+    /// Accept the CONNECT_IND addressed to this advertiser from an allowed initiator.
+    /// The filter policy is not used on directed advertising, only the target may connect.
+    ///
+    /// Ref: Core 6.B.4.3.2
+    fn accepts_connect(&self, pdu: &ConnectInd) -> bool {
+        if !self.is_connectable() || pdu.adv_address() != &self.address {
+            return false;
+        }
+
+        match &self.directed {
+            Some(directed) => pdu.init_address() == &directed.target,
+            None => self
+                .filter_policy
+                .allows_connect(&self.accept_list, pdu.init_address()),
+        }
+    }
+
+    fn is_high_duty_cycle(&self) -> bool {
+        self.deadline().is_some()
+    }
+
+    fn deadline(&self) -> Option<Instant> {
+        self.directed
+            .as_ref()
+            .and_then(|directed| directed.deadline)
+    }
+
+    /// The high duty cycle directed advertising ended
+    fn is_timed_out(&self, now: Instant) -> bool {
+        self.deadline().is_some_and(|deadline| now >= deadline)
+    }
+
+    /// Generate a new resolvable private address once the current one expires
+    fn rotate_address(&mut self, now: Instant) {
+        let Some(privacy) = &mut self.privacy else {
+            return;
+        };
+        if now < privacy.renew {
+            return;
         }
+
+        self.address = Address::new_resolvable(&privacy.irk, &mut self.rng);
+        privacy.renew = now + privacy.timeout;
+    }
+
+    /// Copy the `pdu` to the `buffer` with the current advertiser address,
+    /// the AdvA and the TxAdd of the ADV_* and SCAN_RSP PDUs
+    fn with_address<'b>(&self, pdu: &[u8], buffer: &'b mut [u8]) -> &'b [u8] {
+        let buffer = &mut buffer[..pdu.len()];
+        buffer.copy_from_slice(pdu);
+
+        let mut header = Header::parse(pdu[..2].try_into().unwrap());
+        header.flags.tx_add = self.address.r#type == AddressType::Random;
+        buffer[..2].copy_from_slice(&header.bytes());
+        buffer[2..8].copy_from_slice(&self.address.bytes());
+        buffer
     }
 
     /// The advDelay is a (pseudo-)random value with a range 0 ms to 10 ms generated by the Link Layer for each advertising event.
+    /// The high duty cycle directed advertising has no advDelay.
     fn delay(&mut self) -> Duration {
+        if self.is_high_duty_cycle() {
+            return Duration::from_ticks(0);
+        }
+
         let delay = self.rng.gen_range(0..10_000);
         Duration::from_micros(delay)
     }
@@ -150,3 +607,123 @@ impl<'a, RNG: Rng> Advertising<'a, RNG> {
         self.event + self.interval + self.delay()
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        phy::ChannelMap,
+        testing::{block_on, now, Silence},
+    };
+
+    fn connect_ind(init_address: Address, adv_address: Address) -> ConnectInd {
+        let ll_data = LLData {
+            access_address: 0x50654DE1,
+            crc_init: 0x8F_A3_55,
+            win_size: 2,
+            win_offset: 3,
+            interval: 24,
+            latency: 0,
+            timeout: 72,
+            channel_map: ChannelMap::all(),
+            hop: 9,
+            sca: 5,
+        };
+        ConnectInd::new(init_address, adv_address, ll_data, false)
+    }
+
+    #[test]
+    fn directed_advertising() {
+        let own = Address::new_random(0xc1e1e8d0dc27);
+        let hub = Address::new_public(0x0102030405);
+        let other = Address::new_public(0x0a0b0c0d0e);
+        let mut buffer = [0u8; AdvDirectInd::PDU_LENGTH];
+        AdvDirectInd::new(own.clone(), hub.clone()).bytes(&mut buffer);
+
+        let start = Instant::from_ticks(0);
+        let rng = SmallRng::seed_from_u64(1);
+        let mut high =
+            Advertising::directed_at(rng.clone(), DutyCycle::High, &buffer, start).unwrap();
+
+        // The ADV_DIRECT_IND are sent every 3.75 ms, without advDelay, during 1.28 s
+        assert_eq!(high.next_event(), start + Duration::from_micros(3_750));
+        assert!(!high.is_timed_out(start + Duration::from_millis(1_279)));
+        assert!(high.is_timed_out(start + Duration::from_millis(1_280)));
+
+        // Only the target may connect, even when the filter policy allows everyone
+        assert!(high.accepts_connect(&connect_ind(hub.clone(), own.clone())));
+        assert!(!high.accepts_connect(&connect_ind(other.clone(), own.clone())));
+        assert!(!high.accepts_connect(&connect_ind(hub.clone(), other.clone())));
+
+        let low_interval = Duration::from_millis(100);
+        let mut low =
+            Advertising::directed_at(rng, DutyCycle::Low(low_interval), &buffer, start).unwrap();
+        assert!(low.next_event() >= start + low_interval);
+        assert!(!low.is_timed_out(start + Duration::from_secs(3600)));
+        assert!(low.accepts_connect(&connect_ind(hub, own)));
+    }
+
+    #[test]
+    fn directed_advertising_timeout() {
+        let own = Address::new_random(0xc1e1e8d0dc27);
+        let hub = Address::new_public(0x0102030405);
+        let mut buffer = [0u8; AdvDirectInd::PDU_LENGTH];
+        AdvDirectInd::new(own, hub).bytes(&mut buffer);
+
+        let mut radio = Silence;
+        let start = now();
+        let mut ll = LinkLayer::new(&mut radio)
+            .advertise_directed(DutyCycle::High, &buffer)
+            .unwrap();
+        let error = loop {
+            if let Err(error) = block_on(ll.transmit()) {
+                break error;
+            }
+        };
+        assert_eq!(error, AdvertisingError::Timeout);
+        assert!(now() >= start + Duration::from_millis(1_280));
+
+        // Every following call reports the end of the advertising
+        assert_eq!(block_on(ll.transmit()), Err(AdvertisingError::Timeout));
+        assert_eq!(block_on(ll.transmit_directed()), Ok(DirectedEvent::Timeout));
+    }
+
+    #[test]
+    fn invalid_advertising_data() {
+        let rng = SmallRng::seed_from_u64(1);
+        let interval = Duration::from_millis(100);
+        let start = Instant::from_ticks(0);
+        let address = Address::new_random(0xc1e1e8d0dc27);
+
+        let mut data = [0u8; MAX_PDU_LENGTH];
+        let len = AdvInd::new(address.clone(), &[0x02, 0x01, 0x06]).bytes(&mut data);
+        let mut scan_rsp = [0u8; MAX_PDU_LENGTH];
+        let scan_rsp_len = ScanRsp::new(address.clone(), &[]).bytes(&mut scan_rsp);
+        let at = |data, scan_rsp| Advertising::at(rng.clone(), interval, data, scan_rsp, start);
+
+        assert!(at(&data[..len], Some(&scan_rsp[..scan_rsp_len])).is_ok());
+        assert_eq!(at(&[], None).err(), Some(ParseError::InvalidLength));
+        assert_eq!(at(&data[..7], None).err(), Some(ParseError::InvalidLength));
+        // The header length is longer than the data
+        assert_eq!(
+            at(&data[..len - 1], None).err(),
+            Some(ParseError::InvalidLength)
+        );
+        assert_eq!(
+            at(&data[..len], Some(&scan_rsp[..4])).err(),
+            Some(ParseError::InvalidLength)
+        );
+        // The SCAN_RSP is not an advertising PDU and the ADV_IND is not a SCAN_RSP
+        assert_eq!(
+            at(&scan_rsp[..scan_rsp_len], None).err(),
+            Some(ParseError::InvalidType)
+        );
+        assert_eq!(
+            at(&data[..len], Some(&data[..len])).err(),
+            Some(ParseError::InvalidType)
+        );
+
+        let short = Advertising::directed_at(rng.clone(), DutyCycle::High, &data[..8], start);
+        assert_eq!(short.err(), Some(ParseError::InvalidLength));
+    }
+}
@@ -0,0 +1,397 @@
+//! Scanning state
+//!
+//! The scanner listens on the primary advertising channels, changing the
+//! channel every scan interval and listening during the scan window.
+//! An active scanner also sends a SCAN_REQ to scannable advertisers and
+//! waits for their SCAN_RSP, backing off randomly when the responses are lost
+//! to avoid colliding with the other scanners.
+//!
+//! Ref: [Core 6.B.4.4.3](https://www.bluetooth.com/wp-content/uploads/Files/Specification/HTML/Core-54/out/en/low-energy-controller/link-layer-specification.html)
+
+use defmt::Format;
+use embassy_time::{with_timeout, Duration, Instant, Timer};
+use rand::{rngs::SmallRng, Rng, SeedableRng};
+
+use crate::phy::{AdvertisingChannel, Radio, MAX_PDU_LENGTH};
+
+use super::{
+    adv, response_timeout, Address, AddressAndData, AdvPdu, FilterAcceptList, LinkLayer, ScanReq,
+    ScanRsp, ScannerFilterPolicy, Standby, TwoAddress,
+};
+
+/// Maximum length of a legacy advertising PDU, header included
+const LEGACY_ADV_PDU_LENGTH: usize = 39;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Format)]
+pub enum ScanType {
+    /// Only listen to advertising PDUs
+    Passive,
+
+    /// Send SCAN_REQ to scannable advertisers
+    Active,
+}
+
+/// Largest upperLimit of the backoff procedure
+const MAX_BACKOFF_LIMIT: u16 = 256;
+
+/// Backoff procedure of the active scanner, it skips a random number of scannable
+/// advertising PDUs before each SCAN_REQ. The range doubles after two consecutive
+/// lost SCAN_RSP and halves after two consecutive received ones.
+///
+/// Ref: Core 6.B.4.4.3.2
+struct Backoff {
+    rng: SmallRng,
+
+    /// upperLimit, largest backoffCount
+    upper_limit: u16,
+
+    /// backoffCount, scannable PDUs until the next SCAN_REQ
+    count: u16,
+
+    /// Consecutive SCAN_RSP received
+    successes: u8,
+
+    /// Consecutive SCAN_RSP lost
+    failures: u8,
+}
+
+impl Backoff {
+    fn new(seed: u64) -> Self {
+        Backoff {
+            rng: SmallRng::seed_from_u64(seed),
+            upper_limit: 1,
+            count: 1,
+            successes: 0,
+            failures: 0,
+        }
+    }
+
+    /// Count a scannable advertising PDU, returns if a SCAN_REQ shall be sent to it
+    fn should_request(&mut self) -> bool {
+        self.count -= 1;
+        self.count == 0
+    }
+
+    /// Update the upperLimit with the result of the SCAN_REQ and draw the next backoffCount
+    fn on_response(&mut self, received: bool) {
+        if received {
+            self.failures = 0;
+            self.successes += 1;
+            if self.successes == 2 {
+                self.successes = 0;
+                self.upper_limit = (self.upper_limit / 2).max(1);
+            }
+        } else {
+            self.successes = 0;
+            self.failures += 1;
+            if self.failures == 2 {
+                self.failures = 0;
+                self.upper_limit = (self.upper_limit * 2).min(MAX_BACKOFF_LIMIT);
+            }
+        }
+
+        self.count = self.rng.gen_range(1..=self.upper_limit);
+    }
+}
+
+/// An advertising PDU received while scanning
+#[derive(Debug, Clone, PartialEq, Eq, Format)]
+pub struct AdvReport<'a> {
+    pub pdu: AdvPdu<'a>,
+
+    /// Scan response of the advertiser, only on active scanning
+    pub scan_rsp: Option<ScanRsp<'a>>,
+
+    /// Channel where the PDU was received
+    pub channel: AdvertisingChannel,
+
+    /// When the reception of the PDU was completed
+    pub timestamp: Instant,
+}
+
+pub struct Scanning {
+    scan_type: ScanType,
+
+    /// The scan interval, time between the start of two consecutive scan windows.
+    /// It should be in the range 2.5 ms to 40.959375 s.
+    interval: Duration,
+
+    /// The scan window, time the scanner listens on each interval.
+    /// It should be less than or equal to the scan interval.
+    window: Duration,
+
+    /// Channel used in the current scan window
+    pub(super) channel: AdvertisingChannel,
+
+    pub(super) window_start: Instant,
+
+    /// Which advertising PDUs are reported
+    filter_policy: ScannerFilterPolicy,
+
+    accept_list: FilterAcceptList,
+
+    /// Backoff of the SCAN_REQ, only used on active scanning
+    backoff: Backoff,
+}
+
+impl Scanning {
+    pub fn new(scan_type: ScanType, interval: Duration, window: Duration) -> Self {
+        assert!(interval >= Duration::from_micros(2_500));
+        assert!(interval <= Duration::from_micros(40_959_375));
+        assert!(window >= Duration::from_micros(2_500));
+        assert!(window <= interval);
+
+        let window_start = Instant::now();
+        Scanning {
+            scan_type,
+            interval,
+            window,
+            channel: AdvertisingChannel::Ch37,
+            window_start,
+            filter_policy: ScannerFilterPolicy::AcceptAll,
+            accept_list: FilterAcceptList::new(),
+            backoff: Backoff::new(window_start.as_ticks()),
+        }
+    }
+
+    pub(super) fn window_end(&self) -> Instant {
+        self.window_start + self.window
+    }
+
+    /// A new channel shall be used for each scan window
+    pub(super) fn next_window(&mut self) {
+        self.window_start += self.interval;
+        self.channel = match self.channel {
+            AdvertisingChannel::Ch37 => AdvertisingChannel::Ch38,
+            AdvertisingChannel::Ch38 => AdvertisingChannel::Ch39,
+            AdvertisingChannel::Ch39 => AdvertisingChannel::Ch37,
+        };
+    }
+}
+
+impl<'r, R: Radio> LinkLayer<'r, R, Standby> {
+    /// Listen on the primary advertising channels
+    pub fn scan(self, interval: Duration, window: Duration) -> LinkLayer<'r, R, Scanning> {
+        self.start_scanning(ScanType::Passive, interval, window)
+    }
+
+    /// Listen on the primary advertising channels, requesting the scan response of scannable advertisers
+    pub fn scan_active(self, interval: Duration, window: Duration) -> LinkLayer<'r, R, Scanning> {
+        self.start_scanning(ScanType::Active, interval, window)
+    }
+
+    fn start_scanning(
+        mut self,
+        scan_type: ScanType,
+        interval: Duration,
+        window: Duration,
+    ) -> LinkLayer<'r, R, Scanning> {
+        self.configure_advertising_physical_channel();
+
+        let mut state = Scanning::new(scan_type, interval, window);
+        self.radio.set_channel(state.channel.into());
+
+        // Scanners started together shall not back off in the same way
+        let mut address = [0u8; 8];
+        address[..6].copy_from_slice(&self.radio.device_address().bytes());
+        let seed = state.window_start.as_ticks() ^ u64::from_le_bytes(address);
+        state.backoff = Backoff::new(seed);
+
+        LinkLayer {
+            radio: self.radio,
+            state,
+        }
+    }
+}
+
+impl<'r, R: Radio> LinkLayer<'r, R, Scanning> {
+    /// Only report the advertising allowed by the `policy` and the `list`
+    pub fn set_filter_policy(&mut self, policy: ScannerFilterPolicy, list: FilterAcceptList) {
+        let hardware = policy.filters_all().then_some(&list);
+        self.radio.set_device_address_match(hardware);
+
+        self.state.filter_policy = policy;
+        self.state.accept_list = list;
+    }
+
+    /// Wait for the next valid advertising PDU.
+    /// Invalid PDUs and reception errors are discarded and between scan windows the radio is idle.
+    ///
+    /// No scan response is requested, active scanners use `receive_active`.
+    pub async fn receive<'b>(
+        &mut self,
+        buffer: &'b mut [u8; MAX_PDU_LENGTH],
+    ) -> Result<AdvReport<'b>, R::Error> {
+        let timestamp = self.wait_advertising(buffer).await;
+        let channel = self.state.channel;
+
+        Ok(AdvReport {
+            pdu: adv::parse(buffer).unwrap(),
+            scan_rsp: None,
+            channel,
+            timestamp,
+        })
+    }
+
+    /// Wait for the next valid advertising PDU, as `receive`, and on active scanning
+    /// request the scan response of scannable advertisers in `scan_rsp_buffer`
+    pub async fn receive_active<'b>(
+        &mut self,
+        buffer: &'b mut [u8; MAX_PDU_LENGTH],
+        scan_rsp_buffer: &'b mut [u8; MAX_PDU_LENGTH],
+    ) -> Result<AdvReport<'b>, R::Error> {
+        let timestamp = self.wait_advertising(buffer).await;
+        let channel = self.state.channel;
+
+        let pdu = adv::parse(buffer).unwrap();
+        let scan_rsp = match (self.state.scan_type, &pdu) {
+            (ScanType::Active, AdvPdu::AdvInd(adv)) => {
+                self.request_scan_response(adv.address(), scan_rsp_buffer)
+                    .await?
+            }
+            (ScanType::Active, AdvPdu::AdvScanInd(adv)) => {
+                self.request_scan_response(adv.address(), scan_rsp_buffer)
+                    .await?
+            }
+            _ => None,
+        };
+
+        Ok(AdvReport {
+            pdu,
+            scan_rsp,
+            channel,
+            timestamp,
+        })
+    }
+
+    /// Listen until an advertising PDU accepted by the filter policy is received in `buffer`,
+    /// returning when its reception was completed
+    async fn wait_advertising(&mut self, buffer: &mut [u8; MAX_PDU_LENGTH]) -> Instant {
+        loop {
+            let now = Instant::now();
+            if now >= self.state.window_end() {
+                self.state.next_window();
+                Timer::at(self.state.window_start).await;
+                self.radio.set_channel(self.state.channel.into());
+                continue;
+            }
+
+            // A timeout ends the window, a reception error (as a CRC failure) drops the PDU
+            let timeout = self.state.window_end() - now;
+            let Ok(Ok(())) = with_timeout(timeout, self.radio.receive(buffer)).await else {
+                continue;
+            };
+
+            let local = self.radio.device_address();
+            let accepted = adv::parse(buffer).is_ok_and(|pdu| {
+                let state = &self.state;
+                state
+                    .filter_policy
+                    .accepts(&state.accept_list, &pdu, &local)
+            });
+            if accepted {
+                return Instant::now();
+            }
+        }
+    }
+
+    /// Send a SCAN_REQ within T_IFS and wait for the SCAN_RSP of the same advertiser,
+    /// unless the backoff procedure skips this advertising PDU
+    ///
+    /// Ref: Core 6.B.4.4.3.2
+    async fn request_scan_response<'b>(
+        &mut self,
+        adv_address: &Address,
+        buffer: &'b mut [u8; MAX_PDU_LENGTH],
+    ) -> Result<Option<ScanRsp<'b>>, R::Error> {
+        if !self.state.backoff.should_request() {
+            return Ok(None);
+        }
+
+        let scan_rsp = self.exchange_scan_request(adv_address, buffer).await?;
+        self.state.backoff.on_response(scan_rsp.is_some());
+        Ok(scan_rsp)
+    }
+
+    async fn exchange_scan_request<'b>(
+        &mut self,
+        adv_address: &Address,
+        buffer: &'b mut [u8; MAX_PDU_LENGTH],
+    ) -> Result<Option<ScanRsp<'b>>, R::Error> {
+        let scan_req = ScanReq::new(self.radio.device_address(), adv_address.clone());
+        let mut scan_req_buffer = [0u8; ScanReq::PDU_LENGTH];
+        scan_req.bytes(&mut scan_req_buffer);
+
+        self.radio.transmit(&scan_req_buffer).await?;
+
+        let timeout = response_timeout(LEGACY_ADV_PDU_LENGTH);
+        let Ok(Ok(())) = with_timeout(timeout, self.radio.receive(buffer)).await else {
+            return Ok(None);
+        };
+
+        match ScanRsp::parse(buffer) {
+            Ok(scan_rsp) if scan_rsp.address() == adv_address => Ok(Some(scan_rsp)),
+            _ => Ok(None),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn rotate_channels() {
+        let mut state = Scanning {
+            scan_type: ScanType::Passive,
+            interval: Duration::from_millis(100),
+            window: Duration::from_millis(50),
+            channel: AdvertisingChannel::Ch37,
+            window_start: Instant::from_ticks(0),
+            filter_policy: ScannerFilterPolicy::AcceptAll,
+            accept_list: FilterAcceptList::new(),
+            backoff: Backoff::new(0),
+        };
+
+        state.next_window();
+        assert_eq!(state.channel, AdvertisingChannel::Ch38);
+        state.next_window();
+        assert_eq!(state.channel, AdvertisingChannel::Ch39);
+        state.next_window();
+        assert_eq!(state.channel, AdvertisingChannel::Ch37);
+        assert_eq!(
+            state.window_start,
+            Instant::from_ticks(0) + Duration::from_millis(300)
+        );
+    }
+
+    #[test]
+    fn backoff() {
+        let mut backoff = Backoff::new(7);
+
+        // The first scannable PDU is requested
+        assert!(backoff.should_request());
+
+        // The upper limit doubles every two lost responses, up to 256
+        for _ in 0..20 {
+            backoff.on_response(false);
+            assert!((1..=backoff.upper_limit).contains(&backoff.count));
+        }
+        assert_eq!(backoff.upper_limit, MAX_BACKOFF_LIMIT);
+
+        // backoffCount scannable PDUs are skipped until the next request
+        let skipped = backoff.count - 1;
+        for _ in 0..skipped {
+            assert!(!backoff.should_request());
+        }
+        assert!(backoff.should_request());
+
+        // And halves every two received responses, down to 1
+        for _ in 0..20 {
+            backoff.on_response(true);
+        }
+        assert_eq!(backoff.upper_limit, 1);
+        assert_eq!(backoff.count, 1);
+        assert!(backoff.should_request());
+    }
+}
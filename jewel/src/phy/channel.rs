@@ -2,6 +2,8 @@
 //!
 //! Ref: [Core 6.B.1.4](https://www.bluetooth.com/wp-content/uploads/Files/Specification/HTML/Core-54/out/en/low-energy-controller/link-layer-specification.html#UUID-3abb4023-1a31-b4db-cb9d-a70064cb40a0)
 
+use defmt::Format;
+
 /// Utility trait for RF channels
 pub trait ChannelTrait {
     fn channel_index(&self) -> u8;
@@ -26,8 +28,8 @@ pub trait ChannelTrait {
     /// which the packet is transmitted in the following manner:
     /// - Position 0 is set to one.
     /// - Positions 1 to 6 are set to the channel index of the channel used
-    ///  when transmitting or receiving, from the most significant bit in
-    /// position 1 to the least significant bit in position 6.
+    ///   when transmitting or receiving, from the most significant bit in
+    ///   position 1 to the least significant bit in position 6.
     ///
     /// For example, if the channel index = 23 (0x17), the positions would be set as follows:
     /// Position 0 = 1
@@ -52,14 +54,14 @@ pub trait ChannelTrait {
 
 /// 39 RF channels, separated by Advertising channels (Primary Advertising)
 /// and Data channels (General Purpose)
-#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Format)]
 pub enum Channel {
     Advertising(AdvertisingChannel),
     Data(DataChannel),
 }
 
 /// 3 of the 39 RF channels used for initial advertising and all legacy advertising activitie.
-#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Format)]
 #[repr(u8)]
 pub enum AdvertisingChannel {
     #[doc = "Channel 37"]
@@ -73,7 +75,7 @@ pub enum AdvertisingChannel {
 }
 
 /// 36 of the 39 RF channels used for initial advertising and all legacy advertising activitie.
-#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Format)]
 #[repr(u8)]
 pub enum DataChannel {
     #[doc = "Channel 0"]
@@ -364,6 +366,72 @@ impl TryFrom<u8> for DataChannel {
     }
 }
 
+/// Data channels used by a connection, the 37 bits ChM field.
+/// The n-th bit, counting from the least significant bit of the first byte, is the channel index n.
+///
+/// Ref: [Core 6.B.2.3.3.1](https://www.bluetooth.com/wp-content/uploads/Files/Specification/HTML/Core-54/out/en/low-energy-controller/link-layer-specification.html)
+/// ```
+/// use jewel::phy::{ChannelMap, DataChannel};
+///
+/// let map = ChannelMap::new([0b0000_0101, 0, 0, 0, 0]);
+/// assert!(map.is_used(DataChannel::Ch2));
+/// assert_eq!(map.len(), 2);
+/// assert_eq!(map.used(1), DataChannel::Ch2);
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Format)]
+pub struct ChannelMap {
+    map: u64,
+}
+
+impl ChannelMap {
+    const MASK: u64 = (1 << 37) - 1;
+
+    /// From the ChM field, the RFU bits are ignored
+    pub fn new(bytes: [u8; 5]) -> Self {
+        let mut map = [0u8; 8];
+        map[..5].copy_from_slice(&bytes);
+        Self {
+            map: u64::from_le_bytes(map) & Self::MASK,
+        }
+    }
+
+    /// All the 37 data channels are used
+    pub fn all() -> Self {
+        Self { map: Self::MASK }
+    }
+
+    pub fn bytes(&self) -> [u8; 5] {
+        let bytes = self.map.to_le_bytes();
+        [bytes[0], bytes[1], bytes[2], bytes[3], bytes[4]]
+    }
+
+    pub fn is_used(&self, channel: DataChannel) -> bool {
+        self.map & (1 << channel.channel_index()) != 0
+    }
+
+    /// Number of used channels
+    pub fn len(&self) -> u8 {
+        self.map.count_ones() as u8
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.map == 0
+    }
+
+    /// Used channels in ascending order of channel index
+    pub fn channels(&self) -> impl Iterator<Item = DataChannel> + '_ {
+        (0..37)
+            .filter(|index| self.map & (1 << index) != 0)
+            .map(|index| DataChannel::try_from(index).unwrap())
+    }
+
+    /// The used channel at `index` in the table of used channels,
+    /// sorted in ascending order of channel index
+    pub fn used(&self, index: u8) -> DataChannel {
+        self.channels().nth(index as usize).unwrap()
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -419,4 +487,19 @@ mod test {
         assert_eq!(AdvertisingChannel::Ch38.whitening_init(), 0b0110_0110);
         assert_eq!(AdvertisingChannel::Ch39.whitening_init(), 0b0110_0111);
     }
+
+    #[test]
+    fn channel_map() {
+        let map = ChannelMap::new([0x00, 0x06, 0xE0, 0x00, 0x1E]);
+        assert_eq!(map.len(), 9);
+        assert!(!map.is_used(DataChannel::Ch0));
+        assert!(map.is_used(DataChannel::Ch36));
+        assert_eq!(map.used(0), DataChannel::Ch9);
+        assert_eq!(map.used(8), DataChannel::Ch36);
+        assert_eq!(map.bytes(), [0x00, 0x06, 0xE0, 0x00, 0x1E]);
+
+        // RFU bits are ignored
+        assert_eq!(ChannelMap::new([0xFF; 5]), ChannelMap::all());
+        assert_eq!(ChannelMap::all().len(), 37);
+    }
 }
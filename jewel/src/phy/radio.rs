@@ -28,7 +28,7 @@
 //
 // Each fild is send with the least significant bit first.
 
-use crate::{phy::channel::Channel, Address};
+use crate::{ll::FilterAcceptList, phy::channel::Channel, Address};
 
 /// Maximum PDU length
 pub const MAX_PDU_LENGTH: usize = 258;
@@ -96,5 +96,40 @@ pub trait Radio {
 
     fn device_address(&self) -> Address;
 
-    // TODO: Hardware Link Layer device filtering (6.20.10 Device address match)
+    /// Encrypt the payload in place with the AES-CCM of the Link Layer encryption,
+    /// returning the 4 bytes MIC. The key is the session key, most significant octet first,
+    /// and the additional data is the first octet of the header.
+    ///
+    /// Radios with a CCM block can implement it, `None` uses the software implementation.
+    fn ccm_encrypt(
+        &mut self,
+        _key: &[u8; 16],
+        _nonce: &[u8; 13],
+        _aad: u8,
+        _payload: &mut [u8],
+    ) -> Option<[u8; 4]> {
+        None
+    }
+
+    /// Decrypt the payload in place, returning if the MIC is valid.
+    ///
+    /// Radios with a CCM block can implement it, `None` uses the software implementation.
+    fn ccm_decrypt(
+        &mut self,
+        _key: &[u8; 16],
+        _nonce: &[u8; 13],
+        _aad: u8,
+        _payload: &mut [u8],
+        _mic: &[u8; 4],
+    ) -> Option<bool> {
+        None
+    }
+
+    /// Match the address of the received packets against the `list` in hardware, the
+    /// address after the header, dropping the packets of other devices before they are
+    /// processed. `None` disables the matching.
+    ///
+    /// The link layer applies the filter policies in software anyway, radios with a device
+    /// address match (nRF 6.20.10) can implement it to save the processing of the others.
+    fn set_device_address_match(&mut self, _list: Option<&FilterAcceptList>) {}
 }
@@ -0,0 +1,254 @@
+//! Keys of the bonded peers
+//!
+//! The keys distributed after the pairing are saved in a [`BondStore`], keyed by
+//! the identity address of the peer, to encrypt the link again on the next connections
+//! without pairing. The [`Bond`] is serialized on a fixed number of bytes to write it to flash.
+//!
+//! Ref: [Core 3.H.3.6](https://www.bluetooth.com/wp-content/uploads/Files/Specification/HTML/Core-54/out/en/host/security-manager-specification.html)
+
+use defmt::Format;
+
+use crate::{
+    ll::{AddressType, ParseError},
+    Address,
+};
+
+/// Keys shared with a bonded peer
+#[derive(Debug, Clone, PartialEq, Eq, Format)]
+pub struct Bond {
+    /// Identity address of the peer, the address of the connection
+    /// when the peer did not distribute one
+    pub address: Address,
+
+    /// LTK generated by the pairing, masked to the encryption key size
+    pub ltk: [u8; 16],
+
+    /// Encryption key size agreed on the pairing
+    pub key_size: u8,
+
+    /// The pairing protected against man-in-the-middle attacks
+    pub authenticated: bool,
+
+    /// IRK of the peer, to resolve its private addresses
+    pub irk: Option<[u8; 16]>,
+
+    /// CSRK of the peer, to verify its signed data
+    pub csrk: Option<[u8; 16]>,
+}
+
+impl Bond {
+    /// Version of the serialization, the first byte
+    const FORMAT: u8 = 0x01;
+
+    const RANDOM_ADDRESS: u8 = 1 << 0;
+    const AUTHENTICATED: u8 = 1 << 1;
+    const IRK: u8 = 1 << 2;
+    const CSRK: u8 = 1 << 3;
+
+    /// Number of bytes of the serialized bond
+    pub const LENGTH: usize = 57;
+
+    /// Serialize the bond, the missing keys are zeroed
+    ///
+    ///   ┌──────────┬──────────┬───────────┬──────────┬────────────┬────────────┬────────────┐
+    ///   │ Format   │ Flags    │ Address   │ Key size │ LTK        │ IRK        │ CSRK       │
+    ///   │ (1 byte) │ (1 byte) │ (6 bytes) │ (1 byte) │ (16 bytes) │ (16 bytes) │ (16 bytes) │
+    ///   └──────────┴──────────┴───────────┴──────────┴────────────┴────────────┴────────────┘
+    pub fn bytes(&self, dest: &mut [u8]) -> usize {
+        let mut flags = 0;
+        if self.address.r#type == AddressType::Random {
+            flags |= Self::RANDOM_ADDRESS;
+        }
+        if self.authenticated {
+            flags |= Self::AUTHENTICATED;
+        }
+        if self.irk.is_some() {
+            flags |= Self::IRK;
+        }
+        if self.csrk.is_some() {
+            flags |= Self::CSRK;
+        }
+
+        dest[0] = Self::FORMAT;
+        dest[1] = flags;
+        dest[2..8].copy_from_slice(&self.address.bytes());
+        dest[8] = self.key_size;
+        dest[9..25].copy_from_slice(&self.ltk);
+        dest[25..41].copy_from_slice(&self.irk.unwrap_or_default());
+        dest[41..57].copy_from_slice(&self.csrk.unwrap_or_default());
+        Self::LENGTH
+    }
+
+    pub fn parse(bytes: &[u8]) -> Result<Self, ParseError> {
+        if bytes.len() < Self::LENGTH {
+            return Err(ParseError::InvalidLength);
+        }
+        if bytes[0] != Self::FORMAT || !(7..=16).contains(&bytes[8]) {
+            return Err(ParseError::InvalidValue);
+        }
+
+        let flags = bytes[1];
+        let address_type = match flags & Self::RANDOM_ADDRESS {
+            0 => AddressType::Public,
+            _ => AddressType::Random,
+        };
+        let key = |start: usize| -> [u8; 16] { bytes[start..start + 16].try_into().unwrap() };
+
+        Ok(Bond {
+            address: Address::new_le(bytes[2..8].try_into().unwrap(), address_type),
+            ltk: key(9),
+            key_size: bytes[8],
+            authenticated: flags & Self::AUTHENTICATED != 0,
+            irk: (flags & Self::IRK != 0).then(|| key(25)),
+            csrk: (flags & Self::CSRK != 0).then(|| key(41)),
+        })
+    }
+}
+
+/// The store has no room for a new bond
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Format)]
+pub struct BondStoreFull;
+
+/// Storage of the bonds, keyed by the identity address of the peer
+pub trait BondStore {
+    /// Bond of the peer with the identity `address`
+    fn get(&self, address: &Address) -> Option<Bond>;
+
+    /// Save the bond, replacing the previous bond of the same peer
+    fn insert(&mut self, bond: Bond) -> Result<(), BondStoreFull>;
+
+    /// Forget the bond of the peer
+    fn remove(&mut self, address: &Address) -> Option<Bond>;
+
+    /// Bond of the peer that generated the resolvable private `address` with its IRK
+    fn resolve(&self, address: &Address) -> Option<Bond>;
+}
+
+/// Bond store in RAM of up to `N` peers
+///
+/// The bonds are lost on reset, unless the application writes them to flash
+/// with `Bond::bytes` and inserts them again on start.
+pub struct RamBondStore<const N: usize> {
+    bonds: [Option<Bond>; N],
+}
+
+impl<const N: usize> RamBondStore<N> {
+    pub const fn new() -> Self {
+        RamBondStore {
+            bonds: [const { None }; N],
+        }
+    }
+
+    /// Bonds saved, in no particular order
+    pub fn iter(&self) -> impl Iterator<Item = &Bond> {
+        self.bonds.iter().flatten()
+    }
+
+    fn position(&self, address: &Address) -> Option<usize> {
+        self.bonds
+            .iter()
+            .position(|bond| bond.as_ref().is_some_and(|bond| bond.address == *address))
+    }
+}
+
+impl<const N: usize> Default for RamBondStore<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> BondStore for RamBondStore<N> {
+    fn get(&self, address: &Address) -> Option<Bond> {
+        self.bonds[self.position(address)?].clone()
+    }
+
+    fn insert(&mut self, bond: Bond) -> Result<(), BondStoreFull> {
+        let slot = self
+            .position(&bond.address)
+            .or_else(|| self.bonds.iter().position(Option::is_none))
+            .ok_or(BondStoreFull)?;
+        self.bonds[slot] = Some(bond);
+        Ok(())
+    }
+
+    fn remove(&mut self, address: &Address) -> Option<Bond> {
+        self.bonds[self.position(address)?].take()
+    }
+
+    fn resolve(&self, address: &Address) -> Option<Bond> {
+        self.iter()
+            .find(|bond| bond.irk.is_some_and(|irk| address.is_resolved_by(&irk)))
+            .cloned()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rand::{rngs::SmallRng, SeedableRng};
+
+    fn bond(address: Address) -> Bond {
+        Bond {
+            address,
+            ltk: [0x11; 16],
+            key_size: 16,
+            authenticated: true,
+            irk: Some([0x22; 16]),
+            csrk: None,
+        }
+    }
+
+    #[test]
+    fn serialization() {
+        let bond = bond(Address::new_random(0xc0ffee000001));
+        let mut buffer = [0u8; Bond::LENGTH];
+        assert_eq!(bond.bytes(&mut buffer), Bond::LENGTH);
+        assert_eq!(buffer[..2], [0x01, 0x07]);
+        assert_eq!(Bond::parse(&buffer), Ok(bond));
+
+        buffer[0] = 0xFF;
+        assert_eq!(Bond::parse(&buffer), Err(ParseError::InvalidValue));
+        assert_eq!(Bond::parse(&buffer[..8]), Err(ParseError::InvalidLength));
+    }
+
+    #[test]
+    fn ram_store() {
+        let first = Address::new_public(0x0102030405);
+        let second = Address::new_random(0xc0ffee000001);
+        let mut store = RamBondStore::<1>::new();
+
+        assert_eq!(store.insert(bond(first.clone())), Ok(()));
+        assert_eq!(store.insert(bond(second.clone())), Err(BondStoreFull));
+        assert_eq!(store.get(&second), None);
+
+        // The bond of the same peer is replaced
+        let mut updated = bond(first.clone());
+        updated.authenticated = false;
+        assert_eq!(store.insert(updated.clone()), Ok(()));
+        assert_eq!(store.get(&first), Some(updated.clone()));
+        assert_eq!(store.iter().count(), 1);
+
+        assert_eq!(store.remove(&first), Some(updated));
+        assert_eq!(store.insert(bond(second.clone())), Ok(()));
+        assert!(store.get(&second).is_some());
+    }
+
+    #[test]
+    fn resolve_private_address() {
+        let mut store = RamBondStore::<2>::new();
+        store
+            .insert(bond(Address::new_public(0x0102030405)))
+            .unwrap();
+        let mut rng = SmallRng::seed_from_u64(1);
+
+        let address = Address::new_resolvable(&[0x22; 16], &mut rng);
+        assert_eq!(
+            store.resolve(&address).unwrap().address,
+            Address::new_public(0x0102030405)
+        );
+        assert_eq!(
+            store.resolve(&Address::new_resolvable(&[0x33; 16], &mut rng)),
+            None
+        );
+    }
+}
@@ -0,0 +1,286 @@
+//! LE Secure Connections cryptographic functions
+//!
+//! The values are big endian, the most significant octet first, as in the
+//! specification. The SMP PDUs carry them in little endian.
+//!
+//! Ref: [Core 3.H.2.2](https://www.bluetooth.com/wp-content/uploads/Files/Specification/HTML/Core-54/out/en/host/security-manager-specification.html)
+
+use aes::{
+    cipher::{BlockEncrypt, KeyInit},
+    Aes128,
+};
+use cmac::{Cmac, Mac};
+use p256::{
+    elliptic_curve::sec1::{FromEncodedPoint, ToEncodedPoint},
+    EncodedPoint, SecretKey,
+};
+use rand::{CryptoRng, RngCore};
+
+use crate::{Address, AddressType};
+
+/// SALT of the f5 key derivation
+const F5_SALT: [u8; 16] = [
+    0x6C, 0x88, 0x83, 0x91, 0xAA, 0xF5, 0xA5, 0x38, 0x60, 0x37, 0x0B, 0xDB, 0x5A, 0x60, 0x83, 0xBE,
+];
+
+/// keyID of the f5 key derivation, "btle"
+const F5_KEY_ID: [u8; 4] = [0x62, 0x74, 0x6C, 0x65];
+
+/// Reverse the octets, from little endian to big endian or the opposite
+pub fn swap<const N: usize>(mut value: [u8; N]) -> [u8; N] {
+    value.reverse();
+    value
+}
+
+/// AES-CMAC of the concatenated `parts` with the 128-bit `key`
+///
+/// Ref: Core 3.H.2.2.5
+pub fn aes_cmac(key: &[u8; 16], parts: &[&[u8]]) -> [u8; 16] {
+    let mut mac = <Cmac<Aes128> as Mac>::new(key.into());
+    for part in parts {
+        mac.update(part);
+    }
+    mac.finalize().into_bytes().into()
+}
+
+/// Random address hash function ah, the hash part of a resolvable private address
+/// is ah(IRK, prand), the 24 least significant bits of e(IRK, padding || prand)
+///
+/// Ref: Core 3.H.2.2.2
+pub fn ah(irk: &[u8; 16], prand: [u8; 3]) -> [u8; 3] {
+    let mut block = [0u8; 16];
+    block[13..].copy_from_slice(&prand);
+
+    Aes128::new(irk.into()).encrypt_block((&mut block).into());
+    block[13..].try_into().unwrap()
+}
+
+/// Address of a device in the f5 and f6 functions, the address type followed by the address
+fn address_bytes(address: &Address) -> [u8; 7] {
+    let mut bytes = [0u8; 7];
+    bytes[0] = match address.r#type {
+        AddressType::Public => 0x00,
+        AddressType::Random => 0x01,
+    };
+    bytes[1..].copy_from_slice(&swap(address.bytes()));
+    bytes
+}
+
+/// LE Secure Connections confirm value generation function f4
+///
+/// Ref: Core 3.H.2.2.6
+pub fn f4(u: &[u8; 32], v: &[u8; 32], x: &[u8; 16], z: u8) -> [u8; 16] {
+    aes_cmac(x, &[u, v, &[z]])
+}
+
+/// LE Secure Connections key generation function f5, returns the MacKey and the LTK
+///
+/// Ref: Core 3.H.2.2.7
+pub fn f5(
+    w: &[u8; 32],
+    n1: &[u8; 16],
+    n2: &[u8; 16],
+    a1: &Address,
+    a2: &Address,
+) -> ([u8; 16], [u8; 16]) {
+    let t = aes_cmac(&F5_SALT, &[w]);
+    let a1 = address_bytes(a1);
+    let a2 = address_bytes(a2);
+
+    // Length of the generated key in bits, 256
+    let length = [0x01, 0x00];
+    let key = |counter: u8| aes_cmac(&t, &[&[counter], &F5_KEY_ID, n1, n2, &a1, &a2, &length]);
+
+    (key(0), key(1))
+}
+
+/// LE Secure Connections check value generation function f6
+///
+/// Ref: Core 3.H.2.2.8
+pub fn f6(
+    w: &[u8; 16],
+    n1: &[u8; 16],
+    n2: &[u8; 16],
+    r: &[u8; 16],
+    io_cap: &[u8; 3],
+    a1: &Address,
+    a2: &Address,
+) -> [u8; 16] {
+    aes_cmac(
+        w,
+        &[n1, n2, r, io_cap, &address_bytes(a1), &address_bytes(a2)],
+    )
+}
+
+/// LE Secure Connections numeric comparison value generation function g2,
+/// the six least significant decimal digits are displayed to the user
+///
+/// Ref: Core 3.H.2.2.9
+pub fn g2(u: &[u8; 32], v: &[u8; 32], x: &[u8; 16], y: &[u8; 16]) -> u32 {
+    let mac = aes_cmac(x, &[u, v, y]);
+    u32::from_be_bytes(mac[12..].try_into().unwrap())
+}
+
+/// P-256 public key, the coordinates are big endian
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PublicKey {
+    pub x: [u8; 32],
+    pub y: [u8; 32],
+}
+
+/// P-256 key pair used for the Elliptic Curve Diffie-Hellman key exchange
+///
+/// Ref: Core 3.H.2.3.5.6.1
+pub struct KeyPair {
+    secret: SecretKey,
+}
+
+impl KeyPair {
+    /// Generate a new key pair, the `rng` shall be cryptographically secure
+    pub fn generate(rng: &mut (impl RngCore + CryptoRng)) -> Self {
+        loop {
+            let mut private = [0u8; 32];
+            rng.fill_bytes(&mut private);
+            // Almost every 256-bit value is a valid private key
+            if let Some(pair) = Self::from_private_key(&private) {
+                return pair;
+            }
+        }
+    }
+
+    /// Key pair of the big endian private key, `None` when it is out of range
+    pub fn from_private_key(private: &[u8; 32]) -> Option<Self> {
+        let secret = SecretKey::from_bytes(private.into()).ok()?;
+        Some(Self { secret })
+    }
+
+    pub fn public_key(&self) -> PublicKey {
+        let point = self.secret.public_key().to_encoded_point(false);
+        PublicKey {
+            x: (*point.x().unwrap()).into(),
+            y: (*point.y().unwrap()).into(),
+        }
+    }
+
+    /// Shared secret with the peer, the DHKey. `None` when the public key of the peer
+    /// is not a point of the curve, the pairing shall fail.
+    pub fn dh_key(&self, peer: &PublicKey) -> Option<[u8; 32]> {
+        let point = EncodedPoint::from_affine_coordinates(&peer.x.into(), &peer.y.into(), false);
+        let peer = Option::<p256::PublicKey>::from(p256::PublicKey::from_encoded_point(&point))?;
+
+        let shared = p256::ecdh::diffie_hellman(self.secret.to_nonzero_scalar(), peer.as_affine());
+        Some((*shared.raw_secret_bytes()).into())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // Test vectors of Core 3.H.D
+    const U: [u8; 32] = [
+        0x20, 0xb0, 0x03, 0xd2, 0xf2, 0x97, 0xbe, 0x2c, 0x5e, 0x2c, 0x83, 0xa7, 0xe9, 0xf9, 0xa5,
+        0xb9, 0xef, 0xf4, 0x91, 0x11, 0xac, 0xf4, 0xfd, 0xdb, 0xcc, 0x03, 0x01, 0x48, 0x0e, 0x35,
+        0x9d, 0xe6,
+    ];
+    const V: [u8; 32] = [
+        0x55, 0x18, 0x8b, 0x3d, 0x32, 0xf6, 0xbb, 0x9a, 0x90, 0x0a, 0xfc, 0xfb, 0xee, 0xd4, 0xe7,
+        0x2a, 0x59, 0xcb, 0x9a, 0xc2, 0xf1, 0x9d, 0x7c, 0xfb, 0x6b, 0x4f, 0xdd, 0x49, 0xf4, 0x7f,
+        0xc5, 0xfd,
+    ];
+    const N1: [u8; 16] = [
+        0xd5, 0xcb, 0x84, 0x54, 0xd1, 0x77, 0x73, 0x3e, 0xff, 0xff, 0xb2, 0xec, 0x71, 0x2b, 0xae,
+        0xab,
+    ];
+    const N2: [u8; 16] = [
+        0xa6, 0xe8, 0xe7, 0xcc, 0x25, 0xa7, 0x5f, 0x6e, 0x21, 0x65, 0x83, 0xf7, 0xff, 0x3d, 0xc4,
+        0xcf,
+    ];
+    const MAC_KEY: [u8; 16] = [
+        0x29, 0x65, 0xf1, 0x76, 0xa1, 0x08, 0x4a, 0x02, 0xfd, 0x3f, 0x6a, 0x20, 0xce, 0x63, 0x6e,
+        0x20,
+    ];
+
+    fn a1() -> Address {
+        Address::new_public(0x56123737bfce)
+    }
+
+    fn a2() -> Address {
+        Address::new_public(0xa713702dcfc1)
+    }
+
+    #[test]
+    fn ah_vector() {
+        let irk = [
+            0xec, 0x02, 0x34, 0xa3, 0x57, 0xc8, 0xad, 0x05, 0x34, 0x10, 0x10, 0xa6, 0x0a, 0x39,
+            0x7d, 0x9b,
+        ];
+        assert_eq!(ah(&irk, [0x70, 0x81, 0x94]), [0x0d, 0xfb, 0xaa]);
+    }
+
+    #[test]
+    fn f4_vector() {
+        let expected = [
+            0xf2, 0xc9, 0x16, 0xf1, 0x07, 0xa9, 0xbd, 0x1c, 0xf1, 0xed, 0xa1, 0xbe, 0xa9, 0x74,
+            0x87, 0x2d,
+        ];
+        assert_eq!(f4(&U, &V, &N1, 0), expected);
+    }
+
+    #[test]
+    fn f5_vector() {
+        let w = [
+            0xec, 0x02, 0x34, 0xa3, 0x57, 0xc8, 0xad, 0x05, 0x34, 0x10, 0x10, 0xa6, 0x0a, 0x39,
+            0x7d, 0x9b, 0x99, 0x79, 0x6b, 0x13, 0xb4, 0xf8, 0x66, 0xf1, 0x86, 0x8d, 0x34, 0xf3,
+            0x73, 0xbf, 0xa6, 0x98,
+        ];
+        let ltk = [
+            0x69, 0x86, 0x79, 0x11, 0x69, 0xd7, 0xcd, 0x23, 0x98, 0x05, 0x22, 0xb5, 0x94, 0x75,
+            0x0a, 0x38,
+        ];
+        assert_eq!(f5(&w, &N1, &N2, &a1(), &a2()), (MAC_KEY, ltk));
+    }
+
+    #[test]
+    fn f6_vector() {
+        let r = [
+            0x12, 0xa3, 0x34, 0x3b, 0xb4, 0x53, 0xbb, 0x54, 0x08, 0xda, 0x42, 0xd2, 0x0c, 0x2d,
+            0x0f, 0xc8,
+        ];
+        let expected = [
+            0xe3, 0xc4, 0x73, 0x98, 0x9c, 0xd0, 0xe8, 0xc5, 0xd2, 0x6c, 0x0b, 0x09, 0xda, 0x95,
+            0x8f, 0x61,
+        ];
+        let io_cap = [0x01, 0x01, 0x02];
+        assert_eq!(f6(&MAC_KEY, &N1, &N2, &r, &io_cap, &a1(), &a2()), expected);
+    }
+
+    #[test]
+    fn g2_vector() {
+        assert_eq!(g2(&U, &V, &N1, &N2), 0x2f9ed5ba);
+    }
+
+    #[test]
+    fn debug_keys() {
+        // Debug private key of Core 3.H.2.3.5.6.1, its public key is U
+        let private = [
+            0x3f, 0x49, 0xf6, 0xd4, 0xa3, 0xc5, 0x5f, 0x38, 0x74, 0xc9, 0xb3, 0xe3, 0xd2, 0x10,
+            0x3f, 0x50, 0x4a, 0xff, 0x60, 0x7b, 0xeb, 0x40, 0xb7, 0x99, 0x58, 0x99, 0xb8, 0xa6,
+            0xcd, 0x3c, 0x1a, 0xbd,
+        ];
+        let debug = KeyPair::from_private_key(&private).unwrap();
+        assert_eq!(debug.public_key().x, U);
+
+        let other = KeyPair::from_private_key(&V).unwrap();
+        assert_eq!(
+            debug.dh_key(&other.public_key()),
+            other.dh_key(&debug.public_key())
+        );
+
+        let invalid = PublicKey {
+            x: U,
+            y: [0x01; 32],
+        };
+        assert_eq!(debug.dh_key(&invalid), None);
+    }
+}
@@ -0,0 +1,15 @@
+//! Security Manager Protocol
+//!
+//! The devices pair on the SMP fixed channel, generating the keys that encrypt the link.
+//!
+//! Ref: [Core 3.H](https://www.bluetooth.com/wp-content/uploads/Files/Specification/HTML/Core-54/out/en/host/security-manager-specification.html)
+
+mod bond;
+mod crypto;
+mod pairing;
+mod pdu;
+
+pub use bond::*;
+pub use crypto::*;
+pub use pairing::*;
+pub use pdu::*;
@@ -0,0 +1,1319 @@
+//! LE Secure Connections pairing
+//!
+//! The devices exchange their pairing features, then their public keys and nonces
+//! to authenticate the DHKey, and both derive the same LTK from it.
+//! The association model is selected from the IO capabilities of both devices,
+//! the user takes part in the authentication with numeric comparison and passkey entry.
+//! LE legacy pairing is not supported, its requests are rejected.
+//!
+//! Ref: [Core 3.H.2.3](https://www.bluetooth.com/wp-content/uploads/Files/Specification/HTML/Core-54/out/en/host/security-manager-specification.html)
+
+use defmt::Format;
+use embassy_time::{Duration, Instant};
+use rand::{CryptoRng, RngCore};
+
+use crate::{
+    ll::{ConnectionError, ParseError, Role},
+    Address,
+};
+
+use super::{
+    aes_cmac, f4, f5, f6, g2, AuthReq, Bond, IoCapability, KeyDistribution, KeyPair, PairingError,
+    PairingFeatures, PublicKey, SmpPdu,
+};
+
+/// Time the peer has to answer a command, after it no more commands are exchanged
+/// on the connection
+///
+/// Ref: Core 3.H.3.4
+pub const PAIRING_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Smallest encryption key size accepted
+const MIN_KEY_SIZE: u8 = 7;
+
+/// Passkeys and numeric comparison values have six decimal digits
+const PASSKEY_MODULUS: u32 = 1_000_000;
+
+/// Each round of the passkey entry authenticates one bit of the passkey
+///
+/// Ref: Core 3.H.2.3.5.6.3
+const PASSKEY_ROUNDS: u8 = 20;
+
+/// Keys distributed on LE Secure Connections, the LTK is generated by both devices
+const DISTRIBUTED_KEYS: KeyDistribution = KeyDistribution::ID_KEY.union(KeyDistribution::SIGN_KEY);
+
+/// Commands queued by a single step of the pairing
+const TX_QUEUE_SIZE: usize = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Format)]
+pub enum SecurityError<E> {
+    Connection(ConnectionError<E>),
+
+    /// The pairing failed with the reason sent or received
+    Pairing(PairingError),
+
+    /// The peer did not answer a pairing command in time
+    Timeout,
+}
+
+impl<E> From<ConnectionError<E>> for SecurityError<E> {
+    fn from(error: ConnectionError<E>) -> Self {
+        SecurityError::Connection(error)
+    }
+}
+
+/// Progress of the pairing
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Format)]
+pub enum PairingEvent {
+    /// The passkey shall be displayed to the user, who enters it on the peer
+    DisplayPasskey(u32),
+
+    /// The user shall enter the passkey displayed by the peer,
+    /// or the same passkey on both devices when none has a display
+    PasskeyRequest,
+
+    /// The user shall confirm that the value is the same on both devices
+    NumericComparison(u32),
+
+    /// Both devices have the same LTK and the keys of the bond are distributed
+    Complete,
+
+    /// The pairing failed with the reason sent or received
+    Failed(PairingError),
+
+    /// The peer did not answer in time
+    Timeout,
+}
+
+/// Security requested on the pairing
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Format)]
+pub struct PairingConfig {
+    /// Input and output available to the user on this device
+    pub io_capability: IoCapability,
+
+    /// Require the user to authenticate the peer, the pairing fails
+    /// when the IO capabilities of the devices only allow Just Works
+    pub mitm: bool,
+
+    /// Distribute the keys and save them to encrypt the next connections,
+    /// the peer shall also request bonding
+    pub bonding: bool,
+
+    /// IRK of this device distributed on bonding, with its identity address
+    pub irk: Option<[u8; 16]>,
+
+    /// CSRK of this device distributed on bonding
+    pub csrk: Option<[u8; 16]>,
+}
+
+impl Default for PairingConfig {
+    /// Just Works, without any user interaction
+    fn default() -> Self {
+        PairingConfig {
+            io_capability: IoCapability::NoInputNoOutput,
+            mitm: false,
+            bonding: false,
+            irk: None,
+            csrk: None,
+        }
+    }
+}
+
+/// How the user authenticates the pairing
+///
+/// Ref: Core 3.H.2.3.5.1
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum AssociationModel {
+    JustWorks,
+    NumericComparison,
+    /// The devices that do not input the passkey display it
+    PasskeyEntry {
+        initiator_inputs: bool,
+        responder_inputs: bool,
+    },
+}
+
+impl AssociationModel {
+    /// Select the model from the features of the initiator and the responder,
+    /// Just Works is used when none of them requires MITM protection
+    ///
+    /// Ref: Core 3.H.2.3.5.1, Table 2.8
+    fn select(initiator: &PairingFeatures, responder: &PairingFeatures) -> Self {
+        use IoCapability::*;
+
+        let mitm = initiator.auth_req.contains(AuthReq::MITM)
+            || responder.auth_req.contains(AuthReq::MITM);
+        if !mitm {
+            return AssociationModel::JustWorks;
+        }
+
+        let passkey = |initiator_inputs, responder_inputs| AssociationModel::PasskeyEntry {
+            initiator_inputs,
+            responder_inputs,
+        };
+        match (initiator.io_capability, responder.io_capability) {
+            (NoInputNoOutput, _) | (_, NoInputNoOutput) => AssociationModel::JustWorks,
+            (DisplayYesNo | KeyboardDisplay, DisplayYesNo | KeyboardDisplay) => {
+                AssociationModel::NumericComparison
+            }
+            (KeyboardOnly, KeyboardOnly) => passkey(true, true),
+            (KeyboardOnly, _) | (KeyboardDisplay, DisplayOnly) => passkey(true, false),
+            (_, KeyboardOnly) | (DisplayOnly, KeyboardDisplay) => passkey(false, true),
+            (DisplayOnly | DisplayYesNo, DisplayOnly | DisplayYesNo) => AssociationModel::JustWorks,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Idle,
+    WaitResponse,
+    WaitPublicKey,
+    WaitConfirm,
+    WaitRandom,
+    WaitDhKeyCheck,
+    /// The keys are distributed once the link is encrypted with the LTK
+    WaitEncryption,
+    Distributing,
+    Complete,
+    Failed,
+    TimedOut,
+}
+
+/// Pairing of the connection
+///
+/// The key pair, the nonce and the passkey to display are generated on the creation,
+/// a new pairing shall be created to pair again.
+pub struct Pairing {
+    /// The central is the initiator and the peripheral the responder
+    role: Role,
+
+    local_address: Address,
+    peer_address: Address,
+
+    /// Address distributed with the IRK, the local address may be private
+    identity_address: Address,
+
+    local_features: PairingFeatures,
+    peer_features: Option<PairingFeatures>,
+    model: AssociationModel,
+
+    keys: KeyPair,
+    peer_key: Option<PublicKey>,
+    dh_key: [u8; 32],
+
+    /// Nonce of Just Works and numeric comparison, it derives the nonce
+    /// of each round of the passkey entry
+    nonce: [u8; 16],
+    peer_nonce: [u8; 16],
+
+    /// Passkey displayed when this device does not input it
+    display_passkey: u32,
+
+    /// Passkey displayed or entered by the user
+    passkey: Option<u32>,
+
+    /// Passkey entry round in progress
+    round: u8,
+
+    /// The local confirm value of the round waits for the passkey
+    round_pending: bool,
+
+    /// Confirm value received from the peer
+    peer_confirm: [u8; 16],
+
+    /// The user confirmed the numeric comparison
+    confirmed: bool,
+
+    /// DHKey check received from the peer, waiting for the confirmation of the user
+    peer_check: Option<[u8; 16]>,
+
+    mac_key: [u8; 16],
+    ltk: [u8; 16],
+
+    /// Both devices requested bonding
+    bonding: bool,
+
+    local_irk: Option<[u8; 16]>,
+    local_csrk: Option<[u8; 16]>,
+
+    /// Keys left to send and to receive
+    send_keys: KeyDistribution,
+    expected_keys: KeyDistribution,
+
+    peer_irk: Option<[u8; 16]>,
+    peer_identity: Option<Address>,
+    peer_csrk: Option<[u8; 16]>,
+
+    /// Encryption key size agreed on the pairing features
+    key_size: u8,
+
+    state: State,
+
+    /// The peer shall send the next command before it
+    deadline: Option<Instant>,
+
+    /// Commands to send, in order
+    tx: [Option<SmpPdu>; TX_QUEUE_SIZE],
+}
+
+impl Pairing {
+    /// Create the pairing of the connection, generating the key pair, the nonce and
+    /// the passkey with the `rng`, that shall be cryptographically secure
+    pub fn new(
+        role: Role,
+        local_address: Address,
+        peer_address: Address,
+        config: PairingConfig,
+        rng: &mut (impl RngCore + CryptoRng),
+    ) -> Self {
+        let mut nonce = [0u8; 16];
+        rng.fill_bytes(&mut nonce);
+
+        let mut auth_req = AuthReq::SECURE_CONNECTIONS;
+        if config.mitm {
+            auth_req = auth_req.union(AuthReq::MITM);
+        }
+
+        // The keys of the peer are always requested, the local keys are offered when set
+        let (mut offered, mut requested) = (KeyDistribution::empty(), KeyDistribution::empty());
+        if config.bonding {
+            auth_req = auth_req.union(AuthReq::BONDING);
+            requested = DISTRIBUTED_KEYS;
+            if config.irk.is_some() {
+                offered = offered.union(KeyDistribution::ID_KEY);
+            }
+            if config.csrk.is_some() {
+                offered = offered.union(KeyDistribution::SIGN_KEY);
+            }
+        }
+        let (initiator_keys, responder_keys) = match role {
+            Role::Central => (offered, requested),
+            Role::Peripheral => (requested, offered),
+        };
+
+        Pairing {
+            role,
+            identity_address: local_address.clone(),
+            local_address,
+            peer_address,
+            local_features: PairingFeatures {
+                io_capability: config.io_capability,
+                oob_data: false,
+                auth_req,
+                max_key_size: 16,
+                initiator_keys,
+                responder_keys,
+            },
+            peer_features: None,
+            model: AssociationModel::JustWorks,
+            keys: KeyPair::generate(rng),
+            peer_key: None,
+            dh_key: [0; 32],
+            nonce,
+            peer_nonce: [0; 16],
+            display_passkey: rng.next_u32() % PASSKEY_MODULUS,
+            passkey: None,
+            round: 0,
+            round_pending: false,
+            peer_confirm: [0; 16],
+            confirmed: false,
+            peer_check: None,
+            mac_key: [0; 16],
+            ltk: [0; 16],
+            bonding: false,
+            local_irk: config.irk,
+            local_csrk: config.csrk,
+            send_keys: KeyDistribution::empty(),
+            expected_keys: KeyDistribution::empty(),
+            peer_irk: None,
+            peer_identity: None,
+            peer_csrk: None,
+            key_size: 16,
+            state: State::Idle,
+            deadline: None,
+            tx: [None; TX_QUEUE_SIZE],
+        }
+    }
+
+    /// Distribute the `address` with the IRK instead of the connection address,
+    /// needed when the connection uses a resolvable private address
+    pub fn set_identity_address(mut self, address: Address) -> Self {
+        self.identity_address = address;
+        self
+    }
+
+    /// Start the pairing, the central sends the Pairing Request and
+    /// the peripheral asks the central to send it with a Security Request
+    pub fn start(&mut self, now: Instant) {
+        if self.state != State::Idle {
+            return;
+        }
+
+        match self.role {
+            Role::Central => {
+                self.send(SmpPdu::PairingRequest(self.local_features));
+                self.state = State::WaitResponse;
+                self.deadline = Some(now + PAIRING_TIMEOUT);
+            }
+            Role::Peripheral => self.send(SmpPdu::SecurityRequest(self.local_features.auth_req)),
+        }
+    }
+
+    /// The pairing is in progress
+    pub fn is_pairing(&self) -> bool {
+        !matches!(
+            self.state,
+            State::Idle | State::Complete | State::Failed | State::TimedOut
+        )
+    }
+
+    /// LTK generated by the pairing, masked to the encryption key size,
+    /// available once the DHKey checks passed
+    pub fn long_term_key(&self) -> Option<[u8; 16]> {
+        matches!(
+            self.state,
+            State::WaitEncryption | State::Distributing | State::Complete
+        )
+        .then_some(self.ltk)
+    }
+
+    /// The DHKey checks passed, the link shall be encrypted with the LTK
+    /// to distribute the keys
+    pub fn needs_encryption(&self) -> bool {
+        self.state == State::WaitEncryption
+    }
+
+    /// Keys of the peer to save when both devices requested bonding
+    pub fn bond(&self) -> Option<Bond> {
+        if self.state != State::Complete || !self.bonding {
+            return None;
+        }
+
+        Some(Bond {
+            address: self
+                .peer_identity
+                .clone()
+                .unwrap_or_else(|| self.peer_address.clone()),
+            ltk: self.ltk,
+            key_size: self.key_size,
+            authenticated: self.model != AssociationModel::JustWorks,
+            irk: self.peer_irk,
+            csrk: self.peer_csrk,
+        })
+    }
+
+    /// Encryption key size agreed by both devices
+    pub fn key_size(&self) -> u8 {
+        self.key_size
+    }
+
+    /// Handle a command received on the SMP channel, the answers are read with `poll`
+    pub fn on_pdu(&mut self, bytes: &[u8], now: Instant) -> Option<PairingEvent> {
+        // No more commands are exchanged after a timeout
+        if self.state == State::TimedOut {
+            return None;
+        }
+
+        let pdu = match SmpPdu::parse(bytes) {
+            Ok(pdu) => pdu,
+            Err(ParseError::InvalidType) => return self.fail(PairingError::CommandNotSupported),
+            Err(_) => return self.fail(PairingError::InvalidParameters),
+        };
+
+        let central = self.role == Role::Central;
+        let result = match (self.state, pdu) {
+            (_, SmpPdu::PairingFailed(reason)) => {
+                self.state = State::Failed;
+                self.deadline = None;
+                return Some(PairingEvent::Failed(reason));
+            }
+            (_, SmpPdu::KeypressNotification(_)) => Ok(None),
+            (State::Idle, SmpPdu::PairingRequest(features)) if !central => {
+                self.on_request(features)
+            }
+            // Ignored when already paired or pairing
+            (_, SmpPdu::SecurityRequest(_)) if central => {
+                self.start(now);
+                Ok(None)
+            }
+            (State::WaitResponse, SmpPdu::PairingResponse(features)) => self.on_response(features),
+            (State::WaitPublicKey, SmpPdu::PairingPublicKey { x, y }) => {
+                self.on_public_key(PublicKey { x, y })
+            }
+            (State::WaitConfirm, SmpPdu::PairingConfirm(confirm)) => self.on_confirm(confirm),
+            (State::WaitRandom, SmpPdu::PairingRandom(nonce)) => self.on_random(nonce),
+            (State::WaitDhKeyCheck, SmpPdu::PairingDhKeyCheck(check)) => {
+                self.on_dh_key_check(check)
+            }
+            (State::Distributing, pdu) => self.on_key(pdu),
+            _ => Err(PairingError::UnspecifiedReason),
+        };
+
+        self.resolve(result, now)
+    }
+
+    /// Answer a `PasskeyRequest` with the passkey entered by the user,
+    /// `None` when the user cancelled the entry
+    pub fn passkey_reply(&mut self, passkey: Option<u32>, now: Instant) -> Option<PairingEvent> {
+        let passkey_entry = matches!(self.model, AssociationModel::PasskeyEntry { .. });
+        if !self.is_pairing() || !passkey_entry || self.passkey.is_some() {
+            return None;
+        }
+
+        match passkey.filter(|passkey| *passkey < PASSKEY_MODULUS) {
+            Some(passkey) => {
+                self.passkey = Some(passkey);
+                self.send_round_confirm();
+                self.resolve(Ok(None), now)
+            }
+            None => self.fail(PairingError::PasskeyEntryFailed),
+        }
+    }
+
+    /// Answer a `NumericComparison` with the confirmation of the user
+    pub fn numeric_comparison_reply(
+        &mut self,
+        confirmed: bool,
+        now: Instant,
+    ) -> Option<PairingEvent> {
+        if self.model != AssociationModel::NumericComparison
+            || self.state != State::WaitDhKeyCheck
+            || self.confirmed
+        {
+            return None;
+        }
+        if !confirmed {
+            return self.fail(PairingError::NumericComparisonFailed);
+        }
+
+        self.confirmed = true;
+        if self.role == Role::Central {
+            self.send(SmpPdu::PairingDhKeyCheck(self.check_value(Role::Central)));
+        }
+        let result = match self.peer_check {
+            Some(_) => self.check_peer(),
+            None => Ok(None),
+        };
+        self.resolve(result, now)
+    }
+
+    /// The link was encrypted with the LTK, the responder distributes its keys first
+    ///
+    /// Ref: Core 3.H.3.6.1
+    pub fn on_encrypted(&mut self, now: Instant) -> Option<PairingEvent> {
+        if self.state != State::WaitEncryption {
+            return None;
+        }
+
+        self.state = State::Distributing;
+        let result = self.distribute();
+        self.resolve(result, now)
+    }
+
+    /// Check the pairing timeout
+    pub fn check_timeout(&mut self, now: Instant) -> Option<PairingEvent> {
+        match self.deadline {
+            Some(deadline) if now > deadline => {
+                self.state = State::TimedOut;
+                self.deadline = None;
+                self.tx = [None; TX_QUEUE_SIZE];
+                Some(PairingEvent::Timeout)
+            }
+            _ => None,
+        }
+    }
+
+    /// Serialize the next command to send, if any
+    pub fn poll(&mut self, dest: &mut [u8]) -> Option<usize> {
+        let pdu = self.tx[0].take()?;
+        self.tx.rotate_left(1);
+        Some(pdu.bytes(dest))
+    }
+
+    fn send(&mut self, pdu: SmpPdu) {
+        if let Some(slot) = self.tx.iter_mut().find(|slot| slot.is_none()) {
+            *slot = Some(pdu);
+        }
+    }
+
+    fn fail(&mut self, reason: PairingError) -> Option<PairingEvent> {
+        self.tx = [None; TX_QUEUE_SIZE];
+        self.send(SmpPdu::PairingFailed(reason));
+        self.state = State::Failed;
+        self.deadline = None;
+        Some(PairingEvent::Failed(reason))
+    }
+
+    /// Restart the timeout while pairing, or fail with the reason
+    fn resolve(
+        &mut self,
+        result: Result<Option<PairingEvent>, PairingError>,
+        now: Instant,
+    ) -> Option<PairingEvent> {
+        match result {
+            Ok(event) => {
+                self.deadline = self.is_pairing().then_some(now + PAIRING_TIMEOUT);
+                event
+            }
+            Err(reason) => self.fail(reason),
+        }
+    }
+
+    /// Check the features of the peer, only LE Secure Connections is supported,
+    /// and select the association model
+    fn negotiate(&mut self, peer: PairingFeatures) -> Result<Option<PairingEvent>, PairingError> {
+        let bonding = AuthReq::BONDING;
+        self.bonding =
+            self.local_features.auth_req.contains(bonding) && peer.auth_req.contains(bonding);
+
+        if !peer.auth_req.contains(AuthReq::SECURE_CONNECTIONS) {
+            return Err(PairingError::AuthenticationRequirements);
+        }
+
+        self.key_size = peer.max_key_size.min(self.local_features.max_key_size);
+        if self.key_size < MIN_KEY_SIZE {
+            return Err(PairingError::EncryptionKeySize);
+        }
+
+        let (initiator, responder) = self.ordered(&self.local_features, &peer);
+        self.model = AssociationModel::select(initiator, responder);
+        let mitm = self.local_features.auth_req.contains(AuthReq::MITM);
+        if mitm && self.model == AssociationModel::JustWorks {
+            return Err(PairingError::AuthenticationRequirements);
+        }
+        self.peer_features = Some(peer);
+
+        let AssociationModel::PasskeyEntry {
+            initiator_inputs,
+            responder_inputs,
+        } = self.model
+        else {
+            return Ok(None);
+        };
+        let (inputs, _) = self.ordered(initiator_inputs, responder_inputs);
+        if inputs {
+            Ok(Some(PairingEvent::PasskeyRequest))
+        } else {
+            self.passkey = Some(self.display_passkey);
+            Ok(Some(PairingEvent::DisplayPasskey(self.display_passkey)))
+        }
+    }
+
+    /// The responder answers with its features, the keys distributed are the ones
+    /// requested by the initiator that it also supports
+    fn on_request(
+        &mut self,
+        request: PairingFeatures,
+    ) -> Result<Option<PairingEvent>, PairingError> {
+        let event = self.negotiate(request)?;
+
+        let bonding = self.bonding;
+        let local = &mut self.local_features;
+        if bonding {
+            local.initiator_keys = local.initiator_keys.intersection(request.initiator_keys);
+            local.responder_keys = local.responder_keys.intersection(request.responder_keys);
+        } else {
+            local.initiator_keys = KeyDistribution::empty();
+            local.responder_keys = KeyDistribution::empty();
+        }
+        self.agree_keys(self.local_features);
+
+        self.send(SmpPdu::PairingResponse(self.local_features));
+        self.state = State::WaitPublicKey;
+        Ok(event)
+    }
+
+    fn on_response(
+        &mut self,
+        response: PairingFeatures,
+    ) -> Result<Option<PairingEvent>, PairingError> {
+        let event = self.negotiate(response)?;
+        self.agree_keys(response);
+
+        let key = self.keys.public_key();
+        self.send(SmpPdu::PairingPublicKey { x: key.x, y: key.y });
+        self.state = State::WaitPublicKey;
+        Ok(event)
+    }
+
+    /// Keys to send and to receive from the features of the Pairing Response
+    fn agree_keys(&mut self, response: PairingFeatures) {
+        let (local, peer) = self.ordered(response.initiator_keys, response.responder_keys);
+        if self.bonding {
+            self.send_keys = local.intersection(DISTRIBUTED_KEYS);
+            self.expected_keys = peer.intersection(DISTRIBUTED_KEYS);
+        }
+    }
+
+    /// The responder answers the public key of the initiator with its own, and
+    /// commits to its nonce with the confirm value unless the passkey is entered.
+    /// On passkey entry the initiator starts the first round.
+    ///
+    /// Ref: Core 3.H.2.3.5.6
+    fn on_public_key(&mut self, key: PublicKey) -> Result<Option<PairingEvent>, PairingError> {
+        let local = self.keys.public_key();
+
+        // A reflected public key would let an attacker impersonate us
+        if key == local {
+            return Err(PairingError::InvalidParameters);
+        }
+        self.dh_key = self
+            .keys
+            .dh_key(&key)
+            .ok_or(PairingError::DhKeyCheckFailed)?;
+        self.peer_key = Some(key);
+
+        let passkey_entry = matches!(self.model, AssociationModel::PasskeyEntry { .. });
+        match self.role {
+            Role::Central => {
+                self.state = State::WaitConfirm;
+                self.round_pending = passkey_entry;
+                self.send_round_confirm();
+            }
+            Role::Peripheral => {
+                self.send(SmpPdu::PairingPublicKey {
+                    x: local.x,
+                    y: local.y,
+                });
+                if passkey_entry {
+                    self.state = State::WaitConfirm;
+                } else {
+                    self.send(SmpPdu::PairingConfirm(f4(&local.x, &key.x, &self.nonce, 0)));
+                    self.state = State::WaitRandom;
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    /// The initiator answers the confirm value with its nonce, the responder
+    /// answers with its own confirm value of the passkey entry round
+    fn on_confirm(&mut self, confirm: [u8; 16]) -> Result<Option<PairingEvent>, PairingError> {
+        // The initiator commits first on each passkey entry round
+        if self.role == Role::Central && self.round_pending {
+            return Err(PairingError::UnspecifiedReason);
+        }
+        self.peer_confirm = confirm;
+
+        match self.role {
+            Role::Central => {
+                self.send(SmpPdu::PairingRandom(self.local_nonce()));
+                self.state = State::WaitRandom;
+            }
+            Role::Peripheral => {
+                self.round_pending = true;
+                self.send_round_confirm();
+            }
+        }
+        Ok(None)
+    }
+
+    /// Send the confirm value of the passkey entry round once the passkey is known
+    fn send_round_confirm(&mut self) {
+        let (Some(peer_key), Some(_)) = (self.peer_key, self.passkey) else {
+            return;
+        };
+        if !self.round_pending {
+            return;
+        }
+        self.round_pending = false;
+
+        let local = self.keys.public_key();
+        let confirm = f4(&local.x, &peer_key.x, &self.local_nonce(), self.round_bit());
+        self.send(SmpPdu::PairingConfirm(confirm));
+        if self.role == Role::Peripheral {
+            self.state = State::WaitRandom;
+        }
+    }
+
+    /// Check the confirm value with the nonce of the peer, the responder answers with
+    /// its nonce. The keys are generated after the last round.
+    fn on_random(&mut self, nonce: [u8; 16]) -> Result<Option<PairingEvent>, PairingError> {
+        let peer_key = self.peer_key.ok_or(PairingError::UnspecifiedReason)?;
+        let local = self.keys.public_key();
+        let passkey_entry = matches!(self.model, AssociationModel::PasskeyEntry { .. });
+
+        // The responder does not commit to the peer nonce on Just Works and numeric comparison
+        let committed = self.role == Role::Central || passkey_entry;
+        if committed && f4(&peer_key.x, &local.x, &nonce, self.round_bit()) != self.peer_confirm {
+            return Err(PairingError::ConfirmValueFailed);
+        }
+        self.peer_nonce = nonce;
+        if self.role == Role::Peripheral {
+            self.send(SmpPdu::PairingRandom(self.local_nonce()));
+        }
+
+        if passkey_entry && self.round + 1 < PASSKEY_ROUNDS {
+            self.round += 1;
+            self.state = State::WaitConfirm;
+            self.round_pending = self.role == Role::Central;
+            self.send_round_confirm();
+            return Ok(None);
+        }
+
+        self.generate_keys();
+        self.state = State::WaitDhKeyCheck;
+        if self.model == AssociationModel::NumericComparison {
+            return Ok(Some(PairingEvent::NumericComparison(self.compare_value())));
+        }
+        if self.role == Role::Central {
+            self.send(SmpPdu::PairingDhKeyCheck(self.check_value(Role::Central)));
+        }
+        Ok(None)
+    }
+
+    /// On numeric comparison the check is delayed until the user confirms
+    fn on_dh_key_check(&mut self, check: [u8; 16]) -> Result<Option<PairingEvent>, PairingError> {
+        self.peer_check = Some(check);
+        if self.model == AssociationModel::NumericComparison && !self.confirmed {
+            return Ok(None);
+        }
+        self.check_peer()
+    }
+
+    /// Check the DHKey check value of the peer, the responder answers with its own
+    fn check_peer(&mut self) -> Result<Option<PairingEvent>, PairingError> {
+        let peer_role = match self.role {
+            Role::Central => Role::Peripheral,
+            Role::Peripheral => Role::Central,
+        };
+        if self.peer_check.take() != Some(self.check_value(peer_role)) {
+            return Err(PairingError::DhKeyCheckFailed);
+        }
+
+        if self.role == Role::Peripheral {
+            self.send(SmpPdu::PairingDhKeyCheck(
+                self.check_value(Role::Peripheral),
+            ));
+        }
+
+        if self.send_keys.is_empty() && self.expected_keys.is_empty() {
+            self.state = State::Complete;
+            return Ok(Some(PairingEvent::Complete));
+        }
+        self.state = State::WaitEncryption;
+        Ok(None)
+    }
+
+    /// Send the local keys, the initiator once it received the keys of the responder.
+    /// The pairing completes when all the keys are received.
+    fn distribute(&mut self) -> Result<Option<PairingEvent>, PairingError> {
+        if self.role == Role::Peripheral || self.expected_keys.is_empty() {
+            if self.send_keys.contains(KeyDistribution::ID_KEY) {
+                if let Some(irk) = self.local_irk {
+                    self.send(SmpPdu::IdentityInformation(irk));
+                    self.send(SmpPdu::IdentityAddressInformation {
+                        address_type: self.identity_address.r#type,
+                        address: self.identity_address.bytes(),
+                    });
+                }
+            }
+            if self.send_keys.contains(KeyDistribution::SIGN_KEY) {
+                if let Some(csrk) = self.local_csrk {
+                    self.send(SmpPdu::SigningInformation(csrk));
+                }
+            }
+            self.send_keys = KeyDistribution::empty();
+        }
+
+        if !self.expected_keys.is_empty() {
+            return Ok(None);
+        }
+        self.state = State::Complete;
+        Ok(Some(PairingEvent::Complete))
+    }
+
+    /// Save a key of the peer, the keys are received in the order of the bit field
+    fn on_key(&mut self, pdu: SmpPdu) -> Result<Option<PairingEvent>, PairingError> {
+        let identity = self.expected_keys.contains(KeyDistribution::ID_KEY);
+
+        match pdu {
+            SmpPdu::IdentityInformation(irk) if identity && self.peer_irk.is_none() => {
+                self.peer_irk = Some(irk);
+                return Ok(None);
+            }
+            SmpPdu::IdentityAddressInformation {
+                address_type,
+                address,
+            } if identity && self.peer_irk.is_some() => {
+                self.peer_identity = Some(Address::new_le(address, address_type));
+                self.expected_keys = self.expected_keys.difference(KeyDistribution::ID_KEY);
+            }
+            SmpPdu::SigningInformation(csrk)
+                if !identity && self.expected_keys.contains(KeyDistribution::SIGN_KEY) =>
+            {
+                self.peer_csrk = Some(csrk);
+                self.expected_keys = self.expected_keys.difference(KeyDistribution::SIGN_KEY);
+            }
+            _ => return Err(PairingError::UnspecifiedReason),
+        }
+
+        self.distribute()
+    }
+
+    /// Values of the initiator and of the responder
+    fn ordered<T>(&self, local: T, peer: T) -> (T, T) {
+        match self.role {
+            Role::Central => (local, peer),
+            Role::Peripheral => (peer, local),
+        }
+    }
+
+    /// Nonce of the current round, each passkey entry round uses a new nonce
+    /// derived from the random one
+    fn local_nonce(&self) -> [u8; 16] {
+        match self.model {
+            AssociationModel::PasskeyEntry { .. } => aes_cmac(&self.nonce, &[&[self.round]]),
+            _ => self.nonce,
+        }
+    }
+
+    /// Value the confirm of the round commits to, the passkey bit of the round
+    /// on passkey entry
+    fn round_bit(&self) -> u8 {
+        match (self.model, self.passkey) {
+            (AssociationModel::PasskeyEntry { .. }, Some(passkey)) => {
+                0x80 | ((passkey >> self.round) & 1) as u8
+            }
+            _ => 0,
+        }
+    }
+
+    /// Six digits value compared by the user
+    ///
+    /// Ref: Core 3.H.2.3.5.6.2
+    fn compare_value(&self) -> u32 {
+        let local = self.keys.public_key().x;
+        let peer = self.peer_key.map(|key| key.x).unwrap_or_default();
+        let (pka, pkb) = self.ordered(&local, &peer);
+        let (na, nb) = self.ordered(self.local_nonce(), self.peer_nonce);
+        g2(pka, pkb, &na, &nb) % PASSKEY_MODULUS
+    }
+
+    /// Generate the MacKey and the LTK, the most significant octets of the LTK
+    /// are zero when the key size is smaller than 16 octets
+    ///
+    /// Ref: Core 3.H.2.3.4
+    fn generate_keys(&mut self) {
+        let (na, nb) = self.ordered(self.local_nonce(), self.peer_nonce);
+        let (a, b) = self.ordered(&self.local_address, &self.peer_address);
+
+        let (mac_key, mut ltk) = f5(&self.dh_key, &na, &nb, a, b);
+        ltk[..16 - self.key_size as usize].fill(0);
+
+        self.mac_key = mac_key;
+        self.ltk = ltk;
+    }
+
+    /// DHKey check value of the initiator (Ea) or the responder (Eb), the random
+    /// value is the passkey on passkey entry and zero otherwise
+    fn check_value(&self, role: Role) -> [u8; 16] {
+        let peer_features = self.peer_features.unwrap_or(self.local_features);
+        let (na, nb) = self.ordered(self.local_nonce(), self.peer_nonce);
+        let (a, b) = self.ordered(&self.local_address, &self.peer_address);
+        let (io_a, io_b) = self.ordered(self.local_features.io_cap(), peer_features.io_cap());
+
+        let mut r = [0u8; 16];
+        if let AssociationModel::PasskeyEntry { .. } = self.model {
+            r[12..].copy_from_slice(&self.passkey.unwrap_or_default().to_be_bytes());
+        }
+
+        match role {
+            Role::Central => f6(&self.mac_key, &na, &nb, &r, &io_a, a, b),
+            Role::Peripheral => f6(&self.mac_key, &nb, &na, &r, &io_b, b, a),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use rand::{rngs::SmallRng, SeedableRng};
+
+    use super::*;
+    use IoCapability::*;
+
+    /// Not secure, only for the tests
+    struct TestRng(SmallRng);
+
+    impl RngCore for TestRng {
+        fn next_u32(&mut self) -> u32 {
+            self.0.next_u32()
+        }
+
+        fn next_u64(&mut self) -> u64 {
+            self.0.next_u64()
+        }
+
+        fn fill_bytes(&mut self, dest: &mut [u8]) {
+            self.0.fill_bytes(dest)
+        }
+
+        fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand::Error> {
+            self.0.try_fill_bytes(dest)
+        }
+    }
+
+    impl CryptoRng for TestRng {}
+
+    fn pairings() -> (Pairing, Pairing) {
+        pairings_with(PairingConfig::default(), PairingConfig::default())
+    }
+
+    fn pairings_with(central: PairingConfig, peripheral: PairingConfig) -> (Pairing, Pairing) {
+        let mut rng = TestRng(SmallRng::seed_from_u64(1));
+        let central_address = Address::new_random(0xffe1e8d0dc27);
+        let peripheral_address = Address::new_public(0x0102030405);
+
+        (
+            Pairing::new(
+                Role::Central,
+                central_address.clone(),
+                peripheral_address.clone(),
+                central,
+                &mut rng,
+            ),
+            Pairing::new(
+                Role::Peripheral,
+                peripheral_address,
+                central_address,
+                peripheral,
+                &mut rng,
+            ),
+        )
+    }
+
+    fn config(io_capability: IoCapability) -> PairingConfig {
+        PairingConfig {
+            io_capability,
+            mitm: true,
+            ..PairingConfig::default()
+        }
+    }
+
+    /// Deliver the commands of `from` to `to`, returning the last event of `to`
+    fn deliver(from: &mut Pairing, to: &mut Pairing) -> Option<PairingEvent> {
+        let mut buffer = [0u8; SmpPdu::MAX_LENGTH];
+        let mut event = None;
+        while let Some(len) = from.poll(&mut buffer) {
+            event = to.on_pdu(&buffer[..len], Instant::from_ticks(0)).or(event);
+        }
+        event
+    }
+
+    /// Exchange the commands until both devices wait, returning their last events
+    fn run(
+        central: &mut Pairing,
+        peripheral: &mut Pairing,
+    ) -> (Option<PairingEvent>, Option<PairingEvent>) {
+        let (mut central_event, mut peripheral_event) = (None, None);
+        while central.tx[0].is_some() || peripheral.tx[0].is_some() {
+            peripheral_event = deliver(central, peripheral).or(peripheral_event);
+            central_event = deliver(peripheral, central).or(central_event);
+        }
+        (central_event, peripheral_event)
+    }
+
+    #[test]
+    fn association_models() {
+        use IoCapability::*;
+
+        let features = |io_capability, mitm| PairingFeatures {
+            io_capability,
+            oob_data: false,
+            auth_req: if mitm {
+                AuthReq::MITM
+            } else {
+                AuthReq::empty()
+            },
+            max_key_size: 16,
+            initiator_keys: KeyDistribution::empty(),
+            responder_keys: KeyDistribution::empty(),
+        };
+        let model = |initiator, responder| {
+            AssociationModel::select(&features(initiator, true), &features(responder, false))
+        };
+        let passkey = |initiator_inputs, responder_inputs| AssociationModel::PasskeyEntry {
+            initiator_inputs,
+            responder_inputs,
+        };
+
+        assert_eq!(
+            AssociationModel::select(
+                &features(KeyboardDisplay, false),
+                &features(KeyboardDisplay, false)
+            ),
+            AssociationModel::JustWorks
+        );
+        assert_eq!(
+            model(DisplayOnly, DisplayYesNo),
+            AssociationModel::JustWorks
+        );
+        assert_eq!(
+            model(KeyboardDisplay, NoInputNoOutput),
+            AssociationModel::JustWorks
+        );
+        assert_eq!(
+            model(DisplayYesNo, KeyboardDisplay),
+            AssociationModel::NumericComparison
+        );
+        assert_eq!(model(KeyboardOnly, KeyboardOnly), passkey(true, true));
+        assert_eq!(model(KeyboardOnly, KeyboardDisplay), passkey(true, false));
+        assert_eq!(model(KeyboardDisplay, DisplayOnly), passkey(true, false));
+        assert_eq!(model(DisplayYesNo, KeyboardOnly), passkey(false, true));
+        assert_eq!(model(DisplayOnly, KeyboardDisplay), passkey(false, true));
+    }
+
+    #[test]
+    fn just_works() {
+        let (mut central, mut peripheral) = pairings();
+
+        central.start(Instant::from_ticks(0));
+        // Pairing Request and Pairing Response
+        assert_eq!(deliver(&mut central, &mut peripheral), None);
+        assert_eq!(deliver(&mut peripheral, &mut central), None);
+        // Public keys, then the confirm value of the responder
+        assert_eq!(deliver(&mut central, &mut peripheral), None);
+        assert_eq!(deliver(&mut peripheral, &mut central), None);
+        // Nonces
+        assert_eq!(deliver(&mut central, &mut peripheral), None);
+        assert_eq!(deliver(&mut peripheral, &mut central), None);
+        // DHKey checks
+        assert_eq!(
+            deliver(&mut central, &mut peripheral),
+            Some(PairingEvent::Complete)
+        );
+        assert_eq!(
+            deliver(&mut peripheral, &mut central),
+            Some(PairingEvent::Complete)
+        );
+
+        assert!(central.long_term_key().is_some());
+        assert_eq!(central.long_term_key(), peripheral.long_term_key());
+    }
+
+    #[test]
+    fn numeric_comparison() {
+        let (mut central, mut peripheral) =
+            pairings_with(config(DisplayYesNo), config(KeyboardDisplay));
+
+        central.start(Instant::from_ticks(0));
+        let (Some(PairingEvent::NumericComparison(value)), peripheral_event) =
+            run(&mut central, &mut peripheral)
+        else {
+            panic!("no numeric comparison");
+        };
+        assert!(value < 1_000_000);
+        assert_eq!(
+            peripheral_event,
+            Some(PairingEvent::NumericComparison(value))
+        );
+
+        // The responder waits the confirmation of its user to answer the DHKey check
+        assert_eq!(
+            central.numeric_comparison_reply(true, Instant::from_ticks(0)),
+            None
+        );
+        assert_eq!(run(&mut central, &mut peripheral), (None, None));
+        assert_eq!(
+            peripheral.numeric_comparison_reply(true, Instant::from_ticks(0)),
+            Some(PairingEvent::Complete)
+        );
+        assert_eq!(
+            run(&mut central, &mut peripheral),
+            (Some(PairingEvent::Complete), None)
+        );
+        assert!(central.long_term_key().is_some());
+        assert_eq!(central.long_term_key(), peripheral.long_term_key());
+
+        // Rejected by the user
+        let (mut central, mut peripheral) =
+            pairings_with(config(DisplayYesNo), config(DisplayYesNo));
+        central.start(Instant::from_ticks(0));
+        run(&mut central, &mut peripheral);
+        let failed = Some(PairingEvent::Failed(PairingError::NumericComparisonFailed));
+        assert_eq!(
+            peripheral.numeric_comparison_reply(false, Instant::from_ticks(0)),
+            failed
+        );
+        assert_eq!(run(&mut central, &mut peripheral), (failed, None));
+    }
+
+    #[test]
+    fn passkey_entry() {
+        let (mut central, mut peripheral) =
+            pairings_with(config(KeyboardOnly), config(DisplayOnly));
+
+        central.start(Instant::from_ticks(0));
+        let (central_event, Some(PairingEvent::DisplayPasskey(passkey))) =
+            run(&mut central, &mut peripheral)
+        else {
+            panic!("no passkey displayed");
+        };
+        assert_eq!(central_event, Some(PairingEvent::PasskeyRequest));
+
+        assert_eq!(
+            central.passkey_reply(Some(passkey), Instant::from_ticks(0)),
+            None
+        );
+        assert_eq!(
+            run(&mut central, &mut peripheral),
+            (Some(PairingEvent::Complete), Some(PairingEvent::Complete))
+        );
+        assert!(central.long_term_key().is_some());
+        assert_eq!(central.long_term_key(), peripheral.long_term_key());
+
+        // Both users enter the same passkey, one of them before the public keys are exchanged
+        let (mut central, mut peripheral) =
+            pairings_with(config(KeyboardOnly), config(KeyboardOnly));
+        central.start(Instant::from_ticks(0));
+        deliver(&mut central, &mut peripheral);
+        assert_eq!(
+            peripheral.passkey_reply(Some(123456), Instant::from_ticks(0)),
+            None
+        );
+        assert_eq!(
+            run(&mut central, &mut peripheral),
+            (Some(PairingEvent::PasskeyRequest), None)
+        );
+        central.passkey_reply(Some(123456), Instant::from_ticks(0));
+        assert_eq!(
+            run(&mut central, &mut peripheral),
+            (Some(PairingEvent::Complete), Some(PairingEvent::Complete))
+        );
+
+        // A wrong passkey fails on the round of the first different bit
+        let (mut central, mut peripheral) =
+            pairings_with(config(KeyboardDisplay), config(DisplayOnly));
+        central.start(Instant::from_ticks(0));
+        let (_, Some(PairingEvent::DisplayPasskey(passkey))) = run(&mut central, &mut peripheral)
+        else {
+            panic!("no passkey displayed");
+        };
+        central.passkey_reply(Some(passkey ^ 0x10), Instant::from_ticks(0));
+        let failed = Some(PairingEvent::Failed(PairingError::ConfirmValueFailed));
+        assert_eq!(run(&mut central, &mut peripheral), (failed, failed));
+        assert_eq!(central.long_term_key(), None);
+
+        // Cancelled by the user
+        let (mut central, mut peripheral) =
+            pairings_with(config(KeyboardOnly), config(DisplayOnly));
+        central.start(Instant::from_ticks(0));
+        run(&mut central, &mut peripheral);
+        let failed = Some(PairingEvent::Failed(PairingError::PasskeyEntryFailed));
+        assert_eq!(central.passkey_reply(None, Instant::from_ticks(0)), failed);
+        assert_eq!(run(&mut central, &mut peripheral), (None, failed));
+
+        // MITM protection is not possible without IO capabilities
+        let (mut central, mut peripheral) =
+            pairings_with(config(KeyboardOnly), PairingConfig::default());
+        central.start(Instant::from_ticks(0));
+        let failed = Some(PairingEvent::Failed(
+            PairingError::AuthenticationRequirements,
+        ));
+        assert_eq!(run(&mut central, &mut peripheral), (failed, failed));
+    }
+
+    #[test]
+    fn bonding() {
+        let bonding = |irk, csrk| PairingConfig {
+            bonding: true,
+            irk,
+            csrk,
+            ..PairingConfig::default()
+        };
+        let (mut central, mut peripheral) = pairings_with(
+            bonding(Some([0xC1; 16]), Some([0xC2; 16])),
+            bonding(Some([0x91; 16]), None),
+        );
+
+        central.start(Instant::from_ticks(0));
+        assert_eq!(run(&mut central, &mut peripheral), (None, None));
+        assert!(central.needs_encryption() && peripheral.needs_encryption());
+        assert!(central.long_term_key().is_some());
+        assert_eq!(central.long_term_key(), peripheral.long_term_key());
+        assert_eq!(central.bond(), None);
+
+        // The responder distributes its keys first
+        assert_eq!(central.on_encrypted(Instant::from_ticks(0)), None);
+        assert_eq!(peripheral.on_encrypted(Instant::from_ticks(0)), None);
+        assert_eq!(
+            run(&mut central, &mut peripheral),
+            (Some(PairingEvent::Complete), Some(PairingEvent::Complete))
+        );
+
+        let bond = central.bond().unwrap();
+        assert_eq!(bond.address, Address::new_public(0x0102030405));
+        assert_eq!(Some(bond.ltk), central.long_term_key());
+        assert_eq!((bond.irk, bond.csrk), (Some([0x91; 16]), None));
+        assert!(!bond.authenticated);
+
+        let bond = peripheral.bond().unwrap();
+        assert_eq!(bond.address, Address::new_random(0xffe1e8d0dc27));
+        assert_eq!((bond.irk, bond.csrk), (Some([0xC1; 16]), Some([0xC2; 16])));
+
+        // Without bonding on the peer, no key is distributed
+        let (mut central, mut peripheral) =
+            pairings_with(bonding(Some([0xC1; 16]), None), PairingConfig::default());
+        central.start(Instant::from_ticks(0));
+        assert_eq!(
+            run(&mut central, &mut peripheral),
+            (Some(PairingEvent::Complete), Some(PairingEvent::Complete))
+        );
+        assert_eq!(central.bond(), None);
+    }
+
+    #[test]
+    fn failures() {
+        let (central, mut peripheral) = pairings();
+        let mut buffer = [0u8; SmpPdu::MAX_LENGTH];
+
+        // LE legacy pairing request
+        let mut request = central.local_features;
+        request.auth_req = AuthReq::BONDING;
+        let len = SmpPdu::PairingRequest(request).bytes(&mut buffer);
+        let event = peripheral.on_pdu(&buffer[..len], Instant::from_ticks(0));
+        assert_eq!(
+            event,
+            Some(PairingEvent::Failed(
+                PairingError::AuthenticationRequirements
+            ))
+        );
+        let len = peripheral.poll(&mut buffer).unwrap();
+        assert_eq!(buffer[..len], [0x05, 0x03]);
+
+        // A wrong confirm value of the responder
+        let (mut central, mut peripheral) = pairings();
+        central.start(Instant::from_ticks(0));
+        deliver(&mut central, &mut peripheral);
+        deliver(&mut peripheral, &mut central);
+        deliver(&mut central, &mut peripheral);
+        peripheral.tx[1] = Some(SmpPdu::PairingConfirm([0; 16]));
+        deliver(&mut peripheral, &mut central);
+        deliver(&mut central, &mut peripheral);
+        assert_eq!(
+            deliver(&mut peripheral, &mut central),
+            Some(PairingEvent::Failed(PairingError::ConfirmValueFailed))
+        );
+        assert_eq!(
+            deliver(&mut central, &mut peripheral),
+            Some(PairingEvent::Failed(PairingError::ConfirmValueFailed))
+        );
+        assert_eq!(central.long_term_key(), None);
+    }
+
+    #[test]
+    fn timeout() {
+        let (mut central, _) = pairings();
+        central.start(Instant::from_ticks(0));
+        assert!(central.is_pairing());
+
+        assert_eq!(central.check_timeout(Instant::from_secs(30)), None);
+        assert_eq!(
+            central.check_timeout(Instant::from_secs(31)),
+            Some(PairingEvent::Timeout)
+        );
+        assert_eq!(central.poll(&mut [0u8; SmpPdu::MAX_LENGTH]), None);
+        assert_eq!(central.on_pdu(&[0x05, 0x08], Instant::from_secs(32)), None);
+    }
+}
@@ -0,0 +1,503 @@
+use defmt::Format;
+
+use crate::ll::{AddressType, ParseError};
+
+use super::swap;
+
+/// Reason of a Pairing Failed command
+///
+/// Ref: Core 3.H.3.5.5
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Format)]
+pub enum PairingError {
+    PasskeyEntryFailed,
+    OobNotAvailable,
+    AuthenticationRequirements,
+    ConfirmValueFailed,
+    PairingNotSupported,
+    EncryptionKeySize,
+    CommandNotSupported,
+    UnspecifiedReason,
+    RepeatedAttempts,
+    InvalidParameters,
+    DhKeyCheckFailed,
+    NumericComparisonFailed,
+    BrEdrPairingInProgress,
+    CrossTransportKeyDerivationNotAllowed,
+    KeyRejected,
+    Busy,
+}
+
+impl PairingError {
+    pub fn code(&self) -> u8 {
+        match self {
+            Self::PasskeyEntryFailed => 0x01,
+            Self::OobNotAvailable => 0x02,
+            Self::AuthenticationRequirements => 0x03,
+            Self::ConfirmValueFailed => 0x04,
+            Self::PairingNotSupported => 0x05,
+            Self::EncryptionKeySize => 0x06,
+            Self::CommandNotSupported => 0x07,
+            Self::UnspecifiedReason => 0x08,
+            Self::RepeatedAttempts => 0x09,
+            Self::InvalidParameters => 0x0A,
+            Self::DhKeyCheckFailed => 0x0B,
+            Self::NumericComparisonFailed => 0x0C,
+            Self::BrEdrPairingInProgress => 0x0D,
+            Self::CrossTransportKeyDerivationNotAllowed => 0x0E,
+            Self::KeyRejected => 0x0F,
+            Self::Busy => 0x10,
+        }
+    }
+}
+
+impl TryFrom<u8> for PairingError {
+    type Error = ParseError;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        Ok(match value {
+            0x01 => Self::PasskeyEntryFailed,
+            0x02 => Self::OobNotAvailable,
+            0x03 => Self::AuthenticationRequirements,
+            0x04 => Self::ConfirmValueFailed,
+            0x05 => Self::PairingNotSupported,
+            0x06 => Self::EncryptionKeySize,
+            0x07 => Self::CommandNotSupported,
+            0x08 => Self::UnspecifiedReason,
+            0x09 => Self::RepeatedAttempts,
+            0x0A => Self::InvalidParameters,
+            0x0B => Self::DhKeyCheckFailed,
+            0x0C => Self::NumericComparisonFailed,
+            0x0D => Self::BrEdrPairingInProgress,
+            0x0E => Self::CrossTransportKeyDerivationNotAllowed,
+            0x0F => Self::KeyRejected,
+            0x10 => Self::Busy,
+            _ => return Err(ParseError::InvalidValue),
+        })
+    }
+}
+
+/// Input and output capabilities of a device
+///
+/// Ref: Core 3.H.2.3.2
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Format)]
+pub enum IoCapability {
+    DisplayOnly = 0x00,
+    DisplayYesNo = 0x01,
+    KeyboardOnly = 0x02,
+    NoInputNoOutput = 0x03,
+    KeyboardDisplay = 0x04,
+}
+
+impl TryFrom<u8> for IoCapability {
+    type Error = ParseError;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        Ok(match value {
+            0x00 => Self::DisplayOnly,
+            0x01 => Self::DisplayYesNo,
+            0x02 => Self::KeyboardOnly,
+            0x03 => Self::NoInputNoOutput,
+            0x04 => Self::KeyboardDisplay,
+            _ => return Err(ParseError::InvalidValue),
+        })
+    }
+}
+
+/// Authentication requirements of a device, a bit field of 8 bits
+///
+/// Ref: Core 3.H.3.5.1
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Format)]
+pub struct AuthReq {
+    bits: u8,
+}
+
+impl AuthReq {
+    /// The keys are stored, the Bonding_Flags
+    pub const BONDING: AuthReq = AuthReq::new(0x01);
+    /// Protection against man-in-the-middle attacks is required
+    pub const MITM: AuthReq = AuthReq::new(1 << 2);
+    pub const SECURE_CONNECTIONS: AuthReq = AuthReq::new(1 << 3);
+    pub const KEYPRESS: AuthReq = AuthReq::new(1 << 4);
+    pub const CT2: AuthReq = AuthReq::new(1 << 5);
+
+    pub const fn new(bits: u8) -> Self {
+        Self { bits }
+    }
+
+    pub const fn empty() -> Self {
+        Self::new(0)
+    }
+
+    pub const fn union(self, other: AuthReq) -> Self {
+        Self::new(self.bits | other.bits)
+    }
+
+    pub fn contains(&self, other: AuthReq) -> bool {
+        self.bits & other.bits == other.bits
+    }
+
+    pub fn bits(&self) -> u8 {
+        self.bits
+    }
+}
+
+/// Keys distributed after the pairing, a bit field of 8 bits
+///
+/// Ref: Core 3.H.3.6.1
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Format)]
+pub struct KeyDistribution {
+    bits: u8,
+}
+
+impl KeyDistribution {
+    /// The LTK, not distributed on LE Secure Connections
+    pub const ENC_KEY: KeyDistribution = KeyDistribution::new(1 << 0);
+    /// The IRK and the identity address
+    pub const ID_KEY: KeyDistribution = KeyDistribution::new(1 << 1);
+    /// The CSRK
+    pub const SIGN_KEY: KeyDistribution = KeyDistribution::new(1 << 2);
+    pub const LINK_KEY: KeyDistribution = KeyDistribution::new(1 << 3);
+
+    pub const fn new(bits: u8) -> Self {
+        Self { bits }
+    }
+
+    pub const fn empty() -> Self {
+        Self::new(0)
+    }
+
+    pub const fn union(self, other: KeyDistribution) -> Self {
+        Self::new(self.bits | other.bits)
+    }
+
+    pub const fn intersection(self, other: KeyDistribution) -> Self {
+        Self::new(self.bits & other.bits)
+    }
+
+    /// Keys of `self` that are not in `other`
+    pub const fn difference(self, other: KeyDistribution) -> Self {
+        Self::new(self.bits & !other.bits)
+    }
+
+    pub fn contains(&self, other: KeyDistribution) -> bool {
+        self.bits & other.bits == other.bits
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bits == 0
+    }
+}
+
+/// Parameters of the Pairing Request and Pairing Response
+///
+/// Ref: Core 3.H.3.5.1 and Core 3.H.3.5.2
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Format)]
+pub struct PairingFeatures {
+    pub io_capability: IoCapability,
+
+    /// Out of band data of the peer is available
+    pub oob_data: bool,
+
+    pub auth_req: AuthReq,
+
+    /// Maximum encryption key size, from 7 to 16 octets
+    pub max_key_size: u8,
+
+    /// Keys the initiator distributes
+    pub initiator_keys: KeyDistribution,
+
+    /// Keys the responder distributes
+    pub responder_keys: KeyDistribution,
+}
+
+impl PairingFeatures {
+    pub const LENGTH: usize = 6;
+
+    /// IOcap of the f6 function, the AuthReq as most significant octet
+    pub fn io_cap(&self) -> [u8; 3] {
+        [
+            self.auth_req.bits,
+            self.oob_data as u8,
+            self.io_capability as u8,
+        ]
+    }
+
+    pub fn bytes(&self, dest: &mut [u8]) -> usize {
+        dest[0] = self.io_capability as u8;
+        dest[1] = self.oob_data as u8;
+        dest[2] = self.auth_req.bits;
+        dest[3] = self.max_key_size;
+        dest[4] = self.initiator_keys.bits;
+        dest[5] = self.responder_keys.bits;
+        Self::LENGTH
+    }
+
+    pub fn parse(bytes: &[u8]) -> Result<Self, ParseError> {
+        if bytes.len() != Self::LENGTH {
+            return Err(ParseError::InvalidLength);
+        }
+        if !(7..=16).contains(&bytes[3]) {
+            return Err(ParseError::InvalidValue);
+        }
+
+        Ok(Self {
+            io_capability: IoCapability::try_from(bytes[0])?,
+            oob_data: bytes[1] == 0x01,
+            auth_req: AuthReq::new(bytes[2]),
+            max_key_size: bytes[3],
+            initiator_keys: KeyDistribution::new(bytes[4]),
+            responder_keys: KeyDistribution::new(bytes[5]),
+        })
+    }
+}
+
+/// Security Manager Protocol command
+///
+/// The values are big endian as in the cryptographic functions,
+/// they are sent in little endian.
+///
+///   ┌──────────┬────────────────────────────────┐
+///   │ Code     │ Data                           │
+///   │ (1 byte) │ (0 to 64 bytes)                │
+///   └──────────┴────────────────────────────────┘
+///
+/// Ref: Core 3.H.3.3
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Format)]
+pub enum SmpPdu {
+    PairingRequest(PairingFeatures),
+    PairingResponse(PairingFeatures),
+    PairingConfirm([u8; 16]),
+    PairingRandom([u8; 16]),
+    PairingFailed(PairingError),
+    /// LTK of LE legacy pairing
+    EncryptionInformation([u8; 16]),
+    /// Identifies the LTK of LE legacy pairing
+    CentralIdentification {
+        ediv: u16,
+        rand: u64,
+    },
+    /// IRK of the device
+    IdentityInformation([u8; 16]),
+    /// Public or static random identity address of the device, little endian
+    IdentityAddressInformation {
+        address_type: AddressType,
+        address: [u8; 6],
+    },
+    /// CSRK of the device
+    SigningInformation([u8; 16]),
+    /// Sent by the peripheral to ask the central to start the pairing
+    SecurityRequest(AuthReq),
+    PairingPublicKey {
+        x: [u8; 32],
+        y: [u8; 32],
+    },
+    PairingDhKeyCheck([u8; 16]),
+    /// Passkey entry progress
+    KeypressNotification(u8),
+}
+
+impl SmpPdu {
+    /// Length of the largest command, the Pairing Public Key
+    pub const MAX_LENGTH: usize = 65;
+
+    pub fn code(&self) -> u8 {
+        match self {
+            Self::PairingRequest(_) => 0x01,
+            Self::PairingResponse(_) => 0x02,
+            Self::PairingConfirm(_) => 0x03,
+            Self::PairingRandom(_) => 0x04,
+            Self::PairingFailed(_) => 0x05,
+            Self::EncryptionInformation(_) => 0x06,
+            Self::CentralIdentification { .. } => 0x07,
+            Self::IdentityInformation(_) => 0x08,
+            Self::IdentityAddressInformation { .. } => 0x09,
+            Self::SigningInformation(_) => 0x0A,
+            Self::SecurityRequest(_) => 0x0B,
+            Self::PairingPublicKey { .. } => 0x0C,
+            Self::PairingDhKeyCheck(_) => 0x0D,
+            Self::KeypressNotification(_) => 0x0E,
+        }
+    }
+
+    pub fn bytes(&self, dest: &mut [u8]) -> usize {
+        dest[0] = self.code();
+        let data = &mut dest[1..];
+
+        let len = match self {
+            Self::PairingRequest(features) | Self::PairingResponse(features) => {
+                features.bytes(data)
+            }
+            Self::PairingConfirm(value)
+            | Self::PairingRandom(value)
+            | Self::PairingDhKeyCheck(value)
+            | Self::EncryptionInformation(value)
+            | Self::IdentityInformation(value)
+            | Self::SigningInformation(value) => {
+                data[..16].copy_from_slice(&swap(*value));
+                16
+            }
+            Self::CentralIdentification { ediv, rand } => {
+                data[..2].copy_from_slice(&ediv.to_le_bytes());
+                data[2..10].copy_from_slice(&rand.to_le_bytes());
+                10
+            }
+            Self::IdentityAddressInformation {
+                address_type,
+                address,
+            } => {
+                data[0] = match address_type {
+                    AddressType::Public => 0x00,
+                    AddressType::Random => 0x01,
+                };
+                data[1..7].copy_from_slice(address);
+                7
+            }
+            Self::PairingFailed(reason) => {
+                data[0] = reason.code();
+                1
+            }
+            Self::SecurityRequest(auth_req) => {
+                data[0] = auth_req.bits;
+                1
+            }
+            Self::PairingPublicKey { x, y } => {
+                data[..32].copy_from_slice(&swap(*x));
+                data[32..64].copy_from_slice(&swap(*y));
+                64
+            }
+            Self::KeypressNotification(kind) => {
+                data[0] = *kind;
+                1
+            }
+        };
+
+        1 + len
+    }
+
+    pub fn parse(bytes: &[u8]) -> Result<Self, ParseError> {
+        let (&code, data) = bytes.split_first().ok_or(ParseError::InvalidLength)?;
+
+        let value = |len: usize| -> Result<&[u8], ParseError> {
+            if data.len() != len {
+                return Err(ParseError::InvalidLength);
+            }
+            Ok(data)
+        };
+        let value16 =
+            || -> Result<[u8; 16], ParseError> { Ok(swap(value(16)?.try_into().unwrap())) };
+
+        Ok(match code {
+            0x01 => Self::PairingRequest(PairingFeatures::parse(data)?),
+            0x02 => Self::PairingResponse(PairingFeatures::parse(data)?),
+            0x03 => Self::PairingConfirm(value16()?),
+            0x04 => Self::PairingRandom(value16()?),
+            0x05 => Self::PairingFailed(PairingError::try_from(value(1)?[0])?),
+            0x06 => Self::EncryptionInformation(value16()?),
+            0x07 => {
+                let data = value(10)?;
+                Self::CentralIdentification {
+                    ediv: u16::from_le_bytes([data[0], data[1]]),
+                    rand: u64::from_le_bytes(data[2..].try_into().unwrap()),
+                }
+            }
+            0x08 => Self::IdentityInformation(value16()?),
+            0x09 => {
+                let data = value(7)?;
+                Self::IdentityAddressInformation {
+                    address_type: match data[0] {
+                        0x00 => AddressType::Public,
+                        0x01 => AddressType::Random,
+                        _ => return Err(ParseError::InvalidValue),
+                    },
+                    address: data[1..].try_into().unwrap(),
+                }
+            }
+            0x0A => Self::SigningInformation(value16()?),
+            0x0B => Self::SecurityRequest(AuthReq::new(value(1)?[0])),
+            0x0C => {
+                let key = value(64)?;
+                Self::PairingPublicKey {
+                    x: swap(key[..32].try_into().unwrap()),
+                    y: swap(key[32..].try_into().unwrap()),
+                }
+            }
+            0x0D => Self::PairingDhKeyCheck(value16()?),
+            0x0E => Self::KeypressNotification(value(1)?[0]),
+            _ => return Err(ParseError::InvalidType),
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn pairing_request() {
+        // Pairing Request of a phone, KeyboardDisplay with bonding, MITM and SC
+        let bytes = [0x01, 0x04, 0x00, 0x2D, 0x10, 0x0F, 0x0F];
+        let pdu = SmpPdu::parse(&bytes).unwrap();
+        let SmpPdu::PairingRequest(features) = pdu else {
+            panic!("unexpected {:?}", pdu);
+        };
+        assert_eq!(features.io_capability, IoCapability::KeyboardDisplay);
+        assert!(features
+            .auth_req
+            .contains(AuthReq::SECURE_CONNECTIONS.union(AuthReq::MITM)));
+        assert!(features.initiator_keys.contains(KeyDistribution::ID_KEY));
+        assert_eq!(features.io_cap(), [0x2D, 0x00, 0x04]);
+
+        let mut buffer = [0u8; SmpPdu::MAX_LENGTH];
+        assert_eq!(pdu.bytes(&mut buffer), bytes.len());
+        assert_eq!(buffer[..bytes.len()], bytes);
+
+        // Key size out of range
+        let bytes = [0x01, 0x04, 0x00, 0x2D, 0x06, 0x0F, 0x0F];
+        assert_eq!(SmpPdu::parse(&bytes), Err(ParseError::InvalidValue));
+    }
+
+    #[test]
+    fn little_endian_values() {
+        let mut value = [0u8; 16];
+        value[0] = 0xAA;
+        value[15] = 0x01;
+
+        let mut buffer = [0u8; SmpPdu::MAX_LENGTH];
+        let len = SmpPdu::PairingRandom(value).bytes(&mut buffer);
+        assert_eq!(len, 17);
+        assert_eq!(buffer[1], 0x01);
+        assert_eq!(buffer[16], 0xAA);
+        assert_eq!(
+            SmpPdu::parse(&buffer[..len]),
+            Ok(SmpPdu::PairingRandom(value))
+        );
+
+        let key = SmpPdu::PairingPublicKey {
+            x: [0x11; 32],
+            y: [0x22; 32],
+        };
+        assert_eq!(key.bytes(&mut buffer), SmpPdu::MAX_LENGTH);
+        assert_eq!(SmpPdu::parse(&buffer), Ok(key));
+
+        assert_eq!(
+            SmpPdu::parse(&[0x05, 0x0B]),
+            Ok(SmpPdu::PairingFailed(PairingError::DhKeyCheckFailed))
+        );
+        assert_eq!(SmpPdu::parse(&[0x04, 0x00]), Err(ParseError::InvalidLength));
+
+        let address = [0x01, 0x00, 0x00, 0xee, 0xff, 0xc0];
+        let bytes = [0x09, 0x01, 0x01, 0x00, 0x00, 0xee, 0xff, 0xc0];
+        let identity = SmpPdu::IdentityAddressInformation {
+            address_type: AddressType::Random,
+            address,
+        };
+        assert_eq!(SmpPdu::parse(&bytes), Ok(identity));
+        assert_eq!(identity.bytes(&mut buffer), bytes.len());
+        assert_eq!(buffer[..bytes.len()], bytes);
+        assert_eq!(
+            SmpPdu::parse(&[0x09, 0x02, 0, 0, 0, 0, 0, 0]),
+            Err(ParseError::InvalidValue)
+        );
+        assert_eq!(SmpPdu::parse(&[0x20]), Err(ParseError::InvalidType));
+    }
+}
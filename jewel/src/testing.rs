@@ -0,0 +1,75 @@
+//! Simulated clock and executor for the tests running the connection events
+//!
+//! Each test thread has its own clock, the timers expire as soon as they are awaited.
+
+use core::{
+    cell::Cell,
+    future::Future,
+    pin::pin,
+    task::{Context, Poll, Waker},
+};
+
+use embassy_time::Instant;
+
+use crate::{
+    phy::{Channel, HeaderSize, Mode, Radio},
+    Address,
+};
+
+std::thread_local! {
+    static NOW: Cell<u64> = const { Cell::new(0) };
+}
+
+#[no_mangle]
+fn _embassy_time_now() -> u64 {
+    NOW.with(Cell::get)
+}
+
+#[no_mangle]
+fn _embassy_time_schedule_wake(at: u64, waker: &Waker) {
+    NOW.with(|now| now.set(now.get().max(at)));
+    waker.wake_by_ref();
+}
+
+/// Time of the simulated clock of the test
+pub(crate) fn now() -> Instant {
+    Instant::from_ticks(_embassy_time_now())
+}
+
+/// Poll the future until it completes
+pub(crate) fn block_on<F: Future>(future: F) -> F::Output {
+    let mut future = pin!(future);
+    let mut cx = Context::from_waker(Waker::noop());
+    loop {
+        if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+            return output;
+        }
+    }
+}
+
+/// Radio transmitting in the void, nothing is ever received
+pub(crate) struct Silence;
+
+impl Radio for Silence {
+    type Error = ();
+
+    fn set_mode(&mut self, _mode: Mode) {}
+    fn set_tx_power(&mut self, _power_db: i8) {}
+    fn set_header_size(&mut self, _header_size: HeaderSize) {}
+    fn set_access_address(&mut self, _access_address: u32) {}
+    fn set_channel(&mut self, _channel: Channel) {}
+    fn set_crc_poly(&mut self, _crc_poly: u32) {}
+    fn set_crc_init(&mut self, _crc_init: u32) {}
+
+    async fn transmit(&mut self, _buffer: &[u8]) -> Result<(), ()> {
+        Ok(())
+    }
+
+    async fn receive(&mut self, _buffer: &mut [u8]) -> Result<(), ()> {
+        core::future::pending().await
+    }
+
+    fn device_address(&self) -> Address {
+        Address::new_random(0xc1e1e8d0dc27)
+    }
+}